@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use log::{debug, info, warn};
+use nostr_sdk::{Event, JsonUtil, Timestamp, Url};
+
+use crate::LOCAL_RELAY_NAME;
+
+/// Append-only local store of events, one JSON event per line in a file per relay.
+/// Events of the local relay are stored under its own name.
+#[derive(Debug, Clone)]
+pub(crate) struct EventCache {
+    dir: PathBuf,
+}
+
+impl EventCache {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        EventCache { dir }
+    }
+
+    fn path_for(&self, url: Option<&Url>) -> PathBuf {
+        let name = url.map_or(LOCAL_RELAY_NAME.to_ascii_lowercase(), |url|
+            url.as_str()
+                .trim_end_matches('/')
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' })
+                .collect::<String>());
+        self.dir.join(format!("{name}.jsonl"))
+    }
+
+    /// Load all cached events for the given relay.
+    /// Skips corrupt lines and duplicate ids,
+    /// compacting the file if it holds duplicates since events are appended as they arrive.
+    pub(crate) fn load(&self, url: Option<&Url>) -> Vec<Event> {
+        let name = display_url(url);
+        let path = self.path_for(url);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                debug!("No event cache for {name} at {}: {e}", path.to_string_lossy());
+                return vec![];
+            }
+        };
+        let mut ids = HashSet::new();
+        let (mut events, corrupt) = read_events(BufReader::new(file));
+        let total = events.len();
+        events.retain(|event| ids.insert(event.id));
+        if corrupt > 0 {
+            warn!("Skipped {corrupt} corrupt lines in {}", path.to_string_lossy());
+        }
+        if events.len() < total {
+            debug!("Compacting {} duplicate events in {}", total - events.len(), path.to_string_lossy());
+            if let Err(e) = self.replace(url, &events) {
+                warn!("Could not compact {}: {e}", path.to_string_lossy());
+            }
+        }
+        info!("Loaded {} cached events for {name}", events.len());
+        events
+    }
+
+    /// Append the given events to the cache of the relay.
+    pub(crate) fn append<'a>(&self, url: Option<&Url>, events: impl IntoIterator<Item=&'a Event>) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(self.path_for(url))?;
+        write_events(&mut file, events).map(|_| ())
+    }
+
+    /// Replace the events stored for the relay, removing the file if there are none left.
+    pub(crate) fn replace(&self, url: Option<&Url>, events: &[Event]) -> std::io::Result<()> {
+        let path = self.path_for(url);
+        if events.is_empty() {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            return Ok(());
+        }
+        write_events(&mut File::create(path)?, events).map(|_| ())
+    }
+
+    /// Remove the cache of the given relay.
+    pub(crate) fn clear(&self, url: Option<&Url>) -> std::io::Result<()> {
+        let path = self.path_for(url);
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        info!("Cleared event cache for {}", display_url(url));
+        Ok(())
+    }
+}
+
+/// Parse one JSON event per line, returning the events and the number of unparseable lines.
+pub(crate) fn read_events(reader: impl BufRead) -> (Vec<Event>, usize) {
+    let mut corrupt = 0;
+    let events = reader.lines().flatten()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match Event::from_json(&line) {
+            Ok(event) => Some(event),
+            Err(_) => {
+                corrupt += 1;
+                None
+            }
+        })
+        .collect();
+    (events, corrupt)
+}
+
+/// Write one JSON event per line in a single write, returning the number of events.
+pub(crate) fn write_events<'a>(writer: &mut impl Write, events: impl IntoIterator<Item=&'a Event>) -> std::io::Result<usize> {
+    let mut buffer = String::new();
+    let mut count = 0;
+    for event in events {
+        buffer.push_str(&event.as_json());
+        buffer.push('\n');
+        count += 1;
+    }
+    writer.write_all(buffer.as_bytes())?;
+    Ok(count)
+}
+
+/// Newest timestamp of the given events that is not in the future,
+/// so planned time-tracking does not hide events received later.
+pub(crate) fn newest_timestamp<'a>(events: impl IntoIterator<Item=&'a Event>) -> Option<Timestamp> {
+    let now = Timestamp::now();
+    events.into_iter()
+        .map(|e| e.created_at)
+        .filter(|t| t <= &now)
+        .max()
+}
+
+fn display_url(url: Option<&Url>) -> String {
+    url.map_or(LOCAL_RELAY_NAME.to_string(), |url| url.to_string())
+}
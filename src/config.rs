@@ -0,0 +1,744 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use chrono::NaiveDate;
+use itertools::Itertools;
+use log::{debug, info, warn};
+use nostr_sdk::Url;
+
+/// Days of time-tracking history of other people kept unless configured otherwise
+const DEFAULT_RETENTION_DAYS: u64 = 90;
+
+/// Names of the values in [Settings]
+pub(crate) const SETTINGS_KEYS: [&str; 22] = ["min_length", "undo_delay", "inactivity_delay", "max_offset", "depth", "columns", "author_width", "key_length", "filter_tags_on_creation", "strip_hashtags", "creation", "others_tasks", "max_skew", "owner_states", "project_root", "max_content", "max_tags", "max_future", "adopt_relays", "exit_summary", "note_lines", "note_length"];
+
+/// Task events which can trigger a hook command
+pub(crate) const HOOK_EVENTS: [&str; 5] = ["task_created", "state_changed", "tracking_started", "tracking_stopped", "note_added"];
+
+/// Categories of desktop notifications about updates by others
+pub(crate) const DESKTOP_EVENTS: [&str; 4] = ["assigned", "mentioned", "finished", "due"];
+
+/// Section of commands to run at startup, one per line
+const STARTUP_SECTION: &str = "startup";
+
+/// Settings from the config file,
+/// written as `key = value` lines in sections started by a `[NAME]` line.
+/// Lines starting with `#` or `;` are comments,
+/// except in the `[startup]` section where `#` filters by hashtag.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Config {
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl Config {
+    /// Load the config file, falling back to an empty config if it is missing or unreadable.
+    pub(crate) fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                info!("Loaded config from {}", path.to_string_lossy());
+                Self::parse(&content)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                warn!("Could not read config from {}: {e}", path.to_string_lossy());
+                Config::default()
+            }
+        }
+    }
+
+    pub(crate) fn parse(content: &str) -> Self {
+        let mut sections: Vec<(String, Vec<(String, String)>)> = vec![];
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            let startup = sections.last().is_some_and(|(name, _)| name == STARTUP_SECTION);
+            if line.is_empty() || line.starts_with(';') || (line.starts_with('#') && !startup) {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                sections.push((name.trim().to_string(), vec![]));
+            } else if let (true, Some((_, entries))) = (startup, sections.last_mut()) {
+                entries.push((String::new(), line.to_string()));
+            } else if let (Some((key, value)), Some((_, entries))) = (line.split_once('='), sections.last_mut()) {
+                entries.push((key.trim().to_string(), value.trim().to_string()));
+            } else {
+                warn!("Ignoring line {} of config: {line}", index + 1);
+            }
+        }
+        Config { sections }
+    }
+
+    /// Sections with their entries in the order of the file
+    pub(crate) fn sections(&self) -> &[(String, Vec<(String, String)>)] {
+        &self.sections
+    }
+
+    /// Defaults for the given relay from its `[relay URL]` section,
+    /// with the history retention falling back to the `[history]` section.
+    pub(crate) fn relay_defaults(&self, url: &Url) -> RelayDefaults {
+        let mut defaults = RelayDefaults {
+            retention: Some(DEFAULT_RETENTION_DAYS),
+            ..Default::default()
+        };
+        let history = self.sections.iter()
+            .filter(|(name, _)| name == "history")
+            .flat_map(|(_, entries)| entries);
+        for (key, value) in history {
+            match key.as_str() {
+                "retention" => defaults.retention = parse_retention(value),
+                _ => warn!("Ignoring unknown history setting {key}"),
+            }
+        }
+        let entries = self.sections.iter()
+            .filter(|(name, _)| name.strip_prefix("relay ")
+                .is_some_and(|section_url| Url::parse(section_url.trim()).is_ok_and(|u| &u == url)))
+            .flat_map(|(_, entries)| entries);
+        for (key, value) in entries {
+            let words = || value.split_whitespace().map(|s| s.to_string());
+            match key.as_str() {
+                "position" => defaults.position = Some(value.to_string()),
+                "tags" => defaults.tags = words().map(|t| t.trim_start_matches('#').to_string()).collect(),
+                "sorting" => defaults.sorting = Some(words().collect()),
+                "columns" => defaults.columns = Some(words().collect()),
+                "retention" => defaults.retention = parse_retention(value),
+                _ => warn!("Ignoring unknown setting {key} for relay {url}"),
+            }
+        }
+        defaults
+    }
+
+    /// Settings from the `[settings]` section, falling back to the defaults
+    pub(crate) fn settings(&self) -> Settings {
+        let mut settings = Settings::default();
+        let entries = self.sections.iter()
+            .filter(|(name, _)| name == "settings")
+            .flat_map(|(_, entries)| entries);
+        for (key, value) in entries {
+            if let Err(e) = settings.set(key, value) {
+                warn!("Ignoring setting {key}: {e}");
+            }
+        }
+        settings
+    }
+
+    /// Timezone and week start from the `[time]` section
+    pub(crate) fn time_settings(&self) -> TimeSettings {
+        let mut settings = TimeSettings::default();
+        let entries = self.sections.iter()
+            .filter(|(name, _)| name == "time")
+            .flat_map(|(_, entries)| entries);
+        for (key, value) in entries {
+            match key.as_str() {
+                "timezone" => settings.timezone = Some(value.to_string()),
+                "week_start" => match value.to_ascii_lowercase().as_str() {
+                    "monday" | "mon" => settings.week_starts_sunday = false,
+                    "sunday" | "sun" => settings.week_starts_sunday = true,
+                    _ => warn!("Weeks can only start on monday or sunday, not {value}"),
+                },
+                _ => warn!("Ignoring unknown time setting {key}"),
+            }
+        }
+        settings
+    }
+
+    /// Commands to run after the relays are selected, in order
+    pub(crate) fn startup(&self) -> Vec<String> {
+        self.sections.iter()
+            .filter(|(name, _)| name == STARTUP_SECTION)
+            .flat_map(|(_, entries)| entries)
+            .map(|(_, command)| command.to_string())
+            .collect()
+    }
+
+    /// Aliases from the `[alias]` section, with surrounding quotes removed
+    pub(crate) fn aliases(&self) -> Aliases {
+        let entries = self.sections.iter()
+            .filter(|(name, _)| name == "alias")
+            .flat_map(|(_, entries)| entries);
+        let mut aliases = HashMap::new();
+        for (name, command) in entries {
+            if name.contains(char::is_whitespace) || name.is_empty() {
+                warn!("Ignoring alias \"{name}\", it has to be a single word");
+                continue;
+            }
+            let command = command.strip_prefix('"').and_then(|c| c.strip_suffix('"')).unwrap_or(command);
+            aliases.insert(name.to_string(), command.to_string());
+        }
+        Aliases(aliases)
+    }
+
+    /// Hashtag hierarchy from the `[tags]` section,
+    /// each entry listing the children of a parent hashtag like `work = clientA clientB`
+    pub(crate) fn tag_hierarchy(&self) -> TagHierarchy {
+        let entries = self.sections.iter()
+            .filter(|(name, _)| name == "tags")
+            .flat_map(|(_, entries)| entries);
+        TagHierarchy(entries
+            .map(|(parent, children)| (
+                parent.trim_start_matches('#').to_string(),
+                children.split_whitespace().map(|c| c.trim_start_matches('#').to_string()).collect(),
+            ))
+            .collect())
+    }
+
+    /// Templates for new tasks from the `[snippets]` section
+    pub(crate) fn snippets(&self) -> Snippets {
+        let entries = self.sections.iter()
+            .filter(|(name, _)| name == "snippets")
+            .flat_map(|(_, entries)| entries);
+        let mut snippets = HashMap::new();
+        for (name, template) in entries {
+            if name.contains(char::is_whitespace) || name.is_empty() {
+                warn!("Ignoring snippet \"{name}\", it has to be a single word");
+                continue;
+            }
+            let template = template.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(template);
+            snippets.insert(name.to_string(), template.to_string());
+        }
+        Snippets(snippets)
+    }
+
+    /// Hook commands from the `[hooks]` section
+    pub(crate) fn hooks(&self) -> Hooks {
+        let mut hooks = Hooks::default();
+        let entries = self.sections.iter()
+            .filter(|(name, _)| name == "hooks")
+            .flat_map(|(_, entries)| entries);
+        for (key, value) in entries {
+            match key.as_str() {
+                "others" => match value.parse() {
+                    Ok(others) => hooks.others = others,
+                    Err(_) => warn!("Hook setting others can only be true or false, not {value}"),
+                },
+                event if HOOK_EVENTS.contains(&event) => {
+                    hooks.commands.insert(event.to_string(), value.to_string());
+                }
+                _ => warn!("Ignoring unknown hook {key}, available are {}", HOOK_EVENTS.join(", ")),
+            }
+        }
+        hooks
+    }
+
+    /// Categories of desktop notifications enabled in the `[desktop]` section
+    pub(crate) fn desktop(&self) -> DesktopNotifications {
+        let mut desktop = DesktopNotifications::default();
+        let entries = self.sections.iter()
+            .filter(|(name, _)| name == "desktop")
+            .flat_map(|(_, entries)| entries);
+        for (key, value) in entries {
+            let Ok(enabled) = value.parse() else {
+                warn!("Desktop notification setting {key} can only be true or false, not {value}");
+                continue;
+            };
+            match key.as_str() {
+                "assigned" => desktop.assigned = enabled,
+                "mentioned" => desktop.mentioned = enabled,
+                "finished" => desktop.finished = enabled,
+                "due" => desktop.due = enabled,
+                _ => warn!("Ignoring unknown desktop notification {key}, available are {}", DESKTOP_EVENTS.join(", ")),
+            }
+        }
+        desktop
+    }
+}
+
+/// Hashtags implied by a parent hashtag when filtering, as configured in the `[tags]` section
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TagHierarchy(Vec<(String, Vec<String>)>);
+
+impl TagHierarchy {
+    /// Child hashtags of the given one, compared case-insensitively
+    pub(crate) fn children(&self, tag: &str) -> &[String] {
+        self.0.iter()
+            .find(|(parent, _)| parent.eq_ignore_ascii_case(tag))
+            .map_or(&[], |(_, children)| children.as_slice())
+    }
+
+    /// Parent hashtag of the given one, if it is configured as a child
+    pub(crate) fn parent(&self, tag: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(_, children)| children.iter().any(|c| c.eq_ignore_ascii_case(tag)))
+            .map(|(parent, _)| parent.as_str())
+    }
+
+    /// The hashtag together with all hashtags below it
+    pub(crate) fn expand(&self, tag: &str) -> Vec<String> {
+        let mut expanded = vec![tag.to_string()];
+        let mut index = 0;
+        while index < expanded.len() {
+            for child in self.children(&expanded[index]) {
+                if !expanded.iter().any(|t| t.eq_ignore_ascii_case(child)) {
+                    expanded.push(child.clone());
+                }
+            }
+            index += 1;
+        }
+        expanded
+    }
+}
+
+/// Shell commands run in the background on task events
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Hooks {
+    /// Command by event name as listed in [HOOK_EVENTS]
+    pub(crate) commands: HashMap<String, String>,
+    /// Whether state changes by others received from relays trigger hooks as well
+    pub(crate) others: bool,
+}
+
+/// Desktop notifications about updates by others, each category as listed in [DESKTOP_EVENTS]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct DesktopNotifications {
+    /// A task being assigned to oneself
+    pub(crate) assigned: bool,
+    /// A note mentioning oneself
+    pub(crate) mentioned: bool,
+    /// An own task being marked as done or closed
+    pub(crate) finished: bool,
+    /// An own or assigned task coming due or being resumed after it was deferred
+    pub(crate) due: bool,
+}
+
+/// Short names for commands
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Aliases(HashMap<String, String>);
+
+impl Aliases {
+    /// Replace an alias at the start of the input by its command,
+    /// with `$1` to `$9` substituted by the following words.
+    /// If the command references none of them, the words are appended instead.
+    /// Aliases can build upon others, but not upon themselves.
+    pub(crate) fn expand(&self, input: &str) -> Result<String, String> {
+        let mut input = input.to_string();
+        let mut used: Vec<String> = vec![];
+        loop {
+            let mut words = input.split_whitespace();
+            let Some((name, command)) = words.next().and_then(|name| self.0.get(name).map(|command| (name, command))) else {
+                return Ok(input);
+            };
+            if used.iter().any(|n| n == name) {
+                return Err(format!("Alias {name} is recursive: {} -> {name}", used.join(" -> ")));
+            }
+            used.push(name.to_string());
+            let expanded = substitute(command, &words.collect_vec());
+            debug!("Expanded alias {name} to \"{expanded}\"");
+            input = expanded;
+        }
+    }
+}
+
+/// Named templates for the input of new tasks
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Snippets(HashMap<String, String>);
+
+impl Snippets {
+    /// Fill in the snippet named by the first word of the input,
+    /// none if there is no such snippet.
+    /// `{arg}` is replaced by the remaining words, `{date}` by the given day
+    /// and `{parent}` by the title of the current task.
+    pub(crate) fn fill(&self, input: &str, date: NaiveDate, parent: Option<&str>) -> Option<Result<String, String>> {
+        let (name, arg) = input.trim().split_once(char::is_whitespace).unwrap_or((input.trim(), ""));
+        let template = self.0.get(name)?;
+        let arg = arg.trim();
+        Some(if template.contains("{arg}") && arg.is_empty() {
+            Err(format!("Snippet {name} needs an argument"))
+        } else if !template.contains("{arg}") && !arg.is_empty() {
+            Err(format!("Snippet {name} takes no argument"))
+        } else if template.contains("{parent}") && parent.is_none() {
+            Err(format!("Snippet {name} needs a current task as parent"))
+        } else {
+            Ok(template
+                .replace("{arg}", arg)
+                .replace("{date}", &date.format("%Y-%m-%d").to_string())
+                .replace("{parent}", parent.unwrap_or_default()))
+        })
+    }
+}
+
+/// Substitute `$1` to `$9` in the command by the given arguments,
+/// appending them if none is referenced
+fn substitute(command: &str, args: &[&str]) -> String {
+    let mut result = String::with_capacity(command.len());
+    let mut referenced = false;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        if let Some(index) = chars.peek().and_then(|d| d.to_digit(10)).filter(|d| c == '$' && *d > 0) {
+            chars.next();
+            result.push_str(args.get(index as usize - 1).unwrap_or(&""));
+            referenced = true;
+        } else {
+            result.push(c);
+        }
+    }
+    if !referenced && !args.is_empty() {
+        result.push(' ');
+        result.push_str(&args.join(" "));
+    }
+    result.trim_end().to_string()
+}
+
+/// How times are displayed and grouped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TimeSettings {
+    /// Timezone to use instead of the one of the system, unparsed
+    pub(crate) timezone: Option<String>,
+    pub(crate) week_starts_sunday: bool,
+}
+
+/// Tunable behavior, configurable in the `[settings]` section
+/// or with environment variables like `MOSTR_UNDO_DELAY`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Settings {
+    /// Minimum number of characters of new task names and notes
+    pub(crate) min_length: usize,
+    /// Seconds for which changes are held back to be undoable
+    pub(crate) undo_delay: u64,
+    /// Seconds without input after which held back changes are sent
+    pub(crate) inactivity_delay: u64,
+    /// Seconds by which position changes may be shifted into the future when moving quickly
+    pub(crate) max_offset: u64,
+    /// View depth to start out with
+    pub(crate) depth: usize,
+    /// Columns to start out with unless a relay configures its own
+    pub(crate) columns: Vec<String>,
+    /// Maximum width of the author column, 0 for full names
+    pub(crate) author_width: usize,
+    /// Characters of the npub shown for users without a name
+    pub(crate) key_length: usize,
+    /// Whether new tasks get the active tag filters in addition to the creation tags
+    pub(crate) filter_tags_on_creation: bool,
+    /// Whether words like `#tag` in new task names and notes are left out of the text
+    pub(crate) strip_hashtags: bool,
+    /// How input without a matching task creates a new one
+    pub(crate) creation: Creation,
+    /// Whether state and other changes to tasks of other people are allowed
+    pub(crate) others_tasks: Ownership,
+    /// Seconds state updates of others may be dated ahead of their arrival,
+    /// also the distance within which state updates of different people conflict
+    pub(crate) max_skew: u64,
+    /// Whether state updates of the task creator win over conflicting ones of others
+    pub(crate) owner_states: bool,
+    /// Directory relative paths of attached files are resolved against, empty for the working directory
+    pub(crate) project_root: String,
+    /// Characters of the content of incoming tasks used for display and matching
+    pub(crate) max_content: usize,
+    /// Tags of incoming tasks processed into hashtags and references
+    pub(crate) max_tags: usize,
+    /// Seconds incoming events may be dated ahead, beyond that they are rejected
+    pub(crate) max_future: u64,
+    /// Whether events from a connected relay which is not set up add it instead of being ignored
+    pub(crate) adopt_relays: bool,
+    /// Whether to recap the day when exiting
+    pub(crate) exit_summary: bool,
+    /// Lines and characters of each note shown above the task listing, 0 for no limit
+    pub(crate) note_lines: usize,
+    pub(crate) note_length: usize,
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            min_length: 3,
+            undo_delay: 60,
+            inactivity_delay: 200,
+            max_offset: 9,
+            depth: 1,
+            columns: ["author", "state", "rtime", "hashtags", "rpath", "desc"]
+                .into_iter().map(|s| s.to_string()).collect(),
+            author_width: 0,
+            key_length: 12,
+            filter_tags_on_creation: true,
+            strip_hashtags: false,
+            creation: Creation::Direct,
+            others_tasks: Ownership::Warn,
+            max_skew: 300,
+            owner_states: false,
+            project_root: String::new(),
+            max_content: 1000,
+            max_tags: 1000,
+            max_future: 366 * 86400,
+            adopt_relays: true,
+            exit_summary: false,
+            note_lines: 10,
+            note_length: 1000,
+        }
+    }
+}
+impl Settings {
+    /// Change a setting by name, as listed in [SETTINGS_KEYS]
+    pub(crate) fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let number = || value.trim().parse::<u64>().map_err(|e| format!("\"{value}\" is not a number: {e}"));
+        match key {
+            "min_length" => self.min_length = number()? as usize,
+            "undo_delay" => self.undo_delay = number()?,
+            "inactivity_delay" => self.inactivity_delay = number()?.max(1),
+            "max_offset" => self.max_offset = number()?,
+            "depth" => self.depth = number()? as usize,
+            "columns" => self.columns = value.split_whitespace().map(|s| s.to_string()).collect(),
+            "author_width" => self.author_width = number()? as usize,
+            "key_length" => self.key_length = number()? as usize,
+            "filter_tags_on_creation" => self.filter_tags_on_creation = value.trim().parse().map_err(|_| format!("\"{value}\" is neither true nor false"))?,
+            "strip_hashtags" => self.strip_hashtags = value.trim().parse().map_err(|_| format!("\"{value}\" is neither true nor false"))?,
+            "creation" => self.creation = match value.trim() {
+                "direct" => Creation::Direct,
+                "confirm" => Creation::Confirm,
+                "explicit" => Creation::Explicit,
+                _ => return Err(format!("\"{value}\" is not one of direct, confirm or explicit")),
+            },
+            "max_skew" => self.max_skew = number()?,
+            "owner_states" => self.owner_states = value.trim().parse().map_err(|_| format!("\"{value}\" is neither true nor false"))?,
+            "project_root" => self.project_root = value.trim().to_string(),
+            "max_content" => self.max_content = number()?.max(1) as usize,
+            "max_tags" => self.max_tags = number()? as usize,
+            "max_future" => self.max_future = number()?,
+            "adopt_relays" => self.adopt_relays = value.trim().parse().map_err(|_| format!("\"{value}\" is neither true nor false"))?,
+            "exit_summary" => self.exit_summary = value.trim().parse().map_err(|_| format!("\"{value}\" is neither true nor false"))?,
+            "note_lines" => self.note_lines = number()? as usize,
+            "note_length" => self.note_length = number()? as usize,
+            "others_tasks" => self.others_tasks = match value.trim() {
+                "allow" => Ownership::Allow,
+                "warn" => Ownership::Warn,
+                "forbid" => Ownership::Forbid,
+                _ => return Err(format!("\"{value}\" is not one of allow, warn or forbid")),
+            },
+            _ => return Err(format!("unknown setting, available are {}", SETTINGS_KEYS.join(", "))),
+        }
+        Ok(())
+    }
+
+    /// Current value of a setting by name
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        Some(match key {
+            "min_length" => self.min_length.to_string(),
+            "undo_delay" => self.undo_delay.to_string(),
+            "inactivity_delay" => self.inactivity_delay.to_string(),
+            "max_offset" => self.max_offset.to_string(),
+            "depth" => self.depth.to_string(),
+            "columns" => self.columns.join(" "),
+            "author_width" => self.author_width.to_string(),
+            "key_length" => self.key_length.to_string(),
+            "filter_tags_on_creation" => self.filter_tags_on_creation.to_string(),
+            "strip_hashtags" => self.strip_hashtags.to_string(),
+            "creation" => match self.creation {
+                Creation::Direct => "direct",
+                Creation::Confirm => "confirm",
+                Creation::Explicit => "explicit",
+            }.to_string(),
+            "max_skew" => self.max_skew.to_string(),
+            "owner_states" => self.owner_states.to_string(),
+            "project_root" => self.project_root.clone(),
+            "max_content" => self.max_content.to_string(),
+            "max_tags" => self.max_tags.to_string(),
+            "max_future" => self.max_future.to_string(),
+            "adopt_relays" => self.adopt_relays.to_string(),
+            "exit_summary" => self.exit_summary.to_string(),
+            "note_lines" => self.note_lines.to_string(),
+            "note_length" => self.note_length.to_string(),
+            "others_tasks" => match self.others_tasks {
+                Ownership::Allow => "allow",
+                Ownership::Warn => "warn",
+                Ownership::Forbid => "forbid",
+            }.to_string(),
+            _ => return None,
+        })
+    }
+}
+
+/// How input that matches no task is handled
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Creation {
+    /// Create a new task right away
+    Direct,
+    /// Ask before creating a new task
+    Confirm,
+    /// Only create a new task if the input ends with `!`
+    Explicit,
+}
+
+/// How changes to tasks created by other people are handled
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Ownership {
+    /// Change them like own tasks
+    Allow,
+    /// Warn on the first change of each task, which goes through when repeated
+    Warn,
+    /// Refuse to change them
+    Forbid,
+}
+
+/// Days of history to keep, or none for `all`
+fn parse_retention(value: &str) -> Option<u64> {
+    if value == "all" {
+        return None;
+    }
+    value.trim_end_matches('d').parse().map_err(|e| warn!("Invalid retention \"{value}\", keeping all history: {e}")).ok()
+}
+
+/// View and settings a relay starts out with when it is selected
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RelayDefaults {
+    /// Path of task names separated by slashes or task id to move to
+    pub(crate) position: Option<String>,
+    /// Hashtags to filter by
+    pub(crate) tags: Vec<String>,
+    pub(crate) sorting: Option<VecDeque<String>>,
+    pub(crate) columns: Option<Vec<String>>,
+    /// Days of time-tracking history of other people to keep, all if none
+    pub(crate) retention: Option<u64>,
+}
+
+#[test]
+fn test_relay_defaults() {
+    let config = Config::parse("
+# Work
+[relay wss://relay.example.com]
+position = Company/Projects
+tags = #company work
+columns = state name
+
+[relay wss://other.example.com]
+sorting = rtime
+");
+    let defaults = config.relay_defaults(&Url::parse("wss://relay.example.com").unwrap());
+    assert_eq!(defaults.position.as_deref(), Some("Company/Projects"));
+    assert_eq!(defaults.tags, vec!["company", "work"]);
+    assert_eq!(defaults.columns, Some(vec!["state".to_string(), "name".to_string()]));
+    assert_eq!(defaults.sorting, None);
+    assert_eq!(config.relay_defaults(&Url::parse("wss://unknown.example.com").unwrap()),
+               RelayDefaults { retention: Some(DEFAULT_RETENTION_DAYS), ..Default::default() });
+}
+
+#[test]
+fn test_retention() {
+    let config = Config::parse("
+[relay wss://relay.example.com]
+position = Company/Projects
+
+[relay wss://other.example.com]
+retention = all
+
+[history]
+retention = 30
+");
+    assert_eq!(config.relay_defaults(&Url::parse("wss://relay.example.com").unwrap()).retention, Some(30));
+    assert_eq!(config.relay_defaults(&Url::parse("wss://other.example.com").unwrap()).retention, None);
+    assert_eq!(config.relay_defaults(&Url::parse("wss://unknown.example.com").unwrap()),
+               RelayDefaults { retention: Some(30), ..Default::default() });
+    assert_eq!(Config::default().relay_defaults(&Url::parse("wss://unknown.example.com").unwrap()).retention, Some(DEFAULT_RETENTION_DAYS));
+}
+
+#[test]
+fn test_time_settings() {
+    let config = Config::parse("
+[time]
+timezone = +02:00
+week_start = Sunday
+");
+    assert_eq!(config.time_settings(), TimeSettings { timezone: Some("+02:00".to_string()), week_starts_sunday: true });
+    assert_eq!(Config::default().time_settings(), TimeSettings::default());
+}
+
+#[test]
+fn test_settings() {
+    let config = Config::parse("
+[settings]
+min_length = 2
+columns = state name
+undo_delay = soon
+");
+    let settings = config.settings();
+    assert_eq!(settings.min_length, 2);
+    assert_eq!(settings.columns, vec!["state", "name"]);
+    assert_eq!(settings.undo_delay, Settings::default().undo_delay);
+    let mut settings = Settings::default();
+    assert!(settings.set("undo_delay", "300").is_ok());
+    assert_eq!(settings.get("undo_delay").as_deref(), Some("300"));
+    assert!(settings.set("unknown", "1").is_err());
+}
+
+#[test]
+fn test_hooks() {
+    let hooks = Config::parse(r#"
+[hooks]
+state_changed = notify-send "$MOSTR_TASK_PATH" "$MOSTR_STATE"
+tracking_stopped = true
+others = yes
+unknown = false
+"#).hooks();
+    assert_eq!(hooks.commands.len(), 2);
+    assert_eq!(hooks.commands.get("state_changed").map(|c| c.as_str()), Some(r#"notify-send "$MOSTR_TASK_PATH" "$MOSTR_STATE""#));
+    assert!(!hooks.others);
+    assert_eq!(Config::default().hooks(), Hooks::default());
+}
+
+#[test]
+fn test_desktop_notifications() {
+    let config = Config::parse("
+[desktop]
+assigned = true
+due = yes
+mentioned = false
+");
+    assert_eq!(config.desktop(), DesktopNotifications { assigned: true, ..Default::default() });
+}
+
+#[test]
+fn test_startup_and_aliases() {
+    let config = Config::parse(r#"
+[startup]
+?All
+#work
+::rtime
+; skipped
+[alias]
+w = "| waiting: $1"
+ww = w $2
+loop = again
+again = loop
+two words = nothing
+"#);
+    assert_eq!(config.startup(), vec!["?All", "#work", "::rtime"]);
+    let aliases = config.aliases();
+    assert_eq!(aliases.expand("w Bob"), Ok("| waiting: Bob".to_string()));
+    assert_eq!(aliases.expand("w"), Ok("| waiting:".to_string()));
+    assert_eq!(aliases.expand("ww x Alice"), Ok("| waiting: Alice".to_string()));
+    assert_eq!(aliases.expand("water plants"), Ok("water plants".to_string()));
+    assert!(aliases.expand("loop").is_err());
+    assert_eq!(Aliases(HashMap::from([("d".to_string(), "?Done".to_string())])).expand("d recent"), Ok("?Done recent".to_string()));
+    assert_eq!(substitute("price $5 and $x", &["1"]), "price  and $x");
+}
+
+#[test]
+fn test_tag_hierarchy() {
+    let hierarchy = Config::parse("
+[tags]
+work = #clientA clientB
+clientA = frontend backend
+backend = work
+").tag_hierarchy();
+    assert_eq!(hierarchy.children("Work"), ["clientA", "clientB"]);
+    assert_eq!(hierarchy.parent("clientb"), Some("work"));
+    assert_eq!(hierarchy.parent("work"), Some("backend"));
+    assert_eq!(hierarchy.parent("other"), None);
+    // Cycles end where they started
+    assert_eq!(hierarchy.expand("work"), vec!["work", "clientA", "clientB", "frontend", "backend"]);
+    assert_eq!(hierarchy.expand("other"), vec!["other"]);
+}
+
+#[test]
+fn test_snippets() {
+    let snippets = Config::parse(r#"
+[snippets]
+ticket = "{arg}: clientA support"
+standup = Standup {date}: meeting
+followup = "Follow up on {parent} {arg}"
+"#).snippets();
+    let day = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+    assert_eq!(snippets.fill("ticket 4521", day, None), Some(Ok("4521: clientA support".to_string())));
+    assert_eq!(snippets.fill("standup", day, None), Some(Ok("Standup 2024-05-01: meeting".to_string())));
+    assert_eq!(snippets.fill("followup  call", day, Some("Release")), Some(Ok("Follow up on Release call".to_string())));
+    assert!(snippets.fill("ticket", day, None).unwrap().is_err());
+    assert!(snippets.fill("standup now", day, None).unwrap().is_err());
+    assert!(snippets.fill("followup call", day, None).unwrap().is_err());
+    assert_eq!(snippets.fill("unknown 1", day, None), None);
+}
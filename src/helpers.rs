@@ -1,11 +1,81 @@
 use std::ops::Sub;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
 
-use chrono::LocalResult::Single;
-use chrono::{DateTime, Local, NaiveTime, TimeDelta, TimeZone, Utc};
+use chrono::{DateTime, Datelike, Days, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
 use log::{debug, error, info, trace, warn};
 use nostr_sdk::Timestamp;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-pub const CHARACTER_THRESHOLD: usize = 3;
+/// Timezone to use instead of the one of the system
+static TIMEZONE: RwLock<Option<FixedOffset>> = RwLock::new(None);
+/// Whether weeks start on Sunday rather than Monday
+static WEEK_STARTS_SUNDAY: AtomicBool = AtomicBool::new(false);
+
+/// Parse a timezone as "UTC", an offset such as "+02:00" or "-0530",
+/// or "local" for the timezone of the system.
+pub fn parse_timezone(str: &str) -> Option<Option<FixedOffset>> {
+    let str = str.trim();
+    match str.to_ascii_uppercase().as_str() {
+        "LOCAL" | "SYSTEM" => return Some(None),
+        "UTC" | "GMT" | "Z" => return Some(FixedOffset::east_opt(0)),
+        _ => {}
+    }
+    let (sign, offset) = match str.strip_prefix('-') {
+        Some(offset) => (-1, offset),
+        None => (1, str.trim_start_matches('+').trim_start_matches("UTC+")),
+    };
+    let digits = offset.replace(':', "");
+    if digits.is_empty() || digits.len() > 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = if digits.len() > 2 { digits.split_at(digits.len() - 2) } else { (digits.as_str(), "0") };
+    let seconds = hours.parse::<i32>().ok()? * 3600 + minutes.parse::<i32>().ok()? * 60;
+    FixedOffset::east_opt(sign * seconds).map(Some)
+}
+
+/// Use the given timezone for parsing and displaying times, or the system timezone if none.
+pub fn set_timezone(offset: Option<FixedOffset>) {
+    *TIMEZONE.write().unwrap() = offset;
+}
+
+/// The configured timezone, none if the one of the system is used
+pub fn timezone() -> Option<FixedOffset> {
+    *TIMEZONE.read().unwrap()
+}
+
+pub fn set_week_starts_sunday(sunday: bool) {
+    WEEK_STARTS_SUNDAY.store(sunday, Ordering::Relaxed);
+}
+
+/// First day of the week as configured
+pub fn week_start() -> Weekday {
+    if WEEK_STARTS_SUNDAY.load(Ordering::Relaxed) { Weekday::Sun } else { Weekday::Mon }
+}
+
+/// The time in the configured timezone
+pub fn to_local<Tz: TimeZone>(time: &DateTime<Tz>) -> DateTime<FixedOffset> {
+    match timezone() {
+        Some(offset) => time.with_timezone(&offset),
+        None => time.with_timezone(&Local).fixed_offset(),
+    }
+}
+
+/// The current time in the configured timezone
+pub fn now_local() -> DateTime<FixedOffset> {
+    to_local(&Utc::now())
+}
+
+/// Place the time of day in the configured timezone,
+/// taking the earlier one if it is ambiguous.
+fn from_local(time: &NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    match timezone() {
+        Some(offset) => offset.from_local_datetime(time).single(),
+        None => Local.from_local_datetime(time).earliest().map(|time| time.fixed_offset()),
+    }
+}
 
 pub fn some_non_empty(str: &str) -> Option<String> {
     if str.is_empty() { None } else { Some(str.to_string()) }
@@ -18,6 +88,188 @@ pub fn trim_start_count(str: &str, char: char) -> (&str, usize) {
     (result, dots)
 }
 
+/// Number of terminal columns the string takes up,
+/// counting wide characters such as CJK twice and combining characters not at all.
+pub fn display_width(str: &str) -> usize {
+    str.width()
+}
+
+/// Cut the string to at most the given display width,
+/// marking the cut with an ellipsis without splitting any grapheme.
+pub fn truncate_width(str: &str, max: usize) -> String {
+    if str.width() <= max {
+        return str.to_string();
+    }
+    let mut width = 0;
+    let mut result = String::new();
+    for grapheme in str.graphemes(true) {
+        width += grapheme.width();
+        if width >= max {
+            break;
+        }
+        result.push_str(grapheme);
+    }
+    if max > 0 {
+        result.push('…');
+    }
+    result
+}
+
+/// Like [truncate_width], but cutting off the start of the string to keep its end.
+pub fn truncate_width_start(str: &str, max: usize) -> String {
+    if str.width() <= max {
+        return str.to_string();
+    }
+    let mut width = 0;
+    let mut graphemes = Vec::new();
+    for grapheme in str.graphemes(true).rev() {
+        width += grapheme.width();
+        if width >= max {
+            break;
+        }
+        graphemes.push(grapheme);
+    }
+    if max > 0 {
+        graphemes.push("…");
+    }
+    graphemes.into_iter().rev().collect()
+}
+
+/// Cut the text down to the given number of lines and visible characters, 0 meaning no limit.
+/// Graphemes and escape sequences are kept intact, and styles are reset after a cut.
+/// Returns the text with a description of what was left out, like "84 more lines", if it was cut.
+pub fn clip_text(text: &str, max_lines: usize, max_chars: usize) -> (String, Option<String>) {
+    let total_lines = text.split('\n').count();
+    let mut clipped = String::with_capacity(text.len().min(max_chars.saturating_mul(4)));
+    let mut lines = 0;
+    let mut chars = 0;
+    'lines: for line in text.split('\n').take(if max_lines == 0 { usize::MAX } else { max_lines }) {
+        if lines > 0 {
+            clipped.push('\n');
+        }
+        lines += 1;
+        let mut rest = line;
+        while let Some(grapheme) = rest.graphemes(true).next() {
+            let taken = if grapheme == "\u{1b}" {
+                // Up to and including the final letter of the sequence
+                rest.char_indices().skip(1)
+                    .find(|(_, c)| c.is_ascii_alphabetic())
+                    .map_or(rest.len(), |(index, c)| index + c.len_utf8())
+            } else if max_chars > 0 && chars >= max_chars {
+                if rest.len() == line.len() && lines > 1 {
+                    // Nothing of this line fits
+                    clipped.pop();
+                    lines -= 1;
+                }
+                break 'lines;
+            } else {
+                chars += 1;
+                grapheme.len()
+            };
+            clipped.push_str(&rest[..taken]);
+            rest = &rest[taken..];
+        }
+    }
+    let more_lines = total_lines - lines;
+    let more_chars = strip_ansi(text).split('\n').take(lines)
+        .map(|line| line.graphemes(true).count())
+        .sum::<usize>() - chars;
+    let left_out = match (more_lines, more_chars) {
+        (0, 0) => return (clipped, None),
+        (1, _) => "1 more line".to_string(),
+        (0, 1) => "1 more character".to_string(),
+        (0, more) => format!("{more} more characters"),
+        (more, _) => format!("{more} more lines"),
+    };
+    if clipped.contains('\u{1b}') {
+        clipped.push_str("\u{1b}[0m");
+    }
+    (clipped, Some(left_out))
+}
+
+/// Remove the escape sequences for colors and styles from the string
+pub fn strip_ansi(str: &str) -> String {
+    let mut result = String::with_capacity(str.len());
+    let mut chars = str.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Quote the value for CSV if it contains separators, quotes or line breaks
+pub fn escape_csv(str: &str) -> String {
+    if str.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", str.replace('"', "\"\""))
+    } else {
+        str.to_string()
+    }
+}
+
+/// Path to open an attached file path at, resolving relative paths against the root unless it is empty
+/// and expanding a leading `~` to the home directory
+pub fn resolve_path(path: &str, root: &str) -> String {
+    let home = std::env::var("HOME").ok();
+    let expand = |path: &str| match (path.strip_prefix("~/"), &home) {
+        (Some(rest), Some(home)) => Path::new(home).join(rest),
+        _ => Path::new(path).to_path_buf(),
+    };
+    let path = expand(path);
+    if path.is_relative() && !root.is_empty() {
+        expand(root).join(path)
+    } else {
+        path
+    }.to_string_lossy().to_string()
+}
+
+/// Remove comment lines, which start with `# ` or are just `#`, keeping hashtags and headings,
+/// and trim the remaining text
+pub fn strip_comments(text: &str) -> String {
+    text.lines()
+        .filter(|line| *line != "#" && !line.starts_with("# "))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Replace words starting with the prefix, like `@NAME`, by the result of the function for the name,
+/// keeping trailing punctuation and words for which it returns none
+pub fn replace_mentions(text: &str, prefix: &str, mut replace: impl FnMut(&str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    for word in text.split_inclusive(char::is_whitespace) {
+        let replaced = word.strip_prefix(prefix).and_then(|rest| {
+            let name = rest.trim_end_matches(|c: char| !c.is_alphanumeric());
+            if name.is_empty() {
+                return None;
+            }
+            replace(name).map(|replacement| format!("{replacement}{}", &rest[name.len()..]))
+        });
+        result.push_str(replaced.as_deref().unwrap_or(word));
+    }
+    result
+}
+
+/// Escape the characters with special meaning in HTML text and attribute values
+pub fn escape_html(str: &str) -> String {
+    let mut result = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
 pub trait ToTimestamp {
     fn to_timestamp(&self) -> Timestamp;
 }
@@ -30,28 +282,44 @@ impl<T: TimeZone> ToTimestamp for DateTime<T> {
     }
 }
 
-/// Parses the hour from a plain number in the String,
-/// with max of max_future hours into the future.
-pub fn parse_hour(str: &str, max_future: i64) -> Option<DateTime<Local>> {
-    str.parse::<u32>().ok().and_then(|hour| {
-        let now = Local::now();
-        #[allow(deprecated)]
-        now.date().and_hms_opt(hour, 0, 0).map(|time| {
-            if time - now > TimeDelta::hours(max_future) {
-                time.sub(TimeDelta::days(1))
-            } else {
-                time
-            }
-        })
+/// Parses a time of day from the String, with max of max_future hours into the future:
+/// - One or two digits as hour, like `8`
+/// - Three or four digits as hour and minutes, like `130` for 1:30 or `1530`
+/// - Hour and minutes separated by a colon, like `15:30`
+///
+/// Signs are not accepted, so offsets like `+130` are left alone.
+pub fn parse_hour(str: &str, max_future: i64) -> Option<DateTime<FixedOffset>> {
+    parse_hour_at(str, max_future, now_local())
+}
+
+/// [parse_hour] relative to the given time
+fn parse_hour_at(str: &str, max_future: i64, now: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+    if str.is_empty() || !str.chars().all(|c| c.is_ascii_digit() || c == ':') {
+        return None;
+    }
+    let (hour, minute) = match str.split_once(':') {
+        Some((hour, minute)) if minute.len() == 2 => (hour, minute),
+        Some(_) => return None,
+        None if str.len() <= 2 => (str, "0"),
+        None if str.len() <= 4 => str.split_at(str.len() - 2),
+        None => return None,
+    };
+    let (Ok(hour), Ok(minute)) = (hour.parse::<u32>(), minute.parse::<u32>()) else { return None };
+    now.date_naive().and_hms_opt(hour, minute, 0).and_then(|time| from_local(&time)).map(|time| {
+        if time - now > TimeDelta::hours(max_future) {
+            time.sub(TimeDelta::days(1))
+        } else {
+            time
+        }
     })
 }
 
 pub fn parse_date(str: &str) -> Option<DateTime<Utc>> {
     // Using two libraries for better exhaustiveness, see https://github.com/uutils/parse_datetime/issues/84
-    match interim::parse_date_string(str, Local::now(), interim::Dialect::Us) {
+    match interim::parse_date_string(str, now_local(), interim::Dialect::Us) {
         Ok(date) => Some(date.to_utc()),
         Err(e) => {
-            match parse_datetime::parse_datetime_at_date(Local::now(), str) {
+            match parse_datetime::parse_datetime_at_date(now_local(), str) {
                 Ok(date) => Some(date.to_utc()),
                 Err(_) => {
                     warn!("Could not parse date from \"{str}\": {e}");
@@ -60,24 +328,130 @@ pub fn parse_date(str: &str) -> Option<DateTime<Utc>> {
             }
         }
     }.map(|time| {
-        // TODO properly map date without time to day start, also support intervals
-        if str.chars().any(|c| c.is_numeric()) {
-            time
+        if is_day(str) {
+            day_start(to_local(&time).date_naive())
         } else {
-            #[allow(deprecated)]
-            time.date().and_time(NaiveTime::default()).unwrap()
+            time
         }
     })
 }
 
+/// Whether the string names a day without a time, like "monday", "yesterday" or "2024-05-01"
+fn is_day(str: &str) -> bool {
+    let lower = str.trim().to_ascii_lowercase();
+    if lower.contains(':') || lower.starts_with(['-', '+']) ||
+        ["now", "hour", "min", "sec", "noon", "night", "am", "pm"].iter().any(|word| lower.contains(word)) {
+        return false;
+    }
+    !lower.chars().any(|c| c.is_ascii_digit()) ||
+        (lower.chars().all(|c| c.is_ascii_digit() || "-./".contains(c)) && lower.contains(['-', '.', '/']))
+}
+
+/// Start of the given day in the configured timezone
+pub fn day_start(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    from_local(&midnight).map_or_else(|| midnight.and_utc(), |time| time.to_utc())
+}
+
+/// Parse a time window such as "monday..friday", "2024-05-01..2024-05-31",
+/// "last week", "this week" or "yesterday".
+/// Either end of a range may be left open, like "2024-05-01.." or "..friday".
+/// Days are expanded to cover them completely, ending at the start of the following day.
+pub fn parse_date_range(str: &str) -> Option<(Option<Timestamp>, Option<Timestamp>)> {
+    let str = str.trim();
+    let today = now_local().date_naive();
+    let days_into_week = (today.weekday().num_days_from_monday() + 7 - week_start().num_days_from_monday()) % 7;
+    let week_start = today - Days::new(days_into_week as u64);
+    let month_start = today.with_day(1)?;
+    match str.to_ascii_lowercase().as_str() {
+        "this week" => return Some((Some(day_start(week_start).to_timestamp()), None)),
+        "last week" => return Some((
+            Some(day_start(week_start - Days::new(7)).to_timestamp()),
+            Some(day_start(week_start).to_timestamp()))),
+        "this month" => return Some((Some(day_start(month_start).to_timestamp()), None)),
+        "last month" => return Some((
+            Some(day_start((month_start - Days::new(1)).with_day(1)?).to_timestamp()),
+            Some(day_start(month_start).to_timestamp()))),
+        _ => {}
+    }
+    let (start, end) = str.split_once("..").unwrap_or((str, str));
+    let start = match start.trim() {
+        "" => None,
+        start => Some(parse_date(start)?.to_timestamp()),
+    };
+    let end = match end.trim() {
+        "" => None,
+        end => Some(parse_date(end).map(|time| if is_day(end) {
+            day_start(to_local(&time).date_naive() + Days::new(1))
+        } else {
+            time
+        })?.to_timestamp()),
+    };
+    Some((start, end)).filter(|range| range != &(None, None))
+}
+
+/// Parse a starting point from an age in days such as "90d" or a date.
+pub fn parse_since(str: &str) -> Option<Timestamp> {
+    str.strip_suffix('d')
+        .and_then(|days| days.parse::<u64>().ok())
+        .map(|days| Timestamp::now() - days * 86_400)
+        .or_else(|| parse_date(str).map(|time| time.to_timestamp()))
+}
+
+/// Parse a duration such as "45m", "2h", "1h30m", "1d2h" or "2w" into seconds.
+/// Every number needs a unit, so plain numbers are never taken as a duration.
+pub fn parse_duration(str: &str) -> Option<u64> {
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in str.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86_400,
+            'w' => 604_800,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    Some(total).filter(|_| number.is_empty() && !str.trim().is_empty())
+}
+
+/// Parse an offset from now given as a duration, like "+45m" or "-2h30m".
+/// Unsigned durations count backwards from now.
+pub fn parse_offset(str: &str) -> Option<Timestamp> {
+    let str = str.trim();
+    let now = Timestamp::now();
+    match str.strip_prefix('+') {
+        Some(forward) => parse_duration(forward).map(|secs| now + secs),
+        None => parse_duration(str.strip_prefix('-').unwrap_or(str)).map(|secs| now - secs),
+    }
+}
+
+/// Parse a point in the future from a duration such as "30m", "12h", "3d" or "1w2d", or a date.
+pub fn parse_expiration(str: &str) -> Option<Timestamp> {
+    parse_duration(str.trim_start_matches('+'))
+        .map(|secs| Timestamp::now() + secs)
+        .or_else(|| parse_date(str).map(|time| time.to_timestamp()))
+}
+
 /// Turn a human-readable relative timestamp into a nostr Timestamp.
 /// - Plain number as hour, 18 hours back or 6 hours forward
+/// - Duration with units as offset, backwards unless prefixed with `+`
 /// - Number with prefix as minute offset
 /// - Otherwise try to parse a relative date
 pub fn parse_tracking_stamp(str: &str) -> Option<Timestamp> {
     if let Some(num) = parse_hour(str, 6) {
         return Some(num.to_timestamp());
     }
+    if let Some(stamp) = parse_offset(str) {
+        return Some(stamp);
+    }
     let stripped = str.trim().trim_start_matches('+').trim_start_matches("in ");
     if let Ok(num) = stripped.parse::<i64>() {
         return Some(Timestamp::from(Timestamp::now().as_u64().saturating_add_signed(num * 60)));
@@ -93,12 +467,43 @@ pub fn parse_tracking_stamp(str: &str) -> Option<Timestamp> {
     })
 }
 
+/// Parse a range of times like `15:00-16:30` to plan ahead,
+/// each time as in [parse_tracking_stamp] but with times of day as the next block not yet over:
+/// the end follows the start, and both move to the next day once the end has passed,
+/// while a block across midnight that is already running keeps its start from yesterday.
+pub fn parse_time_range(str: &str) -> Option<(Timestamp, Timestamp)> {
+    parse_time_range_at(str, now_local())
+}
+
+/// [parse_time_range] relative to the given time
+fn parse_time_range_at(str: &str, now: DateTime<FixedOffset>) -> Option<(Timestamp, Timestamp)> {
+    const DAY: u64 = 86_400;
+    let (start, end) = str.split_once('-')?;
+    let parse = |str: &str| match parse_hour_at(str, 24, now) {
+        Some(time) => Some((time.to_timestamp(), true)),
+        None => parse_tracking_stamp(str).map(|stamp| (stamp, false)),
+    };
+    let ((mut start, start_of_day), (mut end, end_of_day)) = (parse(start)?, parse(end)?);
+    if end_of_day && end <= start {
+        end = end + ((start.as_u64() - end.as_u64()) / DAY + 1) * DAY;
+        if start_of_day && end > now.to_timestamp() + DAY {
+            start = start - DAY;
+            end = end - DAY;
+        }
+    }
+    if start_of_day && end_of_day && end < now.to_timestamp() {
+        start = start + DAY;
+        end = end + DAY;
+    }
+    Some((start, end))
+}
+
 /// Format DateTime easily comprehensible for human but unambiguous.
 /// Length may vary.
-pub fn format_datetime_relative(time: DateTime<Local>) -> String {
+pub fn format_datetime_relative(time: DateTime<FixedOffset>) -> String {
     let date = time.date_naive();
     let prefix =
-        match Local::now()
+        match now_local()
             .date_naive()
             .signed_duration_since(date)
             .num_days() {
@@ -116,14 +521,19 @@ pub fn format_datetime_relative(time: DateTime<Local>) -> String {
 /// Format a nostr timestamp with the given formatting function.
 pub fn format_as_datetime<F>(stamp: &Timestamp, formatter: F) -> String
 where
-    F: Fn(DateTime<Local>) -> String,
+    F: Fn(DateTime<FixedOffset>) -> String,
 {
-    match Local.timestamp_opt(stamp.as_u64() as i64, 0) {
-        Single(time) => formatter(time),
-        _ => stamp.to_human_datetime(),
+    match DateTime::from_timestamp(stamp.as_u64() as i64, 0) {
+        Some(time) => formatter(to_local(&time)),
+        None => stamp.to_human_datetime(),
     }
 }
 
+/// Day of the timestamp in the configured timezone
+pub fn local_date(stamp: &Timestamp) -> Option<NaiveDate> {
+    DateTime::from_timestamp(stamp.as_u64() as i64, 0).map(|time| to_local(&time).date_naive())
+}
+
 /// Format nostr Timestamp relative to local time
 /// with optional day specifier or full date depending on distance to today.
 pub fn format_timestamp_relative(stamp: &Timestamp) -> String {
@@ -149,4 +559,208 @@ pub fn format_timestamp_relative_to(stamp: &Timestamp, reference: &Timestamp) ->
         -3..=3 => format_timestamp(stamp, "%a %H:%M"),
         _ => format_timestamp_local(stamp),
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("45m"), Some(2_700));
+    assert_eq!(parse_duration("2h"), Some(7_200));
+    assert_eq!(parse_duration("1h30m"), Some(5_400));
+    assert_eq!(parse_duration("1d2h"), Some(93_600));
+    assert_eq!(parse_duration("1w"), Some(604_800));
+    assert_eq!(parse_duration("90s"), Some(90));
+    assert_eq!(parse_duration("8"), None);
+    assert_eq!(parse_duration("1h30"), None);
+    assert_eq!(parse_duration("h"), None);
+    assert_eq!(parse_duration(""), None);
+    assert_eq!(parse_duration("-2h"), None);
+    assert_eq!(parse_duration("2 hours"), None);
+}
+
+#[test]
+fn test_parse_tracking_offsets() {
+    let near = |stamp: Option<Timestamp>, expected: Timestamp| {
+        let stamp = stamp.unwrap().as_u64();
+        assert!(stamp.abs_diff(expected.as_u64()) <= 1, "{stamp} is not {expected}");
+    };
+    let now = Timestamp::now();
+    // Plain numbers are hours of the day
+    let hour = parse_tracking_stamp("8").unwrap();
+    assert_eq!(format_timestamp(&hour, "%H:%M"), "08:00");
+    // With a unit, it is a duration ago
+    near(parse_tracking_stamp("8h"), now - 8 * 3600);
+    near(parse_tracking_stamp("-8h"), now - 8 * 3600);
+    near(parse_tracking_stamp("-2h30m"), now - 9_000);
+    near(parse_tracking_stamp("+45m"), now + 2_700);
+    near(parse_tracking_stamp("90m"), now - 5_400);
+    // Signed plain numbers stay minute offsets
+    near(parse_tracking_stamp("-8"), now - 480);
+    near(parse_tracking_stamp("+15"), now + 900);
+
+    near(parse_expiration("1d2h"), now + 93_600);
+    near(parse_expiration("+30m"), now + 1_800);
+}
+
+#[test]
+fn test_parse_date_range() {
+    let local = |y, m, d| day_start(NaiveDate::from_ymd_opt(y, m, d).unwrap()).to_timestamp();
+    assert_eq!(parse_date_range("2024-05-01..2024-05-31"),
+               Some((Some(local(2024, 5, 1)), Some(local(2024, 6, 1)))));
+    assert_eq!(parse_date_range("2024-05-01.."), Some((Some(local(2024, 5, 1)), None)));
+    assert_eq!(parse_date_range("..2024-05-31"), Some((None, Some(local(2024, 6, 1)))));
+    assert_eq!(parse_date_range("2024-05-03"), Some((Some(local(2024, 5, 3)), Some(local(2024, 5, 4)))));
+    assert_eq!(parse_date("2024-05-03").map(|t| t.to_timestamp()), Some(local(2024, 5, 3)));
+    assert_eq!(parse_date_range(".."), None);
+    assert_eq!(parse_date_range("nonsense..2024-05-31"), None);
+
+    let today = now_local().date_naive();
+    let yesterday = parse_date_range("yesterday").unwrap();
+    assert_eq!(yesterday, (Some(day_start(today - Days::new(1)).to_timestamp()), Some(day_start(today).to_timestamp())));
+
+    let (Some(start), Some(end)) = parse_date_range("last week").unwrap() else { panic!("last week is not closed") };
+    let weekday = |stamp: Timestamp| format_timestamp(&stamp, "%a %H:%M");
+    assert_eq!(weekday(start), "Mon 00:00");
+    assert_eq!(weekday(end), "Mon 00:00");
+    assert!(end > start && end <= Timestamp::now());
+
+    let (Some(start), Some(end)) = parse_date_range("last month").unwrap() else { panic!("last month is not closed") };
+    assert_eq!(format_timestamp(&start, "%d %H:%M"), "01 00:00");
+    assert_eq!(end, parse_date_range("this month").unwrap().0.unwrap());
+    assert!(end.as_u64() - start.as_u64() > 27 * 86_400 && end <= Timestamp::now());
+
+    let (Some(start), Some(end)) = parse_date_range("monday..friday").unwrap() else { panic!("weekdays are not closed") };
+    assert_eq!(weekday(start), "Mon 00:00");
+    assert_eq!(weekday(end), "Sat 00:00");
+}
+
+#[test]
+fn test_parse_timezone() {
+    assert_eq!(parse_timezone("local"), Some(None));
+    assert_eq!(parse_timezone("UTC"), Some(FixedOffset::east_opt(0)));
+    assert_eq!(parse_timezone("+02:00"), Some(FixedOffset::east_opt(7200)));
+    assert_eq!(parse_timezone("2"), Some(FixedOffset::east_opt(7200)));
+    assert_eq!(parse_timezone("-0530"), Some(FixedOffset::east_opt(-19_800)));
+    assert_eq!(parse_timezone("Europe/Berlin"), None);
+    assert_eq!(parse_timezone("+25"), None);
+}
+
+#[test]
+fn test_parse_hour() {
+    let time = |str: &str| parse_hour(str, 24).map(|time| time.format("%H:%M").to_string());
+    assert_eq!(time("8").as_deref(), Some("08:00"));
+    assert_eq!(time("130").as_deref(), Some("01:30"));
+    assert_eq!(time("1530").as_deref(), Some("15:30"));
+    assert_eq!(time("15:30").as_deref(), Some("15:30"));
+    assert_eq!(time("0:05").as_deref(), Some("00:05"));
+    assert_eq!(time("+130"), None);
+    assert_eq!(time("-8"), None);
+    assert_eq!(time("1560"), None);
+    assert_eq!(time("2400"), None);
+    assert_eq!(time("15:3"), None);
+    assert_eq!(time("15300"), None);
+    assert_eq!(time(""), None);
+
+    let near = |stamp: Option<Timestamp>, expected: Timestamp| {
+        assert!(stamp.unwrap().as_u64().abs_diff(expected.as_u64()) <= 1);
+    };
+    // Signed numbers stay minute offsets
+    near(parse_tracking_stamp("+130"), Timestamp::now() + 130 * 60);
+    near(parse_tracking_stamp("-130"), Timestamp::now() - 130 * 60);
+    assert_eq!(format_timestamp(&parse_tracking_stamp("130").unwrap(), "%H:%M"), "01:30");
+}
+
+#[test]
+fn test_parse_time_range() {
+    let day = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+    let at = |hour, minute| from_local(&day.and_hms_opt(hour, minute, 0).unwrap()).unwrap();
+    let stamp = |hour, minute| at(hour, minute).to_timestamp();
+    let afternoon = Some((stamp(15, 0), stamp(16, 30)));
+    assert_eq!(parse_time_range_at("15:00-16:30", at(12, 0)), afternoon);
+    // A block in progress stays today
+    assert_eq!(parse_time_range_at("15:00-16:30", at(15, 30)), afternoon);
+    assert_eq!(parse_time_range_at("15:00-16:30", at(17, 0)), Some((stamp(15, 0) + 86_400, stamp(16, 30) + 86_400)));
+    // The end follows the start across midnight
+    assert_eq!(parse_time_range_at("23:00-1", at(22, 0)), Some((stamp(23, 0), stamp(1, 0) + 86_400)));
+    assert_eq!(parse_time_range_at("23:00-1", at(0, 30)), Some((stamp(23, 0) - 86_400, stamp(1, 0))));
+    assert_eq!(parse_time_range_at("15:00", at(12, 0)), None);
+}
+
+#[test]
+fn test_escape_csv() {
+    assert_eq!(escape_csv("plain"), "plain");
+    assert_eq!(escape_csv("a, b"), "\"a, b\"");
+    assert_eq!(escape_csv("say \"hi\"\nnow"), "\"say \"\"hi\"\"\nnow\"");
+}
+
+#[test]
+fn test_resolve_path() {
+    assert_eq!(resolve_path("docs/spec.md", ""), "docs/spec.md");
+    assert_eq!(resolve_path("docs/spec.md", "/work/project"), "/work/project/docs/spec.md");
+    assert_eq!(resolve_path("/etc/hosts", "/work/project"), "/etc/hosts");
+}
+
+#[test]
+fn test_strip_comments() {
+    assert_eq!(strip_comments("\n# Note on task\n#\n"), "");
+    assert_eq!(strip_comments("First line\n\n#tag ## Heading\n# comment\nlast\n"), "First line\n\n#tag ## Heading\nlast");
+}
+
+#[test]
+fn test_replace_mentions() {
+    let upper = |name: &str| Some(name.to_uppercase()).filter(|_| name != "nobody");
+    assert_eq!(replace_mentions("hi @alice, meet @bob!\n@nobody @ @", "@", upper), "hi ALICE, meet BOB!\n@nobody @ @");
+    assert_eq!(replace_mentions("mail a@b.c", "@", upper), "mail a@b.c");
+    assert_eq!(replace_mentions("see nostr:npub1abc.", "nostr:", |_| Some("@x".to_string())), "see @x.");
+}
+
+#[test]
+fn test_escape_html() {
+    assert_eq!(escape_html("plain"), "plain");
+    assert_eq!(escape_html("<script>alert('x')</script>"), "&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;");
+    assert_eq!(escape_html("a & \"b\""), "a &amp; &quot;b&quot;");
+    assert_eq!(escape_html("&amp;"), "&amp;amp;");
+}
+
+#[test]
+fn test_clip_text() {
+    assert_eq!(clip_text("short\nnote", 3, 100), ("short\nnote".to_string(), None));
+    assert_eq!(clip_text("short\nnote", 0, 0), ("short\nnote".to_string(), None));
+    let log = (1..=90).map(|i| format!("line {i}")).collect::<Vec<_>>().join("\n");
+    assert_eq!(clip_text(&log, 6, 1000), ((1..=6).map(|i| format!("line {i}")).collect::<Vec<_>>().join("\n"), Some("84 more lines".to_string())));
+    assert_eq!(clip_text("a\nb", 1, 0).1.as_deref(), Some("1 more line"));
+    assert_eq!(clip_text("abc\ndef", 0, 3), ("abc".to_string(), Some("1 more line".to_string())));
+    // Graphemes and escape sequences stay whole
+    assert_eq!(clip_text("e\u{301}e\u{301}e\u{301}", 0, 2), ("e\u{301}e\u{301}".to_string(), Some("1 more character".to_string())));
+    assert_eq!(clip_text("日本語テキスト", 5, 3), ("日本語".to_string(), Some("4 more characters".to_string())));
+    assert_eq!(clip_text("\u{1b}[1mbold\u{1b}[0m text", 0, 2), ("\u{1b}[1mbo\u{1b}[0m".to_string(), Some("7 more characters".to_string())));
+    assert_eq!(clip_text("\u{1b}[1mbold\u{1b}[0m", 0, 4), ("\u{1b}[1mbold\u{1b}[0m".to_string(), None));
+}
+
+#[test]
+fn test_strip_ansi() {
+    assert_eq!(strip_ansi("\u{1b}[32mDone\u{1b}[0m \u{1b}[1;91mBlocked\u{1b}[0m"), "Done Blocked");
+    assert_eq!(strip_ansi("plain"), "plain");
+}
+
+#[test]
+fn test_truncate_width() {
+    assert_eq!(truncate_width("Alice", 6), "Alice");
+    assert_eq!(truncate_width("Maximilian", 6), "Maxim…");
+    assert_eq!(truncate_width("Maximilian", 0), "");
+    // Wide characters take up two columns each
+    assert_eq!(display_width("日本語"), 6);
+    assert_eq!(truncate_width("日本語テキスト", 6), "日本…");
+    assert_eq!(display_width(&truncate_width("日本語テキスト", 6)), 5);
+    assert_eq!(truncate_width("日本語テキスト", 5), "日本…");
+    // Emoji and combining characters are kept whole
+    assert_eq!(display_width(&truncate_width("👍👍👍👍", 6)), 5);
+    assert_eq!(truncate_width("e\u{301}e\u{301}e\u{301}e\u{301}", 3), "e\u{301}e\u{301}…");
+    assert_eq!(display_width("e\u{301}e\u{301}"), 2);
+    for str in ["日本語", "👨‍👩‍👧👨‍👩‍👧", "e\u{301}x", "ab"] {
+        for max in 0..8 {
+            assert!(display_width(&truncate_width(str, max)) <= max);
+            assert!(display_width(&truncate_width_start(str, max)) <= max);
+        }
+    }
+    assert_eq!(truncate_width_start("Company/Projects/日本", 10), "…ects/日本");
+}
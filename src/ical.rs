@@ -0,0 +1,120 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use chrono::DateTime;
+use itertools::Itertools;
+use nostr_sdk::Timestamp;
+
+/// Maximum length of a content line in octets, excluding the line break
+const LINE_OCTETS: usize = 75;
+
+/// A component of an iCalendar file like VTODO or VEVENT, see https://www.rfc-editor.org/rfc/rfc5545
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Component {
+    name: &'static str,
+    /// Properties in order with their values, already escaped where needed
+    properties: Vec<(&'static str, String)>,
+}
+
+impl Component {
+    pub(crate) fn new(name: &'static str) -> Self {
+        Component { name, properties: vec![] }
+    }
+
+    /// Add a property with a text value, escaping it
+    pub(crate) fn text(self, name: &'static str, value: &str) -> Self {
+        self.raw(name, escape_text(value))
+    }
+
+    /// Add a property with a date-time value in UTC
+    pub(crate) fn time(self, name: &'static str, stamp: &Timestamp) -> Self {
+        self.raw(name, format_time(stamp))
+    }
+
+    /// Add a property with a value which needs no escaping
+    pub(crate) fn raw(mut self, name: &'static str, value: String) -> Self {
+        self.properties.push((name, value));
+        self
+    }
+}
+
+impl Display for Component {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "BEGIN:{}\r\n", self.name)?;
+        for (name, value) in &self.properties {
+            write!(f, "{}\r\n", fold_line(&format!("{name}:{value}")))?;
+        }
+        write!(f, "END:{}\r\n", self.name)
+    }
+}
+
+/// A calendar holding the given components
+pub(crate) fn calendar(components: &[Component]) -> String {
+    format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//mostr//mostr//EN\r\n{}END:VCALENDAR\r\n",
+            components.iter().join(""))
+}
+
+/// Escape a text value, where backslashes, semicolons, commas and line breaks are special
+pub(crate) fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Fold a content line longer than [LINE_OCTETS] onto continuation lines starting with a space,
+/// without splitting characters
+pub(crate) fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + line.len() / LINE_OCTETS * 3);
+    let mut octets = 0;
+    for char in line.chars() {
+        if octets + char.len_utf8() > LINE_OCTETS {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(char);
+        octets += char.len_utf8();
+    }
+    folded
+}
+
+/// Format a timestamp as UTC date-time like `20240501T120000Z`
+pub(crate) fn format_time(stamp: &Timestamp) -> String {
+    DateTime::from_timestamp(stamp.as_u64() as i64, 0)
+        .map_or_else(String::new, |time| time.format("%Y%m%dT%H%M%SZ").to_string())
+}
+
+#[test]
+fn test_escape_text() {
+    assert_eq!(escape_text("Buy milk"), "Buy milk");
+    assert_eq!(escape_text("a, b; c\\d"), "a\\, b\\; c\\\\d");
+    assert_eq!(escape_text("first\nsecond\r\nthird"), "first\\nsecond\\nthird");
+}
+
+#[test]
+fn test_fold_line() {
+    assert_eq!(fold_line("SUMMARY:short"), "SUMMARY:short");
+    let long = format!("DESCRIPTION:{}", "x".repeat(150));
+    let folded = fold_line(&long);
+    let lines = folded.split("\r\n").collect_vec();
+    assert_eq!(lines.len(), 3);
+    assert!(lines.iter().all(|line| line.len() <= LINE_OCTETS));
+    assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+    assert_eq!(folded.replace("\r\n ", ""), long);
+
+    let umlauts = format!("SUMMARY:{}", "ä".repeat(40));
+    let folded = fold_line(&umlauts);
+    assert!(folded.split("\r\n").all(|line| line.len() <= LINE_OCTETS));
+    assert_eq!(folded.replace("\r\n ", ""), umlauts);
+}
+
+#[test]
+fn test_component() {
+    let todo = Component::new("VTODO")
+        .raw("UID", "1@mostr".to_string())
+        .text("SUMMARY", "Plan trip, then book")
+        .time("DUE", &Timestamp::from(1714564800));
+    assert_eq!(todo.to_string(), "BEGIN:VTODO\r\nUID:1@mostr\r\nSUMMARY:Plan trip\\, then book\r\nDUE:20240501T120000Z\r\nEND:VTODO\r\n");
+    assert!(calendar(&[todo]).starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+}
@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, Movement, RepeatCount};
+
+/// Time within which another Ctrl-C exits even though a line was typed again
+const EXIT_WINDOW: Duration = Duration::from_secs(2);
+
+/// Effect of Ctrl-C at the prompt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Interrupt {
+    /// Discard the line in progress and stay at the prompt
+    Clear,
+    /// Leave the prompt through the regular shutdown
+    Exit,
+}
+
+/// Ctrl-C on an empty prompt or shortly after clearing a line exits,
+/// otherwise it only clears the line in progress.
+pub(crate) fn interrupt(line: &str, cleared: Option<Instant>, now: Instant) -> Interrupt {
+    if line.is_empty() || cleared.is_some_and(|cleared| now.duration_since(cleared) < EXIT_WINDOW) {
+        Interrupt::Exit
+    } else {
+        Interrupt::Clear
+    }
+}
+
+/// Binding for Ctrl-C which keeps the session unless pressed twice, see [interrupt]
+#[derive(Default)]
+pub(crate) struct InterruptHandler {
+    /// When Ctrl-C last cleared a line
+    cleared: Mutex<Option<Instant>>,
+}
+
+impl ConditionalEventHandler for InterruptHandler {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        let now = Instant::now();
+        let mut cleared = self.cleared.lock().unwrap();
+        match interrupt(ctx.line(), *cleared, now) {
+            Interrupt::Clear => {
+                *cleared = Some(now);
+                Some(Cmd::Kill(Movement::WholeBuffer))
+            }
+            Interrupt::Exit => Some(Cmd::Interrupt),
+        }
+    }
+}
+
+#[test]
+fn test_interrupt() {
+    let now = Instant::now();
+    assert_eq!(interrupt("", None, now), Interrupt::Exit);
+    assert_eq!(interrupt("half a ta", None, now), Interrupt::Clear);
+    assert_eq!(interrupt("half a ta", Some(now - Duration::from_secs(1)), now), Interrupt::Exit);
+    assert_eq!(interrupt("half a ta", Some(now - Duration::from_secs(5)), now), Interrupt::Clear);
+    assert_eq!(interrupt("", Some(now - Duration::from_secs(5)), now), Interrupt::Exit);
+}
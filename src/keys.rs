@@ -0,0 +1,139 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::warn;
+use nostr_sdk::prelude::*;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+
+/// Prefix of secret keys encrypted according to NIP-49
+const ENCRYPTED_PREFIX: &str = "ncryptsec";
+const PASSPHRASE_ATTEMPTS: usize = 3;
+
+/// Path of the secret key file for the given profile, if any.
+pub(crate) fn keys_file(config_dir: &Path, profile: Option<&str>) -> PathBuf {
+    config_dir.join(profile.map_or("key".to_string(), |p| format!("key.{p}")))
+}
+
+/// Whether the file contains an encrypted secret key.
+pub(crate) fn is_encrypted(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|s| s.trim().starts_with(ENCRYPTED_PREFIX))
+}
+
+/// Read keys from the given file, asking for the passphrase if it is encrypted.
+/// Returns None if the file is missing or invalid, or decryption failed.
+pub(crate) fn read_keys(path: &Path) -> Option<Keys> {
+    let content = fs::read_to_string(path).ok()?;
+    let content = content.trim();
+    if !content.starts_with(ENCRYPTED_PREFIX) {
+        return parse_keys(content);
+    }
+    let encrypted = match EncryptedSecretKey::from_bech32(content) {
+        Ok(encrypted) => encrypted,
+        Err(e) => {
+            warn!("Invalid encrypted key in {}: {e}", path.to_string_lossy());
+            return None;
+        }
+    };
+    for _ in 0..PASSPHRASE_ATTEMPTS {
+        let passphrase = match read_passphrase(&format!("Passphrase for {}: ", path.to_string_lossy())) {
+            Ok(passphrase) => passphrase,
+            Err(e) => {
+                warn!("Could not read passphrase: {e}");
+                return None;
+            }
+        };
+        match encrypted.clone().to_secret_key(passphrase) {
+            Ok(secret) => return Some(Keys::new(secret)),
+            Err(e) => warn!("Could not decrypt key: {e}"),
+        }
+    }
+    None
+}
+
+/// Keys from an unencrypted secret key, or watch-only keys from an npub.
+pub(crate) fn parse_keys(key: &str) -> Option<Keys> {
+    let key = key.trim();
+    if key.starts_with("npub") {
+        // Watch-only
+        return PublicKey::parse(key).ok().map(Keys::from_public_key);
+    }
+    Keys::from_str(key).ok()
+}
+
+/// Content for a key file, encrypted with the passphrase unless it is empty.
+pub(crate) fn serialize_keys(keys: &Keys, passphrase: &str) -> Result<String> {
+    let secret = keys.secret_key()?;
+    Ok(if passphrase.is_empty() {
+        secret.to_string()
+    } else {
+        secret.encrypt(passphrase)?.to_bech32()?
+    })
+}
+
+/// Ask for a new passphrase twice, returning an empty one if the user declines.
+pub(crate) fn read_new_passphrase(prompt: &str) -> rustyline::Result<String> {
+    loop {
+        let passphrase = read_passphrase(prompt)?;
+        if passphrase.is_empty() || read_passphrase("Repeat passphrase: ")? == passphrase {
+            return Ok(passphrase);
+        }
+        warn!("Passphrases do not match");
+    }
+}
+
+/// Read a line without showing the entered characters.
+pub(crate) fn read_passphrase(prompt: &str) -> rustyline::Result<String> {
+    let mut editor = Editor::<Masked, DefaultHistory>::new()?;
+    editor.set_helper(Some(Masked));
+    editor.readline(prompt)
+}
+
+/// Line editor helper replacing all input with asterisks
+struct Masked;
+impl Completer for Masked {
+    type Candidate = String;
+}
+impl Hinter for Masked {
+    type Hint = String;
+}
+impl Validator for Masked {}
+impl Highlighter for Masked {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        "*".repeat(line.chars().count()).into()
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        true
+    }
+}
+impl Helper for Masked {}
+
+#[test]
+fn test_serialize_keys() {
+    let keys = Keys::generate();
+    let plain = serialize_keys(&keys, "").unwrap();
+    assert_eq!(Keys::from_str(&plain).unwrap().public_key(), keys.public_key());
+
+    let encrypted = serialize_keys(&keys, "secret").unwrap();
+    assert!(encrypted.starts_with(ENCRYPTED_PREFIX));
+    let decrypted = EncryptedSecretKey::from_bech32(&encrypted).unwrap().to_secret_key("secret").unwrap();
+    assert_eq!(Keys::new(decrypted).public_key(), keys.public_key());
+}
+
+#[test]
+fn test_parse_keys() {
+    let keys = Keys::generate();
+    let secret = keys.secret_key().unwrap().to_bech32().unwrap();
+    assert_eq!(parse_keys(&secret).unwrap().public_key(), keys.public_key());
+    let watched = parse_keys(&keys.public_key().to_bech32().unwrap()).unwrap();
+    assert_eq!(watched.public_key(), keys.public_key());
+    assert!(watched.secret_key().is_err());
+    assert!(parse_keys("/home/user/.config/mostr/key").is_none());
+}
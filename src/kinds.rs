@@ -1,28 +1,63 @@
 use itertools::Itertools;
 use log::info;
 use nostr_sdk::TagStandard::Hashtag;
-use nostr_sdk::{Alphabet, EventBuilder, EventId, Kind, Tag, TagStandard};
+use nostr_sdk::{Alphabet, Coordinate, Event, EventBuilder, EventId, Kind, PublicKey, Tag, TagKind, TagStandard, Timestamp, Url};
 use std::collections::HashSet;
+use std::iter::once;
 
-use crate::task::{State, MARKER_PARENT};
+use crate::helpers::{format_timestamp_relative, parse_expiration};
+use crate::task::{State, MARKER_ASSIGNEE, MARKER_PARENT};
 
 pub const TASK_KIND: Kind = Kind::GitIssue;
 pub const PROCEDURE_KIND_ID: u16 = 1639;
 pub const PROCEDURE_KIND: Kind = Kind::Regular(PROCEDURE_KIND_ID);
 pub const TRACKING_KIND: Kind = Kind::Regular(1650);
-pub const BASIC_KINDS: [Kind; 4] = [
+pub const ASSIGNMENT_KIND: Kind = Kind::Regular(1640);
+/// Update of the expiration of a task, with the timestamp as content or empty for none
+pub const EXPIRATION_KIND: Kind = Kind::Regular(1641);
+/// Replacement of the parent and dependency references of a task,
+/// referencing the task first followed by its new references with markers
+pub const REFERENCES_KIND: Kind = Kind::Regular(1642);
+/// Update of the NIP-34 git repository of a task, with its coordinate or none to remove it
+pub const REPOSITORY_KIND: Kind = Kind::Regular(1643);
+/// Attachment of a reference to a task, a URL as `r` tag or a file path as `path` tag,
+/// removed by deleting it
+pub const ATTACHMENT_KIND: Kind = Kind::Regular(1644);
+/// Tag holding the path of a file attached to a task
+pub const PATH_TAG: &str = "path";
+/// Prefix for setting the expiration in task creation
+const EXPIRES_PREFIX: &str = "expires:";
+/// Tag holding the priority of a task as a letter, `A` being the highest
+pub const PRIORITY_TAG: &str = "priority";
+/// Tag holding the timestamp a task is due at
+pub const DUE_TAG: &str = "due";
+/// Tag marking a state update as step of a review: `request`, `approved` or `rejected`
+pub const REVIEW_TAG: &str = "review";
+/// Name of the pending state of tasks awaiting review
+pub const REVIEW_LABEL: &str = "Review";
+/// Identifier of the bookmark set maintained by mostr
+pub const BOOKMARKS_IDENTIFIER: &str = "mostr";
+pub const BASIC_KINDS: [Kind; 5] = [
     Kind::Metadata,
     Kind::TextNote,
     TASK_KIND,
     Kind::Bookmarks,
+    Kind::BookmarkSet,
 ];
-pub const PROP_KINDS: [Kind; 6] = [
+pub const PROP_KINDS: [Kind; 13] = [
     TRACKING_KIND,
     Kind::GitStatusOpen,
     Kind::GitStatusApplied,
     Kind::GitStatusClosed,
     Kind::GitStatusDraft,
     PROCEDURE_KIND,
+    Kind::EventDeletion,
+    Kind::Reaction,
+    ASSIGNMENT_KIND,
+    EXPIRATION_KIND,
+    REFERENCES_KIND,
+    REPOSITORY_KIND,
+    ATTACHMENT_KIND,
 ];
 
 // TODO: use formatting - bold / heading / italics - and generate from code
@@ -34,7 +69,7 @@ Immutable:
 - `parentid` - unique task id of the parent, if any
 - `name` - initial name of the task
 - `created` - task creation timestamp
-- `author` - name or abbreviated key of the task creator
+- `author` - name or npub prefix of the task creator, colored per author
 Task:
 - `status` - pure task status
 - `hashtags` - list of hashtags set for the task
@@ -42,14 +77,31 @@ Task:
 - `desc` - last note on the task
 - `description` - accumulated notes on the task
 - `time` - time tracked on this task by you
+- `reactions` - reactions on the task with the people who reacted
+- `assignee` - name or abbreviated key of the person the task is assigned to
+- `statechanger` - name or abbreviated key of whoever set the current state
+- `expires` - when the task expires, after which it is hidden like a closed task
+- `updated` - time of the latest change or time-tracking of the task
+- `age` - how long ago the task was last updated
+- `completed` - when the task was done or closed
+- `priority` - priority letter, `A` being the highest
+- `due` - when the task is due
+- `repo` - identifier of the NIP-34 git repository the task belongs to
+- `attachments` - number of URLs and files attached to the task
 Utilities:
 - `state` - indicator of current progress
 - `rtime` - time tracked on this tasks and its subtree by everyone
-- `progress` - recursive subtask completion in percent
+- `rtime:N` - time tracked on this task and its subtasks down to N levels by everyone
+- `dtime` - time tracked directly on this task by everyone
+- `progress` - recursive subtask completion in percent, averaged per level
+- `wprogress` - recursive subtask completion in percent, weighting all leaf tasks equally
 - `subtasks` - how many direct subtasks are complete
+- `rsubtasks` - how many subtasks in the whole subtree are complete
 - `path` - name including parent tasks
 - `rpath` - name including parent tasks up to active task
-- TBI `depends` - list all tasks this task depends on before it becomes actionable
+- `project` - name of the topmost parent task, or the shortened id of an unknown parent
+- `depends` - list all tasks this task depends on before it becomes actionable
+- `dependents` - list all tasks depending on this task
 Debugging: `kind`, `pubkey`, `props`, `alltags`, `descriptions`";
 
 pub(crate) fn build_tracking<I>(id: I) -> EventBuilder
@@ -63,6 +115,115 @@ where
     )
 }
 
+/// Build an assignment of the task, superseding earlier ones.
+/// Without assignee, the task is unassigned.
+pub(crate) fn build_assignment(id: EventId, assignee: Option<PublicKey>) -> EventBuilder {
+    EventBuilder::new(
+        ASSIGNMENT_KIND,
+        "",
+        once(Tag::event(id)).chain(assignee.map(|public_key|
+            Tag::from(TagStandard::PublicKey {
+                public_key,
+                relay_url: None,
+                alias: Some(MARKER_ASSIGNEE.to_string()),
+                uppercase: false,
+            }))),
+    )
+}
+
+/// Build an update of the expiration of the task, clearing it without timestamp.
+pub(crate) fn build_expiration(id: EventId, expiration: Option<Timestamp>) -> EventBuilder {
+    EventBuilder::new(
+        EXPIRATION_KIND,
+        expiration.map_or(String::new(), |stamp| stamp.to_string()),
+        [Tag::event(id)],
+    )
+}
+
+/// Build a replacement of the references of the task,
+/// which need to be event tags with markers.
+pub(crate) fn build_references(id: EventId, refs: impl IntoIterator<Item=Tag>) -> EventBuilder {
+    EventBuilder::new(REFERENCES_KIND, "", once(Tag::event(id)).chain(refs))
+}
+
+/// Build an update of the git repository of the task, removing it without coordinate.
+pub(crate) fn build_repository(id: EventId, repository: Option<Coordinate>) -> EventBuilder {
+    EventBuilder::new(REPOSITORY_KIND, "", once(Tag::event(id)).chain(repository.map(Tag::coordinate)))
+}
+
+/// Build an attachment of the URL or file path to the task.
+pub(crate) fn build_attachment(id: EventId, reference: &str) -> EventBuilder {
+    let tag = if is_url(reference) {
+        Tag::from(TagStandard::Reference(reference.to_string()))
+    } else {
+        Tag::custom(TagKind::Custom(PATH_TAG.into()), [reference])
+    };
+    EventBuilder::new(ATTACHMENT_KIND, "", [Tag::event(id), tag])
+}
+
+/// URL or file path of an attachment
+pub(crate) fn attachment(event: &Event) -> Option<&str> {
+    event.tags.iter().find_map(|tag| match tag.as_vec() {
+        [key, value, ..] if key == "r" || key == PATH_TAG => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+/// Whether the reference is a URL rather than a file path,
+/// not mistaking drive letters of Windows paths for schemes
+pub(crate) fn is_url(reference: &str) -> bool {
+    Url::parse(reference).is_ok_and(|url| url.scheme().len() > 1)
+}
+
+/// Build a pending state update asking for a review, tagging the reviewer if given.
+pub(crate) fn build_review_request(id: EventId, reviewer: Option<PublicKey>) -> EventBuilder {
+    build_prop(State::Pending.into(), REVIEW_LABEL, id)
+        .add_tags(once(Tag::custom(TagKind::Custom(REVIEW_TAG.into()), ["request"]))
+            .chain(reviewer.map(Tag::public_key)))
+}
+
+/// Build the verdict of a review: done when approved, otherwise open again.
+pub(crate) fn build_review(id: EventId, approved: bool, comment: &str) -> EventBuilder {
+    let (state, verdict) = if approved { (State::Done, "approved") } else { (State::Open, "rejected") };
+    build_prop(state.into(), comment, id)
+        .add_tags([Tag::custom(TagKind::Custom(REVIEW_TAG.into()), [verdict])])
+}
+
+/// Identifier of the bookmark set of mostr for the given relay,
+/// the plain identifier being used for the local relay
+/// and by earlier versions which kept one set for all relays.
+pub(crate) fn bookmarks_identifier(url: Option<&Url>) -> String {
+    url.map_or(BOOKMARKS_IDENTIFIER.to_string(), |url| format!("{BOOKMARKS_IDENTIFIER}:{url}"))
+}
+
+/// Build the bookmark set of mostr for the given relay,
+/// which coexists with bookmark lists of other clients and other relays
+pub(crate) fn build_bookmarks<I>(url: Option<&Url>, ids: I) -> EventBuilder
+where
+    I: IntoIterator<Item=EventId>,
+{
+    EventBuilder::new(
+        Kind::BookmarkSet,
+        "mostr pins",
+        once(Tag::identifier(bookmarks_identifier(url))).chain(ids.into_iter().map(Tag::event)),
+    )
+}
+
+/// Whether the event is the bookmark set of mostr for the given relay
+pub(crate) fn is_relay_bookmarks(event: &Event, url: Option<&Url>) -> bool {
+    event.kind == Kind::BookmarkSet && event.identifier() == Some(bookmarks_identifier(url).as_str())
+}
+
+/// Whether the event is a bookmark list shared by all relays,
+/// as maintained by earlier versions of mostr.
+pub(crate) fn is_mostr_bookmarks(event: &Event) -> bool {
+    match event.kind {
+        Kind::BookmarkSet => event.identifier() == Some(BOOKMARKS_IDENTIFIER),
+        Kind::Bookmarks => event.content == "mostr pins",
+        _ => false,
+    }
+}
+
 /// Build a task with informational output and optional labeled kind
 pub(crate) fn build_task(name: &str, tags: Vec<Tag>, kind: Option<(&str, Kind)>) -> EventBuilder {
     info!("Created {}task \"{name}\" with tags [{}]",
@@ -83,27 +244,89 @@ pub(crate) fn build_prop(
     )
 }
 
+pub(crate) fn build_priority_tag(priority: char) -> Tag {
+    Tag::custom(TagKind::Custom(PRIORITY_TAG.into()), [priority.to_string()])
+}
+
+pub(crate) fn build_due_tag(due: Timestamp) -> Tag {
+    Tag::custom(TagKind::Custom(DUE_TAG.into()), [due.to_string()])
+}
+
 /// Return Hashtags embedded in the string.
 pub(crate) fn extract_hashtags(input: &str) -> impl Iterator<Item=Tag> + '_ {
     input.split_ascii_whitespace()
-        .filter(|s| s.starts_with('#'))
-        .map(|s| s.trim_start_matches('#'))
+        .filter_map(hashtag_word)
         .map(to_hashtag)
 }
 
-/// Extracts everything after a ": " as a list of tags.
+/// Content of a word like `#tag`, without trailing punctuation
+fn hashtag_word(word: &str) -> Option<&str> {
+    word.strip_prefix('#')
+        .map(|tag| tag.trim_start_matches('#').trim_end_matches(|c: char| c.is_ascii_punctuation() && c != '-' && c != '_'))
+        .filter(|tag| !tag.is_empty())
+}
+
+/// Remove words like `#tag` from the text, keeping its lines.
+pub(crate) fn strip_hashtags(text: &str) -> String {
+    text.lines()
+        .map(|line| line.split(' ').filter(|word| hashtag_word(word).is_none()).join(" "))
+        .join("\n")
+}
+
+/// Extracts everything after a ": " as a list of tags,
+/// as well as words like `#tag` anywhere else, which are removed from the name if `strip` is set.
+/// A tag or trailing word like `expires:3d` becomes a NIP-40 expiration, or stays in the name if unparseable.
+/// Text in double quotes is taken literally, without the quotes,
+/// and a leading backslash makes the whole input the name.
 ///
 /// Expects sanitized input.
-pub(crate) fn extract_tags(input: &str) -> (&str, Vec<Tag>) {
-    match input.split_once(": ") {
-        None => (input, extract_hashtags(input).collect_vec()),
-        Some((name, tags)) => {
-            let tags = extract_hashtags(name)
-                .chain(tags.split_ascii_whitespace().map(to_hashtag))
-                .collect();
-            (name, tags)
-        }
+pub(crate) fn extract_tags(input: &str, strip: bool) -> (String, Vec<Tag>) {
+    if let Some(raw) = input.strip_prefix('\\') {
+        return (raw.to_string(), vec![]);
     }
+    let quoted = input.matches('"').count() % 2 == 0;
+    let (name, words) = match find_unquoted(input, ": ", quoted) {
+        None => match input.rsplit_once(' ') {
+            Some((name, last)) if last.starts_with(EXPIRES_PREFIX) && !(quoted && last.contains('"')) => (name, vec![last]),
+            _ => (input, vec![]),
+        },
+        Some(index) => (&input[..index], input[index + 2..].split_ascii_whitespace().collect_vec()),
+    };
+    let (name, unquoted) = if quoted {
+        (name.split('"').enumerate()
+             .map(|(index, part)| if strip && index % 2 == 0 { strip_hashtags(part) } else { part.to_string() })
+             .join(""),
+         name.split('"').step_by(2).join(" "))
+    } else if strip {
+        (strip_hashtags(name), name.to_string())
+    } else {
+        (name.to_string(), name.to_string())
+    };
+    let name = if strip { name.split_ascii_whitespace().join(" ") } else { name };
+    let mut unparsed = vec![];
+    let tags = extract_hashtags(&unquoted)
+        .chain(words.into_iter().filter_map(|word| match word.strip_prefix(EXPIRES_PREFIX) {
+            Some(time) => parse_expiration(time).map(Tag::expiration).or_else(|| {
+                unparsed.push(word);
+                None
+            }),
+            None => Some(to_hashtag(word)),
+        }))
+        .collect();
+    // Keep what could not be parsed rather than losing it
+    let name = once(name.as_str()).chain(unparsed).filter(|s| !s.is_empty()).join(" ");
+    (name, tags)
+}
+
+/// Byte index of the first occurrence of the pattern outside of double quotes, if they are considered
+fn find_unquoted(input: &str, pattern: &str, quoted: bool) -> Option<usize> {
+    let mut inside = false;
+    input.char_indices().find_map(|(index, c)| {
+        if quoted && c == '"' {
+            inside = !inside;
+        }
+        Some(index).filter(|_| !inside && input[index..].starts_with(pattern))
+    })
 }
 
 fn to_hashtag(tag: &str) -> Tag {
@@ -124,6 +347,10 @@ fn format_tag(tag: &Tag) -> String {
              }) => format!("Key{}: {:.8}", public_key, alias.as_ref().map(|s| format!(" {s}")).unwrap_or_default()),
         Some(TagStandard::Hashtag(content)) =>
             format!("#{content}"),
+        Some(TagStandard::Expiration(stamp)) =>
+            format!("expires {}", format_timestamp_relative(stamp)),
+        Some(TagStandard::Coordinate { coordinate, .. }) if coordinate.kind == Kind::GitRepoAnnouncement =>
+            format!("repo {}", coordinate.identifier),
         _ => tag.content().map_or_else(
             || format!("Kind {}", tag.kind()),
             |content| content.to_string(),
@@ -139,6 +366,27 @@ pub(crate) fn is_hashtag(tag: &Tag) -> bool {
 
 #[test]
 fn test_extract_tags() {
-    assert_eq!(extract_tags("Hello from #mars with #greetings: yeah done-it"),
-               ("Hello from #mars with #greetings", ["mars", "greetings", "yeah", "done-it"].into_iter().map(to_hashtag).collect()))
-}
\ No newline at end of file
+    assert_eq!(extract_tags("Hello from #mars with #greetings: yeah done-it", false),
+               ("Hello from #mars with #greetings", ["mars", "greetings", "yeah", "done-it"].into_iter().map(to_hashtag).collect()));
+    for input in ["buy milk expires:2h", "buy milk: expires:2h"] {
+        let (name, tags) = extract_tags(input, false);
+        assert_eq!(name, "buy milk");
+        assert!(matches!(tags.as_slice(), [tag] if matches!(tag.as_standardized(), Some(TagStandard::Expiration(stamp)) if stamp > &Timestamp::now())));
+    }
+    assert_eq!(extract_tags("\"Meeting: agenda #1\" with #team: work", false),
+               ("Meeting: agenda #1 with #team".to_string(), vec![to_hashtag("team"), to_hashtag("work")]));
+    assert_eq!(extract_tags("\">follow up\"", false), (">follow up".to_string(), vec![]));
+    assert_eq!(extract_tags("buy milk expires:whenever", false), ("buy milk expires:whenever".to_string(), vec![]));
+    assert_eq!(extract_tags("buy milk: dairy expires:whenever", false), ("buy milk expires:whenever".to_string(), vec![to_hashtag("dairy")]));
+    assert_eq!(extract_tags("note \"expires:3d\"", false), ("note expires:3d".to_string(), vec![]));
+    assert_eq!(extract_tags("\\/etc cleanup: #now", false), ("/etc cleanup: #now".to_string(), vec![]));
+    assert_eq!(extract_tags("5\" screen: hardware", false), ("5\" screen".to_string(), vec![to_hashtag("hardware")]));
+
+    assert_eq!(extract_tags("fix login #auth, #urgent # now", false),
+               ("fix login #auth, #urgent # now".to_string(), vec![to_hashtag("auth"), to_hashtag("urgent")]));
+    assert_eq!(extract_tags("fix login #auth #urgent: backend", true),
+               ("fix login".to_string(), ["auth", "urgent", "backend"].into_iter().map(to_hashtag).collect()));
+    assert_eq!(extract_tags("\"Meeting: agenda #1\" with #team: work", true),
+               ("Meeting: agenda #1 with".to_string(), vec![to_hashtag("team"), to_hashtag("work")]));
+    assert_eq!(strip_hashtags("#review the #draft\nthen #ship it"), "the\nthen it");
+}
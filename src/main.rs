@@ -1,16 +1,19 @@
 use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
 use std::env::{args, var};
+use std::fmt::{Display, Formatter};
 use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{stdout, BufRead, BufReader, Write};
 use std::iter::once;
+use std::net::SocketAddr;
 use std::ops::Sub;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use chrono::Local;
 use colored::Colorize;
 use env_logger::{Builder, Target, WriteStyle};
 use itertools::Itertools;
@@ -21,25 +24,54 @@ use regex::Regex;
 use rustyline::config::Configurer;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
-use tokio::sync::mpsc;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tokio::task::JoinSet;
 use tokio::time::error::Elapsed;
 use tokio::time::timeout;
 use xdg::BaseDirectories;
 
+use crate::cache::{newest_timestamp, EventCache};
+use crate::config::{Config, SETTINGS_KEYS};
+use crate::interrupt::InterruptHandler;
+use crate::keys::{is_encrypted, keys_file, parse_keys, read_keys, read_new_passphrase, serialize_keys};
 use crate::helpers::*;
-use crate::kinds::{BASIC_KINDS, PROPERTY_COLUMNS, PROP_KINDS, TRACKING_KIND};
+use crate::kinds::{is_url, BASIC_KINDS, PROPERTY_COLUMNS, PROP_KINDS, TRACKING_KIND};
+use crate::outbox::Outbox;
+use crate::serve::{Server, Snapshot};
+use crate::session::Session;
 use crate::task::{State, Task, TaskState, MARKER_DEPENDS};
 use crate::tasks::{PropertyCollection, StateFilter, TasksRelay};
 
 mod helpers;
+mod ical;
+mod interrupt;
 mod task;
 mod tasks;
 mod kinds;
+mod cache;
+mod config;
+mod keys;
+mod outbox;
+mod serve;
+mod session;
+mod taskwarrior;
+mod todotxt;
 
-const UNDO_DELAY: u64 = 60;
-const INACTVITY_DELAY: u64 = 200;
+const DEFAULT_RETRIES: u32 = 4;
+/// Time to wait for a relay to connect before subscribing anyway
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Display width beyond which the start of the current path is cut off in the prompt
+const PROMPT_PATH_WIDTH: usize = 60;
+/// Minimum interval between reports of updates while watching
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+/// Interval for checking whether planned time-tracking started
+const PLAN_INTERVAL: Duration = Duration::from_secs(10);
 const LOCAL_RELAY_NAME: &str = "TEMP";
+const MERGED_RELAY_NAME: &str = "ALL";
+/// Placeholder url for the merged view of all relays
+const MERGED_RELAY_URL: &str = "mostr:all";
 
 /// Turn a Result into an Option, showing a warning on error with optional prefix
 macro_rules! or_warn {
@@ -65,61 +97,125 @@ macro_rules! or_warn {
 
 type Events = Vec<Event>;
 
+/// Signed event waiting to be sent.
+/// Events kept by the relay anyway are only referenced
+/// and looked up when sending, so they are not held twice.
+#[derive(Debug, Clone)]
+pub(crate) enum Queued {
+    /// Event not kept elsewhere, such as a deletion
+    Held(Event),
+    /// Event kept by the relay, with the first event it references
+    Stored {
+        id: EventId,
+        kind: Kind,
+        created_at: Timestamp,
+        reference: Option<EventId>,
+    },
+}
+impl Queued {
+    fn kind(&self) -> Kind {
+        match self {
+            Queued::Held(event) => event.kind,
+            Queued::Stored { kind, .. } => *kind,
+        }
+    }
+    fn created_at(&self) -> Timestamp {
+        match self {
+            Queued::Held(event) => event.created_at,
+            Queued::Stored { created_at, .. } => *created_at,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct EventSender {
     url: Option<Url>,
     tx: Sender<MostrMessage>,
     keys: Keys,
-    queue: RefCell<Events>,
+    queue: RefCell<Vec<Queued>>,
+    cache: Option<EventCache>,
+    /// Seconds for which queued events can be undone
+    undo_delay: u64,
 }
 impl EventSender {
-    fn from(url: Option<Url>, tx: &Sender<MostrMessage>, keys: &Keys) -> Self {
+    fn from(url: Option<Url>, tx: &Sender<MostrMessage>, keys: &Keys, cache: Option<EventCache>, undo_delay: u64) -> Self {
         EventSender {
             url,
             tx: tx.clone(),
             keys: keys.clone(),
             queue: Default::default(),
+            cache,
+            undo_delay,
         }
     }
 
-    fn submit(&self, event_builder: EventBuilder) -> Result<Event> {
-        {
-            // Always flush if oldest event older than a minute or newer than now
-            let borrow = self.queue.borrow();
-            let min = Timestamp::now().sub(UNDO_DELAY);
-            if borrow.iter().any(|e| e.created_at < min || e.created_at > Timestamp::now()) {
-                drop(borrow);
-                debug!("Flushing event queue because it is older than a minute");
-                self.force_flush();
-            }
-        }
+    fn sign(&self, event_builder: EventBuilder) -> Result<Event> {
+        Ok(event_builder.to_event(&self.keys)?)
+    }
+    /// Whether the queue has to be flushed before queueing more,
+    /// because its oldest event is older than the undo window or newer than now
+    fn is_stale(&self) -> bool {
+        let min = Timestamp::now().sub(self.undo_delay);
+        self.queue.borrow().iter().any(|q| q.created_at() < min || q.created_at() > Timestamp::now())
+    }
+    /// Queues the event, superseding any queued time-tracking by a new one
+    fn enqueue(&self, queued: Queued) {
         let mut queue = self.queue.borrow_mut();
-        Ok(event_builder.to_event(&self.keys).inspect(|event| {
-            if event.kind == TRACKING_KIND {
-                queue.retain(|e| {
-                    e.kind != TRACKING_KIND
-                });
-            }
-            queue.push(event.clone());
-        })?)
+        if queued.kind() == TRACKING_KIND {
+            queue.retain(|q| q.kind() != TRACKING_KIND);
+        }
+        queue.push(queued);
     }
-    /// Sends all pending events
-    fn force_flush(&self) {
-        debug!("Flushing {} events from queue", self.queue.borrow().len());
-        let values = self.clear();
+    /// Caches the given events and hands them to the relay, bypassing the queue
+    fn send(&self, events: Events) {
+        if let Some(cache) = &self.cache {
+            or_warn!(cache.append(self.url.as_ref(), &events), "Could not cache events");
+        }
         self.url.as_ref().map(|url| {
-            self.tx.try_send(MostrMessage::AddTasks(url.clone(), values)).err().map(|e| {
+            self.tx.try_send(MostrMessage::AddTasks(url.clone(), events)).err().map(|e| {
                 error!("Nostr communication thread failure, changes will not be persisted: {}", e)
             })
         });
     }
-    /// Sends all pending events if there is a non-tracking event
-    fn flush(&self) {
-        if self.queue.borrow().iter().any(|event| event.kind != TRACKING_KIND) {
-            self.force_flush()
+    /// Requests metadata of the given users from the relay, delivered as regular events
+    fn fetch_metadata(&self, keys: Vec<PublicKey>) {
+        if let Some(url) = &self.url {
+            or_warn!(self.tx.try_send(MostrMessage::FetchMetadata(url.clone(), keys)), "Could not request metadata");
+        }
+    }
+    /// Requests the given events from the relay, delivered as regular events
+    fn fetch_events(&self, ids: Vec<EventId>) -> bool {
+        match &self.url {
+            Some(url) => or_warn!(self.tx.try_send(MostrMessage::FetchEvents(url.clone(), ids)), "Could not request events").is_some(),
+            None => false,
         }
     }
-    fn clear(&self) -> Events {
+    /// Hands a direct message to the person over to the relay connection
+    fn notify(&self, recipient: PublicKey, message: String) {
+        or_warn!(self.tx.try_send(MostrMessage::Notify(recipient, message)), "Could not queue notification");
+    }
+    /// Whether there is a queued event other than time-tracking
+    fn has_changes(&self) -> bool {
+        self.queue.borrow().iter().any(|q| q.kind() != TRACKING_KIND)
+    }
+    fn queued(&self) -> usize {
+        self.queue.borrow().len()
+    }
+    /// Copy of the queued events, oldest first
+    fn peek(&self) -> Vec<Queued> {
+        self.queue.borrow().clone()
+    }
+    /// Drop the queued event with the given id, returning whether it was queued
+    fn dequeue(&self, id: &EventId) -> bool {
+        let mut queue = self.queue.borrow_mut();
+        let len = queue.len();
+        queue.retain(|q| match q {
+            Queued::Held(event) => &event.id != id,
+            Queued::Stored { id: queued, .. } => queued != id,
+        });
+        queue.len() < len
+    }
+    fn clear(&self) -> Vec<Queued> {
         trace!("Cleared queue: {:?}", self.queue.borrow());
         self.queue.replace(Vec::with_capacity(3))
     }
@@ -127,24 +223,547 @@ impl EventSender {
         self.keys.public_key()
     }
 }
-impl Drop for EventSender {
-    fn drop(&mut self) {
-        self.force_flush();
-        debug!("Dropped {:?}", self);
-    }
-}
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Connection state, number of queued and of undelivered events per relay
+/// as seen by the nostr communication thread
+type RelayReport = HashMap<Url, (String, usize, usize)>;
+
+#[derive(Debug)]
 pub(crate) enum MostrMessage {
     Flush,
     NewRelay(Url),
     AddTasks(Url, Vec<Event>),
+    Rescope(Scope),
+    Report(oneshot::Sender<RelayReport>),
+    RemoveRelay(Url),
+    PauseRelay(Url),
+    ResumeRelay(Url),
+    Notify(PublicKey, String),
+    FetchMetadata(Url, Vec<PublicKey>),
+    /// Request events by id, such as tasks referenced but never received
+    FetchEvents(Url, Vec<EventId>),
+    Whois(Url, PublicKey, oneshot::Sender<Events>),
+    SwitchIdentity(Keys),
+    /// Send queued events and stop, answering once done
+    Shutdown(oneshot::Sender<()>),
+}
+
+/// Restriction of the events fetched from relays
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct Scope {
+    since: Option<Timestamp>,
+    authors: Option<Vec<PublicKey>>,
+}
+impl Scope {
+    /// Whether this scope takes in events the other one leaves out
+    fn widens(&self, other: &Scope) -> bool {
+        let earlier = match (self.since, other.since) {
+            (None, Some(_)) => true,
+            (Some(new), Some(old)) => new < old,
+            (_, None) => false,
+        };
+        let more_authors = match (&self.authors, &other.authors) {
+            (None, Some(_)) => true,
+            (Some(new), Some(old)) => new.iter().any(|key| !old.contains(key)),
+            (_, None) => false,
+        };
+        earlier || more_authors
+    }
+
+    /// Filters for all relevant kinds within this scope,
+    /// starting no earlier than the given timestamp.
+    fn filters(&self, since: Option<Timestamp>) -> Vec<Filter> {
+        let since = self.since.max(since);
+        [Filter::new().kinds(BASIC_KINDS), Filter::new().kinds(PROP_KINDS)]
+            .into_iter()
+            .map(|filter| match since {
+                Some(stamp) => filter.since(stamp),
+                None => filter,
+            })
+            .map(|filter| match &self.authors {
+                Some(authors) => filter.authors(authors.clone()),
+                None => filter,
+            })
+            .collect()
+    }
+}
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.since.map(|s| format!(" since {}", format_timestamp_relative(&s)))
+                .into_iter()
+                .chain(self.authors.as_ref().map(|a| format!(" by {} authors", a.len())))
+                .join("")
+        )
+    }
+}
+
+/// Subscribe to all relevant kinds in the scope on the given relay
+/// as well as the own contact list,
+/// optionally only fetching events newer than the given timestamp.
+async fn subscribe_to(client: &Client, url: &Url, scope: &Scope, since: Option<Timestamp>, own: &PublicKey) {
+    let filters = scope.filters(since).into_iter()
+        .chain(once(Filter::new().kind(Kind::ContactList).author(*own)))
+        .collect_vec();
+    match client.subscribe_to(vec![url.clone()], filters, None).await {
+        Ok(output) => info!("Subscribed to {url}{scope}{} with {:?}",
+            since.map(|s| format!(" fetching from {}", format_timestamp_local(&s))).unwrap_or_default(), output.val),
+        Err(e) => warn!("Unable to subscribe to {url}: {e}"),
+    }
+}
+
+/// Find the relay whose url or host starts with the given prefix.
+fn find_relay<T>(relays: &HashMap<Option<Url>, T>, prefix: &str) -> Option<Url> {
+    relays.keys()
+        .flatten()
+        .find(|url| url.as_str().starts_with(prefix) || url.host_str().is_some_and(|host| host.starts_with(prefix)))
+        .cloned()
+}
+
+/// Ask the nostr communication thread for the state of each relay
+async fn relay_report(tx: &Sender<MostrMessage>) -> RelayReport {
+    let (response, report) = oneshot::channel();
+    match tx.try_send(MostrMessage::Report(response)) {
+        Ok(_) => or_warn!(timeout(Duration::from_secs(3), report).await, "Nostr communication thread is busy")
+            .and_then(|r| or_warn!(r, "No report from nostr communication thread")),
+        Err(e) => {
+            error!("Nostr communication thread failure, cannot query relays: {e}");
+            None
+        }
+    }.unwrap_or_default()
+}
+
+/// Ask a yes-or-no question, taking anything but yes as no.
+fn confirm(rl: &mut DefaultEditor, question: &str) -> bool {
+    rl.readline(&format!("{question} [y/N] "))
+        .is_ok_and(|answer| matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Log output held back while an external editor has the terminal
+static HELD_LOGS: std::sync::Mutex<Option<Vec<u8>>> = std::sync::Mutex::new(None);
+
+/// Log target which holds back output while an editor runs, see [edit_in_editor]
+struct HoldingWriter<W>(W);
+
+impl<W: Write> Write for HoldingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(held) = HELD_LOGS.lock().unwrap().as_mut() {
+            held.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if HELD_LOGS.lock().unwrap().is_some() {
+            return Ok(());
+        }
+        self.0.flush()
+    }
+}
+
+/// Show text longer than the given number of lines, unless 0, through the pager from PAGER if one is set,
+/// holding back logs meanwhile like [edit_in_editor], otherwise print it.
+fn page(text: &str, max_lines: usize) {
+    let pager = var("PAGER").ok().filter(|pager| !pager.trim().is_empty());
+    let Some(pager) = pager.filter(|_| max_lines > 0 && text.lines().count() > max_lines) else {
+        println!("{text}");
+        return;
+    };
+    let mut words = pager.split_whitespace();
+    let program = words.next().unwrap_or("less");
+    *HELD_LOGS.lock().unwrap() = Some(vec![]);
+    let result = std::process::Command::new(program).args(words)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                // Quitting the pager early closes the pipe
+                let _ = stdin.write_all(text.as_bytes());
+            }
+            child.wait()
+        });
+    if let Some(held) = HELD_LOGS.lock().unwrap().take() {
+        let _ = stdout().write_all(&held);
+    }
+    if let Err(e) = result {
+        warn!("Could not run pager {program}: {e}");
+        println!("{text}");
+    }
+}
+
+/// Let the user edit the text in the editor from VISUAL or EDITOR, falling back to vi.
+/// Logs are held back until the editor exits so they do not garble its screen.
+/// Returns None if the editor could not be run or failed.
+fn edit_in_editor(text: &str) -> Option<String> {
+    let editor = var("VISUAL").or_else(|_| var("EDITOR")).unwrap_or("vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let path = std::env::temp_dir().join(format!("mostr-{}.md", std::process::id()));
+    if let Err(e) = fs::write(&path, text) {
+        warn!("Could not prepare {} for editing: {e}", path.to_string_lossy());
+        return None;
+    }
+    *HELD_LOGS.lock().unwrap() = Some(vec![]);
+    let status = std::process::Command::new(program).args(words).arg(&path).status();
+    if let Some(held) = HELD_LOGS.lock().unwrap().take() {
+        let _ = stdout().write_all(&held);
+    }
+    let result = match status {
+        Ok(status) if status.success() => fs::read_to_string(&path)
+            .map_err(|e| warn!("Could not read back {}: {e}", path.to_string_lossy())).ok(),
+        Ok(status) => {
+            warn!("Editor {program} exited with {status}, discarding changes");
+            None
+        }
+        Err(e) => {
+            warn!("Could not run editor {program}: {e}");
+            None
+        }
+    };
+    let _ = fs::remove_file(&path);
+    result
+}
+
+/// Open a URL or file with the opener of the platform without waiting for it,
+/// resolving relative paths against the root.
+fn open_reference(reference: &str, root: &str) {
+    let target = if is_url(reference) { reference.to_string() } else { resolve_path(reference, root) };
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    match std::process::Command::new(opener).arg(&target)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn() {
+        Ok(mut child) => {
+            info!("Opening {target}");
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => warn!("Could not run {opener} to open {target}: {e}"),
+    }
+}
+
+/// Compose a note on the current task in the editor, starting from a commented template.
+fn compose_note(tasks: &mut TasksRelay) {
+    let Some(id) = tasks.get_current_task().map(|task| *task.get_id()) else {
+        warn!("Select a task to write a note on it");
+        return;
+    };
+    let template = format!("\n# Note on {}\n# Lines starting with \"# \" are ignored, an empty note is discarded\n",
+                           strip_ansi(&tasks.get_task_path(Some(id))));
+    match edit_in_editor(&template).map(|text| strip_comments(&text)) {
+        None => {}
+        Some(text) if text.is_empty() => info!("Empty note discarded"),
+        Some(text) => tasks.make_note(&text),
+    }
+}
+
+/// Wait until the relay is connected, returning false after the given time.
+async fn await_connection(client: &Client, url: &Url, wait: Duration) -> bool {
+    let start = std::time::Instant::now();
+    while start.elapsed() < wait {
+        if let Ok(relay) = client.relay(url).await {
+            if relay.is_connected().await {
+                return true;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+/// Connect to all relays concurrently, fetching events newer than the given timestamp from each
+/// once it is connected or the connection timed out.
+/// Connected relays are announced through the channel.
+async fn connect_all(
+    client: Client,
+    relays: HashMap<Url, Option<Timestamp>>,
+    scope: Scope,
+    own: PublicKey,
+    connected: mpsc::UnboundedSender<Url>,
+) {
+    let mut connections = JoinSet::new();
+    for (url, since) in relays {
+        let (client, scope, connected) = (client.clone(), scope.clone(), connected.clone());
+        connections.spawn(async move {
+            or_warn!(client.connect_relay(&url).await, "Unable to connect to relay {url}");
+            let success = await_connection(&client, &url, CONNECT_TIMEOUT).await;
+            subscribe_to(&client, &url, &scope, since, &own).await;
+            if success {
+                let _ = connected.send(url);
+            }
+            success
+        });
+    }
+    let mut any = connections.is_empty();
+    while let Some(result) = connections.join_next().await {
+        any |= result.unwrap_or(false);
+    }
+    if !any {
+        warn!("Could not connect to any relay yet - use %sync to publish local changes once connected");
+    }
+}
+
+/// What to show about updates arriving while waiting for input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Watch {
+    #[default]
+    Off,
+    /// A line per changed task
+    Updates,
+    /// The changes followed by the refreshed task list
+    Table,
+}
+
+type Relays = HashMap<Option<Url>, TasksRelay>;
+
+/// Key of the merged view of all relays
+fn merged_key() -> Option<Url> {
+    Url::parse(MERGED_RELAY_URL).ok()
+}
+
+/// Name of the relay for display, the merged view showing where changes go
+fn relay_name(url: &Option<Url>, relays: &Relays) -> String {
+    match url {
+        None => LOCAL_RELAY_NAME.to_string(),
+        Some(_) if url == &merged_key() =>
+            format!("{MERGED_RELAY_NAME}→{}", relays.get(url).and_then(|all| all.url()).map_or("?".to_string(), |u| u.to_string())),
+        Some(url) => url.to_string(),
+    }
+}
+
+/// Log the collected changes of the watched relay, refreshing its task list if requested.
+async fn report_changes(changes: &mut Vec<String>, relays: &Mutex<Relays>, watching: &Mutex<(Option<Url>, Watch)>) {
+    let (url, watch) = watching.lock().await.clone();
+    let mut report = format!("Updates: {}", changes.drain(..).join("\n         "));
+    if watch == Watch::Table {
+        if let Some(tasks) = relays.lock().await.get(&url) {
+            let mut table = vec![];
+            if tasks.write_tasks(&mut table).is_ok() {
+                report.push('\n');
+                report.push_str(String::from_utf8_lossy(&table).trim_end());
+            }
+        }
+    }
+    info!("{report}");
+}
+
+/// Announce when planned time-tracking on the watched relay starts while waiting for input,
+/// and show desktop notifications for tasks coming due on any relay.
+async fn announce_plans(relays: Arc<Mutex<Relays>>, watching: Arc<Mutex<(Option<Url>, Watch)>>) {
+    let mut upcoming: Vec<(Timestamp, Option<Timestamp>, EventId)> = vec![];
+    loop {
+        tokio::time::sleep(PLAN_INTERVAL).await;
+        let (watched, _) = watching.lock().await.clone();
+        let mut relays = relays.lock().await;
+        relays.values_mut().for_each(|tasks| tasks.notify_due());
+        let Some(tasks) = relays.get(&watched) else { continue };
+        let now = Timestamp::now();
+        for (_, _, id) in upcoming.iter().filter(|(start, _, _)| start <= &now) {
+            if tasks.get_position().as_ref() == Some(id) {
+                info!("Now tracking \"{}\" as planned", tasks.get_task_title(id));
+            }
+        }
+        upcoming = tasks.planned_blocks();
+    }
+}
+
+/// Apply events received from relays in the background until the relay pool shuts down.
+/// Notifications are drained in batches, each applied while holding the relays,
+/// so commands never see a partially applied batch.
+/// Changes to the watched relay are reported at most every [WATCH_INTERVAL]
+/// and refresh the served status page right away.
+async fn ingest(
+    mut notifications: broadcast::Receiver<RelayPoolNotification>,
+    relays: Arc<Mutex<Relays>>,
+    cache: Option<EventCache>,
+    watching: Arc<Mutex<(Option<Url>, Watch)>>,
+    newest: Arc<Mutex<HashMap<Url, Timestamp>>>,
+    server: Arc<Mutex<Option<Server>>>,
+) {
+    let mut idle = true;
+    let merged = merged_key();
+    let mut changes: Vec<String> = vec![];
+    let mut last_report = std::time::Instant::now() - WATCH_INTERVAL;
+    loop {
+        let next = if idle {
+            notifications.recv().await
+        } else {
+            match timeout(Duration::from_secs(1), notifications.recv()).await {
+                Ok(next) => next,
+                Err(Elapsed { .. }) => {
+                    if !changes.is_empty() && last_report.elapsed() >= WATCH_INTERVAL {
+                        report_changes(&mut changes, &relays, &watching).await;
+                        last_report = std::time::Instant::now();
+                    }
+                    if changes.is_empty() {
+                        // Once updates calm down, retry events whose task may have arrived since
+                        // and drop old time-tracking of others
+                        relays.lock().await.values_mut().for_each(|tasks| {
+                            tasks.process_overflow();
+                            tasks.prune_history();
+                        });
+                        idle = true;
+                    }
+                    continue;
+                }
+            }
+        };
+        let mut batch = match next {
+            Ok(notification) => vec![notification],
+            Err(RecvError::Lagged(count)) => {
+                warn!("Missed {count} notifications from relays, restart to fetch all events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        while let Ok(notification) = notifications.try_recv() {
+            batch.push(notification);
+        }
+
+        let mut count = 0;
+        let mut received: HashMap<Url, Events> = HashMap::new();
+        let mut latest: HashMap<Url, Timestamp> = HashMap::new();
+        let now = Timestamp::now();
+        let mut unknown: HashMap<Url, usize> = HashMap::new();
+        let (watched, watch) = watching.lock().await.clone();
+        {
+            let mut relays = relays.lock().await;
+            let before = relays.get(&watched)
+                .filter(|_| watch != Watch::Off)
+                .map(|tasks| tasks.snapshot());
+            for notification in batch {
+                match notification {
+                    RelayPoolNotification::Event {
+                        relay_url,
+                        event,
+                        ..
+                    } => {
+                        debug!(
+                            "At {} found {} kind {} content \"{}\" tags {:?}",
+                            event.created_at, event.id, event.kind, event.content, event.tags.iter().map(|tag| tag.as_vec()).collect_vec()
+                        );
+                        if cache.is_some() {
+                            received.entry(relay_url.clone()).or_default().push((*event).clone());
+                        }
+                        // Planned time-tracking lies ahead and would hide events received later
+                        if event.created_at <= now {
+                            let latest = latest.entry(relay_url.clone()).or_insert(event.created_at);
+                            *latest = event.created_at.max(*latest);
+                        }
+                        if let Some(all) = relays.get_mut(&merged) {
+                            all.receive((*event).clone());
+                        }
+                        if !relays.contains_key(&Some(relay_url.clone())) {
+                            // The client may connect to relays on its own, such as from hints
+                            let template = relays.iter()
+                                .find(|(url, _)| url.is_some() && **url != merged)
+                                .map(|(_, tasks)| tasks)
+                                .filter(|tasks| tasks.adopts_relays());
+                            if let Some(template) = template {
+                                info!("Adding relay {relay_url} which sent events without being set up");
+                                let tasks = template.sibling(relay_url.clone());
+                                relays.insert(Some(relay_url.clone()), tasks);
+                            }
+                        }
+                        match relays.get_mut(&Some(relay_url.clone())) {
+                            Some(tasks) => tasks.receive(*event),
+                            None => {
+                                debug!("Event received from unknown relay {relay_url}: {:?}", *event);
+                                *unknown.entry(relay_url).or_default() += 1;
+                            }
+                        }
+                        count += 1;
+                    }
+                    RelayPoolNotification::Message {
+                        relay_url,
+                        message: RelayMessage::EndOfStoredEvents(subscription),
+                    } => {
+                        debug!("Received all stored events for {subscription} from {relay_url}");
+                        if let Some(tasks) = relays.get_mut(&Some(relay_url)) {
+                            tasks.set_synced(true);
+                        }
+                    }
+                    RelayPoolNotification::Shutdown => return,
+                    _ => {}
+                }
+            }
+            if let Some((before, tasks)) = before.zip(relays.get(&watched)) {
+                changes.append(&mut tasks.describe_changes(&before));
+            }
+            // Each relay reports duplicates, so the merged view would only repeat them
+            for (url, tasks) in relays.iter_mut() {
+                let duplicates = tasks.take_duplicates();
+                if duplicates > 0 && *url != merged {
+                    warn!("Skipped {duplicates} duplicate events ({})", url.as_ref().map_or("local".to_string(), |url| url.to_string()));
+                }
+            }
+            // Keep the status page current while waiting for input
+            if count > 0 {
+                if let Some((server, tasks)) = server.lock().await.as_ref().zip(relays.get(&watched)) {
+                    server.update(Snapshot::of(tasks));
+                }
+            }
+        }
+        {
+            let mut newest = newest.lock().await;
+            for (url, stamp) in latest {
+                let newest = newest.entry(url).or_insert(stamp);
+                *newest = stamp.max(*newest);
+            }
+        }
+        for (url, ignored) in unknown {
+            warn!("Ignored {ignored} events from unknown relay {url}");
+        }
+        if count > 0 {
+            if watch == Watch::Off {
+                info!("Received {count} Updates");
+            }
+            idle = false;
+        }
+        if !changes.is_empty() && last_report.elapsed() >= WATCH_INTERVAL {
+            report_changes(&mut changes, &relays, &watching).await;
+            last_report = std::time::Instant::now();
+        }
+        if let Some(cache) = &cache {
+            for (url, events) in received {
+                or_warn!(cache.append(Some(&url), &events), "Could not cache events for {}", url);
+            }
+        }
+    }
+}
+
+/// Upon SIGTERM, such as when the terminal is closed, submit pending updates and exit,
+/// since the prompt holds up the regular shutdown
+#[cfg(unix)]
+async fn terminate(relays: Arc<Mutex<Relays>>, tx: Sender<MostrMessage>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminated = match signal(SignalKind::terminate()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            warn!("Could not listen for termination: {e}");
+            return;
+        }
+    };
+    terminated.recv().await;
+    info!("Terminated, submitting pending updates...");
+    // Dropping the relays queues their pending events
+    relays.lock().await.clear();
+    let (done, finished) = oneshot::channel();
+    if tx.send(MostrMessage::Shutdown(done)).await.is_ok() {
+        let _ = timeout(Duration::from_secs(10), finished).await;
+    }
+    // Exit status as if the signal had not been handled
+    std::process::exit(128 + 15);
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let mut rl = DefaultEditor::new()?;
     rl.set_auto_add_history(true);
+    rl.bind_sequence(rustyline::KeyEvent::ctrl('C'), rustyline::EventHandler::Conditional(Box::new(InterruptHandler::default())));
 
     let mut args = args().skip(1).peekable();
     let mut builder = if args.peek().is_some_and(|arg| arg == "--debug") {
@@ -165,41 +784,151 @@ async fn main() -> Result<()> {
             |wr| builder
                 .filter(Some("rustyline"), LevelFilter::Warn)
                 .write_style(WriteStyle::Always)
-                .target(Target::Pipe(wr)))
+                .target(Target::Pipe(Box::new(HoldingWriter(wr)))))
     );
     builder.init();
 
-    let config_dir = or_warn!(BaseDirectories::new(), "Could not determine config directory")
-        .and_then(|d| or_warn!(d.create_config_directory("mostr"), "Could not create config directory"))
+    let mut use_cache = true;
+    let mut offline = false;
+    let mut scope = Scope::default();
+    let mut mine = false;
+    let mut notify = false;
+    let mut advance = false;
+    let mut hooks_enabled = true;
+    let mut quiet_desktop = false;
+    let mut json = false;
+    let mut profile: Option<String> = None;
+    let mut retries = DEFAULT_RETRIES;
+    let mut key: Option<String> = None;
+    let mut relay_urls: Vec<String> = vec![];
+    let mut save_relays = false;
+    let mut custom_dir: Option<PathBuf> = None;
+    let mut temp = false;
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--") && arg != "--watch-events") {
+        match flag.as_str() {
+            "--no-cache" => use_cache = false,
+            "--offline" => offline = true,
+            "--mine" => mine = true,
+            "--notify" => notify = true,
+            "--json" => json = true,
+            "--profile" => profile = args.next(),
+            "--key" => key = args.next(),
+            "--relay" => match args.next() {
+                Some(url) if url.contains("://") => relay_urls.push(url),
+                Some(url) => relay_urls.push("wss://".to_string() + &url),
+                None => warn!("--relay expects a url"),
+            },
+            "--save-relays" => save_relays = true,
+            "--config-dir" => custom_dir = args.next().map(PathBuf::from),
+            "--temp" => temp = true,
+            "--retries" => match args.next().and_then(|arg| arg.parse().ok()) {
+                Some(count) => retries = count,
+                None => warn!("--retries expects a number"),
+            },
+            "--since" => match args.next().and_then(|arg| parse_since(&arg)) {
+                Some(stamp) => scope.since = Some(stamp),
+                None => warn!("--since expects an age like 90d or a date"),
+            },
+            _ => warn!("Ignoring unknown flag {flag}"),
+        }
+    }
+
+    // A custom directory holds everything, while a temporary session touches no files
+    let custom_dir = custom_dir.filter(|_| !temp)
+        .and_then(|dir| or_warn!(fs::create_dir_all(&dir).map(|_| dir), "Could not create config directory"));
+    let dirs = if temp || custom_dir.is_some() {
+        None
+    } else {
+        or_warn!(BaseDirectories::new(), "Could not determine config directory")
+    };
+    let data_dir = |name: &str| match &custom_dir {
+        Some(dir) => or_warn!(fs::create_dir_all(dir.join(name)).map(|_| dir.join(name)), "Could not create {name} directory"),
+        None => dirs.as_ref()
+            .and_then(|d| or_warn!(d.create_data_directory(format!("mostr/{name}")), "Could not create {name} directory")),
+    };
+    let config_dir = custom_dir.clone()
+        .or_else(|| dirs.as_ref().and_then(|d| or_warn!(d.create_config_directory("mostr"), "Could not create config directory")))
         .unwrap_or(PathBuf::new());
-    let keysfile = config_dir.join("key");
+    let cache = if use_cache { data_dir("cache") } else { None }.map(EventCache::new);
+    let outbox_store = data_dir("outbox").map(EventCache::new);
+    let state_dir = custom_dir.clone()
+        .or_else(|| dirs.as_ref().and_then(|d| or_warn!(d.create_state_directory("mostr"), "Could not create state directory")));
+    let history_file = state_dir.as_ref().map(|dir| dir.join("history"));
+    let session_file = state_dir.as_ref().map(|dir| dir.join("session"));
+    let session = session_file.as_deref().map(Session::load).unwrap_or_default();
+    let keysfile = keys_file(&config_dir, profile.as_deref());
     let relayfile = config_dir.join("relays");
+    let config = if temp { Config::default() } else { Config::load(&config_dir.join("config")) };
+    let time_settings = config.time_settings();
+    if let Some(zone) = var("MOSTR_TZ").ok().or(time_settings.timezone) {
+        match parse_timezone(&zone) {
+            Some(offset) => set_timezone(offset),
+            None => warn!("Unknown timezone \"{zone}\", expected an offset like +02:00, UTC or local"),
+        }
+    }
+    set_week_starts_sunday(time_settings.week_starts_sunday);
+    let mut settings = config.settings();
+    for key in SETTINGS_KEYS {
+        if let Ok(value) = var(format!("MOSTR_{}", key.to_ascii_uppercase())) {
+            if let Err(e) = settings.set(key, &value) {
+                warn!("Ignoring MOSTR_{}: {e}", key.to_ascii_uppercase());
+            }
+        }
+    }
+    let settings = RefCell::new(settings);
+    let hooks = config.hooks();
+    let desktop = config.desktop();
+    let tag_hierarchy = config.tag_hierarchy();
+    let aliases = config.aliases();
+    let snippets = config.snippets();
+    let mut startup: VecDeque<String> = config.startup().into();
 
-    let keys = if let Ok(Ok(key)) = fs::read_to_string(&keysfile).map(|s| Keys::from_str(&s)) {
-        key
+    let keys = if let Some(key) = &key {
+        match parse_keys(key).or_else(|| read_keys(Path::new(key))) {
+            Some(keys) => keys,
+            None => {
+                error!("Could not read a key from {key}");
+                return Ok(());
+            }
+        }
+    } else if temp {
+        info!("Using a new key for this session only");
+        Keys::generate()
+    } else if let Some(keys) = read_keys(&keysfile) {
+        keys
+    } else if is_encrypted(&keysfile) {
+        error!("Could not decrypt {}, aborting to leave it intact", keysfile.to_string_lossy());
+        return Ok(());
     } else {
         warn!("Could not read keys from {}", keysfile.to_string_lossy());
-        let line = rl.readline("Secret key? (leave blank to generate and save a new keypair) ")?;
-        let keys = if line.is_empty() {
-            info!("Generating and persisting new key");
-            Keys::generate()
+        let line = rl.readline("Secret key? (leave blank to generate and save a new keypair, enter an npub to only watch) ")?;
+        if line.trim().starts_with("npub") {
+            let keys = Keys::from_public_key(PublicKey::parse(line.trim()).inspect_err(|_| eprintln!())?);
+            info!("Watching {} without saving the key", line.trim());
+            keys
         } else {
-            Keys::from_str(&line).inspect_err(|_| eprintln!())?
-        };
-        let mut file = match File::create_new(&keysfile) {
-            Ok(file) => file,
-            Err(e) => {
-                let line = rl.readline(&format!("Overwrite {}? (enter anything to abort) ", keysfile.to_string_lossy()))?;
-                if line.is_empty() {
-                    File::create(&keysfile)?
-                } else {
-                    eprintln!();
-                    Err(e)?
+            let keys = if line.is_empty() {
+                info!("Generating and persisting new key");
+                Keys::generate()
+            } else {
+                Keys::from_str(&line).inspect_err(|_| eprintln!())?
+            };
+            let mut file = match File::create_new(&keysfile) {
+                Ok(file) => file,
+                Err(e) => {
+                    let line = rl.readline(&format!("Overwrite {}? (enter anything to abort) ", keysfile.to_string_lossy()))?;
+                    if line.is_empty() {
+                        File::create(&keysfile)?
+                    } else {
+                        eprintln!();
+                        Err(e)?
+                    }
                 }
-            }
-        };
-        file.write_all(keys.secret_key().unwrap().to_string().as_bytes())?;
-        keys
+            };
+            let passphrase = read_new_passphrase("Passphrase to encrypt the key? (leave blank to store it unencrypted) ")?;
+            file.write_all(serialize_keys(&keys, &passphrase)?.as_bytes())?;
+            keys
+        }
     };
 
     let client = ClientBuilder::new()
@@ -209,44 +938,84 @@ async fn main() -> Result<()> {
         .signer(&keys)
         .build();
     info!("My public key: {}", keys.public_key());
+    if mine {
+        scope.authors = Some(vec![keys.public_key()]);
+    }
 
     // TODO use NewRelay message for all relays
-    match var("MOSTR_RELAY") {
-        Ok(relay) => {
-            or_warn!(client.add_relay(relay).await);
-        }
-        _ => match File::open(&relayfile).map(|f| BufReader::new(f).lines().flatten()) {
-            Ok(lines) => {
-                for line in lines {
-                    or_warn!(client.add_relay(line).await);
+    if !offline {
+        if !relay_urls.is_empty() {
+            for url in &relay_urls {
+                or_warn!(client.add_relay(url.as_str()).await);
+            }
+            if save_relays && temp {
+                warn!("Not saving relays in a temporary session");
+            } else if save_relays {
+                let saved = fs::read_to_string(&relayfile).unwrap_or_default();
+                let added = relay_urls.iter()
+                    .filter(|url| !saved.lines().any(|line| line.trim() == url.as_str()))
+                    .collect_vec();
+                if !added.is_empty() {
+                    let lines = saved.lines().chain(added.iter().map(|url| url.as_str())).join("\n");
+                    or_warn!(fs::write(&relayfile, lines), "Could not save relays");
                 }
             }
-            Err(e) => {
-                warn!("Could not read relays file: {}", e);
-                if let Ok(line) = rl.readline("Relay? ") {
-                    let url = if line.contains("://") {
-                        line
-                    } else {
-                        "wss://".to_string() + &line
-                    };
-                    or_warn!(client.add_relay(url.clone()).await).map(|bool| {
-                        if bool {
-                            or_warn!(fs::write(&relayfile, url));
+        } else if temp {
+            info!("No relays given with --relay, only the local relay is available");
+        } else {
+            match var("MOSTR_RELAY") {
+                Ok(relay) => {
+                    or_warn!(client.add_relay(relay).await);
+                }
+                _ => match File::open(&relayfile).map(|f| BufReader::new(f).lines().flatten()) {
+                    Ok(lines) => {
+                        for line in lines {
+                            or_warn!(client.add_relay(line).await);
                         }
-                    });
-                };
+                    }
+                    Err(e) => {
+                        warn!("Could not read relays file: {}", e);
+                        if let Ok(line) = rl.readline("Relay? ") {
+                            let url = if line.contains("://") {
+                                line
+                            } else {
+                                "wss://".to_string() + &line
+                            };
+                            or_warn!(client.add_relay(url.clone()).await).map(|bool| {
+                                if bool {
+                                    or_warn!(fs::write(&relayfile, url));
+                                }
+                            });
+                        };
+                    }
+                },
             }
-        },
+        }
     }
 
-    let mut notifications = client.notifications();
-    client.connect().await;
-
-    let sub1 = client.subscribe(vec![Filter::new().kinds(BASIC_KINDS)], None).await;
-    info!("Subscribed to tasks with {:?}", sub1);
+    // Load cached events before connecting to only fetch newer events
+    let mut cached: HashMap<Url, Events> = HashMap::new();
+    if let Some(cache) = &cache {
+        for url in client.relays().await.into_keys() {
+            let events = cache.load(Some(&url));
+            cached.insert(url, events);
+        }
+    }
 
-    let sub2 = client.subscribe(vec![Filter::new().kinds(PROP_KINDS)], None).await;
-    info!("Subscribed to updates with {:?}", sub2);
+    let mut notifications = client.notifications();
+    // Connect in the background so one slow relay does not hold up the others or the prompt
+    let (connected_tx, mut connected) = mpsc::unbounded_channel::<Url>();
+    let since: HashMap<Url, Option<Timestamp>> = client.relays().await.into_keys()
+        .map(|url| {
+            let since = cached.get(&url).and_then(|events| newest_timestamp(events));
+            (url, since)
+        })
+        .collect();
+    // Kept up to date by the ingestion so resubscribing only fetches newer events
+    let newest = Arc::new(Mutex::new(since.iter()
+        .filter_map(|(url, since)| since.map(|since| (url.clone(), since)))
+        .collect::<HashMap<_, _>>()));
+    tokio::spawn(connect_all(client.clone(), since, scope.clone(), keys.public_key(), connected_tx));
 
     if args.peek().is_some_and(|arg| arg == "--watch-events") {
         loop {
@@ -264,44 +1033,173 @@ async fn main() -> Result<()> {
         }
     }
 
-    let metadata = var("USER").ok().map(
-        |user| Metadata::new().name(user));
+    // Without secret key the profile belongs to someone else
+    let metadata = var("USER").ok()
+        .filter(|_| keys.secret_key().is_ok())
+        .map(|user| Metadata::new().name(user));
     let moved_metadata = metadata.clone();
 
+    let keys = RefCell::new(keys);
     let (tx, mut rx) = mpsc::channel::<MostrMessage>(64);
-    let tasks_for_url = |url: Option<Url>| TasksRelay::from(url, &tx, &keys, metadata.clone());
+    let tasks_for_url = |url: Option<Url>| {
+        let defaults = url.as_ref().map(|url| config.relay_defaults(url)).unwrap_or_default();
+        let mut tasks = TasksRelay::from(url, &tx, &keys.borrow(), metadata.clone(), cache.clone(), defaults, &settings.borrow());
+        tasks.hooks = hooks.clone();
+        tasks.desktop = desktop;
+        tasks.tag_hierarchy = tag_hierarchy.clone();
+        tasks
+    };
     let mut relays: HashMap<Option<Url>, TasksRelay> =
         client.relays().await.into_keys().map(|url| (Some(url.clone()), tasks_for_url(Some(url)))).collect();
+    for (url, events) in cached {
+        if let Some(tasks) = relays.get_mut(&Some(url)) {
+            for event in events {
+                tasks.add(event);
+            }
+            tasks.process_overflow();
+            tasks.prune_history();
+            let duplicates = tasks.take_duplicates();
+            if duplicates > 0 {
+                debug!("Cache held {duplicates} duplicate events");
+            }
+        }
+    }
 
+    let moved_scope = scope.clone();
+    let moved_newest = newest.clone();
+    let mut own = keys.borrow().public_key();
+    let undelivered = Arc::new(AtomicUsize::new(0));
+    let inactivity_delay = Arc::new(AtomicU64::new(settings.borrow().inactivity_delay));
+    let moved_inactivity_delay = inactivity_delay.clone();
+    let mut outbox = Outbox::new(outbox_store, retries, undelivered.clone());
+    for url in client.relays().await.into_keys() {
+        outbox.load(&url);
+    }
     let sender = tokio::spawn(async move {
         let mut queue: Option<(Url, Vec<Event>)> = None;
+        let mut scope = moved_scope;
+        let mut shutdown = None;
 
         if let Some(meta) = moved_metadata.as_ref() {
             or_warn!(client.set_metadata(meta).await, "Unable to set metadata");
         }
 
         'repl: loop {
-            let result_received = timeout(Duration::from_secs(INACTVITY_DELAY), rx.recv()).await;
+            // Undelivered events go out before any new traffic
+            outbox.retry(&client).await;
+            let inactivity = Duration::from_secs(moved_inactivity_delay.load(Ordering::Relaxed));
+            let wait = outbox.next_retry().map_or(inactivity, |d| d.min(inactivity));
+            let result_received = timeout(wait, rx.recv()).await;
             match result_received {
                 Ok(Some(MostrMessage::NewRelay(url))) => {
                     if client.add_relay(&url).await.unwrap() {
                         match client.connect_relay(&url).await {
-                            Ok(()) => info!("Connected to {url}"),
+                            Ok(()) => {
+                                info!("Connected to {url}");
+                                outbox.load(&url);
+                                outbox.flush(&client, &url).await;
+                                let since = moved_newest.lock().await.get(&url).copied();
+                                subscribe_to(&client, &url, &scope, since, &own).await;
+                            }
                             Err(e) => warn!("Unable to connect to relay {url}: {e}")
                         }
                     } else {
                         warn!("Relay {url} already added");
                     }
                 }
+                Ok(Some(MostrMessage::Rescope(new))) => {
+                    // Events outside of the previous scope were never fetched
+                    let widened = new.widens(&scope);
+                    scope = new;
+                    client.unsubscribe_all().await;
+                    for url in client.relays().await.into_keys() {
+                        let since = moved_newest.lock().await.get(&url).copied().filter(|_| !widened);
+                        subscribe_to(&client, &url, &scope, since, &own).await;
+                    }
+                }
+                Ok(Some(MostrMessage::RemoveRelay(url))) => {
+                    if let Some((queue_url, queue_events)) = queue.take_if(|(u, _)| u == &url) {
+                        info!("Sending {} events to {queue_url} before removing it", queue_events.len());
+                        outbox.send(&client, &queue_url, queue_events).await;
+                    }
+                    outbox.forget(&url);
+                    // Its tasks are dropped, so adding it again fetches everything
+                    moved_newest.lock().await.remove(&url);
+                    match client.remove_relay(&url).await {
+                        Ok(()) => info!("Removed relay {url}"),
+                        Err(e) => warn!("Unable to remove relay {url}: {e}"),
+                    }
+                }
+                Ok(Some(MostrMessage::PauseRelay(url))) => {
+                    match client.relay(&url).await {
+                        Ok(relay) => match relay.unsubscribe_all(RelaySendOptions::new()).await {
+                            Ok(()) => info!("Paused subscriptions on {url}"),
+                            Err(e) => warn!("Unable to pause {url}: {e}"),
+                        },
+                        Err(e) => warn!("Unable to pause {url}: {e}"),
+                    }
+                }
+                Ok(Some(MostrMessage::ResumeRelay(url))) => {
+                    let since = moved_newest.lock().await.get(&url).copied();
+                    subscribe_to(&client, &url, &scope, since, &own).await;
+                }
+                Ok(Some(MostrMessage::Notify(recipient, message))) => {
+                    match client.send_private_msg(recipient, message, None).await {
+                        Ok(output) => debug!("Notified {recipient} in {}", output.val),
+                        Err(e) => warn!("Unable to notify {recipient}: {e}"),
+                    }
+                }
+                Ok(Some(MostrMessage::FetchMetadata(url, keys))) => {
+                    let filter = Filter::new().kind(Kind::Metadata).authors(keys);
+                    or_warn!(client.subscribe_to(vec![url], vec![filter], Some(SubscribeAutoCloseOptions::default())).await,
+                        "Unable to request metadata");
+                }
+                Ok(Some(MostrMessage::FetchEvents(url, ids))) => {
+                    let filter = Filter::new().ids(ids);
+                    or_warn!(client.subscribe_to(vec![url], vec![filter], Some(SubscribeAutoCloseOptions::default())).await,
+                        "Unable to request events");
+                }
+                Ok(Some(MostrMessage::Whois(url, key, response))) => {
+                    let filter = Filter::new().kind(Kind::Metadata).author(key);
+                    let events = or_warn!(client.get_events_from(vec![url], vec![filter], Some(Duration::from_secs(5))).await,
+                        "Unable to fetch metadata").unwrap_or_default();
+                    if response.send(events).is_err() {
+                        debug!("Metadata was not awaited");
+                    }
+                }
+                Ok(Some(MostrMessage::SwitchIdentity(keys))) => {
+                    own = keys.public_key();
+                    let watch_only = keys.secret_key().is_err();
+                    client.set_signer(Some(keys.into())).await;
+                    if let Some(meta) = moved_metadata.as_ref().filter(|_| !watch_only) {
+                        or_warn!(client.set_metadata(meta).await, "Unable to set metadata");
+                    }
+                    let filter = Filter::new()
+                        .kinds([Kind::Metadata, Kind::ContactList, Kind::Bookmarks, Kind::BookmarkSet])
+                        .author(own);
+                    or_warn!(client.subscribe(vec![filter], None).await, "Unable to fetch lists of {own}");
+                }
+                Ok(Some(MostrMessage::Report(response))) => {
+                    let mut report = RelayReport::new();
+                    for (url, relay) in client.relays().await {
+                        let queued = queue.as_ref().filter(|(u, _)| u == &url).map_or(0, |(_, events)| events.len());
+                        let pending = outbox.pending(&url);
+                        report.insert(url, (relay.status().await.to_string(), queued, pending));
+                    }
+                    if response.send(report).is_err() {
+                        debug!("Relay report was not awaited");
+                    }
+                }
                 Ok(Some(MostrMessage::AddTasks(url, mut events))) => {
                     trace!("Queueing {:?}", &events);
+                    outbox.persist(&url, &events);
                     if let Some((queue_url, mut queue_events)) = queue {
                         if queue_url == url {
                             queue_events.append(&mut events);
                             queue = Some((queue_url, queue_events));
                         } else {
-                            info!("Sending {} events to {url} due to relay change", queue_events.len());
-                            client.batch_event_to(vec![queue_url], queue_events, RelaySendOptions::new()).await;
+                            info!("Sending {} events to {queue_url} due to relay change", queue_events.len());
+                            outbox.send(&client, &queue_url, queue_events).await;
                             queue = None;
                         }
                     }
@@ -313,9 +1211,13 @@ async fn main() -> Result<()> {
                 Ok(Some(MostrMessage::Flush)) | Err(Elapsed { .. }) => if let Some((url, events)) = queue {
                     info!("Sending {} events to {url} due to {}", events.len(),
                         result_received.map_or("inactivity", |_| "flush message"));
-                    client.batch_event_to(vec![url], events, RelaySendOptions::new()).await;
+                    outbox.send(&client, &url, events).await;
                     queue = None;
                 }
+                Ok(Some(MostrMessage::Shutdown(done))) => {
+                    shutdown = Some(done);
+                    break 'repl;
+                }
                 Ok(None) => {
                     debug!("Finalizing nostr communication thread because communication channel was closed");
                     break 'repl;
@@ -324,18 +1226,51 @@ async fn main() -> Result<()> {
         }
         if let Some((url, events)) = queue {
             info!("Sending {} events to {url} before exiting", events.len());
-            client.batch_event_to(vec![url], events, RelaySendOptions::new()).await;
+            if !outbox.send(&client, &url, events).await {
+                warn!("Undelivered events for {url} are kept in the outbox for the next session");
+            }
         }
         info!("Shutting down nostr communication thread");
+        if let Some(done) = shutdown {
+            let _ = done.send(());
+        }
     });
 
-    if relays.is_empty() {
-        relays.insert(None, tasks_for_url(None));
-    }
-    let mut selected_relay: Option<Url> = relays.keys()
+    // Start on a relay with cached tasks, otherwise locally until a relay connects
+    let cached_relay = relays.iter()
+        .filter(|(_, tasks)| tasks.len() > 0)
+        .map(|(url, _)| url.clone())
         .find_or_first(|url| url.as_ref().is_some_and(|u| u.scheme() == "wss"))
-        .unwrap().clone();
+        .flatten();
+    let mut awaiting_relay = !offline && !relays.is_empty() && cached_relay.is_none();
+
+    let local_events = cache.as_ref().map(|c| c.load(None)).unwrap_or_default();
+    if relays.is_empty() || offline || awaiting_relay || !local_events.is_empty() {
+        let mut local = tasks_for_url(None);
+        for event in local_events {
+            local.add(event);
+        }
+        local.process_overflow();
+        if local.len() > 0 && !offline {
+            info!("Found {} local tasks, use %sync to publish them to the selected relay", local.len());
+        }
+        relays.insert(None, local);
+    }
+    let mut selected_relay: Option<Url> = if offline { None } else { cached_relay };
+    // Pick up on the relay of the last session if it is still around
+    let previous = session.selected.clone();
+    if !offline && session.relays.contains_key(&previous) && relays.contains_key(&previous) {
+        awaiting_relay = awaiting_relay && previous.is_none();
+        selected_relay = previous;
+    }
 
+    relays.get_mut(&selected_relay).unwrap().apply_defaults();
+    // The last session takes precedence over the configured defaults
+    for (url, tasks) in relays.iter_mut() {
+        if let Some(previous) = session.relays.get(url) {
+            tasks.restore_session(previous);
+        }
+    }
     {
         let tasks = relays.get_mut(&selected_relay).unwrap();
         for argument in args {
@@ -343,43 +1278,99 @@ async fn main() -> Result<()> {
         }
     }
 
+    // Leave out the key prompts
+    or_warn!(rl.clear_history());
+    if let Some(path) = history_file.as_ref().filter(|path| path.exists()) {
+        or_warn!(rl.load_history(path), "Could not load command history from {}", path.to_string_lossy());
+    }
+
+    let relays_lock = Arc::new(Mutex::new(relays));
+    let mut watch = Watch::Off;
+    let server: Arc<Mutex<Option<Server>>> = Arc::new(Mutex::new(None));
+    let watching = Arc::new(Mutex::new((selected_relay.clone(), watch)));
+    let ingest = tokio::spawn(ingest(notifications, relays_lock.clone(), cache.clone(), watching.clone(), newest, server.clone()));
+    let plans = tokio::spawn(announce_plans(relays_lock.clone(), watching.clone()));
+    #[cfg(unix)]
+    let terminated = tokio::spawn(terminate(relays_lock.clone(), tx.clone()));
+
     'repl: loop {
+        if json {
+            let relays = relays_lock.lock().await;
+            println!("{}", relays[&selected_relay].to_json(false));
+            break 'repl;
+        }
         println!();
-        let tasks = relays.get(&selected_relay).unwrap();
-        let prompt = format!(
-            "{} {}{}) ",
-            selected_relay.as_ref().map_or(LOCAL_RELAY_NAME.to_string(), |url| url.to_string()).dimmed(),
-            tasks.get_task_path(tasks.get_position()).bold(),
-            tasks.get_prompt_suffix().italic(),
-        );
-        match rl.readline(&prompt) {
+        while let Ok(url) = connected.try_recv() {
+            if awaiting_relay && selected_relay.is_none() {
+                info!("Switching to {url} now that it is connected");
+                let mut relays = relays_lock.lock().await;
+                if let Some(tasks) = relays.get_mut(&Some(url.clone())) {
+                    tasks.apply_defaults();
+                    selected_relay = Some(url);
+                }
+            }
+            awaiting_relay = false;
+        }
+        *watching.lock().await = (selected_relay.clone(), watch);
+        let prompt = {
+            let relays = relays_lock.lock().await;
+            let tasks = relays.get(&selected_relay).unwrap();
+            if let Some(server) = server.lock().await.as_ref() {
+                server.update(Snapshot::of(tasks));
+            }
+            let prompt = format!(
+                "{} {}{}) ",
+                format!("{}{}{}{}",
+                        if tasks.is_watch_only() { "🔒 " } else { "" },
+                        relay_name(&selected_relay, &relays),
+                        scope,
+                        profile.as_ref().map(|p| format!(" as {p}")).unwrap_or_default()).dimmed(),
+                truncate_width_start(&tasks.get_task_path(tasks.get_position()), PROMPT_PATH_WIDTH).bold(),
+                tasks.get_prompt_suffix().italic(),
+            );
+            let prompt = match tasks.sticky_time {
+                None => prompt,
+                Some(stamp) => format!("{} {prompt}", format!("⏱ {}", format_timestamp_relative(&stamp)).yellow()),
+            };
+            match tasks.planned_blocks().first() {
+                None => prompt,
+                Some((start, _, id)) => format!("{} {prompt}",
+                                                format!("⏲ {} {}", format_timestamp_relative(start), truncate_width(&tasks.get_task_title(id), 16)).cyan()),
+            }
+        };
+        let prompt = match undelivered.load(Ordering::Relaxed) {
+            0 => prompt,
+            count => format!("{} {prompt}", format!("↑{count}").yellow()),
+        };
+        let line = match startup.pop_front() {
+            Some(command) => {
+                println!("{prompt}{command}");
+                Ok(command)
+            }
+            None => rl.readline(&prompt),
+        };
+        match line {
             Ok(input) => {
-                let mut count = 0;
-                while let Ok(notification) = notifications.try_recv() {
-                    if let RelayPoolNotification::Event {
-                        relay_url,
-                        event,
-                        ..
-                    } = notification
-                    {
-                        debug!(
-                            "At {} found {} kind {} content \"{}\" tags {:?}",
-                            event.created_at, event.id, event.kind, event.content, event.tags.iter().map(|tag| tag.as_vec()).collect_vec()
-                        );
-                        match relays.get_mut(&Some(relay_url.clone())) {
-                            Some(tasks) => tasks.add(*event),
-                            None => warn!("Event received from unknown relay {relay_url}: {:?}", *event)
-                        }
-                        count += 1;
+                let input = match aliases.expand(&input) {
+                    Ok(input) => input,
+                    Err(e) => {
+                        warn!("{e}");
+                        continue 'repl;
                     }
-                }
-                if count > 0 {
-                    info!("Received {count} Updates");
-                } else {
-                    relays.values_mut().for_each(|tasks| tasks.process_overflow());
-                }
-
-                let tasks = relays.get_mut(&selected_relay).unwrap();
+                };
+                // Hold the relays for the whole command so no update is applied midway,
+                // only letting go while waiting for the user
+                let mut relays = relays_lock.lock().await;
+                // Also covers updates received for the other relays
+                relays.values_mut().for_each(|tasks| tasks.quiet = quiet_desktop);
+                let mut tasks = relays.get_mut(&selected_relay).unwrap();
+                let (input, quiet) = match input.strip_suffix(" --quiet") {
+                    Some(stripped) => (stripped.to_string(), true),
+                    None => (input, false),
+                };
+                tasks.notify = notify && !quiet;
+                tasks.advance = advance;
+                tasks.hooks_enabled = hooks_enabled;
 
                 let operator = input.chars().next();
                 let mut command = input;
@@ -390,11 +1381,28 @@ async fn main() -> Result<()> {
                         or_warn!(tasks.print_tasks());
                         continue 'repl;
                     }
-                    Some('@') => {}
+                    Some('@') => if let Some(time) = command.strip_prefix("@@") {
+                        let time = time.trim();
+                        if !time.is_empty() {
+                            match parse_hour(time, 20).or_else(|| parse_date(time).map(|utc| to_local(&utc))) {
+                                Some(time) => {
+                                    tasks.sticky_time = Some(time.to_timestamp());
+                                    info!("Backfilling from {} until cleared with @@", format_datetime_relative(time));
+                                }
+                                None => warn!("Could not parse a time from \"{time}\""),
+                            }
+                            continue 'repl;
+                        } else if tasks.sticky_time.take().is_some() {
+                            info!("Back to the current time");
+                            continue 'repl;
+                        }
+                    }
+                    // Task names taken literally
+                    Some('\\') | Some('"') => {}
                     Some(_) => {
                         if let Some((left, arg)) = command.split_once("@") {
                             if let Some(time) = parse_hour(arg, 20)
-                                .or_else(|| parse_date(arg).map(|utc| utc.with_timezone(&Local))) {
+                                .or_else(|| parse_date(arg).map(|utc| to_local(&utc))) {
                                 command = left.to_string();
                                 tasks.custom_time = Some(time.to_timestamp());
                             }
@@ -439,23 +1447,49 @@ async fn main() -> Result<()> {
                         match arg {
                             None => {
                                 tasks.get_current_task().map_or_else(
-                                    || info!("With a task selected, use ,NOTE to attach NOTE and , to list all its notes"),
-                                    |task| println!("{}", task.description_events().map(|e| format!("{} {}", format_timestamp_local(&e.created_at), e.content)).join("\n")),
+                                    || info!("With a task selected, use ,NOTE to attach NOTE, ,, to write it in your editor, , INDEX REPLY to reply to a note, ,edit INDEX or ,rm INDEX to change your notes and , to list all its notes"),
+                                    |task| page(&tasks.format_notes(task).join("\n"), settings.borrow().note_lines),
                                 );
                                 continue 'repl;
                             }
+                            Some(",") => compose_note(tasks),
+                            Some(arg) if arg.split_once(' ').is_some_and(|(action, index)|
+                                matches!(action, "edit" | "rm") && index.trim().parse::<usize>().is_ok()) => {
+                                let (action, index) = arg.split_once(' ').unwrap();
+                                let index = index.trim().parse::<usize>().unwrap();
+                                if action == "rm" {
+                                    tasks.remove_note(index);
+                                    continue 'repl;
+                                }
+                                let Some(note) = tasks.own_note(index, "edit") else { continue 'repl };
+                                match edit_in_editor(&note.content).map(|text| text.trim().to_string()) {
+                                    None => {}
+                                    Some(text) if text.is_empty() => warn!("Empty note, use ,rm {index} to remove it"),
+                                    Some(text) if text == note.content.trim() => info!("Note {index} unchanged"),
+                                    Some(text) => { tasks.edit_note(index, &text); }
+                                }
+                            }
                             Some(arg) => {
-                                if arg.len() < CHARACTER_THRESHOLD {
-                                    warn!("Note needs at least {CHARACTER_THRESHOLD} characters!");
+                                // A space before the index distinguishes replies from notes starting with a number
+                                let reply = command[1..].strip_prefix(' ')
+                                    .and_then(|rest| rest.trim_start().split_once(' '))
+                                    .and_then(|(index, text)| Some((index.parse::<usize>().ok()?, text.trim())));
+                                let note = reply.map_or(arg, |(_, text)| text);
+                                let min_length = settings.borrow().min_length;
+                                if note.len() < min_length {
+                                    warn!("Note needs at least {min_length} characters!");
                                     continue 'repl;
                                 }
-                                tasks.make_note(arg)
+                                match reply {
+                                    Some((index, text)) => { tasks.reply_to_note(index, text); }
+                                    None => tasks.make_note(arg),
+                                }
                             }
                         }
 
                     Some('>') => {
                         tasks.update_state(arg_default, State::Done);
-                        if tasks.custom_time.is_none() { tasks.move_up(); }
+                        if tasks.custom_time.is_none() { tasks.move_on(); }
                     }
 
                     Some('<') => {
@@ -488,21 +1522,67 @@ async fn main() -> Result<()> {
                                 tasks.set_filter_from(today)
                             }
                             Some(arg) => {
-                                if arg == "@" {
+                                if arg == "+" {
+                                    if tasks.toggle_follows_only() {
+                                        info!("Showing only tasks by you and people you follow");
+                                    } else {
+                                        info!("Showing tasks by everyone");
+                                    }
+                                    true
+                                } else if arg == "@" {
                                     info!("Filtering for own tasks");
-                                    tasks.set_filter_author(keys.public_key())
-                                } else if let Ok(key) = PublicKey::from_str(arg) {
-                                    let author = tasks.get_author(&key);
-                                    info!("Filtering for tasks by {author}");
-                                    tasks.set_filter_author(key)
+                                    tasks.add_author_filter(keys.borrow().public_key());
+                                    true
+                                } else if arg == "." {
+                                    tasks.clear_author_filter();
+                                    true
+                                } else if arg == "me" {
+                                    info!("Filtering for tasks assigned to you");
+                                    tasks.set_filter_assignee(keys.borrow().public_key())
+                                } else if let Some(name) = arg.strip_prefix('=') {
+                                    match tasks.find_user(name) {
+                                        Some(key) => {
+                                            info!("Filtering for tasks assigned to {}", tasks.get_author(&key, None));
+                                            tasks.set_filter_assignee(key)
+                                        }
+                                        None => {
+                                            warn!("Unknown user \"{name}\"");
+                                            false
+                                        }
+                                    }
+                                } else if arg.contains("..") || arg.ends_with(" week") || arg.ends_with(" month") {
+                                    match parse_date_range(arg) {
+                                        Some((start, end)) => {
+                                            info!("Filtering for tasks updated {}{}",
+                                                start.map_or(String::new(), |t| format!("from {} ", format_timestamp_relative(&t))),
+                                                end.map_or(String::new(), |t| format!("until {}", format_timestamp_relative(&t))));
+                                            tasks.set_filter_range(start, end)
+                                        }
+                                        None => {
+                                            warn!("Could not parse a time range from \"{arg}\"");
+                                            false
+                                        }
+                                    }
+                                } else if let Some(time) = parse_hour(arg, 1)
+                                    .or_else(|| parse_date(arg).map(|utc| to_local(&utc))) {
+                                    info!("Filtering for tasks from {}", format_datetime_relative(time));
+                                    tasks.set_filter_from(time.to_timestamp())
                                 } else {
-                                    parse_hour(arg, 1)
-                                        .or_else(|| parse_date(arg).map(|utc| utc.with_timezone(&Local)))
-                                        .map(|time| {
-                                            info!("Filtering for tasks from {}", format_datetime_relative(time));
-                                            tasks.set_filter_from(time.to_timestamp())
-                                        })
-                                        .unwrap_or(false)
+                                    match arg.split_whitespace()
+                                        .map(|name| tasks.find_user(name).ok_or(name))
+                                        .collect::<Result<Vec<_>, _>>() {
+                                        Ok(authors) => {
+                                            for key in authors {
+                                                info!("Filtering for tasks by {}", tasks.get_author(&key, None));
+                                                tasks.add_author_filter(key);
+                                            }
+                                            true
+                                        }
+                                        Err(name) => {
+                                            warn!("Unknown user or time \"{name}\"");
+                                            false
+                                        }
+                                    }
                                 }
                             }
                         };
@@ -525,6 +1605,7 @@ async fn main() -> Result<()> {
                                         None => {}
                                     }
                             },
+                            Some("-") => tasks.toggle_bookmarks(),
                             Some(arg) => info!("Setting priority not yet implemented"),
                         }
                     }
@@ -541,6 +1622,23 @@ async fn main() -> Result<()> {
                                 }
                             },
                             Some(arg) => 'arm: {
+                                if let Some(pos) = tasks.get_position() {
+                                    let index = tasks.step_index(&pos);
+                                    let target = match arg {
+                                        "^" => index.map(|i| i.saturating_sub(1)),
+                                        "v" => index.map(|i| i + 1),
+                                        _ => arg.parse::<usize>().ok().filter(|_| index.is_some()).map(|n| n.saturating_sub(1)),
+                                    };
+                                    if let Some(target) = target {
+                                        if let Some(index) = tasks.move_step(pos, target) {
+                                            info!("Moved \"{}\" to step {}", tasks.get_task_title(&pos), index + 1);
+                                        }
+                                        break 'arm;
+                                    } else if arg == "^" || arg == "v" {
+                                        warn!("Only steps of a procedure can be moved");
+                                        break 'arm;
+                                    }
+                                }
                                 if !arg.starts_with('|') {
                                     if let Some(pos) = tasks.get_position() {
                                         tasks.move_up();
@@ -560,6 +1658,11 @@ async fn main() -> Result<()> {
                         match arg {
                             None => tasks.set_state_filter(StateFilter::Default),
                             Some("?") => tasks.set_state_filter(StateFilter::All),
+                            Some("+closed") => tasks.toggle_closed(),
+                            Some("review") => tasks.set_state_filter(StateFilter::Review(keys.borrow().public_key())),
+                            Some("new") => if tasks.set_view_updated() {
+                                info!("Showing tasks others changed since the last session");
+                            },
                             Some(arg) => tasks.set_state_filter(StateFilter::State(arg.to_string())),
                         }
                     }
@@ -577,10 +1680,10 @@ async fn main() -> Result<()> {
                                             tasks.set_state_for(id, right, state);
                                             break 'block;
                                         }
-                                        if let Some(time) = parse_hour(left, 20)
-                                            .map(|dt| dt.to_utc())
-                                            .or_else(|| parse_date(left)) {
-                                            let stamp = time.to_timestamp();
+                                        if let Some(stamp) = parse_hour(left, 20)
+                                            .map(|dt| dt.to_timestamp())
+                                            .or_else(|| parse_duration(left).map(|secs| Timestamp::now() + secs))
+                                            .or_else(|| parse_date(left).map(|time| time.to_timestamp())) {
                                             let state = tasks.get_by_id(&id).and_then(Task::state);
                                             tasks.set_state_for(id, right, State::Pending);
                                             tasks.custom_time = Some(stamp);
@@ -598,15 +1701,23 @@ async fn main() -> Result<()> {
                         }
 
                     Some('#') =>
-                        tasks.set_tags(arg_default.split_whitespace().map(|s| Hashtag(s.to_string()).into())),
+                        if let Some(add) = arg_default.strip_prefix('+') {
+                            tasks.add_creation_tags(add.split_whitespace().map(|s| s.to_string()));
+                        } else if let Some(remove) = arg_default.strip_prefix('-') {
+                            tasks.remove_creation_tags(&remove.split_whitespace().collect_vec());
+                        } else if arg.is_none() {
+                            tasks.clear_tag_filters();
+                        } else {
+                            tasks.set_tags(arg_default.split_whitespace().map(|s| Hashtag(s.to_string()).into()))
+                        },
 
                     Some('+') =>
                         match arg {
                             Some(arg) => tasks.add_tag(arg.to_string()),
                             None => {
-                                println!("Hashtags of all known tasks:\n{}", tasks.all_hashtags().join(" ").italic());
+                                println!("Hashtags of all known tasks:\n{}", tasks.grouped_hashtags().join(" ").italic());
                                 if tasks.has_tag_filter() {
-                                    println!("Use # to remove tag filters and . to remove all filters.")
+                                    println!("Use -TAG to drop a single tag filter, # to remove all tag filters and . to remove all filters.")
                                 }
                                 continue 'repl;
                             }
@@ -681,7 +1792,16 @@ async fn main() -> Result<()> {
                             }
                             tasks.set_depth(depth);
                         } else {
-                            tasks.filter_or_create(pos.cloned().as_ref(), &remaining).map(|id| tasks.move_to(Some(id)));
+                            let pos = pos.cloned();
+                            let confirmed = tasks.needs_confirmation(pos.as_ref(), &remaining) && {
+                                drop(relays);
+                                let confirmed = confirm(&mut rl, &format!("Create new task \"{remaining}\"?"));
+                                relays = relays_lock.lock().await;
+                                tasks = relays.get_mut(&selected_relay).unwrap();
+                                confirmed
+                            };
+                            tasks.filter_or_create(pos.as_ref(), &remaining, |_| confirmed)
+                                .map(|id| tasks.move_to(Some(id)));
                         }
                     }
 
@@ -719,11 +1839,842 @@ async fn main() -> Result<()> {
                         }
                     }
 
+                    Some('%') => {
+                        let (name, remaining) = arg_default.split_once(' ').unwrap_or((arg_default, ""));
+                        match name {
+                            "cache" => match remaining.trim() {
+                                "clear" => match &cache {
+                                    Some(cache) => {
+                                        or_warn!(cache.clear(selected_relay.as_ref()), "Could not clear cache");
+                                    }
+                                    None => warn!("Event cache is disabled"),
+                                },
+                                _ => info!("Usage: %cache clear"),
+                            },
+                            "scope" => {
+                                let mut words = remaining.split_whitespace();
+                                match words.next() {
+                                    None => {
+                                        info!("Fetching{}", some_non_empty(&scope.to_string()).unwrap_or(" everything".to_string()));
+                                        info!("Usage: %scope all|since AGE|mine [PUBKEY...]");
+                                        continue 'repl;
+                                    }
+                                    Some("all") => scope = Scope::default(),
+                                    Some("since") => match words.next().and_then(parse_since) {
+                                        Some(stamp) => scope.since = Some(stamp),
+                                        None => {
+                                            warn!("Expected an age like 90d or a date");
+                                            continue 'repl;
+                                        }
+                                    },
+                                    Some("mine") => {
+                                        scope.authors = Some(once(keys.borrow().public_key())
+                                            .chain(words.filter_map(|key| or_warn!(PublicKey::parse(key))))
+                                            .collect());
+                                    }
+                                    Some(other) => {
+                                        warn!("Unknown scope \"{other}\"");
+                                        continue 'repl;
+                                    }
+                                }
+                                match tx.try_send(MostrMessage::Rescope(scope.clone())) {
+                                    Ok(_) => {
+                                        info!("Resubscribing{}", scope);
+                                        relays.iter_mut()
+                                            .filter(|(url, _)| url.is_some())
+                                            .for_each(|(_, tasks)| tasks.set_synced(false));
+                                    }
+                                    Err(e) => error!("Nostr communication thread failure, cannot change scope: {e}"),
+                                }
+                            }
+                            "relay" => {
+                                let (action, prefix) = remaining.trim().split_once(' ').unwrap_or((remaining.trim(), ""));
+                                let Some(url) = find_relay(&relays, prefix.trim()).filter(|_| !prefix.trim().is_empty()) else {
+                                    warn!("No relay matching \"{}\"", prefix.trim());
+                                    info!("Usage: %relay rm|pause|resume URL");
+                                    continue 'repl;
+                                };
+                                let message = match action {
+                                    "rm" | "remove" => {
+                                        // Dropping flushes pending events before the relay is removed
+                                        relays.remove(&Some(url.clone()));
+                                        if let Some(content) = fs::read_to_string(&relayfile).ok().filter(|_| !temp) {
+                                            let lines = content.lines()
+                                                .filter(|line| Url::parse(line.trim()).ok().as_ref() != Some(&url))
+                                                .join("\n");
+                                            or_warn!(fs::write(&relayfile, lines), "Could not update relays file");
+                                        }
+                                        if relays.get(&merged_key()).is_some_and(|all| all.url() == Some(&url)) {
+                                            relays.remove(&merged_key());
+                                            info!("Stopped merging relays since changes were sent to {url}");
+                                        }
+                                        if !relays.contains_key(&selected_relay) {
+                                            if relays.is_empty() {
+                                                relays.insert(None, tasks_for_url(None));
+                                            }
+                                            selected_relay = relays.keys()
+                                                .find_or_first(|url| url.as_ref().is_some_and(|u| u.scheme() == "wss"))
+                                                .unwrap().clone();
+                                        }
+                                        MostrMessage::RemoveRelay(url)
+                                    }
+                                    "pause" => MostrMessage::PauseRelay(url),
+                                    "resume" => {
+                                        if let Some(tasks) = relays.get_mut(&Some(url.clone())) {
+                                            tasks.set_synced(false);
+                                        }
+                                        MostrMessage::ResumeRelay(url)
+                                    }
+                                    _ => {
+                                        info!("Usage: %relay rm|pause|resume URL");
+                                        continue 'repl;
+                                    }
+                                };
+                                or_warn!(tx.try_send(message), "Nostr communication thread failure");
+                            }
+                            "json" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                println!("{}", tasks.to_json(remaining.trim() == "all"));
+                            }
+                            "export" | "import" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let mut words = remaining.split_whitespace();
+                                match (name, words.next(), words.next()) {
+                                    ("export", Some("events"), Some(path)) => {
+                                        match File::create(path).and_then(|mut file| tasks.export_events(&mut file)) {
+                                            Ok(count) => info!("Exported {count} events to {path}"),
+                                            Err(e) => warn!("Could not export events to {path}: {e}"),
+                                        }
+                                    }
+                                    ("export", Some(format @ ("md" | "todotxt" | "csv" | "html" | "ical")), path) => {
+                                        let mut writer: Box<dyn Write> = match path {
+                                            None => Box::new(stdout()),
+                                            Some(path) => match File::create(path) {
+                                                Ok(file) => Box::new(file),
+                                                Err(e) => {
+                                                    warn!("Could not export to {path}: {e}");
+                                                    continue 'repl;
+                                                }
+                                            },
+                                        };
+                                        let result = match format {
+                                            "md" => tasks.export_markdown(&mut writer),
+                                            "csv" => tasks.export_csv(&mut writer),
+                                            "html" => tasks.export_html(&mut writer),
+                                            "ical" => tasks.export_ical(&mut writer),
+                                            _ => tasks.export_todotxt(&mut writer),
+                                        };
+                                        match result {
+                                            Ok(count) => info!("Exported {count} tasks{}", path.map(|p| format!(" to {p}")).unwrap_or_default()),
+                                            Err(e) => warn!("Could not export tasks: {e}"),
+                                        }
+                                    }
+                                    ("import", Some("events"), Some(path)) => {
+                                        let publish = words.next() == Some("publish");
+                                        match File::open(path) {
+                                            Ok(file) => {
+                                                let (count, skipped) = tasks.import_events(BufReader::new(file), publish);
+                                                info!("Imported {count} events from {path}{}, skipped {skipped} invalid lines",
+                                                    if publish { " for publishing" } else { "" });
+                                            }
+                                            Err(e) => warn!("Could not import events from {path}: {e}"),
+                                        }
+                                    }
+                                    ("import", Some("todotxt"), Some(path)) => {
+                                        match File::open(path) {
+                                            Ok(file) => {
+                                                let (count, skipped) = tasks.import_todotxt(BufReader::new(file));
+                                                info!("Created {count} tasks from {path}, skipped {skipped} already present");
+                                            }
+                                            Err(e) => warn!("Could not import tasks from {path}: {e}"),
+                                        }
+                                    }
+                                    ("import", Some("taskwarrior"), Some(path)) => {
+                                        match fs::read_to_string(path).and_then(|json| tasks.import_taskwarrior(&json)) {
+                                            Ok((count, unmapped)) => {
+                                                info!("Created {count} tasks from {path}");
+                                                if !unmapped.is_empty() {
+                                                    info!("Fields not imported: {}", unmapped.iter().join(", "));
+                                                }
+                                            }
+                                            Err(e) => warn!("Could not import tasks from {path}: {e}"),
+                                        }
+                                    }
+                                    _ => info!("Usage: %export events FILE | %export md|todotxt|csv|html|ical [FILE] | %import events FILE [publish] | %import todotxt|taskwarrior FILE"),
+                                }
+                            }
+                            "assign" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let Some(id) = tasks.get_position() else {
+                                    warn!("Select a task to assign");
+                                    continue 'repl;
+                                };
+                                let name = remaining.trim();
+                                if name.is_empty() {
+                                    tasks.assign(id, None);
+                                } else {
+                                    match tasks.find_user(name) {
+                                        Some(key) => { tasks.assign(id, Some(key)); }
+                                        None => warn!("Unknown user \"{name}\""),
+                                    }
+                                }
+                            }
+                            "review" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let Some(id) = tasks.get_position() else {
+                                    warn!("Select a task to request a review for");
+                                    continue 'repl;
+                                };
+                                let Some(name) = remaining.trim().strip_prefix("request").map(|n| n.trim().trim_start_matches('@')) else {
+                                    info!("Usage: %review request [PERSON]");
+                                    continue 'repl;
+                                };
+                                if name.is_empty() {
+                                    tasks.request_review(id, None);
+                                } else {
+                                    match tasks.find_user(name) {
+                                        Some(key) => { tasks.request_review(id, Some(key)); }
+                                        None => warn!("Unknown user \"{name}\""),
+                                    }
+                                }
+                            }
+                            "approve" | "reject" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let Some(id) = tasks.get_position() else {
+                                    warn!("Select a task to {name}");
+                                    continue 'repl;
+                                };
+                                let comment = remaining.trim();
+                                if name == "reject" && comment.is_empty() {
+                                    warn!("Usage: %reject COMMENT");
+                                    continue 'repl;
+                                }
+                                tasks.review(id, name == "approve", comment);
+                            }
+                            "follow" | "unfollow" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                match tasks.find_user(remaining) {
+                                    Some(key) => { tasks.set_following(key, name == "follow"); }
+                                    None => warn!("Unknown user \"{}\"", remaining.trim()),
+                                }
+                            }
+                            "key" => {
+                                if remaining.trim() != "encrypt" {
+                                    info!("Usage: %key encrypt");
+                                    continue 'repl;
+                                }
+                                if temp {
+                                    warn!("The key of a temporary session is not stored");
+                                    continue 'repl;
+                                }
+                                if key.is_some() {
+                                    warn!("The key was given with --key, so it is not stored");
+                                    continue 'repl;
+                                }
+                                let path = keys_file(&config_dir, profile.as_deref());
+                                if is_encrypted(&path) {
+                                    info!("{} is already encrypted", path.to_string_lossy());
+                                    continue 'repl;
+                                }
+                                // Typing may take a while, so updates are applied meanwhile
+                                drop(relays);
+                                let passphrase = match or_warn!(read_new_passphrase("New passphrase: ")) {
+                                    Some(passphrase) if !passphrase.is_empty() => passphrase,
+                                    _ => {
+                                        info!("Leaving {} unencrypted", path.to_string_lossy());
+                                        continue 'repl;
+                                    }
+                                };
+                                match serialize_keys(&keys.borrow(), &passphrase) {
+                                    Ok(content) => match fs::write(&path, content) {
+                                        Ok(()) => info!("Encrypted {}", path.to_string_lossy()),
+                                        Err(e) => warn!("Could not write {}: {e}", path.to_string_lossy()),
+                                    },
+                                    Err(e) => warn!("Could not encrypt key: {e}"),
+                                }
+                            }
+                            "identity" => {
+                                if temp {
+                                    warn!("Identities are not available in a temporary session");
+                                    continue 'repl;
+                                }
+                                if key.is_some() {
+                                    warn!("Identities are not available with a key given by --key");
+                                    continue 'repl;
+                                }
+                                let name = remaining.trim();
+                                if name.is_empty() {
+                                    let profiles = fs::read_dir(&config_dir).into_iter()
+                                        .flatten()
+                                        .flatten()
+                                        .filter_map(|entry| entry.file_name().into_string().ok())
+                                        .filter_map(|file| if file == "key" {
+                                            Some("default".to_string())
+                                        } else {
+                                            file.strip_prefix("key.").map(|p| p.to_string())
+                                        })
+                                        .sorted()
+                                        .join(", ");
+                                    info!("Acting as {} with public key {}", profile.as_deref().unwrap_or("default"), keys.borrow().public_key());
+                                    info!("Available identities: {profiles}");
+                                    continue 'repl;
+                                }
+                                let new_profile = Some(name.to_string()).filter(|n| n != "default");
+                                let path = keys_file(&config_dir, new_profile.as_deref());
+                                // Asks for the passphrase of an encrypted key
+                                drop(relays);
+                                let new_keys = read_keys(&path);
+                                relays = relays_lock.lock().await;
+                                let Some(new_keys) = new_keys else {
+                                    warn!("Could not read keys from {} - start mostr with --profile {name} to create them", path.to_string_lossy());
+                                    continue 'repl;
+                                };
+                                // Pending events are flushed first since they are signed by the previous identity
+                                for tasks in relays.values_mut() {
+                                    tasks.switch_keys(&new_keys, metadata.clone().filter(|_| new_keys.secret_key().is_ok()));
+                                }
+                                or_warn!(tx.try_send(MostrMessage::SwitchIdentity(new_keys.clone())), "Nostr communication thread failure");
+                                info!("Switched to identity {name} with public key {}", new_keys.public_key());
+                                keys.replace(new_keys);
+                                profile = new_profile;
+                            }
+                            "whois" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let Some(key) = tasks.find_user(remaining) else {
+                                    warn!("Unknown user \"{}\"", remaining.trim());
+                                    continue 'repl;
+                                };
+                                if let Some(url) = &selected_relay {
+                                    let (response, events) = oneshot::channel();
+                                    match tx.try_send(MostrMessage::Whois(url.clone(), key, response)) {
+                                        Ok(_) => {
+                                            let events = or_warn!(timeout(Duration::from_secs(6), events).await, "Nostr communication thread is busy")
+                                                .and_then(|r| or_warn!(r, "No metadata from nostr communication thread"))
+                                                .unwrap_or_default();
+                                            for event in events {
+                                                tasks.add(event);
+                                            }
+                                        }
+                                        Err(e) => error!("Nostr communication thread failure, cannot fetch metadata: {e}"),
+                                    }
+                                }
+                                println!("{}", tasks.whois(&key));
+                            }
+                            "notify" => {
+                                match remaining.trim() {
+                                    "on" => notify = true,
+                                    "off" => notify = false,
+                                    "" => {}
+                                    _ => warn!("Usage: %notify [on|off]"),
+                                }
+                                info!("Notifications via direct message are {}", if notify { "on" } else { "off" });
+                            }
+                            "advance" => {
+                                match remaining.trim() {
+                                    "on" => advance = true,
+                                    "off" => advance = false,
+                                    "" => advance = !advance,
+                                    _ => warn!("Usage: %advance [on|off]"),
+                                }
+                                info!("Completing a procedure step {}", if advance { "activates the next one" } else { "moves up" });
+                            }
+                            "serve" => {
+                                let mut server = server.lock().await;
+                                let address = match remaining.trim() {
+                                    "" => None,
+                                    "off" => {
+                                        match server.take() {
+                                            Some(server) => {
+                                                info!("Stopped serving on http://{}", server.address);
+                                                server.stop();
+                                            }
+                                            None => info!("Not serving"),
+                                        }
+                                        continue 'repl;
+                                    }
+                                    arg => match arg.parse::<u16>() {
+                                        Ok(port) => Some(SocketAddr::from(([127, 0, 0, 1], port))),
+                                        Err(_) => arg.parse::<SocketAddr>().ok(),
+                                    }
+                                };
+                                let Some(address) = address else {
+                                    match server.as_ref() {
+                                        Some(server) => info!("Serving on http://{}", server.address),
+                                        None => info!("Usage: %serve PORT|ADDRESS:PORT|off"),
+                                    }
+                                    continue 'repl;
+                                };
+                                if let Some(previous) = server.take() {
+                                    previous.stop();
+                                }
+                                let tasks = relays.get(&selected_relay).unwrap();
+                                match Server::start(address, Snapshot::of(tasks)).await {
+                                    Ok(started) => {
+                                        info!("Serving the current view read-only on http://{}, with the listing at /json", started.address);
+                                        *server = Some(started);
+                                    }
+                                    Err(e) => warn!("Could not serve on {address}: {e}"),
+                                }
+                            }
+                            "quiet" => {
+                                match remaining.trim() {
+                                    "on" => quiet_desktop = true,
+                                    "off" => quiet_desktop = false,
+                                    "" => quiet_desktop = !quiet_desktop,
+                                    _ => warn!("Usage: %quiet [on|off]"),
+                                }
+                                relays.values_mut().for_each(|tasks| tasks.quiet = quiet_desktop);
+                                info!("Desktop notifications are {}", if quiet_desktop { "off for this session" } else { "on" });
+                            }
+                            "hooks" => {
+                                match remaining.trim() {
+                                    "on" => hooks_enabled = true,
+                                    "off" => hooks_enabled = false,
+                                    "" => {}
+                                    _ => warn!("Usage: %hooks [on|off]"),
+                                }
+                                // Also covers updates received for the other relays
+                                for tasks in relays.values_mut() {
+                                    tasks.hooks_enabled = hooks_enabled;
+                                }
+                                if hooks.commands.is_empty() {
+                                    info!("No hooks configured");
+                                } else {
+                                    info!("Hooks for {} are {}", hooks.commands.keys().sorted().join(", "), if hooks_enabled { "on" } else { "off" });
+                                }
+                            }
+                            "set" => {
+                                let (key, value) = remaining.trim().split_once(' ').unwrap_or((remaining.trim(), ""));
+                                let mut settings = settings.borrow_mut();
+                                if key.is_empty() {
+                                    for key in SETTINGS_KEYS {
+                                        println!("{key} = {}", settings.get(key).unwrap_or_default());
+                                    }
+                                    continue 'repl;
+                                }
+                                if !value.trim().is_empty() {
+                                    if let Err(e) = settings.set(key, value) {
+                                        warn!("Could not change {key}: {e}");
+                                        continue 'repl;
+                                    }
+                                    inactivity_delay.store(settings.inactivity_delay, Ordering::Relaxed);
+                                    for tasks in relays.values_mut() {
+                                        tasks.apply_settings(&settings);
+                                    }
+                                }
+                                match settings.get(key) {
+                                    Some(value) => info!("{key} = {value}"),
+                                    None => warn!("Unknown setting {key}, available are {}", SETTINGS_KEYS.join(", ")),
+                                }
+                            }
+                            "tz" => {
+                                let zone = remaining.trim();
+                                if !zone.is_empty() {
+                                    match parse_timezone(zone) {
+                                        Some(offset) => set_timezone(offset),
+                                        None => {
+                                            warn!("Expected an offset like +02:00, UTC or local");
+                                            continue 'repl;
+                                        }
+                                    }
+                                }
+                                info!("Showing times in {} - it is {}",
+                                    timezone().map_or("the system timezone".to_string(), |offset| format!("UTC{offset}")),
+                                    format_timestamp_local(&Timestamp::now()));
+                            }
+                            "inbox" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                if tasks.set_view_inbox() {
+                                    info!("Showing open tasks assigned to you by others");
+                                }
+                            }
+                            "expires" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let Some(id) = tasks.get_position() else {
+                                    warn!("Select a task to set its expiration");
+                                    continue 'repl;
+                                };
+                                match remaining.trim() {
+                                    "" => info!("\"{}\" {}", tasks.get_task_title(&id),
+                                        tasks.get_by_id(&id).and_then(|t| t.get("expires")).map_or("does not expire".to_string(), |time| format!("expires {time}"))),
+                                    "never" => { tasks.set_expiration(id, None); }
+                                    time => match parse_expiration(time) {
+                                        Some(stamp) => { tasks.set_expiration(id, Some(stamp)); }
+                                        None => warn!("Expected an offset like 3d or a date"),
+                                    },
+                                }
+                            }
+                            "repo" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let mut words = remaining.split_whitespace();
+                                match (words.next(), words.next()) {
+                                    (Some("filter"), None) => tasks.set_repo_filter(None),
+                                    (Some("filter"), Some(name)) => match tasks.find_repository(name) {
+                                        Some(repo) => tasks.set_repo_filter(Some(repo)),
+                                        None => warn!("Unknown repository \"{name}\""),
+                                    },
+                                    (arg, _) => {
+                                        let Some(id) = tasks.get_position() else {
+                                            warn!("Select a task to associate it with a repository");
+                                            continue 'repl;
+                                        };
+                                        match arg {
+                                            None => info!("\"{}\" {}", tasks.get_task_title(&id),
+                                                tasks.get_by_id(&id).and_then(|t| t.repository())
+                                                    .map_or("belongs to no repository".to_string(), |repo| format!("belongs to repository {}", repo))),
+                                            Some("-") => { tasks.set_repository(id, None); }
+                                            Some(repo) => match tasks.find_repository(repo) {
+                                                Some(repo) => { tasks.set_repository(id, Some(repo)); }
+                                                None => warn!("Expected a repository coordinate like 30617:PUBKEY:NAME, its naddr or a known repository name"),
+                                            },
+                                        }
+                                    }
+                                }
+                            }
+                            "react" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let reaction = some_non_empty(remaining.trim()).unwrap_or("+".to_string());
+                                if tasks.get_position().is_none() {
+                                    warn!("Select a task to react to");
+                                } else {
+                                    tasks.react(&reaction);
+                                }
+                            }
+                            "watch" => {
+                                watch = match remaining.trim() {
+                                    "" => if watch == Watch::Off { Watch::Updates } else { Watch::Off },
+                                    "on" => Watch::Updates,
+                                    "off" => Watch::Off,
+                                    "table" => Watch::Table,
+                                    _ => {
+                                        info!("Usage: %watch [on|off|table]");
+                                        continue 'repl;
+                                    }
+                                };
+                                info!("{}", match watch {
+                                    Watch::Off => "Not watching for updates",
+                                    Watch::Updates => "Showing updates to the visible tasks while idle",
+                                    Watch::Table => "Showing updates with the refreshed task list while idle",
+                                });
+                            }
+                            "all" => {
+                                let merged = merged_key();
+                                let current = relays.get(&merged).and_then(|all| all.url().cloned());
+                                let primary = match remaining.trim() {
+                                    "off" => {
+                                        if relays.remove(&merged).is_some() {
+                                            info!("Stopped merging relays");
+                                        }
+                                        if selected_relay == merged {
+                                            selected_relay = current;
+                                        }
+                                        continue 'repl;
+                                    }
+                                    "" => selected_relay.clone()
+                                        .filter(|url| Some(url) != merged.as_ref())
+                                        .or(current)
+                                        .or_else(|| relays.keys().flatten().find(|url| url.scheme() == "wss").cloned()),
+                                    prefix => find_relay(&relays, prefix).filter(|url| Some(url) != merged.as_ref()),
+                                };
+                                let Some(primary) = primary else {
+                                    warn!("No relay to send changes to, connect to one first");
+                                    info!("Usage: %all [URL|off]");
+                                    continue 'repl;
+                                };
+                                let mut all = tasks_for_url(Some(primary.clone()));
+                                // The merged relays notify and run hooks for changes by others on their own
+                                all.desktop = Default::default();
+                                all.hooks.others = false;
+                                let sources = relays.iter()
+                                    .filter(|(url, _)| url.is_some() && url != &&merged)
+                                    .map(|(_, tasks)| tasks)
+                                    .collect_vec();
+                                for tasks in &sources {
+                                    all.merge_from(tasks);
+                                }
+                                all.set_synced(true);
+                                all.apply_defaults();
+                                info!("Showing {} tasks from {} relays, sending changes to {primary}", all.len(), sources.len());
+                                relays.insert(merged.clone(), all);
+                                selected_relay = merged;
+                                or_warn!(relays.get(&selected_relay).unwrap().print_tasks());
+                            }
+                            "timeline" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let Some(id) = tasks.get_position() else {
+                                    warn!("Select a task to show its timeline");
+                                    continue 'repl;
+                                };
+                                println!("{}", tasks.describe_timeline(&id).join("\n"));
+                            }
+                            "completed" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let Some(since) = parse_since(some_non_empty(remaining.trim()).as_deref().unwrap_or("7d")) else {
+                                    warn!("Expected an age like 7d or a date");
+                                    continue 'repl;
+                                };
+                                let completed = tasks.completed_since(tasks.get_position_ref(), since);
+                                if completed.is_empty() {
+                                    info!("Nothing completed here since {}", format_timestamp_local(&since));
+                                }
+                                for task in completed {
+                                    println!("{} {}{} {}",
+                                             task.get("completed").unwrap_or_default(),
+                                             task.state_or_default().get_colored_label(),
+                                             tasks.state_changer(task).map_or(String::new(), |changer| format!(" {}", changer.dimmed())),
+                                             tasks.get_task_path(Some(task.event.id)));
+                                }
+                            }
+                            "tracked" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let range = some_non_empty(remaining.trim()).unwrap_or("today".to_string());
+                                let Some((start, end)) = parse_date_range(&range) else {
+                                    warn!("Expected a range like yesterday, this week or monday..friday");
+                                    continue 'repl;
+                                };
+                                if tasks.tracked_between(start, end).is_empty() {
+                                    info!("Nothing tracked {range}");
+                                    continue 'repl;
+                                }
+                                println!("{}", tasks.tracked_report(start, end).join("\n"));
+                            }
+                            "summary" => {
+                                let tasks = &relays[&selected_relay];
+                                println!("{}", tasks.day_summary(day_start(now_local().date_naive()).to_timestamp()).join("\n"));
+                            }
+                            "history" => {
+                                let arg = remaining.trim();
+                                let max = match arg.split_once(' ').unwrap_or((arg, "")) {
+                                    ("prune", "") => {
+                                        info!("Usage: %history prune DATE");
+                                        continue 'repl;
+                                    }
+                                    ("prune", time) => {
+                                        let tasks = relays.get_mut(&selected_relay).unwrap();
+                                        match parse_since(time.trim()) {
+                                            Some(stamp) => {
+                                                let count = tasks.prune_own_history(stamp);
+                                                info!("Compacted {count} of your tracking events before {}", format_timestamp_local(&stamp));
+                                            }
+                                            None => warn!("Expected an age like 30d or a date"),
+                                        }
+                                        continue 'repl;
+                                    }
+                                    _ if arg.is_empty() => 20,
+                                    _ => match arg.parse::<usize>() {
+                                        Ok(count) => count,
+                                        Err(_) => {
+                                            warn!("Usage: %history [COUNT] or %history prune DATE");
+                                            continue 'repl;
+                                        }
+                                    },
+                                };
+                                let tasks = &relays[&selected_relay];
+                                let merged = selected_relay == merged_key();
+                                for (index, (id, start, held)) in tasks.position_history().into_iter().take(max).enumerate() {
+                                    // The merged view holds tasks of all relays
+                                    let relay = id.filter(|_| merged)
+                                        .and_then(|id| relays.iter().find(|(url, other)| url != &&selected_relay && other.get_by_id(&id).is_some()))
+                                        .map(|(url, _)| format!(" on {}", relay_name(url, &relays)));
+                                    println!("{:>3} {} {} {}m{}",
+                                             index,
+                                             format_timestamp_relative(&start).dimmed(),
+                                             id.map_or("---".to_string(), |id| tasks.get_task_path(Some(id))),
+                                             held / 60,
+                                             relay.unwrap_or_default().dimmed());
+                                }
+                                continue 'repl;
+                            }
+                            "sweep" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let mut words = remaining.split_whitespace();
+                                let age = words.next().unwrap_or_default();
+                                let Some(since) = parse_since(age) else {
+                                    warn!("Usage: %sweep AGE [pending] - with an age like 90d or a date");
+                                    continue 'repl;
+                                };
+                                let state = match words.next() {
+                                    None | Some("closed") => State::Closed,
+                                    Some("pending") => State::Pending,
+                                    Some(other) => {
+                                        warn!("Can only sweep to closed or pending, not {other}");
+                                        continue 'repl;
+                                    }
+                                };
+                                let stale = tasks.stale_tasks(tasks.get_position_ref(), since).into_iter()
+                                    .filter(|(_, task)| task.pure_state() != state)
+                                    .collect_vec();
+                                if stale.is_empty() {
+                                    info!("No open tasks here untouched since {}", format_timestamp_local(&since));
+                                    continue 'repl;
+                                }
+                                for (stamp, task) in &stale {
+                                    println!("{} {}", format_timestamp_relative(stamp).dimmed(), tasks.get_task_path(Some(task.event.id)));
+                                }
+                                let ids = stale.iter().map(|(_, task)| task.event.id).collect_vec();
+                                drop(relays);
+                                if !confirm(&mut rl, &format!("Set {} tasks to {state}?", ids.len())) {
+                                    continue 'repl;
+                                }
+                                relays = relays_lock.lock().await;
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let comment = format!("auto-{} after {age} inactivity", state.to_string().to_lowercase());
+                                let changed = tasks.sweep(&ids, state, &comment);
+                                info!("Swept {changed} tasks");
+                            }
+                            "dedupe" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let groups = tasks.find_duplicates(tasks.get_position_ref());
+                                if groups.is_empty() {
+                                    info!("No duplicate tasks here");
+                                    continue 'repl;
+                                }
+                                for group in &groups {
+                                    println!("{}", tasks.describe_duplicates(group));
+                                }
+                                match remaining.trim() {
+                                    "" => info!("Use %dedupe merge to merge each group into its oldest task"),
+                                    "merge" => {
+                                        let moved = groups.iter().map(|group| tasks.merge_duplicates(group)).sum::<usize>();
+                                        info!("Merged {} groups of duplicates, moving {moved} subtasks", groups.len());
+                                    }
+                                    _ => info!("Usage: %dedupe [merge]"),
+                                }
+                            }
+                            "orphans" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                match remaining.trim() {
+                                    "" => {
+                                        if tasks.orphans().is_empty() {
+                                            info!("No orphaned events");
+                                        }
+                                        for orphan in tasks.orphans() {
+                                            println!("{orphan}");
+                                        }
+                                    }
+                                    "retry" => {
+                                        let count = tasks.orphans().len();
+                                        let sorted = tasks.retry_orphans();
+                                        info!("Requeued {count} orphaned events, sorted {sorted}");
+                                    }
+                                    _ => info!("Usage: %orphans [retry]"),
+                                }
+                            }
+                            "dangling" => {
+                                let tasks = relays.get_mut(&selected_relay).unwrap();
+                                let (action, arg) = remaining.trim().split_once(' ').unwrap_or((remaining.trim(), ""));
+                                match action {
+                                    "" => {
+                                        let dangling = tasks.dangling();
+                                        if dangling.is_empty() {
+                                            info!("No references to unknown tasks");
+                                            continue 'repl;
+                                        }
+                                        for (missing, referencing) in &dangling {
+                                            println!("{} {}", missing.to_string().bold(), format!("referenced by {}", referencing.len()).dimmed());
+                                            for (marker, id) in referencing {
+                                                println!("  {} {}", marker.dimmed(), tasks.get_task_path(Some(*id)));
+                                            }
+                                        }
+                                        info!("Use %dangling fetch to request the missing tasks from the relay, \
+                                               or %dangling detach [ID] to drop the references so the tasks move to the root");
+                                    }
+                                    "fetch" => {
+                                        let count = tasks.dangling().len();
+                                        if count > 0 && tasks.fetch_dangling() {
+                                            info!("Requested {count} missing tasks");
+                                        } else if count > 0 {
+                                            warn!("Missing tasks can only be requested from a relay");
+                                        }
+                                    }
+                                    "detach" => {
+                                        let missing = some_non_empty(arg.trim());
+                                        let detached = tasks.detach_dangling(missing.as_deref());
+                                        info!("Detached {detached} tasks");
+                                    }
+                                    _ => info!("Usage: %dangling [fetch|detach [ID]]"),
+                                }
+                            }
+                            "status" | "relays" => {
+                                let report = relay_report(&tx).await;
+                                for (url, tasks) in relays.iter() {
+                                    let connection = url.as_ref()
+                                        .and_then(|url| report.get(url))
+                                        .map(|(state, queued, pending)| format!("{state}, {queued} queued for sending, {pending} undelivered, "));
+                                    println!("{}: {}{}",
+                                             relay_name(url, &relays).bold(),
+                                             connection.unwrap_or_default(),
+                                             tasks.status());
+                                }
+                            }
+                            "pending" => {
+                                let changes = relays.get(&selected_relay).unwrap().pending_changes();
+                                if changes.is_empty() {
+                                    info!("No changes waiting to be sent");
+                                }
+                                for change in changes {
+                                    println!("{change}");
+                                }
+                                if let Some(url) = &selected_relay {
+                                    if let Some((_, queued, undelivered)) = relay_report(&tx).await.remove(url) {
+                                        info!("{queued} more events handed over for sending, {undelivered} not confirmed by the relay yet");
+                                    }
+                                }
+                            }
+                            "sync" | "promote" => {
+                                let target = if name == "promote" {
+                                    let arg = remaining.trim();
+                                    match find_relay(&relays, arg).filter(|_| !arg.is_empty()) {
+                                        Some(url) => Some(url),
+                                        None => {
+                                            let Some(url) = or_warn!(Url::parse(arg), "Failed to parse url {}", arg) else {
+                                                continue 'repl;
+                                            };
+                                            match tx.try_send(MostrMessage::NewRelay(url.clone())) {
+                                                Err(e) => {
+                                                    error!("Nostr communication thread failure, cannot add relay \"{url}\": {e}");
+                                                    continue 'repl;
+                                                }
+                                                Ok(_) => {
+                                                    info!("Connecting to {url}");
+                                                    relays.insert(Some(url.clone()), tasks_for_url(Some(url.clone())));
+                                                }
+                                            }
+                                            Some(url)
+                                        }
+                                    }
+                                } else {
+                                    selected_relay.clone()
+                                };
+                                let Some(url) = target else {
+                                    warn!("Select a relay to publish local changes to");
+                                    continue 'repl;
+                                };
+                                match relays.remove(&None) {
+                                    None => info!("No local changes to publish"),
+                                    Some(local) => {
+                                        let tasks = relays.get_mut(&Some(url.clone())).unwrap();
+                                        let count = tasks.adopt_from(local);
+                                        if let Some(cache) = &cache {
+                                            or_warn!(cache.clear(None), "Could not clear local cache");
+                                        }
+                                        info!("Published {count} local events to {url}");
+                                        selected_relay = Some(url);
+                                        or_warn!(tasks.print_tasks());
+                                    }
+                                }
+                            }
+                            _ => warn!("Unknown command \"{name}\""),
+                        }
+                        continue 'repl;
+                    }
+
                     _ =>
                         if Regex::new("^wss?://").unwrap().is_match(command.trim()) {
                             tasks.move_to(None);
-                            if let Some((url, tasks)) = relays.iter().find(|(key, _)| key.as_ref().is_some_and(|url| url.as_str().starts_with(&command))) {
+                            if let Some((url, tasks)) = relays.iter_mut().find(|(key, _)| key.as_ref().is_some_and(|url| url.as_str().starts_with(&command))) {
                                 selected_relay.clone_from(url);
+                                tasks.apply_defaults();
                                 or_warn!(tasks.print_tasks());
                                 continue 'repl;
                             }
@@ -738,6 +2689,63 @@ async fn main() -> Result<()> {
                                 }
                             });
                             continue 'repl;
+                        } else if let Some(input) = command.strip_prefix("new ")
+                            .and_then(|rest| snippets.fill(rest, now_local().date_naive(), tasks.get_current_task().map(|t| t.get_title()).as_deref())) {
+                            match input {
+                                Ok(input) => {
+                                    tasks.make_task(&input);
+                                }
+                                Err(e) => {
+                                    warn!("{e}");
+                                    continue 'repl;
+                                }
+                            }
+                        } else if command == "note" {
+                            compose_note(tasks);
+                            continue 'repl;
+                        } else if let Some(reference) = command.strip_prefix("attach ").map(str::trim)
+                            .filter(|reference| is_url(reference) || reference.contains(['/', '.'])) {
+                            tasks.attach(reference);
+                            continue 'repl;
+                        } else if let Some(index) = command.strip_prefix("detach ").and_then(|index| index.trim().parse::<usize>().ok()) {
+                            tasks.detach(index);
+                            continue 'repl;
+                        } else if let Some(index) = command.strip_prefix("open").and_then(|rest|
+                            if rest.trim().is_empty() { Some(None) } else { rest.strip_prefix(' ')?.trim().parse::<usize>().ok().map(Some) }) {
+                            let count = tasks.get_current_task().map_or(0, |task| task.attachments().count());
+                            let index = match index {
+                                Some(index) => index,
+                                None if count <= 1 => 1,
+                                None => {
+                                    info!("Use open INDEX to open one of the {count} attachments of the current task");
+                                    continue 'repl;
+                                }
+                            };
+                            if let Some(reference) = tasks.attachment(index) {
+                                open_reference(&reference, &settings.borrow().project_root);
+                            }
+                            continue 'repl;
+                        } else if let Some(rest) = command.strip_prefix("plan ") {
+                            let (range, name) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+                            match parse_time_range(range) {
+                                None => warn!("Unable to parse time range \"{range}\", expected something like 15:00-16:30"),
+                                Some((start, end)) => {
+                                    let name = name.trim();
+                                    let matching = if name.is_empty() {
+                                        tasks.get_position().into_iter().collect_vec()
+                                    } else {
+                                        tasks.get_matching(tasks.get_position().as_ref(), name)
+                                    };
+                                    match matching.as_slice() {
+                                        [id] => {
+                                            tasks.plan(start, end, *id);
+                                        }
+                                        [] => warn!("No task to plan, name one or move to it"),
+                                        _ => warn!("Multiple tasks match \"{name}\", please be more specific"),
+                                    }
+                                }
+                            }
+                            continue 'repl;
                         } else if command.contains('\n') {
                             command.split('\n').for_each(|line| {
                                 if !line.trim().is_empty() {
@@ -745,23 +2753,60 @@ async fn main() -> Result<()> {
                                 }
                             });
                         } else {
-                            tasks.filter_or_create(tasks.get_position().as_ref(), &command);
+                            let confirmed = tasks.needs_confirmation(tasks.get_position_ref(), &command) && {
+                                drop(relays);
+                                let confirmed = confirm(&mut rl, &format!("Create new task \"{command}\"?"));
+                                relays = relays_lock.lock().await;
+                                tasks = relays.get_mut(&selected_relay).unwrap();
+                                confirmed
+                            };
+                            tasks.filter_or_create(tasks.get_position().as_ref(), &command, |_| confirmed);
                         }
                 }
                 tasks.custom_time = None;
                 or_warn!(tasks.print_tasks());
             }
             Err(ReadlineError::Eof) => break 'repl,
-            Err(ReadlineError::Interrupted) => break 'repl, // TODO exit if prompt was empty, or clear
+            // Ctrl-C on an empty prompt or twice, see InterruptHandler
+            Err(ReadlineError::Interrupted) => break 'repl,
             Err(e) => warn!("{}", e),
         }
     }
     println!();
 
     drop(tx);
-    drop(relays);
+    plans.abort();
+    #[cfg(unix)]
+    terminated.abort();
+    if let Some(server) = server.lock().await.take() {
+        server.stop();
+    }
+    ingest.abort();
+    // Wait for the ingestion to let go of the relays, so dropping them flushes all changes
+    let _ = ingest.await;
+    if let Some(path) = &session_file {
+        let relays = relays_lock.lock().await;
+        Session {
+            selected: selected_relay.clone(),
+            relays: relays.iter().map(|(url, tasks)| (url.clone(), tasks.session())).collect(),
+        }.save(path);
+    }
+    let summary = if settings.borrow().exit_summary {
+        relays_lock.lock().await.get(&selected_relay)
+            .map(|tasks| tasks.day_summary(day_start(now_local().date_naive()).to_timestamp()))
+            .unwrap_or_default()
+    } else {
+        vec![]
+    };
+    if let Some(path) = &history_file {
+        or_warn!(rl.save_history(path), "Could not save command history to {}", path.to_string_lossy());
+    }
+    drop(relays_lock);
 
     info!("Submitting pending updates...");
+    if !summary.is_empty() {
+        println!("{}", summary.join("\n"));
+    }
     or_warn!(sender.await);
 
     Ok(())
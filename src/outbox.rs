@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use nostr_sdk::{Client, Event, RelaySendOptions, Url};
+
+use crate::cache::EventCache;
+
+/// Delay before the first retry, doubled on each further attempt
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+/// Interval for checking whether a relay reconnected after retries were exhausted
+const RECONNECT_POLL: Duration = Duration::from_secs(30);
+
+/// Events a relay has not confirmed yet
+#[derive(Debug)]
+struct Pending {
+    events: Vec<Event>,
+    attempts: u32,
+    retry_at: Instant,
+    connected: bool,
+}
+
+/// Events that were not yet confirmed by their relay.
+/// They are persisted per relay until delivered so they survive a crash or quit,
+/// and always sent before any newer events to the same relay.
+#[derive(Debug)]
+pub(crate) struct Outbox {
+    store: Option<EventCache>,
+    pending: HashMap<Url, Pending>,
+    /// Events persisted while still queued for sending,
+    /// which the store keeps along with the pending ones
+    queued: HashMap<Url, Vec<Event>>,
+    retries: u32,
+    count: Arc<AtomicUsize>,
+}
+
+impl Outbox {
+    /// Create an outbox retrying failed deliveries the given number of times,
+    /// reporting the number of undelivered events through the counter.
+    pub(crate) fn new(store: Option<EventCache>, retries: u32, count: Arc<AtomicUsize>) -> Self {
+        Outbox { store, pending: HashMap::new(), queued: HashMap::new(), retries, count }
+    }
+
+    /// Load the events left over for the relay from an earlier session.
+    pub(crate) fn load(&mut self, url: &Url) {
+        let Some(store) = &self.store else { return };
+        let events = store.load(Some(url));
+        if !events.is_empty() {
+            info!("Found {} undelivered events for {url}", events.len());
+            self.add_pending(url, events);
+            self.update_count();
+        }
+    }
+
+    /// Persist events queued for the relay before they are sent.
+    pub(crate) fn persist(&mut self, url: &Url, events: &[Event]) {
+        self.queued.entry(url.clone()).or_default().extend(events.iter().cloned());
+        if let Some(store) = &self.store {
+            if let Err(e) = store.append(Some(url), events) {
+                warn!("Could not persist events for {url} in outbox: {e}");
+            }
+        }
+    }
+
+    /// Number of events not yet confirmed by the relay.
+    pub(crate) fn pending(&self, url: &Url) -> usize {
+        self.pending.get(url).map_or(0, |p| p.events.len())
+    }
+
+    /// Time until the next retry is due, if any.
+    pub(crate) fn next_retry(&self) -> Option<Duration> {
+        self.pending.values()
+            .map(|p| p.retry_at.saturating_duration_since(Instant::now()))
+            .min()
+    }
+
+    /// Send the events to the relay after any undelivered ones,
+    /// keeping all of them for a later retry if the relay does not confirm them.
+    /// Returns whether delivery succeeded.
+    pub(crate) async fn send(&mut self, client: &Client, url: &Url, events: Vec<Event>) -> bool {
+        if let Some(queued) = self.queued.get_mut(url) {
+            queued.retain(|queued| !events.iter().any(|event| event.id == queued.id));
+        }
+        let (mut all, attempts) = self.pending.remove(url)
+            .map_or((vec![], 0), |p| (p.events, p.attempts));
+        all.extend(events);
+        if all.is_empty() {
+            return true;
+        }
+        let count = all.len();
+        let delivered = match client.batch_event_to(vec![url.clone()], all.clone(), RelaySendOptions::new()).await {
+            Ok(output) if output.success.contains(url) => true,
+            Ok(output) => {
+                warn!("{url} did not accept {count} events: {}",
+                    output.failed.get(url).cloned().flatten().unwrap_or("no response".to_string()));
+                false
+            }
+            Err(e) => {
+                warn!("Unable to send {count} events to {url}: {e}");
+                false
+            }
+        };
+        if delivered {
+            info!("Delivered {count} events to {url}");
+            self.store(url, &[]);
+        } else {
+            let attempts = attempts + 1;
+            if attempts > self.retries {
+                warn!("Giving up on {count} events for {url} after {} retries, keeping them until it reconnects", self.retries);
+            }
+            self.store(url, &all);
+            self.pending.insert(url.clone(), Pending {
+                events: all,
+                attempts,
+                retry_at: Instant::now() + self.backoff(attempts),
+                connected: is_connected(client, url).await,
+            });
+        }
+        self.update_count();
+        delivered
+    }
+
+    /// Immediately send the undelivered events for the relay, e.g. after it (re)connected.
+    pub(crate) async fn flush(&mut self, client: &Client, url: &Url) {
+        if let Some(pending) = self.pending.get_mut(url) {
+            pending.attempts = 0;
+            self.send(client, url, vec![]).await;
+        }
+    }
+
+    /// Retry all deliveries that are due.
+    /// Once the retries are used up, only retry when the relay reconnects.
+    pub(crate) async fn retry(&mut self, client: &Client) {
+        let now = Instant::now();
+        let due = self.pending.iter()
+            .filter(|(_, p)| p.retry_at <= now)
+            .map(|(url, _)| url.clone())
+            .collect::<Vec<_>>();
+        for url in due {
+            let Some((attempts, was_connected, count)) = self.pending.get(&url)
+                .map(|p| (p.attempts, p.connected, p.events.len())) else { continue };
+            if attempts <= self.retries {
+                info!("Retrying delivery of {count} events to {url}");
+                self.send(client, &url, vec![]).await;
+                continue;
+            }
+            let connected = is_connected(client, &url).await;
+            if connected && !was_connected {
+                info!("{url} reconnected, sending {count} undelivered events");
+                self.flush(client, &url).await;
+            } else if let Some(pending) = self.pending.get_mut(&url) {
+                pending.connected = connected;
+                pending.retry_at = now + RECONNECT_POLL;
+            }
+        }
+    }
+
+    /// Stop delivering to the relay, keeping its events persisted for when it is added again.
+    pub(crate) fn forget(&mut self, url: &Url) {
+        self.queued.remove(url);
+        if let Some(pending) = self.pending.remove(url) {
+            warn!("Keeping {} undelivered events for {url} in the outbox", pending.events.len());
+            self.update_count();
+        }
+    }
+
+    fn add_pending(&mut self, url: &Url, events: Vec<Event>) {
+        self.pending.insert(url.clone(), Pending {
+            events,
+            attempts: 0,
+            retry_at: Instant::now(),
+            connected: false,
+        });
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        if attempts > self.retries {
+            RECONNECT_POLL
+        } else {
+            BACKOFF_BASE * 2u32.pow(attempts.saturating_sub(1).min(10))
+        }
+    }
+
+    /// Replace the persisted events of the relay with the given pending and the still queued ones
+    fn store(&self, url: &Url, events: &[Event]) {
+        if let Some(store) = &self.store {
+            let queued = self.queued.get(url).map_or(&[][..], |queued| queued.as_slice());
+            let all = events.iter().chain(queued).cloned().collect::<Vec<_>>();
+            if let Err(e) = store.replace(Some(url), &all) {
+                warn!("Could not update outbox for {url}: {e}");
+            }
+        }
+    }
+
+    fn update_count(&self) {
+        self.count.store(self.pending.values().map(|p| p.events.len()).sum(), Ordering::Relaxed);
+    }
+}
+
+async fn is_connected(client: &Client, url: &Url) -> bool {
+    match client.relay(url).await {
+        Ok(relay) => relay.is_connected().await,
+        Err(_) => false,
+    }
+}
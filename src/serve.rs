@@ -0,0 +1,129 @@
+use std::io;
+use std::net::SocketAddr;
+
+use log::{debug, warn};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::tasks::TasksRelay;
+
+/// Views of the selected relay and position, rendered for serving
+/// so requests never wait for the relays
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Snapshot {
+    /// Report as exported with `%export html`
+    pub(crate) html: String,
+    /// Listed tasks as printed by `%json`
+    pub(crate) json: String,
+}
+
+impl Snapshot {
+    pub(crate) fn of(tasks: &TasksRelay) -> Self {
+        let mut html = vec![];
+        if let Err(e) = tasks.export_html(&mut html) {
+            warn!("Could not render status page: {e}");
+        }
+        Snapshot {
+            html: String::from_utf8_lossy(&html).into_owned(),
+            json: tasks.to_json(false),
+        }
+    }
+}
+
+/// Read-only status page served in the background
+pub(crate) struct Server {
+    pub(crate) address: SocketAddr,
+    snapshots: watch::Sender<Snapshot>,
+    handle: JoinHandle<()>,
+}
+
+impl Server {
+    /// Serve the given snapshot on the address until stopped
+    #[cfg(feature = "serve")]
+    pub(crate) async fn start(address: SocketAddr, snapshot: Snapshot) -> io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+        let (snapshots, receiver) = watch::channel(snapshot);
+        let handle = tokio::spawn(accept(listener, receiver));
+        Ok(Server { address, snapshots, handle })
+    }
+
+    #[cfg(not(feature = "serve"))]
+    pub(crate) async fn start(_address: SocketAddr, _snapshot: Snapshot) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "mostr was built without the serve feature"))
+    }
+
+    /// Replace the served snapshot, unless it is unchanged
+    pub(crate) fn update(&self, snapshot: Snapshot) {
+        self.snapshots.send_if_modified(|current| {
+            let modified = current != &snapshot;
+            *current = snapshot;
+            modified
+        });
+    }
+
+    pub(crate) fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+/// Answer connections with the latest snapshot, each in its own task
+#[cfg(feature = "serve")]
+async fn accept(listener: tokio::net::TcpListener, snapshots: watch::Receiver<Snapshot>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Could not accept connection to status page: {e}");
+                continue;
+            }
+        };
+        let snapshot = snapshots.borrow().clone();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 4096];
+            let result = async {
+                let read = stream.read(&mut buffer).await?;
+                let request = String::from_utf8_lossy(&buffer[..read]);
+                let response = respond(request.lines().next().unwrap_or_default(), &snapshot);
+                stream.write_all(response.as_bytes()).await?;
+                stream.shutdown().await
+            }.await;
+            if let Err(e) = result {
+                debug!("Could not answer {peer}: {e}");
+            }
+        });
+    }
+}
+
+/// Response to a request line like `GET /json HTTP/1.1`, only reading is allowed
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+fn respond(request: &str, snapshot: &Snapshot) -> String {
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default().split('?').next().unwrap_or_default();
+    let (status, content_type, body) = match (method, path) {
+        ("GET" | "HEAD", "/") => ("200 OK", "text/html; charset=utf-8", snapshot.html.as_str()),
+        ("GET" | "HEAD", "/json") => ("200 OK", "application/json", snapshot.json.as_str()),
+        ("GET" | "HEAD", _) => ("404 Not Found", "text/plain; charset=utf-8", "Not found\n"),
+        _ => ("405 Method Not Allowed", "text/plain; charset=utf-8", "Read-only\n"),
+    };
+    format!("HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            if method == "HEAD" { "" } else { body })
+}
+
+#[test]
+fn test_respond() {
+    let snapshot = Snapshot { html: "<html></html>".to_string(), json: "[]".to_string() };
+    assert_eq!(respond("GET / HTTP/1.1", &snapshot),
+               "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: 13\r\nConnection: close\r\n\r\n<html></html>");
+    let json = respond("GET /json?pretty HTTP/1.1", &snapshot);
+    assert!(json.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"));
+    assert!(json.ends_with("\r\n\r\n[]"));
+    assert!(respond("HEAD /json HTTP/1.1", &snapshot).ends_with("Content-Length: 2\r\nConnection: close\r\n\r\n"));
+    assert!(respond("GET /tasks HTTP/1.1", &snapshot).starts_with("HTTP/1.1 404"));
+    assert!(respond("POST / HTTP/1.1", &snapshot).starts_with("HTTP/1.1 405"));
+    assert!(respond("", &snapshot).starts_with("HTTP/1.1 405"));
+}
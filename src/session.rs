@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use itertools::Itertools;
+use log::{debug, warn};
+use nostr_sdk::{EventId, Timestamp, Url};
+
+use crate::config::Config;
+
+/// Section of the local relay in the session file
+const LOCAL_SECTION: &str = "local";
+
+/// Selected relay and view of each relay at the end of the last session,
+/// stored in the format of the config file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Session {
+    /// Selected relay, none for the local relay
+    pub(crate) selected: Option<Url>,
+    pub(crate) relays: HashMap<Option<Url>, RelaySession>,
+}
+
+/// Position and filters of a relay
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RelaySession {
+    pub(crate) position: Option<EventId>,
+    pub(crate) depth: Option<usize>,
+    /// Hashtags to filter by
+    pub(crate) tags: Vec<String>,
+    /// Hashtags filtered out
+    pub(crate) excluded: Vec<String>,
+    /// State to filter by, `all` for all tasks
+    pub(crate) state: Option<String>,
+    /// Coordinate of the repository to filter by
+    pub(crate) repo: Option<String>,
+    /// When the session was saved
+    pub(crate) saved: Option<Timestamp>,
+}
+
+impl Session {
+    /// Load the session file, starting afresh if it is missing or unreadable.
+    pub(crate) fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == ErrorKind::NotFound => Session::default(),
+            Err(e) => {
+                warn!("Could not read session from {}: {e}", path.to_string_lossy());
+                Session::default()
+            }
+        }
+    }
+
+    /// Parse a session file, skipping invalid entries.
+    pub(crate) fn parse(content: &str) -> Self {
+        let mut session = Session::default();
+        for (name, entries) in Config::parse(content).sections() {
+            if name == "session" {
+                for (key, value) in entries {
+                    match key.as_str() {
+                        "selected" if value == LOCAL_SECTION => session.selected = None,
+                        "selected" => session.selected = Url::parse(value).map_err(|e| warn!("Invalid selected relay {value}: {e}")).ok(),
+                        _ => debug!("Ignoring unknown session entry {key}"),
+                    }
+                }
+                continue;
+            }
+            let url = match name.strip_prefix("relay ") {
+                Some(url) => match Url::parse(url.trim()) {
+                    Ok(url) => Some(url),
+                    Err(e) => {
+                        warn!("Ignoring session of invalid relay {url}: {e}");
+                        continue;
+                    }
+                },
+                None if name == LOCAL_SECTION => None,
+                None => {
+                    debug!("Ignoring unknown session section {name}");
+                    continue;
+                }
+            };
+            session.relays.insert(url, RelaySession::from(entries));
+        }
+        session
+    }
+
+    /// Write the session file, only warning on failure.
+    pub(crate) fn save(&self, path: &Path) {
+        if let Err(e) = fs::write(path, self.to_string()) {
+            warn!("Could not save session to {}: {e}", path.to_string_lossy());
+        }
+    }
+}
+
+impl Display for Session {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[session]")?;
+        writeln!(f, "selected = {}", self.selected.as_ref().map_or(LOCAL_SECTION.to_string(), |url| url.to_string()))?;
+        for (url, relay) in self.relays.iter().sorted_by_key(|(url, _)| url.as_ref().map(|u| u.to_string())) {
+            writeln!(f)?;
+            match url {
+                None => writeln!(f, "[{LOCAL_SECTION}]")?,
+                Some(url) => writeln!(f, "[relay {url}]")?,
+            }
+            write!(f, "{relay}")?;
+        }
+        Ok(())
+    }
+}
+
+impl RelaySession {
+    fn from(entries: &[(String, String)]) -> Self {
+        let mut session = RelaySession::default();
+        let words = |value: &str| value.split_whitespace().map(|s| s.to_string()).collect_vec();
+        for (key, value) in entries {
+            match key.as_str() {
+                "position" => session.position = EventId::parse(value).map_err(|e| warn!("Invalid position {value}: {e}")).ok(),
+                "depth" => session.depth = value.parse().map_err(|e| warn!("Invalid depth {value}: {e}")).ok(),
+                "tags" => session.tags = words(value),
+                "excluded" => session.excluded = words(value),
+                "state" => session.state = Some(value.to_string()),
+                "repo" => session.repo = Some(value.to_string()),
+                "saved" => session.saved = value.parse::<u64>().map(Timestamp::from).ok(),
+                _ => debug!("Ignoring unknown session entry {key}"),
+            }
+        }
+        session
+    }
+}
+
+impl Display for RelaySession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(position) = &self.position {
+            writeln!(f, "position = {}", position.to_hex())?;
+        }
+        if let Some(depth) = self.depth {
+            writeln!(f, "depth = {depth}")?;
+        }
+        if !self.tags.is_empty() {
+            writeln!(f, "tags = {}", self.tags.join(" "))?;
+        }
+        if !self.excluded.is_empty() {
+            writeln!(f, "excluded = {}", self.excluded.join(" "))?;
+        }
+        if let Some(state) = &self.state {
+            writeln!(f, "state = {state}")?;
+        }
+        if let Some(repo) = &self.repo {
+            writeln!(f, "repo = {repo}")?;
+        }
+        if let Some(saved) = &self.saved {
+            writeln!(f, "saved = {}", saved.as_u64())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_session_round_trip() {
+    let url = Url::parse("wss://relay.example.com").unwrap();
+    let session = Session {
+        selected: Some(url.clone()),
+        relays: HashMap::from([
+            (Some(url), RelaySession {
+                position: Some(EventId::all_zeros()),
+                depth: Some(2),
+                tags: vec!["work".to_string(), "urgent".to_string()],
+                excluded: vec!["private".to_string()],
+                state: Some("Done".to_string()),
+                repo: None,
+                saved: Some(Timestamp::from(1714564800)),
+            }),
+            (None, RelaySession { depth: Some(1), ..Default::default() }),
+        ]),
+    };
+    assert_eq!(Session::parse(&session.to_string()), session);
+    assert_eq!(Session::parse(""), Session::default());
+
+    let corrupt = Session::parse("[session]\nselected = not a url\n[relay wss://relay.example.com]\nposition = nonsense\ndepth = 3\ngarbage\n[relay ::]\ndepth = 1");
+    assert_eq!(corrupt.selected, None);
+    assert_eq!(corrupt.relays.len(), 1);
+    assert_eq!(corrupt.relays.values().next().unwrap(), &RelaySession { depth: Some(3), ..Default::default() });
+}
@@ -1,6 +1,6 @@
 use fmt::Display;
 use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::string::ToString;
 
@@ -8,13 +8,17 @@ use colored::{ColoredString, Colorize};
 use itertools::Either::{Left, Right};
 use itertools::Itertools;
 use log::{debug, error, info, trace, warn};
-use nostr_sdk::{Event, EventId, Kind, Tag, TagStandard, Timestamp};
+use nostr_sdk::prelude::Marker;
+use nostr_sdk::{Coordinate, Event, EventId, Kind, PublicKey, Tag, TagStandard, Timestamp};
 
-use crate::helpers::{format_timestamp_local, some_non_empty};
-use crate::kinds::{is_hashtag, PROCEDURE_KIND, PROCEDURE_KIND_ID, TASK_KIND};
+use crate::helpers::{format_timestamp_local, format_timestamp_relative, some_non_empty};
+use crate::kinds::{is_hashtag, ASSIGNMENT_KIND, ATTACHMENT_KIND, DUE_TAG, EXPIRATION_KIND, PRIORITY_TAG, PROCEDURE_KIND, PROCEDURE_KIND_ID, REFERENCES_KIND, REPOSITORY_KIND, REVIEW_TAG, TASK_KIND};
 
 pub static MARKER_PARENT: &str = "parent";
 pub static MARKER_DEPENDS: &str = "depends";
+pub static MARKER_ASSIGNEE: &str = "assignee";
+/// Marker of the notes an edited note replaces
+pub static MARKER_SUPERSEDES: &str = "supersedes";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Task {
@@ -22,10 +26,16 @@ pub(crate) struct Task {
     pub(crate) event: Event,
     /// Cached sorted tags of the event with references remove - do not modify!
     pub(crate) tags: Option<BTreeSet<Tag>>,
-    /// Task references derived from the event tags
+    /// Task references derived from the event tags or the latest references update
     refs: Vec<(String, EventId)>,
+    /// Start of the content of an oversized event, used in its place for display and matching
+    excerpt: Option<String>,
+    /// Maximum number of tags processed from an event
+    max_tags: usize,
     /// Events belonging to this task, such as state updates and notes
     pub(crate) props: BTreeSet<Event>,
+    /// State updates left out when resolving the state, with the reason
+    pub(crate) discarded: HashMap<EventId, Discarded>,
 }
 
 impl PartialOrd<Self> for Task {
@@ -42,20 +52,40 @@ impl Ord for Task {
 
 impl Task {
     pub(crate) fn new(event: Event) -> Task {
-        let (refs, tags) = event.tags.iter().partition_map(|tag| match tag.as_standardized() {
-            Some(TagStandard::Event { event_id, marker, .. }) =>
-                Left((marker.as_ref().map_or(MARKER_PARENT.to_string(), |m| m.to_string()), *event_id)),
-            _ => Right(tag.clone()),
+        Self::sanitized(event, usize::MAX, usize::MAX)
+    }
+
+    /// Task using at most the given number of characters of the content and tags of the event,
+    /// which itself is kept complete
+    pub(crate) fn sanitized(event: Event, max_content: usize, max_tags: usize) -> Task {
+        let (refs, tags) = event.tags.iter().take(max_tags).partition_map(|tag| match tag_ref(tag) {
+            Some(reference) => Left(reference),
+            None => Right(tag.clone()),
         });
+        let excerpt = event.content.char_indices().nth(max_content)
+            .map(|(index, _)| format!("{}…", &event.content[..index]));
         // Separate refs for dependencies
         Task {
             props: Default::default(),
+            discarded: Default::default(),
             tags: Some(tags).filter(|t: &BTreeSet<Tag>| !t.is_empty()),
             refs,
+            excerpt,
+            max_tags,
             event,
         }
     }
 
+    /// Whether the content of the event is shortened for display
+    pub(crate) fn is_shortened(&self) -> bool {
+        self.excerpt.is_some()
+    }
+
+    /// Content of the event, shortened if it is oversized
+    fn content(&self) -> &str {
+        self.excerpt.as_deref().unwrap_or(&self.event.content)
+    }
+
     pub(crate) fn get_id(&self) -> &EventId {
         &self.event.id
     }
@@ -64,6 +94,24 @@ impl Task {
         self.refs.iter().filter_map(move |(str, id)| Some(id).filter(|_| str == marker))
     }
 
+    /// References to other tasks with their markers
+    pub(crate) fn refs(&self) -> impl Iterator<Item=&(String, EventId)> {
+        self.refs.iter()
+    }
+
+    /// Take the references from the latest references update if there is one,
+    /// to be called whenever such an update is added or removed.
+    pub(crate) fn update_refs(&mut self) {
+        let source = self.props.iter().rev()
+            .find(|event| event.kind == REFERENCES_KIND)
+            .unwrap_or(&self.event);
+        self.refs = source.tags.iter()
+            .take(self.max_tags)
+            .filter_map(tag_ref)
+            .filter(|(_, id)| id != &self.event.id)
+            .collect();
+    }
+
     pub(crate) fn parent_id(&self) -> Option<&EventId> {
         self.find_refs(MARKER_PARENT).next()
     }
@@ -72,24 +120,124 @@ impl Task {
         self.find_refs(MARKER_DEPENDS).collect()
     }
 
-    /// Trimmed event content or stringified id
+    /// Subject of NIP-34 issues, trimmed event content or stringified id
     pub(crate) fn get_title(&self) -> String {
-        some_non_empty(self.event.content.trim())
+        self.tag_value("subject").and_then(|subject| some_non_empty(subject.trim()))
+            .or_else(|| some_non_empty(self.content().trim()))
             .unwrap_or_else(|| self.get_id().to_string())
     }
 
     pub(crate) fn get_filter_title(&self) -> String {
-        self.event.content.trim().trim_start_matches('#').to_string()
+        self.content().trim().trim_start_matches('#').to_string()
+    }
+
+    /// Contents of the hashtags of the task
+    pub(crate) fn hashtags(&self) -> impl Iterator<Item=&str> + '_ {
+        self.tags.iter().flatten()
+            .filter(|tag| is_hashtag(tag))
+            .filter_map(|tag| tag.content())
     }
 
+    /// Notes in order of their first publication, edited ones in place of the notes they replace.
     pub(crate) fn description_events(&self) -> impl Iterator<Item=&Event> + '_ {
-        self.props.iter().filter(|event| event.kind == Kind::TextNote)
+        let notes = self.props.iter().filter(|event| event.kind == Kind::TextNote).collect_vec();
+        let replaced = notes.iter()
+            .flat_map(|&note| superseded(note).map(move |id| (id, note.pubkey)))
+            .collect::<HashSet<_>>();
+        notes.into_iter()
+            .filter(move |note| !replaced.contains(&(&note.id, note.pubkey)))
+            .sorted_by_key(|note| (published_at(note), note.id))
     }
 
     pub(crate) fn descriptions(&self) -> impl Iterator<Item=&String> + '_ {
         self.description_events().map(|e| &e.content)
     }
 
+    /// Notes with their depth in the discussion,
+    /// each followed by the replies to it in order of creation.
+    /// Replies to notes which are not known count as top-level notes.
+    pub(crate) fn description_thread(&self) -> Vec<(usize, &Event)> {
+        let notes = self.description_events().collect_vec();
+        // Replies to an edited note belong to its latest version
+        let parent = |note: &Event| reply_target(note)
+            .and_then(|id| notes.iter().find(|n| &n.id == id || superseded(n).any(|s| s == id)))
+            .map(|n| n.id);
+        let mut thread = Vec::with_capacity(notes.len());
+        let mut stack = notes.iter().rev()
+            .filter(|note| parent(note).is_none())
+            .map(|note| (0, *note))
+            .collect_vec();
+        while let Some((level, note)) = stack.pop() {
+            thread.push((level, note));
+            stack.extend(notes.iter().rev()
+                .filter(|reply| parent(reply) == Some(note.id))
+                .map(|reply| (level + 1, *reply)));
+        }
+        thread
+    }
+
+    pub(crate) fn reactions(&self) -> impl Iterator<Item=&Event> + '_ {
+        self.props.iter().filter(|event| event.kind == Kind::Reaction)
+    }
+
+    /// Attachments of URLs and files to this task, oldest first
+    pub(crate) fn attachments(&self) -> impl Iterator<Item=&Event> + '_ {
+        self.props.iter().filter(|event| event.kind == ATTACHMENT_KIND)
+    }
+
+    /// Latest assignment event of this task
+    pub(crate) fn assignment(&self) -> Option<&Event> {
+        self.props.iter().rev().find(|event| event.kind == ASSIGNMENT_KIND)
+    }
+
+    pub(crate) fn assignee(&self) -> Option<PublicKey> {
+        self.assignment()?.tags.iter().find_map(|tag| match tag.as_standardized() {
+            Some(TagStandard::PublicKey { public_key, alias: Some(alias), .. }) if alias == MARKER_ASSIGNEE =>
+                Some(*public_key),
+            _ => None,
+        })
+    }
+
+    /// Expiration from the latest update or else the event itself
+    pub(crate) fn expiration(&self) -> Option<Timestamp> {
+        match self.props.iter().rev().find(|event| event.kind == EXPIRATION_KIND) {
+            Some(update) => update.content.parse::<u64>().ok().map(Timestamp::from),
+            None => self.event.expiration().cloned(),
+        }
+    }
+
+    /// Value of the first tag of the event with the given name
+    fn tag_value(&self, name: &str) -> Option<&str> {
+        self.event.tags.iter().find_map(|tag| match tag.as_vec() {
+            [key, value, ..] if key == name => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn priority(&self) -> Option<char> {
+        self.tag_value(PRIORITY_TAG)?.chars().next()
+    }
+
+    pub(crate) fn due(&self) -> Option<Timestamp> {
+        self.tag_value(DUE_TAG)?.parse::<u64>().ok().map(Timestamp::from)
+    }
+
+    /// NIP-34 git repository from the latest update or else the event itself
+    pub(crate) fn repository(&self) -> Option<Coordinate> {
+        let source = self.props.iter().rev()
+            .find(|event| event.kind == REPOSITORY_KIND)
+            .unwrap_or(&self.event);
+        source.tags.iter().find_map(|tag| match tag.as_standardized() {
+            Some(TagStandard::Coordinate { coordinate, .. }) if coordinate.kind == Kind::GitRepoAnnouncement =>
+                Some(coordinate.clone()),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expiration().is_some_and(|stamp| stamp <= Timestamp::now())
+    }
+
     pub(crate) fn is_task_kind(&self) -> bool {
         self.event.kind == TASK_KIND
     }
@@ -99,14 +247,83 @@ impl Task {
             self.states().next().is_some()
     }
 
+    /// State updates which are not discarded, ordered by time with ties broken by id,
+    /// so all clients resolve the same state regardless of arrival order
+    fn state_events(&self) -> impl DoubleEndedIterator<Item=&Event> + '_ {
+        self.props.iter()
+            .filter(|event| State::try_from(event.kind).is_ok() && !self.discarded.contains_key(&event.id))
+            .sorted_by_key(|event| (event.created_at, event.id))
+    }
+
     fn states(&self) -> impl DoubleEndedIterator<Item=TaskState> + '_ {
-        self.props.iter().filter_map(|event| {
-            event.kind.try_into().ok().map(|s| TaskState {
-                name: some_non_empty(&event.content),
-                state: s,
-                time: event.created_at,
+        self.state_events().filter_map(state_from)
+    }
+
+    /// State updates left out when resolving the state, oldest first
+    pub(crate) fn discarded_states(&self) -> impl Iterator<Item=(TaskState, Discarded)> + '_ {
+        self.props.iter()
+            .filter_map(|event| self.discarded.get(&event.id).zip(state_from(event)))
+            .map(|(reason, state)| (state, *reason))
+    }
+
+    /// Discard the state updates of other people which conflict with one of the task creator,
+    /// being at most the given number of seconds apart so their order is unreliable.
+    pub(crate) fn overrule_conflicts(&mut self, window: u64) {
+        self.discarded.retain(|_, reason| reason != &Discarded::Overruled);
+        let owner = self.event.pubkey;
+        let (own, others): (Vec<&Event>, Vec<&Event>) = self.state_events().partition(|event| event.pubkey == owner);
+        let overruled = others.into_iter()
+            .filter(|other| own.iter().any(|event| event.created_at.as_u64().abs_diff(other.created_at.as_u64()) <= window))
+            .map(|event| event.id)
+            .collect_vec();
+        for id in overruled {
+            self.discarded.insert(id, Discarded::Overruled);
+        }
+    }
+
+    /// State of the task before the state event with the given id,
+    /// the default state if there was none before it
+    pub(crate) fn state_before(&self, id: &EventId) -> Option<TaskState> {
+        let events = self.state_events().collect_vec();
+        let index = events.iter().position(|e| &e.id == id)?;
+        Some(events[..index].last()
+            .and_then(|event| state_from(event))
+            .unwrap_or_else(|| self.default_state()))
+    }
+
+    /// Time of the creation or the newest update of this task which is not in the future
+    pub(crate) fn last_activity(&self) -> Timestamp {
+        let now = Timestamp::now();
+        self.props.iter().rev()
+            .map(|event| event.created_at)
+            .find(|stamp| stamp <= &now)
+            .map_or(self.event.created_at, |stamp| stamp.max(self.event.created_at))
+    }
+
+    /// Time the task was completed or closed, none while it is open
+    pub(crate) fn completed_at(&self) -> Option<Timestamp> {
+        self.state()
+            .filter(|s| !s.state.is_open())
+            .map(|s| s.time)
+    }
+
+    /// States the task went through until the given time, starting with the default state at creation,
+    /// each with the seconds spent in it until the next one
+    pub(crate) fn state_timeline(&self, until: Timestamp) -> Vec<(TaskState, u64)> {
+        let states = self.states()
+            .filter(|s| s.time <= until)
+            .collect_vec();
+        let initial = Some(self.default_state())
+            .filter(|_| states.first().map_or(true, |s| s.time > self.event.created_at));
+        let states = initial.into_iter().chain(states).collect_vec();
+        let ends = states.iter().skip(1).map(|s| s.time).chain([until]).collect_vec();
+        states.into_iter()
+            .zip(ends)
+            .map(|(state, end)| {
+                let duration = end.as_u64().saturating_sub(state.time.as_u64());
+                (state, duration)
             })
-        })
+            .collect()
     }
 
     pub(crate) fn last_state_update(&self) -> Timestamp {
@@ -126,6 +343,29 @@ impl Task {
         })
     }
 
+    /// Event of the current state, skipping planned updates
+    fn state_event(&self) -> Option<&Event> {
+        let now = Timestamp::now();
+        self.state_events().rev()
+            .find(|event| event.created_at <= now)
+    }
+
+    /// Whether the current state asks the given person for a review
+    pub(crate) fn awaits_review_by(&self, reviewer: &PublicKey) -> bool {
+        self.state_event().is_some_and(|event|
+            review_step(event) == Some("request") && event.public_keys().any(|key| key == reviewer))
+    }
+
+    /// Whether the current state is an approval or rejection, with its reviewer
+    pub(crate) fn review_verdict(&self) -> Option<(bool, PublicKey)> {
+        let event = self.state_event()?;
+        match review_step(event)? {
+            "approved" => Some((true, event.pubkey)),
+            "rejected" => Some((false, event.pubkey)),
+            _ => None,
+        }
+    }
+
     pub(crate) fn pure_state(&self) -> State {
         self.state().map_or(State::Open, |s| s.state)
     }
@@ -146,6 +386,7 @@ impl Task {
             name: None,
             state: State::Open,
             time: self.event.created_at,
+            author: self.event.pubkey,
         }
     }
 
@@ -166,9 +407,15 @@ impl Task {
             // Static
             "id" => Some(self.event.id.to_string()),
             "parentid" => self.parent_id().map(|i| i.to_string()),
-            "name" => Some(self.event.content.clone()),
+            "name" => Some(self.content().to_string()),
             "pubkey" => Some(self.event.pubkey.to_string()),
             "created" => Some(format_timestamp_local(&self.event.created_at)),
+            "expires" => self.expiration().map(|stamp| format_timestamp_relative(&stamp)),
+            "completed" => self.completed_at().map(|stamp| format_timestamp_local(&stamp)),
+            "priority" => self.priority().map(|p| p.to_string()),
+            "due" => self.due().map(|stamp| format_timestamp_relative(&stamp)),
+            "repo" => self.repository().map(|repo| repo.identifier),
+            "attachments" => Some(self.attachments().count()).filter(|count| *count > 0).map(|count| count.to_string()),
             "kind" => Some(self.event.kind.to_string()),
             // Dynamic
             "status" => self.state_label().map(|c| c.to_string()),
@@ -197,15 +444,90 @@ impl Task {
     }
 }
 
+/// Step of a review the state update marks
+fn review_step(event: &Event) -> Option<&str> {
+    event.tags.iter().find_map(|tag| match tag.as_vec() {
+        [key, value, ..] if key == REVIEW_TAG => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+/// Notes replaced by this edited note
+pub(crate) fn superseded(event: &Event) -> impl Iterator<Item=&EventId> {
+    event.tags.iter().filter_map(|tag| match tag.as_standardized() {
+        Some(TagStandard::Event { event_id, marker: Some(Marker::Custom(marker)), .. }) if marker == MARKER_SUPERSEDES => Some(event_id),
+        _ => None,
+    })
+}
+
+/// When the content was first published, which edits carry over from the replaced note
+pub(crate) fn published_at(event: &Event) -> Timestamp {
+    event.tags.iter().find_map(|tag| match tag.as_standardized() {
+        Some(TagStandard::PublishedAt(stamp)) => Some(*stamp),
+        _ => None,
+    }).unwrap_or(event.created_at)
+}
+
+/// Note which the given note replies to, marked as reply per NIP-10
+pub(crate) fn reply_target(event: &Event) -> Option<&EventId> {
+    event.tags.iter().find_map(|tag| match tag.as_standardized() {
+        Some(TagStandard::Event { event_id, marker: Some(Marker::Reply), .. }) => Some(event_id),
+        _ => None,
+    })
+}
+
+/// State set by the event, if it is a state event
+fn state_from(event: &Event) -> Option<TaskState> {
+    event.kind.try_into().ok().map(|s| TaskState {
+        name: some_non_empty(&event.content),
+        state: s,
+        time: event.created_at,
+        author: event.pubkey,
+    })
+}
+
+/// Reference to another task with its marker if the tag is an event tag,
+/// unmarked references denoting the parent
+fn tag_ref(tag: &Tag) -> Option<(String, EventId)> {
+    match tag.as_standardized() {
+        Some(TagStandard::Event { event_id, marker, .. }) =>
+            Some((marker.as_ref().map_or(MARKER_PARENT.to_string(), |m| m.to_string()), *event_id)),
+        _ => None,
+    }
+}
+
 pub(crate) struct TaskState {
     pub(crate) state: State,
     name: Option<String>,
     pub(crate) time: Timestamp,
+    /// Who set the state, the task creator for the default state
+    pub(crate) author: PublicKey,
+}
+/// Why a state update is left out when resolving the state of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Discarded {
+    /// Dated too far ahead of its arrival
+    Skewed,
+    /// Conflicting with an update by the task creator
+    Overruled,
+}
+impl Display for Discarded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discarded::Skewed => write!(f, "dated too far ahead"),
+            Discarded::Overruled => write!(f, "conflicting with the creator"),
+        }
+    }
 }
+
 impl TaskState {
     pub(crate) fn get_label_for(state: &State, comment: &str) -> String {
         some_non_empty(comment).unwrap_or_else(|| state.to_string())
     }
+    /// Comment given with the state, empty if there is none
+    pub(crate) fn comment(&self) -> &str {
+        self.name.as_deref().unwrap_or_default()
+    }
     pub(crate) fn get_label(&self) -> String {
         self.name.clone().unwrap_or_else(|| self.state.to_string())
     }
@@ -259,6 +581,7 @@ impl TryFrom<&str> for State {
         }
     }
 }
+
 impl TryFrom<Kind> for State {
     type Error = ();
 
@@ -283,6 +606,17 @@ impl State {
         matches!(self, State::Open | State::Pending | State::Procedure)
     }
 
+    /// Position when sorting by state, from actionable to finished
+    pub(crate) fn ordinal(&self) -> u64 {
+        match self {
+            State::Open => 0,
+            State::Procedure => 1,
+            State::Pending => 2,
+            State::Done => 3,
+            State::Closed => 4,
+        }
+    }
+
     pub(crate) fn kind(self) -> u16 {
         self as u16
     }
@@ -296,6 +630,17 @@ impl State {
             State::Procedure => str.blue(),
         }
     }
+
+    /// CSS color matching [State::colorize]
+    pub(crate) fn html_color(&self) -> &'static str {
+        match self {
+            State::Open => "#2e7d32",
+            State::Done => "#757575",
+            State::Closed => "#8e24aa",
+            State::Pending => "#b8860b",
+            State::Procedure => "#1565c0",
+        }
+    }
 }
 impl From<State> for Kind {
     fn from(value: State) -> Self {
@@ -1,48 +1,108 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
-use std::io::{stdout, Error, Write};
+use std::io::{stdout, BufRead, Error, Write};
 use std::iter::{empty, once, FusedIterator};
-use std::ops::{Div, Rem};
+use std::ops::{Deref, Div, Rem};
+use std::process::{Command, Stdio};
 use std::str::FromStr;
+use std::thread;
 use std::time::Duration;
 
-use crate::helpers::{format_timestamp_local, format_timestamp_relative, format_timestamp_relative_to, parse_tracking_stamp, some_non_empty, CHARACTER_THRESHOLD};
+use crate::cache::{read_events, write_events, EventCache};
+use crate::config::{Creation, DesktopNotifications, Hooks, Ownership, RelayDefaults, Settings, TagHierarchy};
+use crate::helpers::{clip_text, day_start, escape_csv, escape_html, format_timestamp, format_timestamp_local, format_timestamp_relative, format_timestamp_relative_to, local_date, parse_tracking_stamp, replace_mentions, some_non_empty, strip_ansi, truncate_width, ToTimestamp};
+use crate::ical::{calendar, escape_text, Component};
 use crate::kinds::*;
-use crate::task::{State, Task, TaskState, MARKER_DEPENDS, MARKER_PARENT};
-use crate::{EventSender, MostrMessage};
-use colored::Colorize;
+use crate::session::RelaySession;
+use crate::task::{published_at, superseded, Discarded, State, Task, TaskState, MARKER_DEPENDS, MARKER_PARENT, MARKER_SUPERSEDES};
+use crate::taskwarrior::parse_export;
+use crate::todotxt::TodoItem;
+use crate::{EventSender, MostrMessage, Queued};
+use colored::{Color, Colorize};
 use itertools::{Either, Itertools};
 use log::{debug, error, info, trace, warn};
+use nostr_sdk::nips::nip19::Nip19Profile;
 use nostr_sdk::prelude::Marker;
-use nostr_sdk::{Event, EventBuilder, EventId, JsonUtil, Keys, Kind, Metadata, PublicKey, Tag, TagStandard, Timestamp, UncheckedUrl, Url};
+use nostr_sdk::serde_json::{json, Value};
+use nostr_sdk::{serde_json, Coordinate, Event, EventBuilder, EventId, FromBech32, JsonUtil, Keys, Kind, Metadata, PublicKey, Tag, TagStandard, Timestamp, ToBech32, UncheckedUrl, Url};
 use regex::bytes::Regex;
+use serde::Serialize;
 use tokio::sync::mpsc::Sender;
 use TagStandard::Hashtag;
 
-const MAX_OFFSET: u64 = 9;
-fn now() -> Timestamp {
-    Timestamp::now() + MAX_OFFSET
+/// Failed attempts after which an unsorted update is parked as orphan
+const OVERFLOW_ATTEMPTS: u32 = 5;
+/// Properties holding a point in time
+const TIMESTAMP_PROPERTIES: [&str; 6] = ["created", "updated", "age", "completed", "expires", "due"];
+/// Age after which an unsorted update is parked as orphan
+const OVERFLOW_MAX_AGE: Duration = Duration::from_secs(600);
+/// Number of own changes which can be undone after they were sent
+const JOURNAL_SIZE: usize = 50;
+/// Marker in front of bookmarked tasks in listings
+const BOOKMARK_MARKER: &str = "★";
+
+/// Tasks by id with an index of the children of each task
+#[derive(Debug, Clone, Default)]
+struct TaskMap {
+    tasks: HashMap<EventId, Task>,
+    /// Ids of the tasks referencing each parent, under None for tasks without parent
+    children: HashMap<Option<EventId>, HashSet<EventId>>,
 }
+impl Deref for TaskMap {
+    type Target = HashMap<EventId, Task>;
 
-type TaskMap = HashMap<EventId, Task>;
-trait TaskMapMethods {
-    fn children_of<'a>(&'a self, task: &'a Task) -> impl Iterator<Item=&Task> + 'a;
-    fn children_for<'a>(&'a self, id: Option<&'a EventId>) -> impl Iterator<Item=&Task> + 'a;
-    fn children_ids_for<'a>(&'a self, id: &'a EventId) -> impl Iterator<Item=&EventId> + 'a;
+    fn deref(&self) -> &Self::Target {
+        &self.tasks
+    }
 }
-impl TaskMapMethods for TaskMap {
-    fn children_of<'a>(&'a self, task: &'a Task) -> impl Iterator<Item=&Task> + 'a {
+impl TaskMap {
+    fn insert(&mut self, task: Task) {
+        self.children.entry(task.parent_id().cloned()).or_default().insert(*task.get_id());
+        self.tasks.insert(*task.get_id(), task);
+    }
+
+    fn remove(&mut self, id: &EventId) -> Option<Task> {
+        let task = self.tasks.remove(id)?;
+        self.unindex(task.parent_id().cloned(), id);
+        Some(task)
+    }
+
+    /// Modify a task, moving it in the children index if its parent changes.
+    fn modify<R>(&mut self, id: &EventId, f: impl FnOnce(&mut Task) -> R) -> Option<R> {
+        let task = self.tasks.get_mut(id)?;
+        let parent = task.parent_id().cloned();
+        let result = f(task);
+        let new_parent = task.parent_id().cloned();
+        if new_parent != parent {
+            self.unindex(parent, id);
+            self.children.entry(new_parent).or_default().insert(*id);
+        }
+        Some(result)
+    }
+
+    fn unindex(&mut self, parent: Option<EventId>, id: &EventId) {
+        if let Some(siblings) = self.children.get_mut(&parent) {
+            siblings.remove(id);
+            if siblings.is_empty() {
+                self.children.remove(&parent);
+            }
+        }
+    }
+
+    fn children_of(&self, task: &Task) -> impl Iterator<Item=&Task> + '_ {
         self.children_for(Some(task.get_id()))
     }
 
-    fn children_for<'a>(&'a self, id: Option<&'a EventId>) -> impl Iterator<Item=&Task> + 'a {
-        self.values()
-            .filter(move |t| t.parent_id() == id)
+    fn children_for(&self, id: Option<&EventId>) -> impl Iterator<Item=&Task> + '_ {
+        self.children_ids_for(id)
+            .filter_map(|id| self.tasks.get(id))
     }
 
-    fn children_ids_for<'a>(&'a self, id: &'a EventId) -> impl Iterator<Item=&EventId> + 'a {
-        self.children_for(Some(id))
-            .map(|t| t.get_id())
+    /// Ids of the children of the given task, which need not be known itself.
+    fn children_ids_for(&self, id: Option<&EventId>) -> impl Iterator<Item=&EventId> + '_ {
+        self.children.get(&id.cloned()).into_iter().flatten()
     }
 }
 
@@ -52,10 +112,33 @@ pub(crate) struct TasksRelay {
     tasks: TaskMap,
     /// History of active tasks by PubKey
     history: HashMap<PublicKey, BTreeMap<Timestamp, Event>>,
+    /// Seconds tracked per task and user in pruned history
+    tracked_before: HashMap<PublicKey, HashMap<EventId, u64>>,
+    /// Key of the own history event defining the current position,
+    /// valid until the next planned position change if any
+    position_cache: Cell<Option<(Option<Timestamp>, Option<Timestamp>)>>,
+    /// Topmost known ancestor of tasks with the unknown parent it refers to if any,
+    /// cleared whenever tasks or their references change
+    root_cache: RefCell<HashMap<EventId, (EventId, Option<EventId>)>>,
+    /// Ids of subtrees by root, whether closed subtasks are included and depth,
+    /// so columns summing up subtrees do not walk them repeatedly - cleared whenever events change
+    subtree_cache: RefCell<HashMap<(EventId, bool, usize), Vec<EventId>>>,
     /// Index of known users with metadata
     users: HashMap<PublicKey, Metadata>,
+    /// Users encountered without metadata since the last request
+    unknown_users: RefCell<HashSet<PublicKey>>,
+    /// Users whose metadata was requested in this session
+    requested_users: RefCell<HashSet<PublicKey>>,
     /// Own pinned tasks
     bookmarks: Vec<EventId>,
+    /// Latest own bookmarks event
+    bookmarks_event: Option<Event>,
+    /// People followed according to the latest own contact list
+    follows: HashSet<PublicKey>,
+    /// Latest own contact list event
+    contacts_event: Option<Event>,
+    /// Ids of deleted events with the author of the deletion
+    deleted: HashMap<EventId, PublicKey>,
 
     /// The task properties currently visible
     properties: Vec<String>,
@@ -72,12 +155,66 @@ pub(crate) struct TasksRelay {
     tags: BTreeSet<Tag>,
     /// Tags filtered out from view
     tags_excluded: BTreeSet<Tag>,
+    /// Tags added to new tasks regardless of the filters
+    creation_tags: BTreeSet<Tag>,
+    /// Authors to limit the view to, in the order they were added
+    authors: Vec<PublicKey>,
+    /// Git repository to limit the view to
+    repo_filter: Option<Coordinate>,
     /// Current active state
     state: StateFilter,
+    /// Only show tasks by oneself and followed people
+    follows_only: bool,
+    /// Show closed tasks and their subtrees with the default state filter
+    show_closed: bool,
+    /// List bookmarks alongside the tasks of any position
+    show_bookmarks: bool,
+    /// Configured view to start out with when the relay is selected
+    defaults: RelayDefaults,
+    settings: Settings,
 
     sender: EventSender,
-    overflow: VecDeque<Event>,
+    /// Updates whose task is not known yet
+    overflow: VecDeque<Orphan>,
+    /// Updates given up on, kept for inspection
+    orphans: Vec<Orphan>,
+    /// Incoming events which were cut down or rejected
+    sanitized: Sanitized,
+    /// Tasks received again since the last report
+    duplicates: usize,
+    /// End of the previous session, tasks others changed since then are new until reviewed
+    reviewed: Option<Timestamp>,
     pub(crate) custom_time: Option<Timestamp>,
+    /// Time for backfilling events across commands, advanced with each event
+    pub(crate) sticky_time: Option<Timestamp>,
+    /// Whether to notify people about assignments and mentions via direct message
+    pub(crate) notify: bool,
+    /// Whether completing a procedure step moves on to the next one
+    pub(crate) advance: bool,
+    /// Commands to run on task events
+    pub(crate) hooks: Hooks,
+    /// Whether hooks are run, can be switched off for a session
+    pub(crate) hooks_enabled: bool,
+    /// Categories of desktop notifications about updates by others
+    pub(crate) desktop: DesktopNotifications,
+    /// Whether desktop notifications are suppressed for the session
+    pub(crate) quiet: bool,
+    /// Time up to which tasks coming due were announced
+    due_checked: Timestamp,
+    /// Hashtags which also match when filtering by their parent
+    pub(crate) tag_hierarchy: TagHierarchy,
+    /// Own task creations, updates and time-tracking of this session, newest last,
+    /// to be undone by compensating events once they were sent
+    journal: VecDeque<EventId>,
+    /// Tasks of others whose change was repeated after a warning
+    changes_confirmed: HashSet<EventId>,
+
+    /// Number of events received from the relay
+    received: usize,
+    /// When the last event was received from the relay
+    last_received: Option<Timestamp>,
+    /// Whether the relay has sent all stored events
+    synced: bool,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -86,6 +223,8 @@ pub(crate) enum StateFilter {
     Default,
     All,
     State(String),
+    /// Tasks awaiting a review by the given person
+    Review(PublicKey),
 }
 impl StateFilter {
     fn indicator(&self) -> String {
@@ -93,14 +232,16 @@ impl StateFilter {
             StateFilter::Default => "".to_string(),
             StateFilter::All => " ?ALL".to_string(),
             StateFilter::State(str) => format!(" ?{str}"),
+            StateFilter::Review(_) => " ?review".to_string(),
         }
     }
 
     fn matches(&self, task: &Task) -> bool {
         match self {
-            StateFilter::Default => task.pure_state().is_open(),
+            StateFilter::Default => task.pure_state().is_open() && !task.is_expired(),
             StateFilter::All => true,
             StateFilter::State(filter) => task.state().is_some_and(|t| t.matches_label(filter)),
+            StateFilter::Review(reviewer) => task.awaits_review_by(reviewer),
         }
     }
 
@@ -121,6 +262,7 @@ impl Display for StateFilter {
                 StateFilter::Default => "open tasks".to_string(),
                 StateFilter::All => "all tasks".to_string(),
                 StateFilter::State(s) => format!("state {s}"),
+                StateFilter::Review(_) => "tasks awaiting your review".to_string(),
             }
         )
     }
@@ -132,9 +274,19 @@ impl TasksRelay {
         tx: &Sender<MostrMessage>,
         keys: &Keys,
         metadata: Option<Metadata>,
+        cache: Option<EventCache>,
+        defaults: RelayDefaults,
+        settings: &Settings,
     ) -> Self {
-        let mut new = Self::with_sender(EventSender::from(url, tx, keys));
+        let local = url.is_none();
+        let mut new = Self::with_sender(EventSender::from(url, tx, keys, cache, settings.undo_delay));
+        new.synced = local;
         metadata.map(|m| new.users.insert(keys.public_key(), m));
+        new.properties = settings.columns.clone();
+        new.depth = settings.depth;
+        new.settings = settings.clone();
+        new.defaults = defaults;
+        new.reset_view();
         new
     }
 
@@ -142,17 +294,20 @@ impl TasksRelay {
         TasksRelay {
             tasks: Default::default(),
             history: Default::default(),
+            tracked_before: Default::default(),
+            position_cache: Default::default(),
+            root_cache: Default::default(),
+            subtree_cache: Default::default(),
             users: Default::default(),
+            unknown_users: Default::default(),
+            requested_users: Default::default(),
             bookmarks: Default::default(),
+            bookmarks_event: None,
+            follows: Default::default(),
+            contacts_event: None,
+            deleted: Default::default(),
 
-            properties: [
-                "author",
-                "state",
-                "rtime",
-                "hashtags",
-                "rpath",
-                "desc",
-            ].into_iter().map(|s| s.to_string()).collect(),
+            properties: Settings::default().columns,
             sorting: [
                 "state",
                 "author",
@@ -164,30 +319,203 @@ impl TasksRelay {
             view: Default::default(),
             tags: Default::default(),
             tags_excluded: Default::default(),
+            creation_tags: Default::default(),
+            authors: Default::default(),
+            repo_filter: None,
             state: Default::default(),
+            follows_only: false,
+            show_closed: false,
+            show_bookmarks: true,
+            defaults: Default::default(),
+            settings: Default::default(),
             depth: 1,
 
             sender,
             overflow: Default::default(),
+            orphans: Default::default(),
+            sanitized: Default::default(),
+            duplicates: 0,
+            reviewed: None,
             custom_time: None,
+            sticky_time: None,
+            notify: false,
+            advance: false,
+            hooks: Default::default(),
+            hooks_enabled: true,
+            desktop: Default::default(),
+            quiet: false,
+            due_checked: Timestamp::now(),
+            tag_hierarchy: Default::default(),
+            journal: Default::default(),
+            changes_confirmed: Default::default(),
+
+            received: 0,
+            last_received: None,
+            synced: true,
+        }
+    }
+
+    /// Adopt changed settings, which only affect views that are set up later
+    pub(crate) fn apply_settings(&mut self, settings: &Settings) {
+        self.settings = settings.clone();
+        self.sender.undo_delay = settings.undo_delay;
+    }
+
+    /// The current time, including position changes shifted slightly into the future
+    fn now(&self) -> Timestamp {
+        Timestamp::now() + self.settings.max_offset
+    }
+
+    /// Count an event received from the relay
+    pub(crate) fn receive(&mut self, event: Event) {
+        self.received += 1;
+        self.last_received = Some(Timestamp::now());
+        // Others' state changes trigger hooks once stored events are through
+        let hooked = (self.synced && self.hooks.others && event.pubkey != self.sender.pubkey() && State::try_from(event.kind).is_ok())
+            .then(|| event.clone());
+        let notified = (self.synced && !self.quiet && event.pubkey != self.sender.pubkey()).then(|| event.clone());
+        self.add(event);
+        if let Some(event) = hooked {
+            self.run_hook(&event);
+        }
+        if let Some((summary, body)) = notified.and_then(|event| self.desktop_notification(&event)) {
+            show_desktop_notification(&summary, &body);
+        }
+    }
+
+    /// Summary and body of the desktop notification about an update by someone else,
+    /// if its category is enabled
+    fn desktop_notification(&self, event: &Event) -> Option<(String, String)> {
+        let own = self.sender.pubkey();
+        if event.pubkey == own {
+            return None;
+        }
+        let id = *referenced_event(event)?;
+        let task = self.get_by_id(&id)?;
+        let author = self.get_author(&event.pubkey, None);
+        let path = self.get_task_path(Some(id));
+        match event.kind {
+            kind if kind == ASSIGNMENT_KIND => (self.desktop.assigned && task.assignee() == Some(own))
+                .then(|| (format!("{author} assigned you"), path)),
+            Kind::TextNote => (self.desktop.mentioned && event.public_keys().any(|key| key == &own))
+                .then(|| (format!("{author} mentioned you on {path}"), event.content.trim().to_string())),
+            kind => {
+                let state = State::try_from(kind).ok().filter(|state| !state.is_open())?;
+                (self.desktop.finished && task.event.pubkey == own)
+                    .then(|| (format!("{author} marked \"{}\" {}", task.get_title(), TaskState::get_label_for(&state, event.content())), path))
+            }
+        }
+    }
+
+    /// Show desktop notifications for own and assigned tasks
+    /// which came due or were resumed after being deferred since the last call.
+    pub(crate) fn notify_due(&mut self) {
+        let now = Timestamp::now();
+        let since = std::mem::replace(&mut self.due_checked, now);
+        if self.quiet || !self.desktop.due {
+            return;
+        }
+        for (summary, body) in self.due_between(since, now) {
+            show_desktop_notification(&summary, &body);
+        }
+    }
+
+    /// Notifications for own and assigned open tasks which came due
+    /// or were resumed after being deferred within the given range, excluding its start
+    fn due_between(&self, since: Timestamp, until: Timestamp) -> Vec<(String, String)> {
+        let own = self.sender.pubkey();
+        let within = |time: Timestamp| since < time && time <= until;
+        self.tasks.values()
+            .filter(|task| task.event.pubkey == own || task.assignee() == Some(own))
+            .filter(|task| task.pure_state().is_open())
+            .filter_map(|task| {
+                let timeline = task.state_timeline(until);
+                let resumed = match timeline.as_slice() {
+                    [.., (previous, _), (current, _)] => previous.state == State::Pending && current.state != State::Pending && within(current.time),
+                    _ => false,
+                };
+                let summary = if resumed {
+                    format!("Resumed \"{}\"", task.get_title())
+                } else if task.due().is_some_and(within) {
+                    format!("Due: \"{}\"", task.get_title())
+                } else {
+                    return None;
+                };
+                Some((summary, self.get_task_path(Some(*task.get_id()))))
+            })
+            .sorted()
+            .collect()
+    }
+
+    /// Mark whether all stored events have been received from the relay
+    pub(crate) fn set_synced(&mut self, synced: bool) {
+        let completed = synced && !self.synced;
+        self.synced = synced;
+        if completed {
+            self.announce_updates();
         }
     }
 
+    pub(crate) fn sync_status(&self) -> String {
+        format!("{} {} events",
+                if self.synced { "synced" } else { "syncing..." },
+                self.received)
+    }
+
+    /// Summary of the data held for this relay
+    pub(crate) fn status(&self) -> String {
+        format!("{} tasks, {} tracking events, {} unsent, {} orphans{}, {}{}",
+                self.len(),
+                self.history.values().map(|h| h.len()).sum::<usize>(),
+                self.sender.queued(),
+                self.orphans.len(),
+                self.sanitized,
+                self.sync_status(),
+                self.last_received.map(|t| format!(", last received {}", format_timestamp_relative(&t))).unwrap_or_default())
+    }
+
+    /// Retry sorting updates whose task was not known yet.
+    /// Updates failing too often or for too long are parked as orphans.
     pub(crate) fn process_overflow(&mut self) {
         let elements = self.overflow.len();
-        let mut issues = 0;
+        let min = Timestamp::now() - OVERFLOW_MAX_AGE;
+        let mut parked = 0;
         for _ in 0..elements {
-            if let Some(event) = self.overflow.pop_back() {
-                if let Some(event) = self.add_prop(event) {
-                    warn!("Unable to sort Event {:?}", event);
-                    issues += 1;
-                    //self.overflow.push_back(event);
+            let Some(mut orphan) = self.overflow.pop_front() else { break };
+            if let Some(event) = self.add_prop(orphan.event) {
+                orphan.event = event;
+                orphan.attempts += 1;
+                if orphan.attempts >= OVERFLOW_ATTEMPTS || orphan.since < min {
+                    debug!("Parking orphaned event {:?}", orphan.event);
+                    self.orphans.push(orphan);
+                    parked += 1;
+                } else {
+                    self.overflow.push_back(orphan);
                 }
             }
         }
-        if elements > 0 {
-            info!("Reprocessed {elements} updates with {issues} issues{}", self.sender.url.clone().map(|url| format!(" from {url}")).unwrap_or_default());
+        let sorted = elements - self.overflow.len() - parked;
+        if sorted > 0 || parked > 0 {
+            info!("Sorted {sorted} of {elements} updates{}{}",
+                if parked > 0 { format!(", parked {parked} as orphans") } else { String::new() },
+                self.sender.url.clone().map(|url| format!(" from {url}")).unwrap_or_default());
         }
+        self.prune_bookmarks();
+    }
+
+    /// Updates given up on because their task never arrived
+    pub(crate) fn orphans(&self) -> &[Orphan] {
+        &self.orphans
+    }
+
+    /// Requeue all orphans and try sorting them again.
+    /// Returns the number of sorted updates.
+    pub(crate) fn retry_orphans(&mut self) -> usize {
+        let pending = self.overflow.len() + self.orphans.len();
+        let orphans = self.orphans.drain(..).map(|o| Orphan::new(o.event)).collect_vec();
+        self.overflow.extend(orphans);
+        self.process_overflow();
+        pending - self.overflow.len() - self.orphans.len()
     }
 
     // Accessors
@@ -198,23 +526,43 @@ impl TasksRelay {
     #[inline]
     pub(crate) fn len(&self) -> usize { self.tasks.len() }
 
+    /// Relay changes are sent to, none for local tasks
+    pub(crate) fn url(&self) -> Option<&Url> { self.sender.url.as_ref() }
+
     pub(crate) fn get_position(&self) -> Option<EventId> {
         self.get_position_ref().cloned()
     }
 
     pub(crate) fn get_position_ref(&self) -> Option<&EventId> {
-        self.get_position_at(now()).1
+        let now = self.now();
+        let history = self.get_own_history()?;
+        let key = match self.position_cache.get() {
+            Some((key, valid_until)) if valid_until.map_or(true, |stamp| now < stamp) => key,
+            _ => {
+                let key = history.range(..=now).next_back().map(|(stamp, _)| *stamp);
+                let valid_until = history.range(now..).map(|(stamp, _)| *stamp).find(|stamp| stamp > &now);
+                self.position_cache.set(Some((key, valid_until)));
+                key
+            }
+        };
+        key.and_then(|stamp| history.get(&stamp)).and_then(referenced_event)
     }
 
-    // TODO binary search
     /// Gets last position change before the given timestamp
     fn get_position_at(&self, timestamp: Timestamp) -> (Timestamp, Option<&EventId>) {
-        self.history_from(timestamp)
-            .last()
-            .filter(|e| e.created_at <= timestamp)
+        self.get_own_history()
+            .and_then(|history| history.range(..=timestamp).next_back())
             .map_or_else(
                 || (Timestamp::now(), None),
-                |e| (e.created_at, referenced_event(e)))
+                |(stamp, e)| (*stamp, referenced_event(e)))
+    }
+
+    /// All events held by this relay: tasks with their properties, time-tracking and unsorted updates.
+    pub(crate) fn all_events(&self) -> impl Iterator<Item=&Event> {
+        self.tasks.values()
+            .flat_map(|t| once(&t.event).chain(t.props.iter()))
+            .chain(self.history.values().flat_map(|h| h.values()))
+            .chain(self.overflow.iter().chain(self.orphans.iter()).map(|o| &o.event))
     }
 
     pub(crate) fn all_hashtags(&self) -> impl Iterator<Item=&str> {
@@ -227,6 +575,32 @@ impl TasksRelay {
             .dedup()
     }
 
+    /// Known hashtags with the ones that have a parent in the tag hierarchy
+    /// listed in parentheses after it, like `work(clientA clientB)`
+    pub(crate) fn grouped_hashtags(&self) -> Vec<String> {
+        let known = self.all_hashtags().collect_vec();
+        let mut groups: Vec<(String, Vec<&str>)> = vec![];
+        for tag in &known {
+            match self.tag_hierarchy.parent(tag) {
+                Some(parent) => match groups.iter_mut().find(|(p, _)| p.eq_ignore_ascii_case(parent)) {
+                    Some((_, children)) => children.push(tag),
+                    None => groups.push((parent.to_string(), vec![tag])),
+                },
+                None => if !groups.iter().any(|(p, _)| p.eq_ignore_ascii_case(tag)) {
+                    groups.push((tag.to_string(), vec![]));
+                },
+            }
+        }
+        groups.into_iter()
+            .sorted_by_key(|(parent, _)| parent.to_lowercase())
+            .map(|(parent, children)| if children.is_empty() {
+                parent
+            } else {
+                format!("{parent}({})", children.join(" "))
+            })
+            .collect()
+    }
+
     /// Dynamic time tracking overview for current task or current user.
     pub(crate) fn times_tracked(&self) -> (String, Box<dyn DoubleEndedIterator<Item=String>>) {
         self.times_tracked_for(&self.sender.pubkey())
@@ -236,6 +610,7 @@ impl TasksRelay {
         match self.get_position_ref() {
             None => {
                 if let Some(hist) = self.history.get(key) {
+                    let now = Timestamp::now();
                     let mut last = None;
                     let mut full = Vec::with_capacity(hist.len());
                     for event in hist.values() {
@@ -243,9 +618,13 @@ impl TasksRelay {
                             .filter_map(|t| t.content())
                             .map(|str| EventId::from_str(str).ok().map_or(str.to_string(), |id| self.get_task_path(Some(id))))
                             .join(" "));
-                        if new != last {
+                        let planned = event.created_at > now;
+                        if (&new, planned) != (&last, false) {
                             // TODO alternate color with grey between days
-                            full.push(format!("{} {}", format_timestamp_local(&event.created_at), new.as_ref().unwrap_or(&"---".to_string())));
+                            full.push(format!("{}{} {}",
+                                              format_timestamp_local(&event.created_at),
+                                              if planned { " (planned)" } else { "" },
+                                              new.as_ref().unwrap_or(&"---".to_string())));
                             last = new;
                         }
                     }
@@ -258,43 +637,133 @@ impl TasksRelay {
             Some(id) => {
                 // TODO consider pubkey
                 let ids = vec![id];
+                let now = Timestamp::now();
+                let mut planned = Vec::new();
                 let history =
                     self.history.iter().flat_map(|(key, set)| {
-                        let mut vec = Vec::with_capacity(set.len() / 2);
-                        let mut iter = timestamps(set.values(), &ids).tuples();
+                        let (past, future) = timestamps(set.values(), &ids, now);
+                        let mut vec = Vec::with_capacity(past.len() / 2);
+                        let mut iter = past.into_iter().tuples();
                         while let Some(((start, _), (end, _))) = iter.next() {
                             vec.push(format!("{} - {} by {}",
                                              format_timestamp_local(start),
                                              format_timestamp_relative_to(end, start),
-                                             self.get_author(key)))
+                                             self.get_author(key, None)))
                         }
                         iter.into_buffer()
                             .for_each(|(stamp, _)|
-                                vec.push(format!("{} started by {}", format_timestamp_local(stamp), self.get_author(key))));
+                                vec.push(format!("{} started by {}", format_timestamp_local(stamp), self.get_author(key, None))));
+                        for (stamp, target) in future {
+                            planned.push((*stamp, format!("{} will {} \"{}\" from {}",
+                                                         self.get_author(key, None),
+                                                         if target.is_some() { "track" } else { "stop tracking" },
+                                                         self.get_task_title(id),
+                                                         format_timestamp_local(stamp))));
+                        }
                         vec
-                    }).sorted_unstable(); // TODO sorting depends on timestamp format - needed to interleave different people
-                (format!("Times Tracked on {:?}", self.get_task_title(id)), Box::from(history))
+                    }).sorted_unstable() // TODO sorting depends on timestamp format - needed to interleave different people
+                        .collect_vec();
+                planned.sort_unstable();
+                (format!("Times Tracked on {:?}", self.get_task_title(id)),
+                 Box::from(history.into_iter().chain(planned.into_iter().map(|(_, line)| line))))
             }
         }
     }
 
     /// Total time in seconds tracked on this task by the current user.
     pub(crate) fn time_tracked(&self, id: EventId) -> u64 {
-        Durations::from(self.get_own_events_history(), &vec![&id]).sum::<Duration>().as_secs()
+        Durations::from(self.get_own_events_history(), &vec![&id]).sum::<Duration>().as_secs() +
+            self.tracked_before.get(&self.sender.pubkey()).and_then(|t| t.get(&id)).unwrap_or(&0)
     }
 
 
-    /// Total time in seconds tracked on this task and its subtasks by all users.
-    fn total_time_tracked(&self, id: EventId) -> u64 {
-        let mut total = 0;
+    /// Total time in seconds tracked on this task and its subtasks by all users,
+    /// optionally skipping closed subtrees.
+    fn total_time_tracked(&self, id: EventId, closed: bool) -> u64 {
+        self.total_time_tracked_to(id, closed, usize::MAX)
+    }
 
-        let children = ChildIterator::from(&self, &id).get_all();
+    /// Total time in seconds tracked by all users on this task
+    /// and its subtasks down to the given depth, 0 for only the task itself,
+    /// optionally skipping closed subtrees.
+    fn total_time_tracked_to(&self, id: EventId, closed: bool, depth: usize) -> u64 {
+        let mut total = 0;
+        let subtree = self.subtree_ids(id, closed, depth);
+        let children = subtree.iter().collect_vec();
         for user in self.history.values() {
             total += Durations::from(user.values(), &children).sum::<Duration>().as_secs();
         }
+        for user in self.tracked_before.values() {
+            total += children.iter().filter_map(|id| user.get(*id)).sum::<u64>();
+        }
         total
     }
 
+    /// Ids of the task and its subtasks down to the given depth, optionally skipping closed subtrees
+    fn subtree_ids(&self, id: EventId, closed: bool, depth: usize) -> Vec<EventId> {
+        if let Some(ids) = self.subtree_cache.borrow().get(&(id, closed, depth)) {
+            return ids.clone();
+        }
+        let collapsed = |task: &Task| !closed && task.get_id() != &id && task.pure_state() == State::Closed;
+        let ids = ChildIterator::from(self, &id)
+            .get_depth_filtered(depth, |task|
+                if collapsed(task) { ChildIteratorFilter::Reject } else { ChildIteratorFilter::Take })
+            .into_iter()
+            // Rejected tasks are still listed, only their children are skipped
+            .filter(|child| !self.get_by_id(child).is_some_and(collapsed))
+            .cloned()
+            .collect_vec();
+        self.subtree_cache.borrow_mut().insert((id, closed, depth), ids.clone());
+        ids
+    }
+
+    /// Start of the time-tracking history kept for other people
+    fn retention_start(&self) -> Option<Timestamp> {
+        self.defaults.retention.map(|days| Timestamp::now() - days * 86_400)
+    }
+
+    /// Compact the time-tracking history of other people to the retention period.
+    /// Returns the number of dropped events.
+    pub(crate) fn prune_history(&mut self) -> usize {
+        let Some(start) = self.retention_start() else { return 0 };
+        let own = self.sender.pubkey();
+        let keys = self.history.keys().filter(|k| **k != own).cloned().collect_vec();
+        let pruned = keys.iter().map(|key| self.prune_history_of(key, start)).sum();
+        if pruned > 0 {
+            debug!("Pruned {pruned} tracking events before {}", format_timestamp_local(&start));
+        }
+        pruned
+    }
+
+    /// Compact the own time-tracking history before the given time in memory,
+    /// keeping the tracked durations.
+    /// Returns the number of dropped events.
+    pub(crate) fn prune_own_history(&mut self, before: Timestamp) -> usize {
+        self.position_cache.take();
+        let own = self.sender.pubkey();
+        self.prune_history_of(&own, before)
+    }
+
+    /// Drop the tracking events of the user before the given time,
+    /// except for the last one if nothing follows it,
+    /// summing up their durations per task.
+    fn prune_history_of(&mut self, key: &PublicKey, before: Timestamp) -> usize {
+        let Some(history) = self.history.get_mut(key) else { return 0 };
+        let totals = self.tracked_before.entry(*key).or_default();
+        let stamps = history.range(..before).map(|(stamp, _)| *stamp).collect_vec();
+        let mut pruned = 0;
+        for stamp in stamps {
+            let Some(end) = history.range(stamp..).nth(1).map(|(end, _)| *end) else { break };
+            if let Some(event) = history.remove(&stamp) {
+                for id in referenced_events(&event) {
+                    *totals.entry(*id).or_default() += end.as_u64() - stamp.as_u64();
+                }
+                pruned += 1;
+            }
+        }
+        pruned
+    }
+
     fn total_progress(&self, id: &EventId) -> Option<f32> {
         self.get_by_id(id).and_then(|task| match task.pure_state() {
             State::Closed => None,
@@ -302,7 +771,7 @@ impl TasksRelay {
             _ => {
                 let mut sum = 0f32;
                 let mut count = 0;
-                for prog in self.tasks.children_ids_for(task.get_id()).filter_map(|e| self.total_progress(e)) {
+                for prog in self.tasks.children_ids_for(Some(task.get_id())).filter_map(|e| self.total_progress(e)) {
                     sum += prog;
                     count += 1;
                 }
@@ -317,6 +786,21 @@ impl TasksRelay {
         })
     }
 
+    /// Completed and total leaf tasks of the subtree, ignoring closed tasks,
+    /// where a completed task counts all of its leaves as completed.
+    fn leaf_progress(&self, id: &EventId) -> Option<(usize, usize)> {
+        let task = self.get_by_id(id)?;
+        let state = task.pure_state();
+        if state == State::Closed {
+            return None;
+        }
+        let (done, total) = self.tasks.children_ids_for(Some(id))
+            .filter_map(|child| self.leaf_progress(child))
+            .fold((0, 0), |(done, total), (d, t)| (done + d, total + t));
+        let total = total.max(1);
+        Some((if state == State::Done { total } else { done }, total))
+    }
+
     // Parents
 
     pub(crate) fn up_by(&self, count: usize) -> Option<&EventId> {
@@ -337,6 +821,13 @@ impl TasksRelay {
             .map(|t| format!(" #{}", t.content().unwrap()))
             .chain(self.tags_excluded.iter()
                 .map(|t| format!(" -#{}", t.content().unwrap())))
+            .chain(self.creation_tags.iter()
+                .map(|t| format!(" #+{}", t.content().unwrap())))
+            .chain(Some(&self.authors).filter(|a| !a.is_empty())
+                .map(|authors| format!(" @{}", authors.iter().map(|key| self.get_author(key, None)).join(","))))
+            .chain(Some(" @+".to_string()).filter(|_| self.follows_only))
+            .chain(Some(" ?+closed".to_string()).filter(|_| self.show_closed))
+            .chain(self.repo_filter.as_ref().map(|repo| format!(" repo:{}", repo.identifier)))
             .chain(once(self.state.indicator()))
             .join("")
     }
@@ -356,6 +847,18 @@ impl TasksRelay {
         }
     }
 
+    /// Topmost known ancestor of the task, which is the task itself at the root,
+    /// with the unknown parent it refers to if any
+    fn project_root(&self, id: EventId) -> Option<(EventId, Option<EventId>)> {
+        if let Some(root) = self.root_cache.borrow().get(&id) {
+            return Some(*root);
+        }
+        let root = self.traverse_up_from(Some(id)).last()
+            .map(|t| (t.event.id, t.parent_id().cloned()))?;
+        self.root_cache.borrow_mut().insert(id, root);
+        Some(root)
+    }
+
     fn relative_path(&self, id: EventId) -> String {
         join_tasks(
             self.traverse_up_from(Some(id))
@@ -381,7 +884,7 @@ impl TasksRelay {
         depth: usize,
     ) -> Vec<&'a Task> {
         iter.flat_map(move |task| {
-            if !self.state.matches(task) {
+            if !self.matches_state(task) {
                 return vec![]
             }
             let new_depth = depth - 1;
@@ -405,7 +908,7 @@ impl TasksRelay {
         for tag in event.tags.iter() {
             if let Some(TagStandard::Event { event_id, marker, .. }) = tag.as_standardized() {
                 if marker.is_none() {
-                    self.tasks.get_mut(event_id).map(|t| {
+                    self.tasks.modify(event_id, |t| {
                         found = true;
                         f(t)
                     });
@@ -420,16 +923,49 @@ impl TasksRelay {
         self.get_position_ref().and_then(|id| self.get_by_id(id))
     }
 
+    /// Whether closed tasks are skipped along with their subtrees,
+    /// which is the case for the default state filter unless they are explicitly shown
+    fn collapses_closed(&self) -> bool {
+        matches!(self.state, StateFilter::Default) && !self.show_closed
+    }
+
+    /// Whether the task is closed or below a closed task and thus hidden
+    fn is_collapsed(&self, id: &EventId) -> bool {
+        self.collapses_closed() &&
+            self.traverse_up_from(Some(*id)).any(|t| t.pure_state() == State::Closed)
+    }
+
+    fn matches_state(&self, task: &Task) -> bool {
+        self.state.matches(task) || (self.show_closed && task.pure_state() == State::Closed)
+    }
+
     fn filter(&self, task: &Task) -> bool {
-        self.state.matches(task) &&
+        self.matches_state(task) &&
+            (!self.follows_only || task.event.pubkey == self.sender.pubkey() || self.follows.contains(&task.event.pubkey)) &&
+            (self.authors.is_empty() || self.authors.contains(&task.event.pubkey)) &&
             task.tags.as_ref().map_or(true, |tags| {
-                !tags.iter().any(|tag| self.tags_excluded.contains(tag))
+                !tags.iter().any(|tag| self.tags_excluded.iter().any(|excluded| same_tag(tag, excluded)))
             }) &&
             (self.tags.is_empty() ||
                 task.tags.as_ref().map_or(false, |tags| {
-                    let mut iter = tags.iter();
-                    self.tags.iter().all(|tag| iter.any(|t| t == tag))
-                }))
+                    self.tags.iter().all(|tag| self.matches_tag(tags, tag))
+                })) &&
+            self.repo_filter.as_ref().map_or(true, |repo| task.repository().is_some_and(|r| same_repository(&r, repo)))
+    }
+
+    /// Whether any of the tags matches the filter tag,
+    /// which for a hashtag includes the hashtags below it in the hierarchy
+    fn matches_tag(&self, tags: &BTreeSet<Tag>, filter: &Tag) -> bool {
+        match filter.content().filter(|_| is_hashtag(filter)) {
+            Some(hashtag) => {
+                let expanded = self.tag_hierarchy.expand(hashtag);
+                tags.iter()
+                    .filter(|t| is_hashtag(t))
+                    .filter_map(|t| t.content())
+                    .any(|content| expanded.iter().any(|e| e.to_lowercase() == content.to_lowercase()))
+            }
+            None => tags.iter().any(|t| same_tag(t, filter)),
+        }
     }
 
     pub(crate) fn filtered_tasks<'a>(&'a self, position: Option<&'a EventId>, sparse: bool) -> Vec<&'a Task> {
@@ -452,12 +988,12 @@ impl TasksRelay {
 
         let ids = current.iter().map(|t| t.get_id()).collect_vec();
         let mut bookmarks =
-            if sparse && current.is_empty() {
+            if !self.show_bookmarks || (sparse && current.is_empty()) {
                 vec![]
             } else {
-                // TODO highlight bookmarks
                 self.bookmarks.iter()
                     .filter(|id| !position.is_some_and(|p| &p == id) && !ids.contains(id))
+                    .filter(|id| !self.is_collapsed(id))
                     .filter_map(|id| self.get_by_id(id))
                     .filter(|t| self.filter(t))
                     .collect_vec()
@@ -478,19 +1014,20 @@ impl TasksRelay {
     }
 
     pub(crate) fn print_tasks(&self) -> Result<(), Error> {
-        let mut lock = stdout().lock();
+        self.write_tasks(&mut stdout().lock())
+    }
+
+    /// Write the current task with the visible tasks as a table.
+    pub(crate) fn write_tasks(&self, lock: &mut impl Write) -> Result<(), Error> {
+        if !self.synced {
+            writeln!(lock, "{}", format!("Still receiving events from the relay ({} so far), tasks may be incomplete", self.received).italic())?;
+        }
         if let Some(t) = self.get_current_task() {
             let state = t.state_or_default();
-            let now = &now();
-            let mut tracking_stamp: Option<Timestamp> = None;
-            for elem in
-                timestamps(self.get_own_events_history(), &[t.get_id()])
-                    .map(|(e, _)| e) {
-                if tracking_stamp.is_some() && elem > now {
-                    break;
-                }
-                tracking_stamp = Some(*elem)
-            }
+            let (past, _) = timestamps(self.get_own_events_history(), &[t.get_id()], self.now());
+            let tracking_stamp = past.last()
+                .filter(|(_, id)| id.is_some())
+                .map(|(stamp, _)| **stamp);
             writeln!(
                 lock,
                 "Tracking since {} (total tracked time {}m) - {} since {}",
@@ -499,48 +1036,275 @@ impl TasksRelay {
                 state.get_label(),
                 format_timestamp_relative(&state.time)
             )?;
-            writeln!(lock, "{}", t.descriptions().join("\n"))?;
+            writeln!(lock, "{}", self.format_notes_clipped(t).join("\n"))?;
+            let reactions = self.reactions(t);
+            if !reactions.is_empty() {
+                writeln!(lock, "{}", reactions.join("  ").italic())?;
+            }
+            for (index, reference) in t.attachments().filter_map(attachment).enumerate() {
+                writeln!(lock, "{} {}", format!("Attachment {}:", index + 1).dimmed(), reference)?;
+            }
         }
 
-        let mut tasks = self.visible_tasks();
+        let tasks = self.listed_tasks();
         if tasks.is_empty() {
             let (label, times) = self.times_tracked();
             let mut times_recent = times.rev().take(6).collect_vec();
             times_recent.reverse();
             // TODO Add recent prefix
             writeln!(lock, "{}\n{}", label.italic(), times_recent.join("\n"))?;
+            self.fetch_unknown_users();
             return Ok(());
         }
 
         // TODO proper column alignment
         // TODO hide empty columns
         writeln!(lock, "{}", self.properties.join("\t").bold())?;
-        let mut total_time = 0;
-        let count = tasks.len();
-        tasks.sort_by_cached_key(|task| {
-            self.sorting
-                .iter()
+        for task in &tasks {
+            let row = self.properties.iter()
                 .map(|p| self.get_property(task, p.as_str()))
-                .collect_vec()
-        });
-        for task in tasks {
-            writeln!(
-                lock,
-                "{}",
-                self.properties.iter()
-                    .map(|p| self.get_property(task, p.as_str()))
-                    .join(" \t")
-            )?;
-            if self.depth < 2 || task.parent_id() == self.get_position_ref() {
-                total_time += self.total_time_tracked(task.event.id)
+                .join(" \t");
+            if self.bookmarks.contains(task.get_id()) {
+                let row = format!("{BOOKMARK_MARKER} {row}");
+                if task.parent_id() == self.get_position_ref() {
+                    writeln!(lock, "{}", row)?;
+                } else {
+                    // Bookmark listed out of context
+                    writeln!(lock, "{}", row.dimmed())?;
+                }
+            } else {
+                writeln!(lock, "{}", row)?;
             }
         }
-        if total_time > 0 {
-            writeln!(lock, "{} visible tasks{}", count, display_time(" tracked a total of HHhMMm", total_time))?;
+        let mut footer = self.properties.iter()
+            .map(|p| self.aggregate_property(&tasks, p))
+            .collect_vec();
+        if footer.iter().all(|cell| cell.is_empty()) {
+            let total_time = self.outermost(&tasks)
+                .map(|task| self.total_time_tracked(task.event.id, !self.collapses_closed()))
+                .sum();
+            if total_time > 0 {
+                writeln!(lock, "{} visible tasks{}", tasks.len(), display_time(" tracked a total of HHhMMm", total_time))?;
+            }
+        } else {
+            if let Some(label) = footer.iter_mut().find(|cell| cell.is_empty()) {
+                *label = format!("{} tasks", tasks.len());
+            }
+            writeln!(lock, "{}", self.properties.iter().map(|p| "-".repeat(p.len())).join(" \t").dimmed())?;
+            writeln!(lock, "{}", footer.join(" \t").bold())?;
         }
+        self.fetch_unknown_users();
         Ok(())
     }
 
+    /// Summary of the property over the listed tasks for the footer row:
+    /// the sum of tracked times and attachments, the average progress and the done of total subtasks,
+    /// empty where it makes no sense.
+    /// Times summing up subtrees only count tasks without listed parent task, so no time is counted twice.
+    fn aggregate_property(&self, tasks: &[&Task], property: &str) -> String {
+        let raw = |task: &Task| self.get_raw_property(task, property);
+        match property {
+            "time" | "dtime" => display_time("HH:MM", tasks.iter().filter_map(|t| raw(*t).as_u64()).sum()),
+            "rtime" => display_time("HH:MM", self.outermost(tasks).filter_map(|t| raw(t).as_u64()).sum()),
+            prop if rtime_depth(prop).is_some() => display_time("HH:MM", self.outermost(tasks).filter_map(|t| raw(t).as_u64()).sum()),
+            "attachments" => Some(tasks.iter().filter_map(|t| raw(*t).as_u64()).sum::<u64>())
+                .filter(|sum| *sum > 0)
+                .map_or(String::new(), |sum| sum.to_string()),
+            "progress" | "wprogress" => {
+                let values = tasks.iter().filter_map(|t| raw(*t).as_f64()).collect_vec();
+                if values.is_empty() {
+                    String::new()
+                } else {
+                    format!("{:2.0}%", values.iter().sum::<f64>() / values.len() as f64 * 100.0)
+                }
+            }
+            "subtasks" | "rsubtasks" => {
+                let (done, total) = tasks.iter()
+                    .map(|t| if property == "subtasks" { self.subtask_counts(t) } else { self.rsubtask_counts(t) })
+                    .fold((0, 0), |(done, total), (d, t)| (done + d, total + t));
+                if total > 0 { format!("{done}/{total}") } else { String::new() }
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// The tasks without any parent task among them
+    fn outermost<'a>(&'a self, tasks: &'a [&'a Task]) -> impl Iterator<Item=&'a Task> + 'a {
+        let ids = tasks.iter().map(|t| t.get_id()).collect::<HashSet<_>>();
+        tasks.iter().copied()
+            .filter(move |task| !self.traverse_up_from(task.parent_id().cloned()).any(|parent| ids.contains(parent.get_id())))
+    }
+
+    /// Done and total direct subtasks of the task, not counting closed ones
+    fn subtask_counts(&self, task: &Task) -> (usize, usize) {
+        self.tasks.children_of(task)
+            .map(|t| t.pure_state())
+            .filter(|state| state != &State::Closed)
+            .fold((0, 0), |(done, total), state| (done + usize::from(state == State::Done), total + 1))
+    }
+
+    /// Done and total subtasks of the task in the whole subtree, not counting closed ones
+    fn rsubtask_counts(&self, task: &Task) -> (usize, usize) {
+        ChildIterator::from(self, task.get_id()).get_all().into_iter()
+            .skip(1)
+            .filter_map(|id| self.get_by_id(id))
+            .map(|t| t.pure_state())
+            .filter(|state| state != &State::Closed)
+            .fold((0, 0), |(done, total), state| (done + usize::from(state == State::Done), total + 1))
+    }
+
+    /// Visible tasks in the configured sorting
+    fn listed_tasks(&self) -> Vec<&Task> {
+        let mut tasks = self.visible_tasks();
+        // ties fall back to creation so the order does not depend on the index
+        tasks.sort_by_cached_key(|task| {
+            (self.sorting
+                 .iter()
+                 .map(|p| self.get_sort_key(task, p.as_str()))
+                 .collect_vec(),
+             task.event.created_at,
+             task.event.id)
+        });
+        tasks
+    }
+
+    /// Write the listed tasks as CSV with a column for each configured property,
+    /// giving durations in seconds and times in ISO 8601.
+    /// Returns the number of tasks written.
+    pub(crate) fn export_csv(&self, writer: &mut impl Write) -> Result<usize, Error> {
+        let tasks = self.listed_tasks();
+        writeln!(writer, "{}", self.properties.iter().map(|p| escape_csv(p)).join(","))?;
+        for task in &tasks {
+            writeln!(writer, "{}", self.properties.iter()
+                .map(|p| match self.get_raw_property(task, p) {
+                    Value::Null => String::new(),
+                    Value::String(str) => escape_csv(&str),
+                    Value::Number(n) if TIMESTAMP_PROPERTIES.contains(&p.as_str()) => n.as_u64()
+                        .map(|stamp| format_timestamp(&Timestamp::from(stamp), "%Y-%m-%dT%H:%M:%S%:z"))
+                        .unwrap_or_default(),
+                    value => value.to_string(),
+                })
+                .join(","))?;
+        }
+        Ok(tasks.len())
+    }
+
+    /// Titles and state labels of the visible tasks,
+    /// to describe their changes later on.
+    pub(crate) fn snapshot(&self) -> HashMap<EventId, (String, String)> {
+        self.visible_tasks().into_iter()
+            .map(|t| (t.event.id, (t.get_title(), t.state_or_default().get_label())))
+            .collect()
+    }
+
+    /// Describe how the visible tasks changed since the snapshot,
+    /// naming whoever touched each task last.
+    pub(crate) fn describe_changes(&self, before: &HashMap<EventId, (String, String)>) -> Vec<String> {
+        let after = self.snapshot();
+        let changed = after.iter()
+            .filter(|(id, entry)| before.get(id) != Some(entry))
+            .chain(before.iter().filter(|(id, _)| !after.contains_key(id)));
+        let mut lines = changed
+            .map(|(id, (title, _))| match self.get_by_id(id) {
+                None => format!("\"{title}\" was removed"),
+                Some(task) => {
+                    let latest = task.props.iter().next_back().unwrap_or(&task.event);
+                    let author = self.get_author(&latest.pubkey, None);
+                    if latest == &task.event {
+                        format!("New task \"{title}\" by {author}")
+                    } else {
+                        format!("\"{title}\" marked {} by {author}", task.state_or_default().get_label())
+                    }
+                }
+            })
+            .collect_vec();
+        lines.sort();
+        lines
+    }
+
+    /// Value of the property for machine-readable output without colors,
+    /// see [TIMESTAMP_PROPERTIES]
+    /// with durations in seconds and timestamps in unix seconds
+    fn get_raw_property(&self, task: &Task, str: &str) -> Value {
+        let id = *task.get_id();
+        let stamp = |stamp: Option<Timestamp>| stamp.map_or(Value::Null, |s| json!(s.as_u64()));
+        match str {
+            "time" => json!(self.time_tracked(id)),
+            "rtime" => json!(self.total_time_tracked(id, !self.collapses_closed())),
+            "dtime" => json!(self.total_time_tracked_to(id, true, 0)),
+            prop if rtime_depth(prop).is_some() =>
+                json!(self.total_time_tracked_to(id, !self.collapses_closed(), rtime_depth(prop).unwrap())),
+            "created" => stamp(Some(task.event.created_at)),
+            "updated" | "age" => stamp(Some(self.last_activity(task))),
+            "completed" => stamp(task.completed_at()),
+            "expires" => stamp(task.expiration()),
+            "due" => stamp(task.due()),
+            "attachments" => json!(task.attachments().count()),
+            "progress" => self.total_progress(&id)
+                .filter(|_| self.tasks.children_of(task).next().is_some())
+                .map_or(Value::Null, |p| json!(p)),
+            "wprogress" => self.leaf_progress(&id)
+                .filter(|_| self.tasks.children_of(task).next().is_some())
+                .map_or(Value::Null, |(done, total)| json!(done as f32 / total as f32)),
+            prop => some_non_empty(&strip_ansi(&self.get_property(task, prop))).map_or(Value::Null, Value::String),
+        }
+    }
+
+    /// Tasks of the current listing, or all tasks below the position,
+    /// with the properties of the configured columns
+    pub(crate) fn task_views(&self, all: bool) -> Vec<TaskView> {
+        let tasks = if all {
+            ChildIterator::rooted(&self.tasks, self.get_position_ref()).get_all().into_iter()
+                .filter_map(|id| self.get_by_id(id))
+                .collect_vec()
+        } else {
+            self.listed_tasks()
+        };
+        tasks.into_iter().map(|task| TaskView {
+            id: task.event.id.to_string(),
+            parent: task.parent_id().map(|id| id.to_string()),
+            state: task.state_or_default().get_label(),
+            hashtags: task.hashtags().map(|tag| tag.to_string()).collect(),
+            tracked: self.total_time_tracked(task.event.id, !self.collapses_closed()),
+            path: strip_ansi(&self.get_task_path(Some(task.event.id))),
+            properties: self.properties.iter()
+                .filter(|p| !TaskView::FIELDS.contains(&p.as_str()))
+                .map(|p| (p.clone(), self.get_raw_property(task, p)))
+                .collect(),
+        }).collect()
+    }
+
+    /// JSON array of the [TaskView]s of the current listing or all tasks below the position
+    pub(crate) fn to_json(&self, all: bool) -> String {
+        serde_json::to_string_pretty(&self.task_views(all)).unwrap_or_default()
+    }
+
+    /// Value of the property to sort by, see [get_raw_property](Self::get_raw_property)
+    fn get_sort_key(&self, task: &Task, str: &str) -> SortKey {
+        match str {
+            "state" => SortKey::Number(task.pure_state().ordinal()),
+            "author" => SortKey::String(self.get_author(&task.event.pubkey, None)),
+            prop => match self.get_raw_property(task, prop) {
+                Value::Number(n) if TIMESTAMP_PROPERTIES.contains(&prop) => SortKey::Timestamp(n.as_u64().map(Timestamp::from)),
+                Value::Null if TIMESTAMP_PROPERTIES.contains(&prop) => SortKey::Timestamp(None),
+                // progress is a fraction
+                Value::Number(n) => SortKey::Number(n.as_u64().unwrap_or_else(|| (n.as_f64().unwrap_or_default() * 1000.0) as u64)),
+                Value::String(str) => SortKey::String(str),
+                _ => SortKey::String(String::new()),
+            },
+        }
+    }
+
+    /// Time of the latest change of the task or time-tracking on it by anyone
+    fn last_activity(&self, task: &Task) -> Timestamp {
+        let now = self.now();
+        self.history.values()
+            .filter_map(|history| history.range(..=now).rev()
+                .find(|(_, event)| referenced_events(event).contains(task.get_id()))
+                .map(|(stamp, _)| *stamp))
+            .fold(task.last_activity(), Timestamp::max)
+    }
+
     fn get_property(&self, task: &Task, str: &str) -> String {
         let mut children = self.tasks.children_of(task).peekable();
         let progress =
@@ -549,13 +1313,7 @@ impl TasksRelay {
         let prog_string = progress.map_or(String::new(), |p| format!("{:2.0}%", p * 100.0));
         match str {
             "subtasks" => {
-                let mut total = 0;
-                let mut done = 0;
-                for subtask in children {
-                    let state = subtask.pure_state();
-                    total += &(state != State::Closed).into();
-                    done += &(state == State::Done).into();
-                }
+                let (done, total) = self.subtask_counts(task);
                 if total > 0 {
                     format!("{done}/{total}")
                 } else {
@@ -563,135 +1321,726 @@ impl TasksRelay {
                 }
             }
             "state" => {
+                if task.is_expired() {
+                    return State::Closed.colorize("Expired").to_string();
+                }
                 if let Some(task) = task.get_dependendees().iter().filter_map(|id| self.get_by_id(id)).find(|t| t.pure_state().is_open()) {
                     return format!("Blocked by \"{}\"", task.get_title()).bright_red().to_string();
                 }
                 let state = task.pure_state();
-                if state.is_open() && progress.is_some_and(|p| p > 0.1) {
+                let label = if state.is_open() && progress.is_some_and(|p| p > 0.1) {
                     state.colorize(&prog_string)
                 } else {
                     task.state_label().unwrap_or_default()
-                }.to_string()
+                }.to_string();
+                match task.review_verdict() {
+                    Some((approved, reviewer)) =>
+                        format!("{label} {} {}", if approved { "✓" } else { "✗" }, self.get_author(&reviewer, None)),
+                    None => match self.state_changer(task) {
+                        Some(changer) => format!("{label} {}", changer.dimmed()),
+                        None => label,
+                    },
+                }
             }
             "progress" => prog_string.clone(),
+            "wprogress" => self.leaf_progress(task.get_id())
+                .filter(|_| children.peek().is_some())
+                .map_or(String::new(), |(done, total)| format!("{:2.0}%", done as f32 / total as f32 * 100.0)),
+            "rsubtasks" => {
+                let (done, total) = self.rsubtask_counts(task);
+                if total > 0 {
+                    format!("{done}/{total}")
+                } else {
+                    "".to_string()
+                }
+            }
 
-            "author" => format!("{:.6}", self.get_author(&task.event.pubkey)), // FIXME temporary until proper column alignment
+            "author" => self.get_author(&task.event.pubkey, Some(self.settings.author_width).filter(|w| *w > 0))
+                .color(author_color(&task.event.pubkey))
+                .to_string(),
             "path" => self.get_task_path(Some(task.event.id)),
             "rpath" => self.relative_path(task.event.id),
+            "project" => match self.project_root(task.event.id) {
+                Some((_, Some(unknown))) => unknown.to_hex()[..8].to_string(),
+                Some((root, None)) => self.get_task_title(&root),
+                None => String::new(),
+            },
             // TODO format strings configurable
             "time" => display_time("MMMm", self.time_tracked(*task.get_id())),
-            "rtime" => display_time("HH:MM", self.total_time_tracked(*task.get_id())),
+            "rtime" => display_time("HH:MM", self.total_time_tracked(*task.get_id(), !self.collapses_closed())),
+            "dtime" => display_time("HH:MM", self.total_time_tracked_to(*task.get_id(), true, 0)),
+            prop if rtime_depth(prop).is_some() =>
+                display_time("HH:MM", self.total_time_tracked_to(*task.get_id(), !self.collapses_closed(), rtime_depth(prop).unwrap())),
+            "reactions" => self.reactions(task).join(" "),
+            "updated" => format_timestamp_local(&self.last_activity(task)),
+            "age" => format_timestamp_relative(&self.last_activity(task)),
+            "depends" => task.get_dependendees().into_iter()
+                .map(|id| self.dependency_label(id))
+                .join(", "),
+            "dependents" => self.tasks.values()
+                .filter(|t| t.get_dependendees().contains(&task.get_id()))
+                .sorted_by_key(|t| t.event.created_at)
+                .map(|t| self.dependency_label(t.get_id()))
+                .join(", "),
+            "statechanger" => task.state().map(|s| self.get_author(&s.author, None)).unwrap_or_default(),
+            "assignee" => task.assignee().map(|key| self.get_author(&key, None)).unwrap_or_default(),
+            "desc" | "description" => self.display_mentions(&task.get(str).unwrap_or_default()),
             prop => task.get(prop).unwrap_or_default(),
         }
     }
 
-    pub(crate) fn get_author(&self, pubkey: &PublicKey) -> String {
-        self.users.get(pubkey)
-            .and_then(|m| m.name.clone())
-            .unwrap_or_else(|| format!("{:.6}", pubkey.to_string()))
+    /// Title of a task in a dependency, red while open and dimmed once done or closed
+    fn dependency_label(&self, id: &EventId) -> String {
+        let title = self.get_task_title(id);
+        match self.get_by_id(id).map(|t| t.pure_state()) {
+            Some(state) if state.is_open() => title.bright_red().to_string(),
+            Some(_) => title.dimmed().to_string(),
+            None => title,
+        }
     }
 
-    // Movement and Selection
-
-    /// Toggle bookmark on the given id.
-    /// Returns whether it was added (true) or removed (false).
-    pub(crate) fn toggle_bookmark(&mut self, id: EventId) -> nostr_sdk::Result<bool> {
-        let added = match self.bookmarks.iter().position(|b| b == &id) {
-            None => {
-                self.bookmarks.push(id);
-                true
-            }
-            Some(pos) => {
-                self.bookmarks.remove(pos);
-                false
+    /// Reactions on the task aggregated by content,
+    /// each with its count and the names of the people who reacted.
+    fn reactions(&self, task: &Task) -> Vec<String> {
+        let mut reactions: BTreeMap<&str, Vec<&PublicKey>> = BTreeMap::new();
+        for event in task.reactions() {
+            let authors = reactions.entry(event.content.as_str()).or_default();
+            if !authors.contains(&&event.pubkey) {
+                authors.push(&event.pubkey);
             }
-        };
-        self.sender.submit(
-            EventBuilder::new(Kind::Bookmarks, "mostr pins",
-                              self.bookmarks.iter().map(|id| Tag::event(*id))))?;
-        Ok(added)
+        }
+        reactions.into_iter()
+            .map(|(content, authors)| format!("{content}{} ({})",
+                                              authors.len(),
+                                              authors.into_iter().map(|key| self.get_author(key, None)).sorted().join(", ")))
+            .collect_vec()
     }
 
-    pub(crate) fn set_filter_author(&mut self, key: PublicKey) -> bool {
-        self.set_filter(|t| t.event.pubkey == key)
+    /// Name of the user from their metadata or the start of their npub,
+    /// cut to the given width if any.
+    pub(crate) fn get_author(&self, pubkey: &PublicKey, max_width: Option<usize>) -> String {
+        if !self.users.contains_key(pubkey) {
+            self.unknown_users.borrow_mut().insert(*pubkey);
+        }
+        let name = self.users.get(pubkey)
+            .and_then(|m| m.name.clone())
+            .unwrap_or_else(|| pubkey.to_bech32().unwrap_or_else(|_| pubkey.to_string())
+                .chars().take(self.settings.key_length).collect());
+        match max_width {
+            Some(max) => truncate_width(&name, max),
+            None => name,
+        }
     }
 
-    pub(crate) fn set_filter_from(&mut self, time: Timestamp) -> bool {
-        // TODO filter at both ends
-        self.set_filter(|t| t.last_state_update() > time)
+    /// Users encountered without metadata which have not been requested yet,
+    /// marking them as requested.
+    fn take_unknown_users(&self) -> Vec<PublicKey> {
+        let mut requested = self.requested_users.borrow_mut();
+        self.unknown_users.take().into_iter()
+            .filter(|key| !self.users.contains_key(key) && requested.insert(*key))
+            .collect_vec()
     }
 
-    pub(crate) fn get_filtered<P>(&self, predicate: P) -> Vec<EventId>
-    where
-        P: Fn(&&Task) -> bool,
-    {
-        self.filtered_tasks(self.get_position_ref(), false)
-            .into_iter()
-            .filter(predicate)
-            .map(|t| t.event.id)
-            .collect()
+    /// Request metadata of users encountered without any, once per session.
+    pub(crate) fn fetch_unknown_users(&self) {
+        let keys = self.take_unknown_users();
+        if !keys.is_empty() {
+            debug!("Requesting metadata for {} users", keys.len());
+            self.sender.fetch_metadata(keys);
+        }
     }
 
-    pub(crate) fn set_filter<P>(&mut self, predicate: P) -> bool
-    where
-        P: Fn(&&Task) -> bool,
-    {
-        self.set_view(self.get_filtered(predicate))
+    /// Describe a user with the known metadata
+    pub(crate) fn whois(&self, key: &PublicKey) -> String {
+        let mut lines = vec![format!("{} {}", self.get_author(key, None).bold(), key.to_bech32().unwrap_or_else(|_| key.to_string()))];
+        match self.users.get(key) {
+            None => lines.push("No metadata known".italic().to_string()),
+            Some(metadata) => {
+                for (label, value) in [
+                    ("Display name", &metadata.display_name),
+                    ("About", &metadata.about),
+                    ("NIP-05", &metadata.nip05),
+                    ("Website", &metadata.website),
+                ] {
+                    if let Some(value) = value {
+                        lines.push(format!("{label}: {value}"));
+                    }
+                }
+            }
+        }
+        lines.join("\n")
     }
 
-    pub(crate) fn set_view_bookmarks(&mut self) -> bool {
-        self.set_view(self.bookmarks.clone())
+    /// Find a user by key, by name or display name from their metadata
+    /// (exact match first, then prefix), or "me" for oneself.
+    pub(crate) fn find_user(&self, name: &str) -> Option<PublicKey> {
+        let name = name.trim().trim_start_matches('@');
+        if name.is_empty() {
+            return None;
+        }
+        if name == "me" {
+            return Some(self.sender.pubkey());
+        }
+        if let Ok(key) = PublicKey::parse(name) {
+            return Some(key);
+        }
+        self.find_user_by_name(name, true)
     }
 
-    /// Set currently visible tasks.
-    /// Returns whether there are any.
-    pub(crate) fn set_view(&mut self, view: Vec<EventId>) -> bool {
-        if view.is_empty() {
-            warn!("No match for filter!");
-            self.view = view;
-            return false;
+    /// Find a user by case-insensitive name or display name,
+    /// falling back to name prefixes if allowed and nobody matches exactly.
+    /// Ambiguous names resolve to nobody with a warning listing the candidates.
+    fn find_user_by_name(&self, name: &str, prefix: bool) -> Option<PublicKey> {
+        let lower = name.to_ascii_lowercase();
+        let matching = |prefix: bool| self.users.iter()
+            .filter(|(_, m)| [&m.name, &m.display_name].into_iter()
+                .flatten()
+                .map(|n| n.to_ascii_lowercase())
+                .any(|n| if prefix { n.starts_with(&lower) } else { n == lower }))
+            .map(|(key, _)| *key)
+            .collect_vec();
+        let mut candidates = matching(false);
+        if candidates.is_empty() && prefix {
+            candidates = matching(true);
+        }
+        match candidates.as_slice() {
+            [] => None,
+            [key] => Some(*key),
+            _ => {
+                warn!("\"{name}\" matches several people, please be more specific: {}",
+                    candidates.iter()
+                        .map(|key| format!("{} ({})", self.get_author(key, None),
+                            key.to_bech32().unwrap_or_default().chars().take(self.settings.key_length).collect::<String>()))
+                        .sorted()
+                        .join(", "));
+                None
+            }
         }
-        self.view = view;
-        true
     }
 
-    pub(crate) fn clear_filters(&mut self) {
-        self.state = StateFilter::Default;
-        self.view.clear();
-        self.tags.clear();
-        self.tags_excluded.clear();
-        info!("Removed all filters");
+    /// Notes of the task as indented discussion thread,
+    /// each with the index to reply to it, time and author
+    pub(crate) fn format_notes(&self, task: &Task) -> Vec<String> {
+        self.format_notes_with(task, |content| content)
     }
 
-    pub(crate) fn has_tag_filter(&self) -> bool {
-        !self.tags.is_empty() || !self.tags_excluded.is_empty()
+    /// Like [Self::format_notes], but cutting long notes down to the configured lines and length
+    fn format_notes_clipped(&self, task: &Task) -> Vec<String> {
+        self.format_notes_with(task, |content| match clip_text(&content, self.settings.note_lines, self.settings.note_length) {
+            (content, None) => content,
+            (clipped, Some(left_out)) => format!("{clipped} {}", format!("(… {left_out}, use , to read)").dimmed()),
+        })
     }
 
-    pub(crate) fn set_tags(&mut self, tags: impl IntoIterator<Item=Tag>) {
+    fn format_notes_with(&self, task: &Task, display: impl Fn(String) -> String) -> Vec<String> {
+        let indices: HashMap<EventId, usize> = task.description_events()
+            .enumerate()
+            .map(|(index, note)| (note.id, index + 1))
+            .collect();
+        task.description_thread().into_iter()
+            .map(|(level, note)| format!("{}{} {}{} {}: {}",
+                                         "  ".repeat(level),
+                                         indices[&note.id].to_string().dimmed(),
+                                         format_timestamp_relative(&published_at(note)),
+                                         if superseded(note).next().is_some() { " (edited)".dimmed().to_string() } else { String::new() },
+                                         self.get_author(&note.pubkey, None),
+                                         display(self.display_mentions(&note.content))))
+            .collect()
+    }
+
+    /// People mentioned as @NAME or @KEY in the text
+    fn mentions(&self, text: &str) -> Vec<PublicKey> {
+        text.split_whitespace()
+            .filter_map(|word| word.strip_prefix('@'))
+            .map(|name| name.trim_end_matches(|c: char| !c.is_alphanumeric()))
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| self.find_mentioned(name))
+            .unique()
+            .collect_vec()
+    }
+
+    fn find_mentioned(&self, name: &str) -> Option<PublicKey> {
+        PublicKey::parse(name).ok().or_else(|| self.find_user_by_name(name, false))
+    }
+
+    /// Replace mentions like @NAME by nostr URIs of the people (NIP-27),
+    /// leaving unknown names as they are
+    fn link_mentions(&self, text: &str) -> String {
+        replace_mentions(text, "@", |name| match self.find_mentioned(name) {
+            Some(key) => key.to_bech32().ok().map(|npub| format!("nostr:{npub}")),
+            None => {
+                warn!("Unknown person @{name}, leaving the mention as plain text");
+                None
+            }
+        })
+    }
+
+    /// Show mentions as nostr URIs by the names of the people
+    fn display_mentions(&self, text: &str) -> String {
+        replace_mentions(text, "nostr:", |uri| PublicKey::from_bech32(uri).ok()
+            .or_else(|| Nip19Profile::from_bech32(uri).ok().map(|profile| profile.public_key))
+            .map(|key| format!("@{}", self.get_author(&key, None))))
+    }
+
+    /// Send a direct message about the task to the given person
+    /// if notifications are enabled and the task is on a remote relay.
+    fn notify_about(&self, recipient: PublicKey, id: EventId, action: &str, text: &str) {
+        if !self.notify || recipient == self.sender.pubkey() {
+            return;
+        }
+        let Some(url) = &self.sender.url else {
+            return;
+        };
+        let mut message = format!("{} {action} \"{}\" on {url}",
+                                  self.get_author(&self.sender.pubkey(), None),
+                                  self.get_task_path(Some(id)));
+        if !text.is_empty() {
+            message.push_str("\n\n");
+            message.push_str(text);
+        }
+        debug!("Notifying {}: {message}", self.get_author(&recipient, None));
+        self.sender.notify(recipient, message);
+    }
+
+    // Movement and Selection
+
+    /// Act as the given identity from now on.
+    /// Pending events are sent beforehand as they are signed by the previous one,
+    /// own lists are dropped until they arrive for the new identity.
+    pub(crate) fn switch_keys(&mut self, keys: &Keys, metadata: Option<Metadata>) {
+        self.force_flush();
+        self.sender.keys = keys.clone();
+        self.position_cache.take();
+        self.bookmarks.clear();
+        self.bookmarks_event = None;
+        self.follows.clear();
+        self.contacts_event = None;
+        metadata.map(|m| self.users.insert(keys.public_key(), m));
+    }
+
+    /// Toggle showing only tasks by oneself and followed people.
+    /// Returns whether only those are shown now.
+    pub(crate) fn toggle_follows_only(&mut self) -> bool {
+        self.follows_only = !self.follows_only;
+        self.follows_only
+    }
+
+    /// Follow or unfollow the given person, republishing the own contact list
+    /// with all other entries preserved.
+    /// Returns whether the list changed.
+    pub(crate) fn set_following(&mut self, key: PublicKey, follow: bool) -> bool {
+        if self.is_watch_only() {
+            warn!("Cannot change the contact list in watch-only mode");
+            return false;
+        }
+        let author = self.get_author(&key, None);
+        if self.follows.contains(&key) == follow {
+            info!("Already {}following {author}", if follow { "" } else { "not " });
+            return false;
+        }
+        if self.contacts_event.is_none() && !self.synced {
+            warn!("Own contact list not received yet, not publishing a new one to avoid overwriting it");
+            return false;
+        }
+        let (content, tags) = match &self.contacts_event {
+            Some(event) => (event.content.clone(), event.tags.iter()
+                .filter(|tag| !matches!(tag.as_standardized(), Some(TagStandard::PublicKey { public_key, .. }) if public_key == &key))
+                .cloned()
+                .collect_vec()),
+            None => (String::new(), vec![]),
+        };
+        let tags = tags.into_iter().chain(Some(Tag::public_key(key)).filter(|_| follow));
+        match self.publish_event(EventBuilder::new(Kind::ContactList, content, tags)) {
+            Ok(_) => {
+                info!("{} {author}", if follow { "Following" } else { "Unfollowed" });
+                true
+            }
+            Err(e) => {
+                warn!("Could not update contact list: {e}");
+                false
+            }
+        }
+    }
+
+    /// Toggle bookmark on the given id.
+    /// Returns whether it was added (true) or removed (false).
+    pub(crate) fn toggle_bookmark(&mut self, id: EventId) -> nostr_sdk::Result<bool> {
+        if self.is_watch_only() {
+            return Err("Cannot change bookmarks in watch-only mode".into());
+        }
+        let added = match self.bookmarks.iter().position(|b| b == &id) {
+            None => {
+                self.bookmarks.push(id);
+                true
+            }
+            Some(pos) => {
+                self.bookmarks.remove(pos);
+                false
+            }
+        };
+        self.publish_bookmarks()?;
+        Ok(added)
+    }
+
+    /// Publish the current bookmarks unless they are unchanged.
+    /// Only tasks of this relay are included,
+    /// such that a list shared by all relays is split up rather than copied.
+    fn publish_bookmarks(&mut self) -> nostr_sdk::Result<()> {
+        let url = self.sender.url.clone();
+        if self.bookmarks_event.as_ref().is_some_and(|e| is_relay_bookmarks(e, url.as_ref()) && referenced_events(e).eq(self.bookmarks.iter())) {
+            debug!("Not republishing unchanged bookmarks");
+            return Ok(());
+        }
+        let ids = self.bookmarks.iter()
+            .filter(|id| self.tasks.contains_key(*id))
+            .cloned()
+            .collect_vec();
+        self.publish_event(build_bookmarks(url.as_ref(), ids))?;
+        Ok(())
+    }
+
+    /// Drop bookmarks of tasks which are still unknown after syncing,
+    /// such as those of other relays in a list shared by all relays.
+    fn prune_bookmarks(&mut self) {
+        if !self.synced || !self.overflow.is_empty() {
+            return;
+        }
+        let count = self.bookmarks.len();
+        self.bookmarks.retain(|id| self.tasks.contains_key(id));
+        if self.bookmarks.len() < count {
+            debug!("Ignoring {} bookmarks of unknown tasks", count - self.bookmarks.len());
+        }
+    }
+
+    /// Add an author to filter by, keeping the ones already filtered for.
+    /// Returns whether it was not filtered for yet.
+    pub(crate) fn add_author_filter(&mut self, key: PublicKey) -> bool {
+        self.view.clear();
+        if self.authors.contains(&key) {
+            return false;
+        }
+        self.authors.push(key);
+        true
+    }
+
+    pub(crate) fn clear_author_filter(&mut self) {
+        self.authors.clear();
+        info!("Showing tasks by everyone");
+    }
+
+    pub(crate) fn set_filter_assignee(&mut self, key: PublicKey) -> bool {
+        self.set_filter(|t| t.assignee() == Some(key))
+    }
+
+    /// Show all tasks assigned to oneself by someone else.
+    pub(crate) fn set_view_inbox(&mut self) -> bool {
+        let own = self.sender.pubkey();
+        let inbox = self.tasks.values()
+            .filter(|t| t.assignment().is_some_and(|e| e.pubkey != own) && t.assignee() == Some(own))
+            .filter(|t| t.pure_state().is_open())
+            .map(|t| t.event.id)
+            .collect_vec();
+        self.set_view(inbox)
+    }
+
+    pub(crate) fn set_filter_from(&mut self, time: Timestamp) -> bool {
+        self.set_filter(|t| t.last_state_update() > time)
+    }
+
+    /// Show tasks whose state was last updated within the given window
+    pub(crate) fn set_filter_range(&mut self, start: Option<Timestamp>, end: Option<Timestamp>) -> bool {
+        self.set_filter(|t| {
+            let time = t.last_state_update();
+            start.map_or(true, |start| time >= start) && end.map_or(true, |end| time < end)
+        })
+    }
+
+    pub(crate) fn get_filtered<P>(&self, predicate: P) -> Vec<EventId>
+    where
+        P: Fn(&&Task) -> bool,
+    {
+        self.filtered_tasks(self.get_position_ref(), false)
+            .into_iter()
+            .filter(predicate)
+            .map(|t| t.event.id)
+            .collect()
+    }
+
+    pub(crate) fn set_filter<P>(&mut self, predicate: P) -> bool
+    where
+        P: Fn(&&Task) -> bool,
+    {
+        self.set_view(self.get_filtered(predicate))
+    }
+
+    /// Tasks others created or changed since the end of the previous session, most recent first.
+    /// Own changes are left out, so edits made offline are no news.
+    pub(crate) fn updated_tasks(&self) -> Vec<EventId> {
+        let Some(since) = self.reviewed else { return vec![] };
+        let own = self.sender.pubkey();
+        let now = self.now();
+        self.tasks.values()
+            .filter(|t| t.is_task())
+            .filter_map(|t| once(&t.event).chain(t.props.iter())
+                .filter(|e| e.pubkey != own && e.created_at > since && e.created_at <= now)
+                .map(|e| e.created_at)
+                .max()
+                .map(|stamp| (stamp, t.event.id)))
+            .sorted_by_key(|(stamp, id)| (Reverse(*stamp), *id))
+            .map(|(_, id)| id)
+            .collect()
+    }
+
+    /// Point out tasks changed by others since the previous session
+    fn announce_updates(&self) {
+        let count = self.updated_tasks().len();
+        if count > 0 {
+            info!("{count} updated tasks{}, use ?new to review",
+                  self.url().map_or(String::new(), |url| format!(" on {url}")));
+        }
+    }
+
+    /// Show the tasks others changed since the previous session, after which they count as reviewed
+    pub(crate) fn set_view_updated(&mut self) -> bool {
+        if self.reviewed.is_none() {
+            info!("No updates left to review");
+            return false;
+        }
+        let updated = self.updated_tasks();
+        self.reviewed = None;
+        self.set_view(updated)
+    }
+
+    pub(crate) fn set_view_bookmarks(&mut self) -> bool {
+        self.set_view(self.bookmarks.clone())
+    }
+
+    /// Set currently visible tasks.
+    /// Returns whether there are any.
+    pub(crate) fn set_view(&mut self, view: Vec<EventId>) -> bool {
+        if view.is_empty() {
+            warn!("No match for filter!");
+            self.view = view;
+            return false;
+        }
+        self.view = view;
+        true
+    }
+
+    pub(crate) fn clear_filters(&mut self) {
+        self.state = StateFilter::Default;
+        self.view.clear();
+        self.tags.clear();
+        self.tags_excluded.clear();
+        self.authors.clear();
+        self.repo_filter = None;
+        info!("Removed all filters");
+    }
+
+    /// Reset tag filters, sorting and columns to the configured defaults.
+    /// Settings without default are left as they are.
+    fn reset_view(&mut self) {
+        if !self.defaults.tags.is_empty() {
+            self.tags_excluded.clear();
+            self.set_tags(self.defaults.tags.iter().map(|t| Hashtag(t.clone()).into()));
+        }
+        if let Some(sorting) = &self.defaults.sorting {
+            self.sorting = sorting.clone();
+        }
+        if let Some(columns) = &self.defaults.columns {
+            self.properties = columns.clone();
+        }
+    }
+
+    /// Apply the configured defaults upon selecting the relay,
+    /// overriding any changes made in the meantime.
+    pub(crate) fn apply_defaults(&mut self) {
+        self.reset_view();
+        if let Some(path) = self.defaults.position.clone() {
+            match self.find_by_path(&path) {
+                Some(id) => self.move_to(Some(id)),
+                None => info!("Default position {path} not found (yet)"),
+            }
+        }
+    }
+
+    /// Position and filters to pick up again in the next session
+    pub(crate) fn session(&self) -> RelaySession {
+        let hashtags = |tags: &BTreeSet<Tag>| tags.iter().filter_map(|t| t.content()).map(|t| t.to_string()).collect_vec();
+        RelaySession {
+            position: self.get_position(),
+            depth: Some(self.depth),
+            tags: hashtags(&self.tags),
+            excluded: hashtags(&self.tags_excluded),
+            state: match &self.state {
+                StateFilter::Default => None,
+                StateFilter::All => Some("all".to_string()),
+                StateFilter::State(state) => Some(state.to_string()),
+                StateFilter::Review(_) => Some("review".to_string()),
+            },
+            repo: self.repo_filter.as_ref().map(|c| c.to_string()),
+            saved: Some(Timestamp::now()),
+        }
+    }
+
+    /// Restore position and filters of a previous session.
+    /// The position is left alone if the task is unknown
+    /// or it has been changed since the session was saved.
+    pub(crate) fn restore_session(&mut self, session: &RelaySession) {
+        if let Some(depth) = session.depth {
+            self.depth = depth;
+        }
+        if !session.tags.is_empty() {
+            self.set_tags(session.tags.iter().map(|t| Hashtag(t.clone()).into()));
+        }
+        self.tags_excluded.extend(session.excluded.iter().map(|t| Tag::from(Hashtag(t.clone()))));
+        match session.state.as_deref() {
+            None => {}
+            Some("all") => self.state = StateFilter::All,
+            Some("review") => self.state = StateFilter::Review(self.sender.pubkey()),
+            Some(state) => self.state = StateFilter::State(state.to_string()),
+        }
+        if let Some(repo) = &session.repo {
+            self.repo_filter = Coordinate::parse(repo).map_err(|e| warn!("Invalid repository filter {repo}: {e}")).ok();
+        }
+        self.reviewed = session.saved;
+        // Relays still syncing point out updates once they are complete
+        if self.synced {
+            self.announce_updates();
+        }
+        let Some(id) = session.position else { return };
+        if self.get_position() == Some(id) {
+            return;
+        }
+        if self.get_by_id(&id).is_none() {
+            info!("Previous position {id} not found (yet)");
+            return;
+        }
+        let moved = self.get_own_history()
+            .and_then(|history| history.keys().next_back())
+            .is_some_and(|last| session.saved.map_or(true, |saved| last > &saved));
+        if moved {
+            debug!("Not restoring previous position since it changed in the meantime");
+        } else {
+            self.move_to(Some(id));
+        }
+    }
+
+    /// Find a task by its id or its path of names separated by slashes,
+    /// matching each name case-insensitively among the children of the previous one.
+    pub(crate) fn find_by_path(&self, path: &str) -> Option<EventId> {
+        if let Ok(id) = EventId::parse(path.trim()) {
+            return Some(id);
+        }
+        let mut position = None;
+        for name in path.split('/').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+            let child = self.tasks.children_for(position.as_ref())
+                .find(|t| t.get_title().eq_ignore_ascii_case(name))?;
+            position = Some(*child.get_id());
+        }
+        position
+    }
+
+    pub(crate) fn has_tag_filter(&self) -> bool {
+        !self.tags.is_empty() || !self.tags_excluded.is_empty()
+    }
+
+    /// Replace the hashtag filters, no longer excluding the given tags.
+    pub(crate) fn set_tags(&mut self, tags: impl IntoIterator<Item=Tag>) {
         self.tags.clear();
         self.tags.extend(tags);
+        self.tags_excluded.retain(|t| !self.tags.contains(t));
     }
 
+    /// Remove all hashtag filters, included as well as excluded ones.
+    pub(crate) fn clear_tag_filters(&mut self) {
+        self.view.clear();
+        self.tags.clear();
+        self.tags_excluded.clear();
+        info!("Removed all tag filters");
+    }
+
+    /// Add a hashtag filter, using the spelling of a known hashtag
+    /// which matches case-insensitively or as the only one by prefix.
     pub(crate) fn add_tag(&mut self, tag: String) {
         self.view.clear();
+        let tag = self.resolve_hashtag(tag);
         info!("Added tag filter for #{tag}");
         let tag: Tag = Hashtag(tag).into();
         self.tags_excluded.remove(&tag);
         self.tags.insert(tag);
     }
 
+    /// Known hashtag matching the given one exactly, case-insensitively or as unique prefix,
+    /// otherwise the given one with a warning.
+    fn resolve_hashtag(&self, tag: String) -> String {
+        let lower = tag.to_lowercase();
+        let known = self.all_hashtags().collect_vec();
+        if known.contains(&tag.as_str()) || !self.tag_hierarchy.children(&tag).is_empty() {
+            return tag;
+        }
+        if let Some(same) = known.iter().find(|t| t.to_lowercase() == lower) {
+            return same.to_string();
+        }
+        let candidates = known.iter().filter(|t| t.to_lowercase().starts_with(&lower)).collect_vec();
+        match candidates.as_slice() {
+            [] => warn!("No known task has #{tag}"),
+            [single] => return single.to_string(),
+            _ => warn!("#{tag} is not a known hashtag, did you mean {}?", candidates.iter().map(|t| format!("#{t}")).join(", ")),
+        }
+        tag
+    }
+
     pub(crate) fn remove_tag(&mut self, tag: &str) {
         self.view.clear();
         let len = self.tags.len();
-        self.tags.retain(|t| !t.content().is_some_and(|value| value.to_string().starts_with(tag)));
+        let lower = tag.to_lowercase();
+        self.tags.retain(|t| !t.content().is_some_and(|value| value.to_lowercase().starts_with(&lower)));
         if self.tags.len() < len {
             info!("Removed tag filters starting with {tag}");
+            return;
+        }
+        let excluded = self.tags_excluded.len();
+        self.tags_excluded.retain(|t| !t.content().is_some_and(|value| value.eq_ignore_ascii_case(tag)));
+        if self.tags_excluded.len() < excluded {
+            info!("No longer excluding #{tag}");
         } else {
             self.tags_excluded.insert(Hashtag(tag.to_string()).into());
             info!("Excluding #{tag} from view");
         }
     }
 
+    /// Add hashtags to new tasks independent of the tag filters
+    pub(crate) fn add_creation_tags(&mut self, tags: impl IntoIterator<Item=String>) {
+        for tag in tags {
+            info!("Tagging new tasks with #{tag}");
+            self.creation_tags.insert(Hashtag(tag).into());
+        }
+    }
+
+    /// Stop adding the given hashtags to new tasks, or all of them if none are given
+    pub(crate) fn remove_creation_tags(&mut self, tags: &[&str]) {
+        if tags.is_empty() {
+            self.creation_tags.clear();
+            info!("Removed all creation tags");
+            return;
+        }
+        self.creation_tags.retain(|t| !t.content().is_some_and(|content| tags.iter().any(|tag| tag.eq_ignore_ascii_case(content))));
+    }
+
+    /// Toggle listing bookmarks outside of their parent
+    pub(crate) fn toggle_bookmarks(&mut self) {
+        self.show_bookmarks = !self.show_bookmarks;
+        info!("{} bookmarks in every view", if self.show_bookmarks { "Listing" } else { "No longer listing" });
+    }
+
+    /// Toggle showing closed tasks and their subtrees with the default state filter
+    pub(crate) fn toggle_closed(&mut self) {
+        self.view.clear();
+        self.show_closed = !self.show_closed;
+        info!("{} closed tasks", if self.show_closed { "Showing" } else { "Hiding" });
+    }
+
     pub(crate) fn set_state_filter(&mut self, state: StateFilter) {
         self.view.clear();
         info!("Filtering for {}", state);
@@ -702,8 +2051,74 @@ impl TasksRelay {
         self.move_to(self.get_current_task().and_then(|t| t.parent_id()).cloned());
     }
 
+    /// Move on from a completed task:
+    /// With advancing enabled, a step of a procedure is followed by the next actionable step,
+    /// or by the procedure itself after the last one.
+    /// Otherwise moves up.
+    pub(crate) fn move_on(&mut self) {
+        let procedure = self.get_current_task()
+            .and_then(|t| t.parent_id())
+            .filter(|id| self.get_by_id(id).is_some_and(|p| p.pure_state() == State::Procedure))
+            .cloned();
+        match procedure.filter(|_| self.advance) {
+            Some(procedure) => match self.next_step(&procedure) {
+                Some(next) => {
+                    info!("Activating next step \"{}\"", self.get_task_title(&next));
+                    self.move_to(Some(next));
+                }
+                None => {
+                    info!("Completed all steps of \"{}\", use > to mark it done", self.get_task_title(&procedure));
+                    self.move_to(Some(procedure));
+                }
+            },
+            None => self.move_up(),
+        }
+    }
+
+    /// First open step of the procedure whose dependencies are all completed or closed
+    pub(crate) fn next_step(&self, procedure: &EventId) -> Option<EventId> {
+        self.procedure_steps(procedure).into_iter().find(|id| {
+            self.get_by_id(id).is_some_and(|step| step.pure_state().is_open() &&
+                step.get_dependendees().iter().all(|dep|
+                    self.get_by_id(dep).is_some_and(|t| !t.pure_state().is_open())))
+        })
+    }
+
+    /// Sends all queued events if there is one other than time-tracking
     pub(crate) fn flush(&self) {
-        self.sender.flush();
+        if self.sender.has_changes() {
+            self.force_flush()
+        }
+    }
+
+    /// Sends all queued events
+    fn force_flush(&self) {
+        let queued = self.sender.clear();
+        debug!("Flushing {} events from queue", queued.len());
+        let events = queued.into_iter().filter_map(|q| self.resolve(q)).collect_vec();
+        self.sender.send(events);
+    }
+
+    /// Look up the event behind a queue entry
+    fn resolve(&self, queued: Queued) -> Option<Event> {
+        let (id, kind, created_at, reference) = match queued {
+            Queued::Held(event) => return Some(event),
+            Queued::Stored { id, kind, created_at, reference } => (id, kind, created_at, reference),
+        };
+        let found = if kind == TRACKING_KIND {
+            self.history.get(&self.sender.pubkey()).and_then(|h| h.get(&created_at))
+        } else {
+            self.tasks.get(&id).map(|t| &t.event)
+                .or_else(|| reference.and_then(|r| self.tasks.get(&r))
+                    .and_then(|t| t.props.iter().find(|e| e.id == id)))
+                .or_else(|| self.contacts_event.iter().chain(self.bookmarks_event.iter()).find(|e| e.id == id))
+                .or_else(|| self.find_event(&id))
+        };
+        let event = found.filter(|e| e.id == id).cloned();
+        if event.is_none() {
+            debug!("Queued event {id} is gone, not sending it");
+        }
+        event
     }
 
     /// Returns ids of tasks matching the given string.
@@ -737,8 +2152,7 @@ impl TasksRelay {
         // Find global exact match
         for task in self.tasks.values() {
             if task.get_filter_title().to_ascii_lowercase() == lowercase_arg &&
-                // exclude closed tasks and their subtasks
-                !self.traverse_up_from(Some(*task.get_id())).any(|t| t.pure_state() == State::Closed) {
+                !self.is_collapsed(task.get_id()) {
                 return vec![task.event.id];
             }
         }
@@ -755,22 +2169,50 @@ impl TasksRelay {
         filtered
     }
 
+    /// Whether [filter_or_create](Self::filter_or_create) would ask before creating a task from the input,
+    /// so the confirmation can be obtained beforehand
+    pub(crate) fn needs_confirmation(&self, position: Option<&EventId>, arg: &str) -> bool {
+        self.settings.creation == Creation::Confirm && !arg.ends_with('!') && self.get_matching(position, arg).is_empty()
+    }
+
     /// Finds out what to do with the given string, one of:
     /// - filtering the visible tasks
     /// - entering the only matching task
     /// - creating a new task
     /// Returns an EventId if a new Task was created.
-    pub(crate) fn filter_or_create(&mut self, position: Option<&EventId>, arg: &str) -> Option<EventId> {
+    /// Move to the only task matching the input, filter for several,
+    /// or create a new one if none matches as allowed by the creation setting.
+    /// A trailing `!` marks the input as explicitly meant to create a task,
+    /// otherwise the given confirmation is asked for if configured.
+    pub(crate) fn filter_or_create<F>(&mut self, position: Option<&EventId>, arg: &str, confirm: F) -> Option<EventId>
+    where
+        F: FnOnce(&str) -> bool,
+    {
         let filtered = self.get_matching(position, arg);
         match filtered.len() {
             0 => {
                 // No match, new task
                 self.view.clear();
-                if arg.len() < CHARACTER_THRESHOLD {
-                    warn!("New task name needs at least {CHARACTER_THRESHOLD} characters");
+                let arg = match (self.settings.creation, arg.strip_suffix('!')) {
+                    (Creation::Direct, _) => arg,
+                    (_, Some(explicit)) => explicit.trim_end(),
+                    (Creation::Confirm, None) => {
+                        if !confirm(arg) {
+                            info!("Not creating \"{arg}\"");
+                            return None;
+                        }
+                        arg
+                    }
+                    (Creation::Explicit, None) => {
+                        info!("No task matches \"{arg}\" - append ! to create it");
+                        return None;
+                    }
+                };
+                if arg.len() < self.settings.min_length {
+                    warn!("New task name needs at least {} characters", self.settings.min_length);
                     return None;
                 }
-                Some(self.make_task_with(arg, self.position_tags_for(position), true))
+                self.make_task_with(arg, self.position_tags_for(position), true)
             }
             1 => {
                 // One match, activate
@@ -786,13 +2228,6 @@ impl TasksRelay {
         }
     }
 
-    /// Returns all recent events from history until the first event at or before the given timestamp.
-    fn history_from(&self, stamp: Timestamp) -> impl Iterator<Item=&Event> {
-        self.history.get(&self.sender.pubkey()).map(|hist| {
-            hist.values().rev().take_while_inclusive(move |e| e.created_at > stamp)
-        }).into_iter().flatten()
-    }
-
     pub(crate) fn move_to(&mut self, target: Option<EventId>) {
         self.view.clear();
         let pos = self.get_position_ref();
@@ -807,14 +2242,29 @@ impl TasksRelay {
             self.flush();
         }
 
+        // Rapid moves are shifted a second into the future each to keep them in order.
+        // Once max_offset is used up, the newest of them is replaced by this one.
         let now = Timestamp::now();
-        let offset: u64 = self.history_from(now).skip_while(|e| e.created_at.as_u64() > now.as_u64() + MAX_OFFSET).count() as u64;
-        if offset >= MAX_OFFSET {
-            warn!("Whoa you are moving around quickly! Give me a few seconds to process.")
-        }
+        let latest = self.get_own_history()
+            .and_then(|history| history.range(..=now + self.settings.max_offset).next_back())
+            .map(|(stamp, event)| (*stamp, event.clone()));
+        let time = match latest {
+            None => now,
+            Some((stamp, _)) if stamp < now => now,
+            Some((stamp, _)) if stamp < now + self.settings.max_offset => stamp + 1,
+            Some((stamp, event)) => {
+                debug!("Replacing move at {stamp} because of moving around quickly");
+                if !self.sender.dequeue(&event.id) {
+                    self.submit(EventBuilder::delete([event.id]));
+                }
+                self.journal.retain(|id| id != &event.id);
+                self.remove(&event);
+                stamp
+            }
+        };
         self.submit(
             build_tracking(target)
-                .custom_created_at(Timestamp::from(now.as_u64() + offset))
+                .custom_created_at(time)
         );
     }
 
@@ -846,49 +2296,139 @@ impl TasksRelay {
         self.position_tags_for(self.get_position_ref())
     }
 
+    /// Tags for a new task at the position,
+    /// referencing it as parent and taking over its git repository or the one filtered by
     pub(crate) fn position_tags_for(&self, position: Option<&EventId>) -> Vec<Tag> {
-        position.map_or(vec![], |pos| {
+        let repository = position.and_then(|pos| self.get_by_id(pos))
+            .and_then(|task| task.repository())
+            .or_else(|| self.repo_filter.clone());
+        let mut tags = position.map_or(vec![], |pos| {
             let mut tags = Vec::with_capacity(2);
             tags.push(self.make_event_tag_from_id(*pos, MARKER_PARENT));
             self.get_by_id(pos)
                 .map(|task| {
                     if task.pure_state() == State::Procedure {
-                        self.tasks.children_of(task)
-                            .max()
-                            .map(|t| tags.push(self.make_event_tag(&t.event, MARKER_DEPENDS)));
+                        self.procedure_steps(pos)
+                            .last()
+                            .map(|id| tags.push(self.make_event_tag_from_id(*id, MARKER_DEPENDS)));
                     }
                 });
             tags
-        })
+        });
+        tags.extend(repository.map(Tag::coordinate));
+        tags
+    }
+
+    /// Subtasks of the procedure in the order given by their dependencies on each other,
+    /// with unconnected steps ordered by creation
+    pub(crate) fn procedure_steps(&self, procedure: &EventId) -> Vec<EventId> {
+        let steps = self.tasks.children_for(Some(procedure))
+            .sorted_by_key(|t| (t.event.created_at, t.event.id))
+            .collect_vec();
+        let ids = steps.iter().map(|t| t.event.id).collect::<HashSet<_>>();
+        let previous = |task: &Task| task.get_dependendees().into_iter().find(|id| ids.contains(*id)).copied();
+        let mut order = Vec::with_capacity(steps.len());
+        let mut queue = steps.iter()
+            .filter(|t| previous(*t).is_none())
+            .map(|t| t.event.id)
+            .collect::<VecDeque<_>>();
+        // Steps in a dependency cycle have no start, so they are appended in creation order
+        let mut remaining = steps.iter().map(|t| t.event.id).collect::<VecDeque<_>>();
+        while let Some(id) = queue.pop_front().or_else(|| remaining.pop_front()) {
+            if order.contains(&id) {
+                continue;
+            }
+            order.push(id);
+            let next = steps.iter()
+                .filter(|t| previous(*t) == Some(id))
+                .map(|t| t.event.id)
+                .collect_vec();
+            for next in next.into_iter().rev() {
+                queue.push_front(next);
+            }
+        }
+        order
+    }
+
+    /// Zero-based index of the task among the steps of its procedure
+    pub(crate) fn step_index(&self, id: &EventId) -> Option<usize> {
+        let procedure = self.get_by_id(id)?.parent_id()?;
+        if self.get_by_id(procedure)?.pure_state() != State::Procedure {
+            return None;
+        }
+        self.procedure_steps(procedure).iter().position(|step| step == id)
+    }
+
+    /// Move a step of its procedure to the given zero-based index,
+    /// publishing new dependencies for the steps whose predecessor changed.
+    /// Returns the new index, or none if the task is not part of a procedure.
+    pub(crate) fn move_step(&mut self, id: EventId, index: usize) -> Option<usize> {
+        let procedure = *self.get_by_id(&id)?.parent_id()?;
+        if self.get_by_id(&procedure)?.pure_state() != State::Procedure {
+            return None;
+        }
+        let before = self.procedure_steps(&procedure);
+        let mut after = before.iter().filter(|step| *step != &id).copied().collect_vec();
+        let index = index.min(after.len());
+        after.insert(index, id);
+        let predecessors = |order: &[EventId]| once(None).chain(order.iter().map(Some)).zip(order.iter())
+            .map(|(previous, step)| (*step, previous.copied()))
+            .collect::<HashMap<_, _>>();
+        let old = predecessors(&before);
+        for (step, previous) in predecessors(&after) {
+            if old.get(&step) == Some(&previous) {
+                continue;
+            }
+            let Some(task) = self.get_by_id(&step) else { continue };
+            let tags = task.refs()
+                .filter(|(marker, target)| marker != MARKER_DEPENDS || !before.contains(target))
+                .map(|(marker, target)| self.make_event_tag_from_id(*target, marker))
+                .chain(previous.map(|p| self.make_event_tag_from_id(p, MARKER_DEPENDS)))
+                .collect_vec();
+            self.submit(build_references(step, tags));
+        }
+        Some(index)
     }
 
     /// Creates a task following the current state
     ///
     /// Sanitizes input
-    pub(crate) fn make_task(&mut self, input: &str) -> EventId {
+    pub(crate) fn make_task(&mut self, input: &str) -> Option<EventId> {
         self.make_task_with(input, self.position_tags(), true)
     }
 
     pub(crate) fn make_task_and_enter(&mut self, input: &str, state: State) {
-        let id = self.make_task_with(input, self.position_tags(), false);
+        let Some(id) = self.make_task_with(input, self.position_tags(), false) else {
+            return;
+        };
         self.set_state_for(id, "", state);
         self.move_to(Some(id));
     }
 
-    /// Creates a task including current tag filters
+    /// Tags to add to new tasks: the creation tags
+    /// and the tag filters unless they are configured to be left out
+    fn new_task_tags(&self) -> BTreeSet<Tag> {
+        let mut tags = self.creation_tags.clone();
+        if self.settings.filter_tags_on_creation {
+            tags.extend(self.tags.iter().cloned());
+        }
+        tags
+    }
+
+    /// Creates a task including current tag filters and creation tags
     ///
     /// Sanitizes input
-    pub(crate) fn make_task_with(&mut self, input: &str, tags: impl IntoIterator<Item=Tag>, set_state: bool) -> EventId {
-        let (input, input_tags) = extract_tags(input.trim());
+    pub(crate) fn make_task_with(&mut self, input: &str, tags: impl IntoIterator<Item=Tag>, set_state: bool) -> Option<EventId> {
+        let (input, input_tags) = extract_tags(input.trim(), self.settings.strip_hashtags);
         let id = self.submit(
-            build_task(input, input_tags, None)
-                .add_tags(self.tags.iter().cloned())
+            build_task(&input, input_tags, None)
+                .add_tags(self.new_task_tags())
                 .add_tags(tags)
-        );
+        )?;
         if set_state {
             self.state.as_option().inspect(|s| self.set_state_for_with(id, s));
         }
-        id
+        Some(id)
     }
 
     pub(crate) fn get_task_title(&self, id: &EventId) -> String {
@@ -934,35 +2474,206 @@ impl TasksRelay {
         self.submit(
             build_tracking(target)
                 .custom_created_at(time)
-        ).into()
+        )
+    }
+
+    /// Upcoming time-tracking of the current user as start, end if known and task.
+    pub(crate) fn planned_blocks(&self) -> Vec<(Timestamp, Option<Timestamp>, EventId)> {
+        let now = self.now();
+        let mut blocks = vec![];
+        if let Some(history) = self.get_own_history() {
+            let mut events = history.range(now..).map(|(_, e)| e).peekable();
+            while let Some(event) = events.next() {
+                if let Some(id) = referenced_event(event).filter(|_| event.created_at > now) {
+                    blocks.push((event.created_at, events.peek().map(|e| e.created_at), *id));
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Plan to track the given task in the future, refusing overlaps with other plans.
+    pub(crate) fn plan(&mut self, start: Timestamp, end: Timestamp, id: EventId) -> bool {
+        if start <= self.now() {
+            warn!("Can only plan ahead, track past times with (");
+            return false;
+        }
+        if end <= start {
+            warn!("Planned time ends before it starts");
+            return false;
+        }
+        if let Some((other_start, _, other)) = self.planned_blocks().into_iter()
+            .find(|(s, e, _)| s < &end && e.map_or(true, |e| start < e)) {
+            warn!("Overlaps with \"{}\" planned from {}", self.get_task_title(&other), format_timestamp_relative(&other_start));
+            return false;
+        }
+        self.track_at(start, Some(id)).is_some() && self.track_at(end, None).is_some()
     }
 
-    /// Sign and queue the event to the relay, returning its id
-    fn submit(&mut self, mut builder: EventBuilder) -> EventId {
+    /// Whether only a public key is known, so no events can be signed
+    pub(crate) fn is_watch_only(&self) -> bool {
+        self.sender.keys.secret_key().is_err()
+    }
+
+    /// Sign and queue the event to the relay, returning its id.
+    /// Rejected in watch-only mode.
+    fn submit(&mut self, mut builder: EventBuilder) -> Option<EventId> {
+        if self.is_watch_only() {
+            warn!("Cannot change anything in watch-only mode, restart with a secret key to do so");
+            return None;
+        }
         if let Some(stamp) = self.custom_time {
             builder = builder.custom_created_at(stamp);
+        } else if let Some(stamp) = self.sticky_time {
+            // Advance so backfilled events keep their order
+            self.sticky_time = Some(stamp + 1);
+            builder = builder.custom_created_at(stamp);
+        }
+        self.publish_event(builder)
+            .inspect_err(|e| error!("Could not sign event: {e}"))
+            .ok()
+    }
+
+    /// Sign the event, add it and queue it for sending.
+    /// Events kept here are only queued by reference.
+    fn publish_event(&mut self, builder: EventBuilder) -> nostr_sdk::Result<EventId> {
+        if self.sender.is_stale() {
+            debug!("Flushing event queue because it is older than a minute");
+            self.force_flush();
         }
-        let event = self.sender.submit(builder).unwrap();
+        let event = self.sender.sign(builder)?;
         let id = event.id;
+        let reference = referenced_event(&event).cloned();
+        let stored = match event.kind {
+            Kind::GitIssue | Kind::ContactList | Kind::Bookmarks | Kind::BookmarkSet => true,
+            Kind::EventDeletion | Kind::Metadata => false,
+            kind => kind == TRACKING_KIND || reference.is_some_and(|r| self.tasks.contains_key(&r)),
+        };
+        self.sender.enqueue(if stored {
+            Queued::Stored { id, kind: event.kind, created_at: event.created_at, reference }
+        } else {
+            Queued::Held(event.clone())
+        });
+        let hooked = (self.hooks_enabled && !self.hooks.commands.is_empty()).then(|| event.clone());
+        if !matches!(event.kind, Kind::EventDeletion | Kind::Metadata | Kind::ContactList | Kind::Bookmarks | Kind::BookmarkSet) {
+            if self.journal.len() >= JOURNAL_SIZE {
+                self.journal.pop_front();
+            }
+            self.journal.push_back(id);
+        }
         self.add(event);
-        id
+        if let Some(event) = hooked {
+            self.run_hook(&event);
+        }
+        Ok(id)
+    }
+
+    /// Name of the hook event and the environment for its command, if the event concerns a known task
+    fn hook_for(&self, event: &Event) -> Option<(&'static str, Vec<(&'static str, String)>)> {
+        let (name, id) = match event.kind {
+            _ if self.tasks.contains_key(&event.id) => ("task_created", event.id),
+            Kind::TextNote => ("note_added", *referenced_event(event)?),
+            kind if kind == TRACKING_KIND => match referenced_event(event) {
+                Some(id) => ("tracking_started", *id),
+                None => ("tracking_stopped", *self.get_own_history()?
+                    .range(..event.created_at)
+                    .next_back()
+                    .and_then(|(_, e)| referenced_event(e))?),
+            },
+            kind => {
+                State::try_from(kind).ok()?;
+                ("state_changed", *referenced_event(event)?)
+            }
+        };
+        let task = self.get_by_id(&id)?;
+        let state = match State::try_from(event.kind) {
+            Ok(state) => TaskState::get_label_for(&state, event.content()),
+            Err(_) => task.state_or_default().get_label(),
+        };
+        Some((name, vec![
+            ("MOSTR_EVENT", name.to_string()),
+            ("MOSTR_TASK_ID", id.to_string()),
+            ("MOSTR_TASK_TITLE", task.get_title()),
+            ("MOSTR_TASK_PATH", self.get_task_path(Some(id))),
+            ("MOSTR_STATE", state),
+            ("MOSTR_RELAY", self.url().map(|url| url.to_string()).unwrap_or_default()),
+            ("MOSTR_DESCRIPTION", self.describe_event(event)),
+        ]))
+    }
+
+    /// Spawn the hook command configured for the event without waiting for it
+    fn run_hook(&self, event: &Event) {
+        if !self.hooks_enabled {
+            return;
+        }
+        let Some((name, env)) = self.hook_for(event) else { return };
+        let Some(command) = self.hooks.commands.get(name) else { return };
+        debug!("Running {name} hook: {command}");
+        match Command::new("sh").arg("-c").arg(command).envs(env).stdin(Stdio::null()).spawn() {
+            Ok(mut child) => {
+                thread::spawn(move || match child.wait() {
+                    Ok(status) if !status.success() => warn!("Hook for {name} failed with {status}"),
+                    Err(e) => warn!("Could not wait for hook for {name}: {e}"),
+                    Ok(_) => {}
+                });
+            }
+            Err(e) => warn!("Could not run hook for {name}: {e}"),
+        }
     }
 
     pub(crate) fn add(&mut self, event: Event) {
+        if self.deleted.get(&event.id).is_some_and(|author| author == &event.pubkey) {
+            debug!("Ignoring deleted event {}", event.id);
+            return;
+        }
+        // Dates far ahead would stick at the end of any timeline
+        if event.pubkey != self.sender.pubkey() && event.created_at > Timestamp::now() + self.settings.max_future {
+            warn!("Rejecting event {} by {} dated {}", event.id, self.get_author(&event.pubkey, None), format_timestamp_local(&event.created_at));
+            self.sanitized.rejected += 1;
+            return;
+        }
+        self.subtree_cache.get_mut().clear();
         match event.kind {
             Kind::GitIssue => self.add_task(event),
+            Kind::EventDeletion => self.delete(&event),
             Kind::Metadata =>
                 match Metadata::from_json(event.content()) {
                     Ok(metadata) => { self.users.insert(event.pubkey, metadata); }
                     Err(e) => warn!("Cannot parse metadata: {} from {:?}", e, event)
                 }
-            Kind::Bookmarks => {
+            Kind::ContactList => {
                 if event.pubkey == self.sender.pubkey() {
-                    self.bookmarks = referenced_events(&event).cloned().collect_vec()
+                    if self.contacts_event.as_ref().is_some_and(|e| e.created_at > event.created_at) {
+                        debug!("Ignoring outdated contact list {}", event.id);
+                        return;
+                    }
+                    self.follows = event.public_keys().cloned().collect();
+                    self.contacts_event = Some(event);
+                }
+            }
+            Kind::Bookmarks | Kind::BookmarkSet => {
+                let url = self.sender.url.as_ref();
+                if event.pubkey == self.sender.pubkey() && (is_relay_bookmarks(&event, url) || is_mostr_bookmarks(&event)) {
+                    // Replaceable, so only a newer list counts -
+                    // a list shared by all relays only until the own list of this relay arrives
+                    if self.bookmarks_event.as_ref().is_some_and(|e|
+                        (is_relay_bookmarks(e, url), e.created_at) > (is_relay_bookmarks(&event, url), event.created_at)) {
+                        debug!("Ignoring outdated bookmarks {}", event.id);
+                        return;
+                    }
+                    self.bookmarks = referenced_events(&event).cloned().collect_vec();
+                    self.bookmarks_event = Some(event);
                 }
             }
             _ => {
                 if event.kind == TRACKING_KIND {
+                    if event.pubkey != self.sender.pubkey() &&
+                        self.tracked_before.contains_key(&event.pubkey) &&
+                        self.retention_start().is_some_and(|start| event.created_at < start) {
+                        trace!("Dropping tracking event {} before retention period", event.id);
+                        return;
+                    }
+                    self.position_cache.take();
                     match self.history.get_mut(&event.pubkey) {
                         Some(c) => { c.insert(event.created_at, event); }
                         None => { self.history.insert(event.pubkey, BTreeMap::from([(event.created_at, event)])); }
@@ -970,27 +2681,731 @@ impl TasksRelay {
                 } else {
                     if let Some(event) = self.add_prop(event) {
                         debug!("Requeueing unknown Event {:?}", event);
-                        self.overflow.push_back(event);
+                        self.overflow.push_back(Orphan::new(event));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write all events including own bookmarks as JSON lines.
+    /// Returns the number of exported events.
+    pub(crate) fn export_events(&self, writer: &mut impl Write) -> Result<usize, Error> {
+        write_events(writer, self.all_events().chain(self.bookmarks_event.iter()))
+    }
+
+    /// Add events from JSON lines, skipping unparseable lines and invalid signatures,
+    /// optionally publishing them to the relay.
+    /// Returns the number of imported and skipped events.
+    pub(crate) fn import_events(&mut self, reader: impl BufRead, publish: bool) -> (usize, usize) {
+        let (events, corrupt) = read_events(reader);
+        let (valid, invalid): (Vec<Event>, Vec<Event>) = events.into_iter().partition(|e| e.verify().is_ok());
+        let count = valid.len();
+        if publish {
+            self.adopt(valid);
+        } else {
+            for event in valid {
+                self.add(event);
+            }
+        }
+        self.process_overflow();
+        (count, corrupt + invalid.len())
+    }
+
+    /// Tasks below the position in depth-first order with their level, starting at zero,
+    /// ordered by creation among siblings
+    fn subtree(&self, position: Option<&EventId>) -> Vec<(usize, &Task)> {
+        let mut result = vec![];
+        let mut stack = self.tasks.children_for(position)
+            .sorted_by_key(|t| Reverse(t.event.created_at))
+            .map(|t| (0, t))
+            .collect_vec();
+        while let Some((level, task)) = stack.pop() {
+            result.push((level, task));
+            stack.extend(self.tasks.children_of(task)
+                .sorted_by_key(|t| Reverse(t.event.created_at))
+                .map(|t| (level + 1, t)));
+        }
+        result
+    }
+
+    /// Write the subtree below the current position as a nested markdown checklist,
+    /// striking through closed tasks.
+    /// Returns the number of tasks written.
+    pub(crate) fn export_markdown(&self, writer: &mut impl Write) -> Result<usize, Error> {
+        let tasks = self.subtree(self.get_position_ref());
+        for (level, task) in &tasks {
+            let state = task.pure_state();
+            let title = if state == State::Closed { format!("~~{}~~", task.get_title()) } else { task.get_title() };
+            writeln!(writer, "{}- [{}] {title}{}",
+                     "  ".repeat(*level),
+                     if state.is_open() { ' ' } else { 'x' },
+                     task.hashtags().map(|tag| format!(" #{tag}")).join(""))?;
+        }
+        Ok(tasks.len())
+    }
+
+    /// Write the subtree below the current position as todo.txt lines,
+    /// with the topmost ancestor of each task below it as project,
+    /// hashtags as contexts and done or closed tasks completed.
+    /// Returns the number of tasks written.
+    pub(crate) fn export_todotxt(&self, writer: &mut impl Write) -> Result<usize, Error> {
+        let tasks = self.subtree(self.get_position_ref());
+        let mut project = None;
+        for (level, task) in &tasks {
+            if *level == 0 {
+                project = Some(task.get_title().split_whitespace().join("-"));
+            }
+            let item = TodoItem {
+                done: task.completed_at().is_some(),
+                priority: task.priority(),
+                completed: task.completed_at().as_ref().and_then(local_date),
+                created: local_date(&task.event.created_at),
+                projects: project.clone().filter(|_| *level > 0).into_iter().collect(),
+                contexts: task.hashtags().map(|tag| tag.to_string()).collect(),
+                due: task.due().as_ref().and_then(local_date),
+                title: task.get_title(),
+            };
+            writeln!(writer, "{item}")?;
+        }
+        Ok(tasks.len())
+    }
+
+    /// Write the open tasks with a due date below the current position as to-dos
+    /// and the own planned time-tracking as events of an iCalendar file.
+    /// Identifiers are derived from the events, so calendars update entries on a repeated import.
+    /// Returns the number of entries written.
+    pub(crate) fn export_ical(&self, writer: &mut impl Write) -> Result<usize, Error> {
+        let now = Timestamp::now();
+        let todos = self.subtree(self.get_position_ref()).into_iter()
+            .filter(|(_, task)| task.pure_state().is_open())
+            .filter_map(|(_, task)| Some((task, task.due()?)))
+            .map(|(task, due)| {
+                let mut todo = Component::new("VTODO")
+                    .raw("UID", format!("{}@mostr", task.get_id()))
+                    .time("DTSTAMP", &now)
+                    .time("CREATED", &task.event.created_at)
+                    .text("SUMMARY", &task.get_title());
+                let description = task.descriptions().join("\n\n");
+                if !description.is_empty() {
+                    todo = todo.text("DESCRIPTION", &description);
+                }
+                let categories = task.hashtags().map(escape_text).join(",");
+                if !categories.is_empty() {
+                    todo = todo.raw("CATEGORIES", categories);
+                }
+                todo.time("DUE", &due)
+                    .raw("STATUS", ical_status(&task.pure_state()).to_string())
+            });
+        let events = self.planned_blocks().into_iter()
+            .map(|(start, end, id)| {
+                let event = Component::new("VEVENT")
+                    .raw("UID", format!("{id}-{}@mostr", start.as_u64()))
+                    .time("DTSTAMP", &now)
+                    .text("SUMMARY", &self.get_task_title(&id))
+                    .text("DESCRIPTION", &self.get_task_path(Some(id)))
+                    .time("DTSTART", &start);
+                match end {
+                    Some(end) => event.time("DTEND", &end),
+                    None => event,
+                }
+            });
+        let components = todos.chain(events).collect_vec();
+        write!(writer, "{}", calendar(&components))?;
+        Ok(components.len())
+    }
+
+    /// Write the subtree below the current position as a standalone HTML report:
+    /// a summary of states, tracked time and contributors
+    /// followed by the collapsible tree of tasks with their notes.
+    /// Returns the number of tasks written.
+    pub(crate) fn export_html(&self, writer: &mut impl Write) -> Result<usize, Error> {
+        let position = self.get_position_ref();
+        let ids = match position {
+            Some(id) => ChildIterator::from(self, id).get_all().into_iter().skip(1).collect_vec(),
+            None => ChildIterator::rooted(&self.tasks, None).get_all(),
+        };
+        let tasks = ids.into_iter().filter_map(|id| self.get_by_id(id)).collect_vec();
+        let title = position.map_or("All tasks".to_string(), |id| self.get_task_path(Some(*id)));
+        let tracked = match position {
+            Some(id) => self.total_time_tracked(*id, true),
+            None => self.tasks.children_for(None).map(|task| self.total_time_tracked(*task.get_id(), true)).sum(),
+        };
+        let states = [State::Open, State::Pending, State::Procedure, State::Done, State::Closed].into_iter()
+            .map(|state| (state, tasks.iter().filter(|task| task.is_task() && task.pure_state() == state).count()))
+            .filter(|(_, count)| *count > 0)
+            .map(|(state, count)| format!("{} {count}", state_badge(&state, &state.to_string())))
+            .join(" ");
+        let contributors = tasks.iter()
+            .flat_map(|task| once(&task.event.pubkey).chain(task.props.iter().map(|e| &e.pubkey)))
+            .unique()
+            .map(|key| self.get_author(key, None))
+            .sorted()
+            .join(", ");
+
+        writeln!(writer, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>",
+                 escape_html(&title))?;
+        writeln!(writer, "<h1>{}</h1>", escape_html(&title))?;
+        writeln!(writer, "<p class=\"summary\">{} tasks: {states}<br>Tracked: {}<br>Contributors: {}<br>Exported {}</p>",
+                 tasks.len(),
+                 some_non_empty(&display_time("HH:MM", tracked)).unwrap_or("00:00".to_string()),
+                 escape_html(&contributors),
+                 format_timestamp_local(&Timestamp::now()))?;
+        writeln!(writer, "<ul>")?;
+        for task in self.tasks.children_for(position).sorted_by_key(|t| t.event.created_at) {
+            self.write_html_task(writer, task)?;
+        }
+        writeln!(writer, "</ul>\n</body>\n</html>")?;
+        Ok(tasks.len())
+    }
+
+    /// Write the task with its notes and subtasks as HTML list item
+    fn write_html_task(&self, writer: &mut impl Write, task: &Task) -> Result<(), Error> {
+        let mut summary = String::new();
+        if task.is_task() {
+            summary.push_str(&state_badge(&task.pure_state(), &task.state_or_default().get_label()));
+            summary.push(' ');
+        }
+        summary.push_str(&escape_html(&task.get_title()));
+        if let Some(progress) = self.total_progress(task.get_id()).filter(|_| self.tasks.children_of(task).next().is_some()) {
+            summary.push_str(&format!(" <progress value=\"{progress:.2}\" max=\"1\"></progress> {}",
+                                      self.get_property(task, "progress").trim()));
+        }
+        let time = self.get_property(task, "rtime");
+        if !time.is_empty() {
+            summary.push_str(&format!(" <span class=\"time\">{time}</span>"));
+        }
+        let notes = task.descriptions().collect_vec();
+        let children = self.tasks.children_of(task).sorted_by_key(|t| t.event.created_at).collect_vec();
+        if notes.is_empty() && children.is_empty() {
+            return writeln!(writer, "<li>{summary}</li>");
+        }
+        writeln!(writer, "<li><details open><summary>{summary}</summary>")?;
+        for note in notes {
+            writeln!(writer, "<p class=\"note\">{}</p>", escape_html(note).replace('\n', "<br>"))?;
+        }
+        if !children.is_empty() {
+            writeln!(writer, "<ul>")?;
+            for child in children {
+                self.write_html_task(writer, child)?;
+            }
+            writeln!(writer, "</ul>")?;
+        }
+        writeln!(writer, "</details></li>")
+    }
+
+    /// Create tasks below the current position from lines in todo.txt format.
+    /// Projects become parent tasks, reused if one with that name exists,
+    /// and contexts become hashtags.
+    /// Lines whose title already exists under the same parent are skipped.
+    /// Returns the number of created and skipped tasks.
+    pub(crate) fn import_todotxt(&mut self, reader: impl BufRead) -> (usize, usize) {
+        let (mut created, mut skipped) = (0, 0);
+        let custom_time = self.custom_time;
+        for line in reader.lines().map_while(Result::ok) {
+            let Some(item) = TodoItem::parse(&line) else { continue };
+            let mut parent = self.get_position();
+            let mut projects = item.projects.iter();
+            if let Some(project) = projects.next() {
+                let existing = self.tasks.children_for(parent.as_ref())
+                    .find(|t| &t.get_title() == project)
+                    .map(|t| t.event.id);
+                parent = existing.or_else(|| {
+                    created += 1;
+                    self.make_task_with(&format!("\\{project}"), self.position_tags_for(parent.as_ref()), false)
+                });
+            }
+            if item.title.is_empty() {
+                continue;
+            }
+            if self.tasks.children_for(parent.as_ref()).any(|t| t.get_title() == item.title) {
+                skipped += 1;
+                continue;
+            }
+            let tags = item.contexts.iter().chain(projects)
+                .map(|tag| Tag::from(Hashtag(tag.clone())))
+                .chain(item.priority.map(build_priority_tag))
+                .chain(item.due.map(|day| build_due_tag(day_start(day).to_timestamp())))
+                .chain(self.position_tags_for(parent.as_ref()))
+                .collect_vec();
+            self.custom_time = item.created.map(|day| day_start(day).to_timestamp()).or(custom_time);
+            let Some(id) = self.make_task_with(&format!("\\{}", item.title), tags, false) else { break };
+            created += 1;
+            if item.done {
+                self.custom_time = item.completed.map(|day| day_start(day).to_timestamp()).or(custom_time);
+                self.set_state_for(id, "", State::Done);
+            }
+            self.custom_time = custom_time;
+        }
+        self.custom_time = custom_time;
+        (created, skipped)
+    }
+
+    /// Create tasks below the current position from the output of `task export`.
+    /// Projects become nested parent tasks, reused if one with that name exists,
+    /// and dependencies are linked once all tasks are created.
+    /// Returns the number of created tasks and the names of fields which were not imported.
+    pub(crate) fn import_taskwarrior(&mut self, json: &str) -> Result<(usize, BTreeSet<String>), Error> {
+        let items = parse_export(json)?;
+        let custom_time = self.custom_time;
+        let mut ids: HashMap<&str, EventId> = HashMap::new();
+        let mut unmapped = BTreeSet::new();
+        let mut created = 0;
+        for item in &items {
+            unmapped.extend(item.unmapped.iter().cloned());
+            let mut parent = self.get_position();
+            for project in &item.project {
+                let existing = self.tasks.children_for(parent.as_ref())
+                    .find(|t| &t.get_title() == project)
+                    .map(|t| t.event.id);
+                parent = existing.or_else(|| {
+                    created += 1;
+                    self.make_task_with(&format!("\\{project}"), self.position_tags_for(parent.as_ref()), false)
+                });
+            }
+            let tags = item.tags.iter()
+                .map(|tag| Tag::from(Hashtag(tag.clone())))
+                .chain(item.priority.map(build_priority_tag))
+                .chain(item.due.map(build_due_tag))
+                .chain(self.position_tags_for(parent.as_ref()))
+                .collect_vec();
+            self.custom_time = item.entry.or(custom_time);
+            let Some(id) = self.make_task_with(&format!("\\{}", item.description), tags, false) else { break };
+            created += 1;
+            ids.insert(&item.uuid, id);
+            for (time, annotation) in &item.annotations {
+                self.custom_time = time.or(custom_time);
+                self.submit(build_prop(Kind::TextNote, annotation, id));
+            }
+            if let Some(state) = item.state() {
+                self.custom_time = item.end.or(custom_time);
+                self.set_state_for(id, "", state);
+            }
+        }
+        self.custom_time = custom_time;
+        for item in &items {
+            let Some(id) = ids.get(item.uuid.as_str()) else { continue };
+            let (known, unknown): (Vec<_>, Vec<_>) = item.depends.iter()
+                .partition(|uuid| ids.contains_key(uuid.as_str()));
+            if !unknown.is_empty() {
+                warn!("\"{}\" depends on tasks missing from the export: {}", item.description, unknown.iter().join(", "));
+            }
+            if known.is_empty() {
+                continue;
+            }
+            let Some(task) = self.get_by_id(id) else { continue };
+            let tags = task.refs()
+                .map(|(marker, target)| self.make_event_tag_from_id(*target, marker))
+                .chain(known.iter().map(|uuid| self.make_event_tag_from_id(ids[uuid.as_str()], MARKER_DEPENDS)))
+                .collect_vec();
+            self.submit(build_references(*id, tags));
+        }
+        Ok((created, unmapped))
+    }
+
+    /// Groups of subtasks of the position which are not closed and have the same title ignoring case,
+    /// each ordered from oldest to newest.
+    pub(crate) fn find_duplicates(&self, position: Option<&EventId>) -> Vec<Vec<EventId>> {
+        self.tasks.children_for(position)
+            .filter(|t| t.pure_state() != State::Closed)
+            .into_group_map_by(|t| t.get_title().to_lowercase())
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .map(|group| group.into_iter()
+                .sorted_by_key(|t| (t.event.created_at, t.event.id))
+                .map(|t| t.event.id)
+                .collect_vec())
+            .sorted_by_key(|group| self.get_task_title(&group[0]).to_lowercase())
+            .collect()
+    }
+
+    /// Name of whoever set the current state of the task, none if it was its creator
+    pub(crate) fn state_changer(&self, task: &Task) -> Option<String> {
+        task.state()
+            .filter(|s| s.author != task.event.pubkey)
+            .map(|s| self.get_author(&s.author, None))
+    }
+
+    /// Every state of the task with its comment, who set it, when and how long it lasted,
+    /// ending with how long the task has been in its current state
+    pub(crate) fn describe_timeline(&self, id: &EventId) -> Vec<String> {
+        let Some(task) = self.get_by_id(id) else { return vec![] };
+        let timeline = task.state_timeline(self.now());
+        let mut lines = timeline.iter()
+            .map(|(state, duration)| format!("{} {} {} - {}",
+                                             format_timestamp_relative(&state.time),
+                                             state.state.colorize(&state.to_string()),
+                                             self.get_author(&state.author, None).dimmed(),
+                                             display_duration(*duration)))
+            .collect_vec();
+        for (state, reason) in task.discarded_states() {
+            lines.push(format!("{} {} {} - discarded as {reason}",
+                               format_timestamp_relative(&state.time),
+                               state.state.colorize(&state.to_string()),
+                               self.get_author(&state.author, None)).dimmed().to_string());
+        }
+        if let Some((state, duration)) = timeline.last() {
+            lines.push(format!("in {} for {}", state.get_colored_label(), display_duration(*duration)));
+        }
+        lines
+    }
+
+    /// Seconds you tracked on each task within the range, clipped at its edges and the current time,
+    /// most first
+    pub(crate) fn tracked_between(&self, start: Option<Timestamp>, end: Option<Timestamp>) -> Vec<(EventId, u64)> {
+        let now = Timestamp::now();
+        let end = end.map_or(now, |end| end.min(now));
+        let events = self.get_own_events_history().collect_vec();
+        let mut totals: HashMap<EventId, u64> = HashMap::new();
+        for (index, event) in events.iter().enumerate() {
+            let Some(id) = referenced_event(event) else { continue };
+            let from = start.map_or(event.created_at, |start| event.created_at.max(start));
+            let to = events.get(index + 1).map_or(now, |next| next.created_at).min(end);
+            if to > from {
+                *totals.entry(*id).or_default() += to.as_u64() - from.as_u64();
+            }
+        }
+        totals.into_iter()
+            .sorted_by_key(|(id, secs)| (Reverse(*secs), *id))
+            .collect()
+    }
+
+    /// Lines with the time you tracked on each task within the range and its path, most first,
+    /// followed by the total
+    pub(crate) fn tracked_report(&self, start: Option<Timestamp>, end: Option<Timestamp>) -> Vec<String> {
+        let tracked = self.tracked_between(start, end);
+        let total = tracked.iter().map(|(_, secs)| secs).sum::<u64>();
+        tracked.into_iter()
+            .map(|(id, secs)| format!("{:>6} {}", display_time("HHhMMm", secs), self.get_task_path(Some(id))))
+            .chain(once(format!("{:>6} {}", display_time("HHhMMm", total), "in total".italic())))
+            .collect()
+    }
+
+    /// Tasks below the position that were completed or closed since the given time,
+    /// in the order they were finished
+    pub(crate) fn completed_since(&self, position: Option<&EventId>, since: Timestamp) -> Vec<&Task> {
+        ChildIterator::rooted(&self.tasks, position)
+            .filter_map(|id| self.get_by_id(id))
+            .filter_map(|t| t.completed_at().filter(|stamp| stamp >= &since).map(|stamp| (stamp, t)))
+            .sorted_by_key(|(stamp, _)| *stamp)
+            .map(|(_, t)| t)
+            .collect()
+    }
+
+    /// Recap of your activity since the given time in at most 15 lines:
+    /// time tracked per top-level project, tasks you completed and created
+    /// and the task you spent the longest on
+    pub(crate) fn day_summary(&self, since: Timestamp) -> Vec<String> {
+        const SHOWN: usize = 5;
+        let own = self.sender.pubkey();
+        let tracked = self.tracked_between(Some(since), None);
+        let total = tracked.iter().map(|(_, secs)| secs).sum::<u64>();
+        let mut projects: HashMap<EventId, u64> = HashMap::new();
+        for (id, secs) in &tracked {
+            let root = self.project_root(*id).map_or(*id, |(root, _)| root);
+            *projects.entry(root).or_default() += secs;
+        }
+        let completed = self.completed_since(None, since).into_iter()
+            .filter(|t| t.state().is_some_and(|s| s.author == own))
+            .collect_vec();
+        let created = self.tasks.values()
+            .filter(|t| t.is_task() && t.event.pubkey == own && t.event.created_at >= since)
+            .count();
+
+        let mut lines = vec![format!("{} tracked, {} completed, {created} created since {}",
+                                     some_non_empty(&display_time("HHhMMm", total)).unwrap_or("Nothing".to_string()),
+                                     completed.len(),
+                                     format_timestamp_relative(&since))];
+        let projects = projects.into_iter().sorted_by_key(|(id, secs)| (Reverse(*secs), *id)).collect_vec();
+        lines.extend(projects.iter().take(SHOWN)
+            .map(|(id, secs)| format!("{:>8} {}", display_time("HHhMMm", *secs), self.get_task_title(id))));
+        if projects.len() > SHOWN {
+            lines.push(format!("{:>8} {} more projects", "", projects.len() - SHOWN).dimmed().to_string());
+        }
+        lines.extend(completed.iter().rev().take(SHOWN)
+            .map(|t| format!("{:>8} {}", t.state_or_default().get_colored_label(), self.get_task_path(Some(t.event.id)))));
+        if completed.len() > SHOWN {
+            lines.push(format!("{:>8} {} more completed", "", completed.len() - SHOWN).dimmed().to_string());
+        }
+        if let Some((id, secs)) = tracked.first() {
+            lines.push(format!("Longest on {} for {}", self.get_task_path(Some(*id)), display_time("HHhMMm", *secs)));
+        }
+        lines
+    }
+
+    /// Open tasks below the position without any activity since the given time
+    /// together with their last activity, least recently active first
+    pub(crate) fn stale_tasks(&self, position: Option<&EventId>, since: Timestamp) -> Vec<(Timestamp, &Task)> {
+        ChildIterator::rooted(&self.tasks, position)
+            .filter_map(|id| self.get_by_id(id))
+            .filter(|t| t.is_task() && t.pure_state().is_open())
+            .map(|t| (self.last_activity(t), t))
+            .filter(|(stamp, _)| stamp < &since)
+            .sorted_by_key(|(stamp, _)| *stamp)
+            .collect()
+    }
+
+    /// Set the state of all given tasks with the same comment,
+    /// sending the changes together.
+    /// Returns how many were changed.
+    pub(crate) fn sweep(&mut self, ids: &[EventId], state: State, comment: &str) -> usize {
+        let changed = ids.iter()
+            .filter(|id| self.set_state_for(**id, comment, state).is_some())
+            .count();
+        self.flush();
+        changed
+    }
+
+    /// Summary of a group of duplicates with their combined subtasks and tracked time
+    pub(crate) fn describe_duplicates(&self, group: &[EventId]) -> String {
+        let subtasks = group.iter().map(|id| self.tasks.children_ids_for(Some(id)).count()).sum::<usize>();
+        let time = group.iter().map(|id| self.total_time_tracked(*id, true)).sum::<u64>();
+        format!("{} {} times - {subtasks} subtasks, {} tracked",
+                group.first().map_or(String::new(), |id| self.get_task_path(Some(*id))),
+                group.len(),
+                some_non_empty(&display_time("HH:MM", time)).unwrap_or("nothing".to_string()))
+    }
+
+    /// Merge the other tasks of the group into the first one:
+    /// Their subtasks and dependents are moved over,
+    /// their notes and state updates republished on it,
+    /// and they are closed.
+    /// Returns the number of moved subtasks.
+    pub(crate) fn merge_duplicates(&mut self, group: &[EventId]) -> usize {
+        let Some((survivor, duplicates)) = group.split_first() else { return 0 };
+        let comment = format!("Duplicate of {}", self.get_task_path(Some(*survivor)));
+        let mut moved = 0;
+        for duplicate in duplicates {
+            let children = self.tasks.children_ids_for(Some(duplicate)).cloned().collect_vec();
+            for child in children {
+                if self.replace_ref(child, duplicate, survivor).is_some() {
+                    moved += 1;
+                }
+            }
+            let dependents = self.tasks.values()
+                .filter(|t| t.get_dependendees().contains(&duplicate))
+                .map(|t| t.event.id)
+                .collect_vec();
+            for dependent in dependents {
+                self.replace_ref(dependent, duplicate, survivor);
+            }
+            let props = self.get_by_id(duplicate).into_iter()
+                .flat_map(|t| t.props.iter())
+                .filter(|e| e.kind == Kind::TextNote || State::try_from(e.kind).is_ok())
+                .map(|e| (e.kind, e.content.clone(), e.created_at))
+                .collect_vec();
+            for (kind, content, created_at) in props {
+                self.submit(build_prop(kind, &content, *survivor).custom_created_at(created_at));
+            }
+            self.set_state_for(*duplicate, &comment, State::Closed);
+        }
+        moved
+    }
+
+    /// References to tasks which were never received, by the missing id,
+    /// with the marker and id of each referencing task
+    pub(crate) fn dangling(&self) -> Vec<(EventId, Vec<(String, EventId)>)> {
+        self.tasks.values()
+            .flat_map(|t| t.refs()
+                .filter(|(marker, target)| (marker == MARKER_PARENT || marker == MARKER_DEPENDS) && !self.tasks.contains_key(target))
+                .map(|(marker, target)| (*target, (marker.clone(), t.event.id))))
+            .into_group_map()
+            .into_iter()
+            .map(|(missing, mut referencing)| {
+                referencing.sort();
+                (missing, referencing)
+            })
+            .sorted()
+            .collect()
+    }
+
+    /// Request the tasks which are referenced but were never received from the relay.
+    /// Returns false if there is no relay to ask.
+    pub(crate) fn fetch_dangling(&self) -> bool {
+        self.sender.fetch_events(self.dangling().into_iter().map(|(missing, _)| missing).collect())
+    }
+
+    /// Drop the references to the missing task, or to all missing tasks,
+    /// moving tasks whose parent is missing to the root.
+    /// The id of the missing task may be abbreviated.
+    /// Returns the number of updated tasks.
+    pub(crate) fn detach_dangling(&mut self, missing: Option<&str>) -> usize {
+        let detached = self.dangling().into_iter()
+            .filter(|(id, _)| missing.map_or(true, |prefix| id.to_hex().starts_with(prefix.trim())))
+            .flat_map(|(id, referencing)| referencing.into_iter().map(move |(_, task)| (task, id)))
+            .collect_vec();
+        if detached.is_empty() {
+            warn!("No references to {}", missing.unwrap_or("unknown tasks"));
+        }
+        let count = detached.into_iter()
+            .filter_map(|(task, missing)| {
+                let tags = self.get_by_id(&task)?.refs()
+                    .filter(|(_, target)| target != &missing)
+                    .map(|(marker, target)| self.make_event_tag_from_id(*target, marker))
+                    .collect_vec();
+                self.submit(build_references(task, tags))
+            })
+            .count();
+        self.flush();
+        count
+    }
+
+    /// Publish new references for the task with one of them replaced by another
+    fn replace_ref(&mut self, id: EventId, old: &EventId, new: &EventId) -> Option<EventId> {
+        let tags = self.get_by_id(&id)?.refs()
+            .map(|(marker, target)| (marker, if target == old { new } else { target }))
+            .unique()
+            .map(|(marker, target)| self.make_event_tag_from_id(*target, marker))
+            .collect_vec();
+        self.submit(build_references(id, tags))
+    }
+
+    /// Add all events and known users of another relay without publishing them,
+    /// such that events present on several relays are held once.
+    pub(crate) fn merge_from(&mut self, other: &TasksRelay) {
+        for (key, metadata) in &other.users {
+            self.users.entry(*key).or_insert_with(|| metadata.clone());
+        }
+        let events = other.all_events()
+            .chain(other.bookmarks_event.iter())
+            .chain(other.contacts_event.iter())
+            .cloned()
+            .collect_vec();
+        for event in events {
+            self.add(event);
+        }
+        self.process_overflow();
+        // Overlap between relays is expected
+        self.take_duplicates();
+    }
+
+    /// Number of tasks received again since the last call,
+    /// to be reported once per batch of received events
+    pub(crate) fn take_duplicates(&mut self) -> usize {
+        std::mem::take(&mut self.duplicates)
+    }
+
+    /// Empty relay at the given url with the same identity, settings and hooks,
+    /// for events from a relay the client connected to without it being set up
+    pub(crate) fn sibling(&self, url: Url) -> Self {
+        let mut new = Self::with_sender(EventSender::from(Some(url), &self.sender.tx, &self.sender.keys, self.sender.cache.clone(), self.settings.undo_delay));
+        if let Some(metadata) = self.users.get(&self.sender.pubkey()) {
+            new.users.insert(self.sender.pubkey(), metadata.clone());
+        }
+        new.properties = self.settings.columns.clone();
+        new.depth = self.settings.depth;
+        new.settings = self.settings.clone();
+        new.hooks = self.hooks.clone();
+        new.hooks_enabled = self.hooks_enabled;
+        new.desktop = self.desktop;
+        new.quiet = self.quiet;
+        new.tag_hierarchy = self.tag_hierarchy.clone();
+        new.reset_view();
+        new
+    }
+
+    /// Whether events from relays without a set up relay create one, see [Self::sibling]
+    pub(crate) fn adopts_relays(&self) -> bool {
+        self.settings.adopt_relays
+    }
+
+    /// Take over events from another relay, such as local changes made while offline,
+    /// and publish them to this relay.
+    /// Skips known events and warns about conflicting state changes.
+    /// Returns the number of adopted events.
+    pub(crate) fn adopt(&mut self, events: impl IntoIterator<Item=Event>) -> usize {
+        let mut adopted = Vec::new();
+        for event in events {
+            if self.tasks.contains_key(&event.id) {
+                continue;
+            }
+            if let Some(task) = referenced_event(&event).and_then(|id| self.get_by_id(id)) {
+                if task.props.contains(&event) {
+                    continue;
+                }
+                if let (Ok(state), Some(current)) = (State::try_from(event.kind), task.state()) {
+                    if current.state == state {
+                        warn!("Not syncing state {} for \"{}\" since it is already {} here",
+                            TaskState::get_label_for(&state, &event.content), task.get_title(), current);
+                        continue;
+                    } else if current.time > event.created_at {
+                        warn!("Conflicting state for \"{}\": {} here is newer than synced {}",
+                            task.get_title(), current, TaskState::get_label_for(&state, &event.content));
                     }
                 }
             }
+            adopted.push(event.clone());
+            self.add(event);
+        }
+        let count = adopted.len();
+        self.force_flush();
+        self.sender.send(adopted);
+        count
+    }
+
+    /// Take over all data of another relay, such as local tasks,
+    /// and publish it to this relay.
+    /// Returns the number of adopted events.
+    pub(crate) fn adopt_from(&mut self, other: TasksRelay) -> usize {
+        // Queued events are contained in the tasks already
+        other.sender.clear();
+        let count = self.adopt(other.all_events().cloned().collect_vec());
+        let bookmarks = other.bookmarks.iter()
+            .filter(|id| !self.bookmarks.contains(id))
+            .cloned()
+            .collect_vec();
+        if !bookmarks.is_empty() {
+            self.bookmarks.extend(bookmarks);
+            if let Err(e) = self.publish_bookmarks() {
+                warn!("Could not publish bookmarks: {e}");
+            }
         }
+        count
     }
 
     pub(crate) fn add_task(&mut self, event: Event) {
         if self.tasks.contains_key(&event.id) {
-            warn!("Did not insert duplicate event {}", event.id);
+            debug!("Did not insert duplicate event {}", event.id);
+            self.duplicates += 1;
         } else {
-            let id = event.id;
-            let task = Task::new(event);
-            self.tasks.insert(id, task);
+            self.root_cache.get_mut().clear();
+            let (id, count) = (event.id, event.tags.len());
+            let task = Task::sanitized(event, self.settings.max_content, self.settings.max_tags);
+            if task.is_shortened() {
+                debug!("Shortening content of {id} with {} characters", task.event.content.chars().count());
+                self.sanitized.shortened += 1;
+            }
+            if count > self.settings.max_tags {
+                warn!("Only processing {} of the {count} tags of {id}", self.settings.max_tags);
+                self.sanitized.capped += 1;
+            }
+            self.tasks.insert(task);
         }
     }
 
     /// Add event as prop, returning it if not processable
     fn add_prop(&mut self, event: Event) -> Option<Event> {
+        // Others may plan state changes too, but not with a clock running far ahead
+        let skewed = State::try_from(event.kind).is_ok() &&
+            event.pubkey != self.sender.pubkey() &&
+            event.created_at > Timestamp::now() + self.settings.max_skew;
+        if skewed {
+            debug!("Discarding state update {} dated {} by {}", event.id, format_timestamp_local(&event.created_at), event.pubkey);
+        }
+        let (owner_states, window) = (self.settings.owner_states, self.settings.max_skew);
+        if event.kind == REFERENCES_KIND {
+            self.root_cache.get_mut().clear();
+        }
         let found = self.referenced_tasks(&event, |t| {
             t.props.insert(event.clone());
+            if skewed {
+                t.discarded.insert(event.id, Discarded::Skewed);
+            } else if owner_states && State::try_from(event.kind).is_ok() {
+                t.overrule_conflicts(window);
+            }
+            if event.kind == REFERENCES_KIND {
+                t.update_refs();
+            }
         });
         if !found {
             if event.kind == Kind::TextNote {
@@ -1012,7 +3427,7 @@ impl TasksRelay {
 
     fn history_before_now(&self) -> impl Iterator<Item=&Event> {
         self.get_own_history().into_iter().flat_map(|hist| {
-            let now = now();
+            let now = self.now();
             hist.values().rev().skip_while(move |e| e.created_at > now)
         })
     }
@@ -1030,34 +3445,166 @@ impl TasksRelay {
         false
     }
 
+    /// Move to the position held the given number of steps back in [Self::position_history].
     pub(crate) fn move_back_by(&mut self, steps: usize) {
-        let id = self.history_before_now().nth(steps)
-            .and_then(|e| referenced_event(e));
-        self.move_to(id.cloned())
+        let id = self.position_history().get(steps)
+            .and_then(|(id, _, _)| *id);
+        self.move_to(id)
+    }
+
+    /// Positions held until now without consecutive repeats, most recent first,
+    /// each with when it was entered and for how many seconds it was held.
+    pub(crate) fn position_history(&self) -> Vec<(Option<EventId>, Timestamp, u64)> {
+        let mut end = self.now();
+        let mut positions: Vec<(Option<EventId>, Timestamp, u64)> = vec![];
+        for event in self.history_before_now() {
+            let target = referenced_event(event).cloned();
+            let held = end.as_u64().saturating_sub(event.created_at.as_u64());
+            match positions.last_mut() {
+                Some((last, start, duration)) if last == &target => {
+                    *start = event.created_at;
+                    *duration += held;
+                }
+                _ => positions.push((target, event.created_at, held)),
+            }
+            end = event.created_at;
+        }
+        positions
     }
 
+    /// Revert the queued changes if there are any,
+    /// otherwise counter the last sent change with a compensating event.
     pub(crate) fn undo(&mut self) {
-        let mut count = 0;
-        self.sender.clear().into_iter().rev().for_each(|event| {
-            count += 1;
-            self.remove(&event)
-        });
-        info!("Reverted last {count} actions!")
+        let queued = self.sender.clear();
+        if queued.is_empty() {
+            self.compensate_last();
+            return;
+        }
+        let count = queued.len();
+        let events = queued.into_iter().filter_map(|q| self.resolve(q)).collect_vec();
+        self.journal.retain(|id| !events.iter().any(|e| &e.id == id));
+        events.iter().rev().for_each(|event| self.remove(event));
+        info!("Reverted last {count} actions before sending them")
+    }
+
+    /// Counter the newest change in the journal which was already sent:
+    /// a state change by setting the previous state again,
+    /// anything else by a deletion.
+    /// The compensating events are sent right away.
+    fn compensate_last(&mut self) {
+        let Some(event) = self.journal.pop_back().and_then(|id| self.find_event(&id).cloned()) else {
+            info!("Nothing left to undo");
+            return;
+        };
+        let previous = State::try_from(event.kind).ok()
+            .and_then(|_| referenced_event(&event).cloned())
+            .and_then(|task| self.get_by_id(&task).and_then(|t| t.state_before(&event.id)).map(|state| (task, state)));
+        match previous {
+            Some((task, state)) => {
+                info!("Undoing \"{}\" by setting it back to {state}", self.describe_event(&event));
+                if let Some(id) = self.publish_state(task, state.comment(), state.state) {
+                    self.journal.retain(|j| j != &id);
+                }
+            }
+            None => {
+                info!("Undoing \"{}\" by publishing a deletion", self.describe_event(&event));
+                self.submit(EventBuilder::delete([event.id]));
+            }
+        }
+        self.force_flush();
+    }
+
+    /// Human-readable description of a change, such as `mark "task" Done`
+    pub(crate) fn describe_event(&self, event: &Event) -> String {
+        let title = |id: &EventId| format!("\"{}\"", self.get_task_title(id));
+        match event.kind {
+            kind if kind == TRACKING_KIND => match referenced_event(event) {
+                Some(id) => format!("switch tracking to {} at {}", title(id), format_timestamp_relative(&event.created_at)),
+                None => format!("stop tracking at {}", format_timestamp_relative(&event.created_at)),
+            },
+            _ if self.tasks.contains_key(&event.id) => match self.get_parent(Some(&event.id)) {
+                Some(parent) => format!("create task {} under {}", title(&event.id), title(parent)),
+                None => format!("create task {}", title(&event.id)),
+            },
+            Kind::TextNote => match referenced_event(event) {
+                Some(id) => format!("add note \"{}\" to {}", event.content, title(id)),
+                None => format!("add note \"{}\"", event.content),
+            },
+            kind if kind == ATTACHMENT_KIND => match referenced_event(event) {
+                Some(id) => format!("attach {} to {}", attachment(event).unwrap_or_default(), title(id)),
+                None => format!("attach {}", attachment(event).unwrap_or_default()),
+            },
+            Kind::EventDeletion => format!("delete {}", referenced_events(event)
+                .map(|id| self.find_event(id).map_or_else(|| id.to_string(), |e| self.describe_event(e)))
+                .join(", ")),
+            Kind::ContactList => "update the list of people you follow".to_string(),
+            Kind::Bookmarks | Kind::BookmarkSet => "update bookmarks".to_string(),
+            Kind::Metadata => "update your profile".to_string(),
+            kind => match (State::try_from(kind), referenced_event(event)) {
+                (Ok(state), Some(id)) => format!("mark {} {}", title(id), TaskState::get_label_for(&state, &event.content)),
+                _ => format!("publish event of kind {kind}"),
+            },
+        }
+    }
+
+    /// Queued changes which were not handed to the relay connection yet, described for humans
+    pub(crate) fn pending_changes(&self) -> Vec<String> {
+        self.sender.peek().into_iter()
+            .filter_map(|queued| self.resolve(queued))
+            .map(|event| self.describe_event(&event))
+            .collect()
+    }
+
+    /// Remove the events referenced by a NIP-09 deletion
+    /// if they were created by the author of the deletion.
+    /// Children of a deleted task are kept with a dangling parent.
+    fn delete(&mut self, deletion: &Event) {
+        for id in referenced_events(deletion) {
+            self.deleted.insert(*id, deletion.pubkey);
+            if let Some(event) = self.find_event(id).filter(|e| e.pubkey == deletion.pubkey).cloned() {
+                debug!("Deleting {} kind {} \"{}\"", event.id, event.kind, event.content);
+                self.remove(&event);
+                self.bookmarks.retain(|b| b != id);
+            }
+        }
+    }
+
+    /// Find a task, property or time-tracking event by id
+    fn find_event(&self, id: &EventId) -> Option<&Event> {
+        self.tasks.get(id).map(|t| &t.event)
+            .or_else(|| self.tasks.values().flat_map(|t| t.props.iter()).find(|e| &e.id == id))
+            .or_else(|| self.history.values().flat_map(|h| h.values()).find(|e| &e.id == id))
     }
 
     fn remove(&mut self, event: &Event) {
+        self.position_cache.take();
+        self.root_cache.get_mut().clear();
+        self.subtree_cache.get_mut().clear();
         self.tasks.remove(&event.id);
-        self.history.get_mut(&self.sender.pubkey())
+        self.history.get_mut(&event.pubkey)
             .map(|t| t.retain(|t, e| e != event &&
                 !referenced_event(e).is_some_and(|id| id == &event.id)));
-        self.referenced_tasks(event, |t| { t.props.remove(event); });
+        self.referenced_tasks(event, |t| {
+            t.props.remove(event);
+            if event.kind == REFERENCES_KIND {
+                t.update_refs();
+            }
+        });
     }
 
     pub(crate) fn set_state_for_with(&mut self, id: EventId, comment: &str) {
         self.set_state_for(id, comment, comment.try_into().unwrap_or(State::Open));
     }
 
-    pub(crate) fn set_state_for(&mut self, id: EventId, comment: &str, state: State) -> EventId {
+    /// Set the state of the task if the others_tasks setting allows changing it.
+    pub(crate) fn set_state_for(&mut self, id: EventId, comment: &str, state: State) -> Option<EventId> {
+        if !self.may_change(&id) {
+            return None;
+        }
+        self.publish_state(id, comment, state)
+    }
+
+    fn publish_state(&mut self, id: EventId, comment: &str, state: State) -> Option<EventId> {
         let prop = build_prop(
             state.into(),
             comment,
@@ -1070,41 +3617,277 @@ impl TasksRelay {
         self.submit(prop)
     }
 
+    /// Whether the task may be changed according to the others_tasks setting.
+    /// Tasks created by others are only changed when allowed,
+    /// or after warning once when the change is repeated.
+    fn may_change(&mut self, id: &EventId) -> bool {
+        let Some(owner) = self.get_by_id(id).map(|t| t.event.pubkey) else { return true };
+        if owner == self.sender.pubkey() {
+            return true;
+        }
+        match self.settings.others_tasks {
+            Ownership::Allow => true,
+            Ownership::Warn if self.changes_confirmed.contains(id) => true,
+            Ownership::Warn => {
+                warn!("\"{}\" was created by {}, repeat to change it anyway", self.get_task_title(id), self.get_author(&owner, None));
+                self.changes_confirmed.insert(*id);
+                false
+            }
+            Ownership::Forbid => {
+                warn!("\"{}\" was created by {}, changing tasks of others is forbidden by the others_tasks setting",
+                    self.get_task_title(id), self.get_author(&owner, None));
+                false
+            }
+        }
+    }
+
     pub(crate) fn update_state(&mut self, comment: &str, state: State) -> Option<EventId> {
         let id = self.get_position_ref()?;
-        Some(self.set_state_for(*id, comment, state))
+        self.set_state_for(*id, comment, state)
     }
 
-    pub(crate) fn make_note(&mut self, note: &str) {
-        if let Some(id) = self.get_position_ref() {
-            if self.get_by_id(id).is_some_and(|t| t.is_task()) {
-                let prop = build_prop(Kind::TextNote, note.trim(), *id);
-                self.submit(prop);
-                return;
-            }
+    /// Ask for a review of the task, optionally by a specific person.
+    pub(crate) fn request_review(&mut self, id: EventId, reviewer: Option<PublicKey>) -> Option<EventId> {
+        info!("Requesting review of \"{}\"{}", self.get_task_title(&id),
+              reviewer.map(|key| format!(" by {}", self.get_author(&key, None))).unwrap_or_default());
+        let event = self.submit(build_review_request(id, reviewer))?;
+        if let Some(key) = reviewer {
+            self.notify_about(key, id, "asked you to review", "");
         }
-        let (input, tags) = extract_tags(note.trim());
-        self.submit(
-            build_task(input, tags, Some(("stateless ", Kind::TextNote)))
-                .add_tags(self.parent_tag())
-                .add_tags(self.tags.iter().cloned())
-        );
+        Some(event)
     }
 
-    // Properties
+    /// Approve the task, completing it, or reject it, reopening it, with a comment.
+    pub(crate) fn review(&mut self, id: EventId, approved: bool, comment: &str) -> Option<EventId> {
+        info!("{} \"{}\"", if approved { "Approving" } else { "Rejecting" }, self.get_task_title(&id));
+        self.submit(build_review(id, approved, comment))
+    }
 
-    pub(crate) fn set_depth(&mut self, depth: usize) {
-        if !self.view.is_empty() {
-            self.view.clear();
-            info!("Cleared search and changed view depth to {depth}");
-        } else {
-            info!("Changed view depth to {depth}");
+    /// Assign the task to the given person, or unassign it.
+    pub(crate) fn assign(&mut self, id: EventId, assignee: Option<PublicKey>) -> Option<EventId> {
+        match assignee {
+            Some(key) => info!("Assigning \"{}\" to {}", self.get_task_title(&id), self.get_author(&key, None)),
+            None => info!("Unassigning \"{}\"", self.get_task_title(&id)),
         }
-        self.depth = depth;
+        let event = self.submit(build_assignment(id, assignee))?;
+        if let Some(key) = assignee {
+            self.notify_about(key, id, "assigned you to", "");
+        }
+        Some(event)
     }
 
-    pub(crate) fn get_columns(&mut self) -> &mut Vec<String> {
-        &mut self.properties
+    /// Associate the task with a NIP-34 git repository, or remove the association.
+    pub(crate) fn set_repository(&mut self, id: EventId, repository: Option<Coordinate>) -> Option<EventId> {
+        if !self.may_change(&id) {
+            return None;
+        }
+        match &repository {
+            Some(repo) => info!("\"{}\" belongs to repository {}", self.get_task_title(&id), repo.identifier),
+            None => info!("\"{}\" belongs to no repository anymore", self.get_task_title(&id)),
+        }
+        self.submit(build_repository(id, repository))
+    }
+
+    /// Repository by its coordinate, naddr or the identifier of a repository known from tasks
+    pub(crate) fn find_repository(&self, str: &str) -> Option<Coordinate> {
+        if let Ok(coordinate) = Coordinate::parse(str) {
+            return Some(coordinate).filter(|c| c.kind == Kind::GitRepoAnnouncement);
+        }
+        self.tasks.values()
+            .filter_map(|task| task.repository())
+            .find(|repo| repo.identifier == str)
+    }
+
+    /// Only show tasks of the given git repository, or of any again
+    pub(crate) fn set_repo_filter(&mut self, repository: Option<Coordinate>) {
+        self.view.clear();
+        match &repository {
+            Some(repo) => info!("Showing only tasks of repository {}", repo.identifier),
+            None => info!("Showing tasks of all repositories"),
+        }
+        self.repo_filter = repository;
+    }
+
+    /// Set when the task expires, or clear its expiration.
+    pub(crate) fn set_expiration(&mut self, id: EventId, expiration: Option<Timestamp>) -> Option<EventId> {
+        if !self.may_change(&id) {
+            return None;
+        }
+        match expiration {
+            Some(stamp) => info!("\"{}\" expires {}", self.get_task_title(&id), format_timestamp_relative(&stamp)),
+            None => info!("\"{}\" does not expire anymore", self.get_task_title(&id)),
+        }
+        self.submit(build_expiration(id, expiration))
+    }
+
+    /// Attach a URL or file path to the current task.
+    pub(crate) fn attach(&mut self, reference: &str) -> Option<EventId> {
+        let Some(id) = self.get_position() else {
+            warn!("Select a task to attach {reference} to it");
+            return None;
+        };
+        info!("Attached {reference} to \"{}\"", self.get_task_title(&id));
+        self.submit(build_attachment(id, reference))
+    }
+
+    /// URL or file path attached to the current task by its index, counting from 1,
+    /// warning if there is none.
+    pub(crate) fn attachment(&self, index: usize) -> Option<String> {
+        self.find_attachment(index).and_then(|event| attachment(&event).map(|r| r.to_string()))
+    }
+
+    fn find_attachment(&self, index: usize) -> Option<Event> {
+        let Some(task) = self.get_current_task() else {
+            warn!("Select a task to access its attachments");
+            return None;
+        };
+        let found = index.checked_sub(1).and_then(|i| task.attachments().nth(i)).cloned();
+        if found.is_none() {
+            warn!("\"{}\" has no attachment {index}", task.get_title());
+        }
+        found
+    }
+
+    /// Remove an own attachment of the current task by its index, counting from 1.
+    pub(crate) fn detach(&mut self, index: usize) -> Option<EventId> {
+        let event = self.find_attachment(index)?;
+        if event.pubkey != self.sender.pubkey() {
+            warn!("Can only remove your own attachments, {} was attached by {}",
+                attachment(&event).unwrap_or_default(), self.get_author(&event.pubkey, None));
+            return None;
+        }
+        info!("Removing attachment {}", attachment(&event).unwrap_or_default());
+        self.submit(EventBuilder::delete([event.id]))
+    }
+
+    /// React to the current task, or retract an own earlier reaction with the same content.
+    /// Returns whether the reaction was added, or None without a current task or in watch-only mode.
+    pub(crate) fn react(&mut self, reaction: &str) -> Option<bool> {
+        let task = self.get_current_task()?;
+        let title = task.get_title();
+        let own = self.sender.pubkey();
+        let existing = task.reactions()
+            .filter(|e| e.pubkey == own && e.content == reaction)
+            .map(|e| e.id)
+            .collect_vec();
+        if existing.is_empty() {
+            let builder = EventBuilder::reaction(&task.event, reaction);
+            self.submit(builder)?;
+            info!("Reacted with {reaction} to \"{title}\"");
+            Some(true)
+        } else {
+            self.submit(EventBuilder::delete(existing))?;
+            info!("Retracted reaction {reaction} from \"{title}\"");
+            Some(false)
+        }
+    }
+
+    pub(crate) fn make_note(&mut self, note: &str) {
+        if let Some(id) = self.get_position() {
+            if self.get_by_id(&id).is_some_and(|t| t.is_task()) {
+                let mentioned = self.mentions(note);
+                let hashtags = extract_hashtags(note).collect_vec();
+                let content = if self.settings.strip_hashtags { strip_hashtags(note) } else { note.to_string() };
+                let prop = build_prop(Kind::TextNote, &self.link_mentions(content.trim()), id)
+                    .add_tags(mentioned.iter().map(|key| Tag::public_key(*key)))
+                    .add_tags(hashtags);
+                if self.submit(prop).is_none() {
+                    return;
+                }
+                for key in mentioned {
+                    self.notify_about(key, id, "mentioned you on", note.trim());
+                }
+                return;
+            }
+        }
+        let (input, tags) = extract_tags(note.trim(), self.settings.strip_hashtags);
+        self.submit(
+            build_task(&input, tags, Some(("stateless ", Kind::TextNote)))
+                .add_tags(self.parent_tag())
+                .add_tags(self.new_task_tags())
+        );
+    }
+
+    /// Reply to a note of the current task by its index, counting from 1 in order of creation,
+    /// marking the note as parent per NIP-10.
+    pub(crate) fn reply_to_note(&mut self, index: usize, reply: &str) -> Option<EventId> {
+        let (id, note) = self.find_note(index, "reply to")?;
+        let mentioned = once(note.pubkey).chain(self.mentions(reply)).unique().collect_vec();
+        let tags = once(Tag::from(TagStandard::Event {
+            event_id: note.id,
+            relay_url: self.sender.url.as_ref().map(|url| UncheckedUrl::new(url.as_str())),
+            marker: Some(Marker::Reply),
+            public_key: Some(note.pubkey),
+        })).chain(mentioned.iter().map(|key| Tag::public_key(*key))).collect_vec();
+        let reply_id = self.submit(build_prop(Kind::TextNote, &self.link_mentions(reply.trim()), id).add_tags(tags))?;
+        for key in mentioned {
+            self.notify_about(key, id, "replied to you on", reply.trim());
+        }
+        Some(reply_id)
+    }
+
+    /// Note of the current task by its index, counting from 1 as listed by [Self::format_notes],
+    /// warning if there is none.
+    fn find_note(&self, index: usize, action: &str) -> Option<(EventId, Event)> {
+        let Some(task) = self.get_current_task() else {
+            warn!("Select a task to {action} one of its notes");
+            return None;
+        };
+        let Some(note) = index.checked_sub(1).and_then(|i| task.description_events().nth(i)) else {
+            warn!("The current task has no note {index}");
+            return None;
+        };
+        Some((*task.get_id(), note.clone()))
+    }
+
+    /// Own note of the current task by its index, warning if there is none
+    pub(crate) fn own_note(&self, index: usize, action: &str) -> Option<Event> {
+        let (_, note) = self.find_note(index, action)?;
+        if note.pubkey != self.sender.pubkey() {
+            warn!("Can only {action} your own notes, note {index} is by {}", self.get_author(&note.pubkey, None));
+            return None;
+        }
+        Some(note)
+    }
+
+    /// Replace an own note of the current task by one with the new content,
+    /// which takes its place in the list, and delete the old one.
+    pub(crate) fn edit_note(&mut self, index: usize, content: &str) -> Option<EventId> {
+        let note = self.own_note(index, "edit")?;
+        let id = *self.get_current_task()?.get_id();
+        let mentioned = self.mentions(content);
+        let tags = once(self.make_event_tag(&note, MARKER_SUPERSEDES))
+            .chain(superseded(&note).map(|earlier| self.make_event_tag_from_id(*earlier, MARKER_SUPERSEDES)))
+            .chain(once(Tag::from(TagStandard::PublishedAt(published_at(&note)))))
+            .chain(mentioned.iter().map(|key| Tag::public_key(*key)))
+            .collect_vec();
+        let replacement = self.submit(build_prop(Kind::TextNote, &self.link_mentions(content.trim()), id).add_tags(tags))?;
+        self.submit(EventBuilder::delete([note.id]));
+        info!("Edited note {index} of \"{}\"", self.get_task_title(&id));
+        Some(replacement)
+    }
+
+    /// Retract an own note of the current task by publishing its deletion.
+    pub(crate) fn remove_note(&mut self, index: usize) -> Option<EventId> {
+        let note = self.own_note(index, "remove")?;
+        info!("Removing note \"{}\"", note.content);
+        self.submit(EventBuilder::delete([note.id]))
+    }
+
+    // Properties
+
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        if !self.view.is_empty() {
+            self.view.clear();
+            info!("Cleared search and changed view depth to {depth}");
+        } else {
+            info!("Changed view depth to {depth}");
+        }
+        self.depth = depth;
+    }
+
+    pub(crate) fn get_columns(&mut self) -> &mut Vec<String> {
+        &mut self.properties
     }
 
     pub(crate) fn set_sorting(&mut self, vec: VecDeque<String>) {
@@ -1120,6 +3903,92 @@ impl TasksRelay {
     }
 }
 
+/// A task with evaluated properties for machine-readable output
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TaskView {
+    id: String,
+    parent: Option<String>,
+    state: String,
+    hashtags: Vec<String>,
+    /// Seconds tracked on the task and its subtree
+    tracked: u64,
+    path: String,
+    /// Raw values of the configured columns
+    #[serde(flatten)]
+    properties: BTreeMap<String, Value>,
+}
+impl TaskView {
+    /// Fields which take precedence over columns of the same name
+    const FIELDS: [&'static str; 6] = ["id", "parent", "state", "hashtags", "tracked", "path"];
+}
+
+/// Whether the coordinates point to the same repository, regardless of relay hints
+fn same_repository(a: &Coordinate, b: &Coordinate) -> bool {
+    a.kind == b.kind && a.public_key == b.public_key && a.identifier == b.identifier
+}
+
+/// Typed value of a property to sort tasks by,
+/// so durations and times compare numerically and text regardless of its colors
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    String(String),
+    Number(u64),
+    Timestamp(Option<Timestamp>),
+}
+
+/// Counts of incoming events which were cut down or rejected, see [Settings::max_content]
+#[derive(Debug, Clone, Copy, Default)]
+struct Sanitized {
+    /// Tasks whose content is shortened for display
+    shortened: usize,
+    /// Tasks of which only some tags were processed
+    capped: usize,
+    /// Events dated too far ahead
+    rejected: usize,
+}
+impl Display for Sanitized {
+    /// Comma-prefixed list of the nonzero counts
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (count, label) in [(self.shortened, "shortened"), (self.capped, "with tags capped"), (self.rejected, "rejected as dated ahead")] {
+            if count > 0 {
+                write!(f, ", {count} {label}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Update whose task is not known
+#[derive(Debug, Clone)]
+pub(crate) struct Orphan {
+    pub(crate) event: Event,
+    attempts: u32,
+    since: Timestamp,
+}
+impl Orphan {
+    fn new(event: Event) -> Self {
+        Orphan { event, attempts: 0, since: Timestamp::now() }
+    }
+}
+impl Display for Orphan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} kind {} referencing {} - queued {}, {} attempts: {}",
+               self.event.id,
+               self.event.kind,
+               referenced_events(&self.event).join(", "),
+               format_timestamp_relative(&self.since),
+               self.attempts,
+               self.event.content)
+    }
+}
+
+impl Drop for TasksRelay {
+    fn drop(&mut self) {
+        self.force_flush();
+        debug!("Dropped {:?}", self.sender);
+    }
+}
+
 pub trait PropertyCollection<T> {
     fn remove_at(&mut self, index: usize);
     fn add_or_remove(&mut self, value: T);
@@ -1156,6 +4025,41 @@ where
     }
 }
 
+/// Status of a to-do in iCalendar for the state of a task
+fn ical_status(state: &State) -> &'static str {
+    match state {
+        State::Open | State::Pending => "NEEDS-ACTION",
+        State::Procedure => "IN-PROCESS",
+        State::Done => "COMPLETED",
+        State::Closed => "CANCELLED",
+    }
+}
+
+/// Show a desktop notification via `notify-send` without waiting for it
+#[cfg(feature = "desktop-notifications")]
+fn show_desktop_notification(summary: &str, body: &str) {
+    match Command::new("notify-send").args(["--app-name=mostr", summary, body]).stdin(Stdio::null()).spawn() {
+        Ok(mut child) => {
+            thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => debug!("notify-send failed with {status}"),
+                Err(e) => debug!("Could not wait for notify-send: {e}"),
+                Ok(_) => {}
+            });
+        }
+        Err(e) => warn!("Could not show desktop notification: {e}"),
+    }
+}
+
+#[cfg(not(feature = "desktop-notifications"))]
+fn show_desktop_notification(summary: &str, _body: &str) {
+    debug!("Built without desktop notifications, not showing \"{summary}\"");
+}
+
+/// Depth of a property like `rtime:2`, summing up time down to that many subtask levels
+fn rtime_depth(property: &str) -> Option<usize> {
+    property.strip_prefix("rtime:")?.parse().ok()
+}
+
 /// Formats the given seconds according to the given format.
 /// MMM - minutes
 /// MM - minutes of the hour
@@ -1171,6 +4075,49 @@ fn display_time(format: &str, secs: u64) -> String {
         )
 }
 
+/// Inline styling of the HTML report
+const HTML_STYLE: &str = "body { font-family: sans-serif; max-width: 60em; margin: auto; padding: 1em; } \
+ul { list-style: none; padding-left: 1.2em; } \
+li { margin: 0.2em 0; } \
+summary { cursor: pointer; } \
+.state { color: white; border-radius: 0.3em; padding: 0 0.3em; font-size: 0.85em; } \
+.time { color: #555; font-family: monospace; } \
+.note { color: #333; margin: 0.2em 0 0.2em 1.2em; white-space: normal; } \
+.summary { background: #f4f4f4; padding: 0.5em; border-radius: 0.3em; }";
+
+/// Label in a badge colored by the state
+fn state_badge(state: &State, label: &str) -> String {
+    format!("<span class=\"state\" style=\"background: {}\">{}</span>", state.html_color(), escape_html(label))
+}
+
+/// Whether the tags are equal, ignoring the case of hashtags
+fn same_tag(tag: &Tag, other: &Tag) -> bool {
+    if is_hashtag(tag) && is_hashtag(other) {
+        tag.content().zip(other.content()).is_some_and(|(a, b)| a.to_lowercase() == b.to_lowercase())
+    } else {
+        tag == other
+    }
+}
+
+/// Colors to tell authors apart, avoiding the ones used for states
+const AUTHOR_COLORS: [Color; 6] = [Color::Cyan, Color::BrightCyan, Color::BrightBlue, Color::BrightMagenta, Color::BrightYellow, Color::BrightWhite];
+
+/// Color of an author derived from their key, so it stays the same across sessions
+fn author_color(pubkey: &PublicKey) -> Color {
+    let hash = pubkey.to_string().bytes().fold(0usize, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as usize));
+    AUTHOR_COLORS[hash % AUTHOR_COLORS.len()]
+}
+
+/// Rough human-readable duration, down to seconds only below a minute
+fn display_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86_399 => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{:02}h", secs / 86_400, secs % 86_400 / 3600),
+    }
+}
+
 pub(crate) fn join_tasks<'a>(
     iter: impl Iterator<Item=&'a Task>,
     include_last_id: bool,
@@ -1208,11 +4155,21 @@ fn matching_tag_id<'a>(event: &'a Event, ids: &'a [&'a EventId]) -> Option<&'a E
     referenced_events(event).find(|id| ids.contains(id))
 }
 
-/// Filters out event timestamps to those that start or stop one of the given events
-fn timestamps<'a>(events: impl Iterator<Item=&'a Event>, ids: &'a [&'a EventId]) -> impl Iterator<Item=(&Timestamp, Option<&EventId>)> {
-    events.map(|event| (&event.created_at, matching_tag_id(event, ids)))
-        .dedup_by(|(_, e1), (_, e2)| e1 == e2)
-        .skip_while(|element| element.1.is_none())
+/// Timestamp of a tracking event with the given event it starts, none if it stops tracking them
+type Stamp<'a> = (&'a Timestamp, Option<&'a EventId>);
+
+/// Filters out event timestamps to those that start or stop one of the given events,
+/// split into those until `now` and those planned after it.
+/// Planned entries only start with a stop if tracking is ongoing at `now`.
+fn timestamps<'a>(events: impl Iterator<Item=&'a Event>, ids: &'a [&'a EventId], now: Timestamp) -> (Vec<Stamp<'a>>, Vec<Stamp<'a>>) {
+    let stamps = |events: Vec<&'a Event>| events.into_iter()
+        .map(|event| (&event.created_at, matching_tag_id(event, ids)))
+        .dedup_by(|(_, e1), (_, e2)| e1 == e2);
+    let (past, planned): (Vec<_>, Vec<_>) = events.partition(|event| event.created_at <= now);
+    let past = stamps(past).skip_while(|element| element.1.is_none()).collect_vec();
+    let tracking = past.last().is_some_and(|element| element.1.is_some());
+    let planned = stamps(planned).skip_while(|element| !tracking && element.1.is_none()).collect_vec();
+    (past, planned)
 }
 
 /// Iterates Events to accumulate times tracked
@@ -1244,7 +4201,8 @@ impl Iterator for Durations<'_> {
                 start = start.or(Some(event.created_at.as_u64()))
             } else {
                 if let Some(stamp) = start {
-                    return Some(Duration::from_secs(event.created_at.as_u64() - stamp));
+                    let end = self.threshold.map_or(event.created_at, |th| event.created_at.min(th));
+                    return Some(Duration::from_secs(end.as_u64().saturating_sub(stamp)));
                 }
             }
         }
@@ -1285,14 +4243,9 @@ struct ChildIterator<'a> {
 }
 impl<'a> ChildIterator<'a> {
     fn rooted(tasks: &'a TaskMap, id: Option<&EventId>) -> Self {
-        let mut queue = Vec::with_capacity(tasks.len());
-        queue.append(
-            &mut tasks
-                .values()
-                .filter(move |t| t.parent_id() == id)
-                .map(|t| t.get_id())
-                .collect_vec()
-        );
+        let queue = tasks.children_for(id)
+            .map(|t| t.get_id())
+            .collect_vec();
         Self::with_queue(tasks, queue)
     }
 
@@ -1403,7 +4356,7 @@ impl<'a> ChildIterator<'a> {
     }
 
     fn queue_children_of(&mut self, task: &'a Task) {
-        self.queue.extend(self.tasks.children_ids_for(task.get_id()));
+        self.queue.extend(self.tasks.children_ids_for(Some(task.get_id())));
     }
 }
 impl FusedIterator for ChildIterator<'_> {}
@@ -1412,19 +4365,8 @@ impl<'a> Iterator for ChildIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.next_task().inspect(|id| {
-            match self.tasks.get(id) {
-                None => {
-                    // Unknown task, might still find children, just slower
-                    for task in self.tasks.values() {
-                        if task.parent_id().is_some_and(|i| i == *id) {
-                            self.queue.push(task.get_id());
-                        }
-                    }
-                }
-                Some(task) => {
-                    self.queue_children_of(&task);
-                }
-            }
+            // Unknown tasks might still have known children
+            self.queue.extend(self.tasks.children_ids_for(Some(*id)));
             self.check_depth();
         })
     }
@@ -1444,77 +4386,1342 @@ impl<'a> Iterator for ParentIterator<'a> {
             t
         })
     }
-}
+}
+
+#[cfg(test)]
+mod tasks_test {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn stub_tasks() -> TasksRelay {
+        use tokio::sync::mpsc;
+        use nostr_sdk::Keys;
+
+        let (tx, _rx) = mpsc::channel(16);
+        TasksRelay::with_sender(EventSender {
+            url: None,
+            tx,
+            keys: Keys::generate(),
+            queue: Default::default(),
+            cache: None,
+            undo_delay: Settings::default().undo_delay,
+        })
+    }
+
+    macro_rules! assert_position {
+        ($left:expr, $right:expr $(,)?) => {
+            assert_eq!($left.get_position_ref(), Some(&$right))
+        };
+    }
+
+    macro_rules! assert_tasks {
+        ($left:expr, $right:expr $(,)?) => {
+            assert_eq!($left.visible_tasks().iter().map(|t| t.event.id).collect::<HashSet<EventId>>(), 
+                       HashSet::from($right))
+        };
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let mut tasks = stub_tasks();
+        let zero = EventId::all_zeros();
+        let test = tasks.make_task("test: tag").unwrap();
+        let parent = tasks.make_task("parent").unwrap();
+        assert_eq!(tasks.visible_tasks().len(), 2);
+        tasks.move_to(Some(parent));
+        let pin = tasks.make_task("pin").unwrap();
+
+        assert_eq!(tasks.filtered_tasks(None, true).len(), 2);
+        assert_eq!(tasks.filtered_tasks(None, false).len(), 2);
+        assert_eq!(tasks.filtered_tasks(Some(&zero), false).len(), 0);
+        assert_eq!(tasks.visible_tasks().len(), 1);
+        assert_eq!(tasks.filtered_tasks(Some(&pin), false).len(), 0);
+        assert_eq!(tasks.filtered_tasks(Some(&zero), false).len(), 0);
+
+        tasks.submit(build_bookmarks(None, [pin, zero]));
+        assert_eq!(tasks.visible_tasks().len(), 1);
+        assert_eq!(tasks.filtered_tasks(Some(&pin), true).len(), 0);
+        assert_eq!(tasks.filtered_tasks(Some(&pin), false).len(), 0);
+        assert_eq!(tasks.filtered_tasks(Some(&zero), true).len(), 0);
+        assert_eq!(tasks.filtered_tasks(Some(&zero), false), vec![tasks.get_by_id(&pin).unwrap()]);
+
+        tasks.move_to(None);
+        assert_eq!(tasks.depth, 1);
+        assert_tasks!(tasks, [pin, test, parent]);
+        tasks.set_depth(2);
+        assert_tasks!(tasks, [pin, test]);
+        tasks.add_tag("tag".to_string());
+        assert_tasks!(tasks, [test]);
+        assert_eq!(tasks.filtered_tasks(None, true), vec![tasks.get_by_id(&test).unwrap()]);
+        tasks.submit(build_bookmarks(None, []));
+        tasks.clear_filters();
+        assert_tasks!(tasks, [pin, test]);
+        tasks.set_depth(1);
+        assert_tasks!(tasks, [test, parent]);
+    }
+
+    #[test]
+    fn test_bookmark_marker() {
+        let mut tasks = stub_tasks();
+        let test = tasks.make_task("test").unwrap();
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.move_to(Some(parent));
+        let pin = tasks.make_task("pin").unwrap();
+        tasks.submit(build_bookmarks(None, [pin]));
+        let columns = tasks.get_columns();
+        columns.clear();
+        columns.push("name".to_string());
+        let rows = |tasks: &TasksRelay| {
+            let mut output = vec![];
+            tasks.write_tasks(&mut output).unwrap();
+            String::from_utf8(output).unwrap().lines().map(|s| s.to_string()).collect_vec()
+        };
+        assert!(rows(&tasks).contains(&"★ pin".to_string()));
+
+        tasks.move_to(None);
+        assert_tasks!(tasks, [pin, test, parent]);
+        let listed = rows(&tasks);
+        assert!(listed.contains(&"★ pin".to_string()));
+        assert!(listed.contains(&"test".to_string()));
+        assert!(listed.contains(&"parent".to_string()));
+
+        tasks.toggle_bookmarks();
+        assert_tasks!(tasks, [test, parent]);
+        assert!(!rows(&tasks).iter().any(|row| row.contains('★')));
+        tasks.move_to(Some(parent));
+        assert!(rows(&tasks).contains(&"★ pin".to_string()));
+        tasks.toggle_bookmarks();
+        tasks.move_to(None);
+        assert_tasks!(tasks, [pin, test, parent]);
+    }
+
+    #[test]
+    fn test_hashtag_filter() {
+        let mut tasks = stub_tasks();
+        let garden = tasks.make_task("garden: Home").unwrap();
+        let upper = tasks.make_task("upper: HOME").unwrap();
+        let clean = tasks.make_task("clean: homework").unwrap();
+        let call = tasks.make_task("call: Work").unwrap();
+
+        tasks.add_tag("home".to_string());
+        assert_tasks!(tasks, [garden, upper]);
+        assert_eq!(tasks.get_prompt_suffix(), " #HOME");
+        tasks.remove_tag("Ho");
+        assert_tasks!(tasks, [garden, upper, clean, call]);
+
+        tasks.add_tag("wo".to_string());
+        assert_tasks!(tasks, [call]);
+        assert_eq!(tasks.get_prompt_suffix(), " #Work");
+        tasks.clear_filters();
+
+        // Ambiguous prefixes are taken literally
+        tasks.add_tag("h".to_string());
+        assert_eq!(tasks.get_prompt_suffix(), " #h");
+        assert_eq!(tasks.visible_tasks().len(), 0);
+        tasks.clear_filters();
+
+        tasks.remove_tag("WORK");
+        assert_tasks!(tasks, [garden, upper, clean]);
+    }
+
+    #[test]
+    fn test_inline_hashtags() {
+        let mut tasks = stub_tasks();
+        let login = tasks.make_task("fix login #auth #urgent: backend").unwrap();
+        let docs = tasks.make_task("write #docs").unwrap();
+        let hashtags = |tasks: &TasksRelay, id: EventId| tasks.get_by_id(&id).unwrap().hashtags().map(|s| s.to_string()).sorted().collect_vec();
+        assert_eq!(hashtags(&tasks, login), vec!["auth", "backend", "urgent"]);
+        assert_eq!(tasks.get_task_title(&login), "fix login #auth #urgent");
+        assert_eq!(tasks.all_hashtags().collect_vec(), vec!["auth", "backend", "docs", "urgent"]);
+        tasks.add_tag("auth".to_string());
+        assert_tasks!(tasks, [login]);
+        tasks.clear_filters();
+
+        tasks.settings.strip_hashtags = true;
+        let review = tasks.make_task("review #auth changes: backend").unwrap();
+        assert_eq!(tasks.get_task_title(&review), "review changes");
+        assert_eq!(hashtags(&tasks, review), vec!["auth", "backend"]);
+
+        tasks.move_to(Some(docs));
+        tasks.make_note("needs a #glossary");
+        let note = tasks.get_current_task().unwrap().description_events().next().unwrap().clone();
+        assert_eq!(note.content, "needs a");
+        assert!(note.tags.iter().any(|tag| tag.as_standardized() == Some(&TagStandard::Hashtag("glossary".to_string()))));
+    }
+
+    #[test]
+    fn test_tag_filter_transitions() {
+        let mut tasks = stub_tasks();
+        let garden = tasks.make_task("garden: home outside").unwrap();
+        let laundry = tasks.make_task("laundry: home").unwrap();
+        let call = tasks.make_task("call: work").unwrap();
+
+        tasks.remove_tag("outside");
+        assert_tasks!(tasks, [laundry, call]);
+        tasks.add_tag("home".to_string());
+        assert_tasks!(tasks, [laundry]);
+        assert_eq!(tasks.get_prompt_suffix(), " #home -#outside");
+        // Excluding again toggles the exclusion off
+        tasks.remove_tag("Outside");
+        assert_tasks!(tasks, [garden, laundry]);
+        assert_eq!(tasks.get_prompt_suffix(), " #home");
+        // Removing an included tag does not exclude it
+        tasks.remove_tag("home");
+        assert_tasks!(tasks, [garden, laundry, call]);
+        assert!(!tasks.has_tag_filter());
+
+        tasks.remove_tag("work");
+        tasks.remove_tag("outside");
+        assert_eq!(tasks.get_prompt_suffix(), " -#outside -#work");
+        tasks.set_tags([Hashtag("work".to_string()).into()]);
+        assert_tasks!(tasks, [call]);
+        assert_eq!(tasks.get_prompt_suffix(), " #work -#outside");
+        tasks.clear_tag_filters();
+        assert!(!tasks.has_tag_filter());
+        assert_tasks!(tasks, [garden, laundry, call]);
+    }
+
+    #[test]
+    fn test_tag_hierarchy_filter() {
+        let mut tasks = stub_tasks();
+        tasks.tag_hierarchy = crate::config::Config::parse("[tags]\nwork = clientA clientB").tag_hierarchy();
+        let a = tasks.make_task("design: clientA urgent").unwrap();
+        let b = tasks.make_task("invoice: clientB").unwrap();
+        let work = tasks.make_task("meeting: work").unwrap();
+        let home = tasks.make_task("laundry: home urgent").unwrap();
+        assert_eq!(tasks.grouped_hashtags(), vec!["home", "urgent", "work(clientA clientB)"]);
+
+        tasks.add_tag("work".to_string());
+        assert_tasks!(tasks, [a, b, work]);
+        assert_eq!(tasks.get_prompt_suffix(), " #work");
+        // Every added tag has to match, each one by itself or a child
+        tasks.add_tag("urgent".to_string());
+        assert_tasks!(tasks, [a]);
+        tasks.remove_tag("work");
+        assert_tasks!(tasks, [a, home]);
+
+        tasks.set_tags([Hashtag("clientB".to_string()).into()]);
+        assert_tasks!(tasks, [b]);
+        tasks.set_tags([Hashtag("work".to_string()).into()]);
+        let new = tasks.make_task("report").unwrap();
+        assert_eq!(tasks.get_by_id(&new).unwrap().hashtags().collect_vec(), vec!["work"]);
+    }
+
+    #[test]
+    fn test_creation_tags() {
+        let mut tasks = stub_tasks();
+        let hashtags = |tasks: &TasksRelay, id: EventId| tasks.get_by_id(&id).unwrap().hashtags().map(|t| t.to_string()).sorted().collect_vec();
+        tasks.add_creation_tags(["inbox".to_string()]);
+        assert_eq!(tasks.get_prompt_suffix(), " #+inbox");
+        let task = tasks.make_task("first").unwrap();
+        assert_eq!(hashtags(&tasks, task), vec!["inbox"]);
+        // Creation tags do not filter
+        tasks.make_task("other: elsewhere");
+        assert_eq!(tasks.visible_tasks().len(), 2);
+
+        tasks.add_tag("work".to_string());
+        let both = tasks.make_task("both").unwrap();
+        assert_eq!(hashtags(&tasks, both), vec!["inbox", "work"]);
+        tasks.settings.filter_tags_on_creation = false;
+        let created = tasks.make_task("created").unwrap();
+        assert_eq!(hashtags(&tasks, created), vec!["inbox"]);
+
+        tasks.clear_filters();
+        assert_eq!(tasks.get_prompt_suffix(), " #+inbox");
+        tasks.remove_creation_tags(&["INBOX"]);
+        assert_eq!(tasks.get_prompt_suffix(), "");
+        tasks.add_creation_tags(["a".to_string(), "b".to_string()]);
+        tasks.remove_creation_tags(&[]);
+        assert_eq!(tasks.get_prompt_suffix(), "");
+    }
+
+    #[test]
+    fn test_adopt() {
+        let mut local = stub_tasks();
+        let mut tasks = stub_tasks();
+        let id = local.make_task("offline").unwrap();
+        tasks.add(local.get_by_id(&id).unwrap().event.clone());
+        local.set_state_for(id, "", State::Closed);
+        tasks.set_state_for(id, "", State::Closed);
+        let child = local.make_task_with("child", local.position_tags_for(Some(&id)), false).unwrap();
+
+        let events = local.all_events().cloned().collect_vec();
+        assert_eq!(events.len(), 3);
+        assert_eq!(tasks.adopt(events), 1);
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks.get_by_id(&child).unwrap().parent_id(), Some(&id));
+        assert_eq!(tasks.get_by_id(&id).unwrap().props.len(), 1);
+    }
+
+    #[test]
+    fn test_deletion() {
+        let mut tasks = stub_tasks();
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.move_to(Some(parent));
+        let child = tasks.make_task("child").unwrap();
+        tasks.make_note("note on parent");
+        tasks.set_state_for(parent, "", State::Pending);
+        tasks.move_to(None);
+        assert_eq!(tasks.get_by_id(&parent).unwrap().props.len(), 2);
+        assert_eq!(tasks.get_own_events_history().count(), 2);
+
+        // Only the author can delete
+        tasks.add(EventBuilder::delete([parent]).to_event(&Keys::generate()).unwrap());
+        assert!(tasks.get_by_id(&parent).is_some());
+
+        tasks.submit(EventBuilder::delete([parent]));
+        assert_eq!(tasks.get_by_id(&parent), None);
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks.get_own_events_history().count(), 1);
+        assert_eq!(tasks.get_task_path(Some(child)), format!("{}>child", parent));
+    }
+
+    #[test]
+    fn test_reactions() {
+        let mut tasks = stub_tasks();
+        let task = tasks.make_task("task").unwrap();
+        assert_eq!(tasks.react("+"), None);
+        tasks.move_to(Some(task));
+        assert_eq!(tasks.react("+"), Some(true));
+        let other = Keys::generate();
+        let target = tasks.get_by_id(&task).unwrap().event.clone();
+        tasks.add(EventBuilder::reaction(&target, "+").to_event(&other).unwrap());
+        tasks.add(EventBuilder::reaction(&target, "🎉").to_event(&other).unwrap());
+        let me = tasks.get_author(&tasks.sender.pubkey(), None);
+        let them = tasks.get_author(&other.public_key(), None);
+        assert_eq!(tasks.get_property(tasks.get_by_id(&task).unwrap(), "reactions"),
+                   format!("+2 ({}) 🎉1 ({them})", [&me, &them].into_iter().sorted().join(", ")));
+
+        // Reacting again retracts
+        assert_eq!(tasks.react("+"), Some(false));
+        assert_eq!(tasks.get_property(tasks.get_by_id(&task).unwrap(), "reactions"),
+                   format!("+1 ({them}) 🎉1 ({them})"));
+    }
+
+    #[test]
+    fn test_assignment() {
+        let mut tasks = stub_tasks();
+        let task = tasks.make_task("task").unwrap();
+        let other = Keys::generate();
+        assert_eq!(tasks.find_user(&other.public_key().to_string()), Some(other.public_key()));
+        assert_eq!(tasks.find_user("@me"), Some(tasks.sender.pubkey()));
+        assert_eq!(tasks.find_user("nobody"), None);
+
+        tasks.custom_time = Some(Timestamp::now() - 10);
+        tasks.assign(task, Some(other.public_key()));
+        tasks.custom_time = None;
+        assert_eq!(tasks.get_by_id(&task).unwrap().assignee(), Some(other.public_key()));
+        assert_eq!(tasks.get_property(tasks.get_by_id(&task).unwrap(), "assignee"), tasks.get_author(&other.public_key(), None));
+        assert!(!tasks.set_filter_assignee(tasks.sender.pubkey()));
+
+        // Latest assignment wins
+        tasks.assign(task, Some(tasks.sender.pubkey()));
+        assert!(tasks.set_filter_assignee(tasks.sender.pubkey()));
+        // Assigned by myself, so not in the inbox
+        assert!(!tasks.set_view_inbox());
+
+        tasks.add(build_assignment(task, Some(tasks.sender.pubkey()))
+            .custom_created_at(Timestamp::now() + 1)
+            .to_event(&other).unwrap());
+        assert!(tasks.set_view_inbox());
+        assert_eq!(tasks.visible_tasks().len(), 1);
+    }
+
+    #[test]
+    fn test_mentions() {
+        let mut tasks = stub_tasks();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        tasks.users.insert(alice, Metadata::new().name("Alice"));
+        tasks.users.insert(bob, Metadata::new().display_name("Bobby"));
+        assert_eq!(tasks.mentions("ping @alice, @bob and @alice"), vec![alice]);
+        assert_eq!(tasks.mentions(&format!("@{bob}: @bobby")), vec![bob]);
+        assert_eq!(tasks.find_user("bob"), Some(bob));
+
+        let task = tasks.make_task("meeting").unwrap();
+        tasks.move_to(Some(task));
+        tasks.make_note("ask @alice and @nobody");
+        let note = tasks.get_by_id(&task).unwrap().description_events().next().unwrap().clone();
+        assert_eq!(note.content, format!("ask nostr:{} and @nobody", alice.to_bech32().unwrap()));
+        assert_eq!(note.public_keys().collect_vec(), vec![&alice]);
+        let notes = tasks.format_notes(tasks.get_by_id(&task).unwrap());
+        assert!(notes[0].ends_with(": ask @Alice and @nobody"));
+        assert_eq!(tasks.get_property(tasks.get_by_id(&task).unwrap(), "desc"), "ask @Alice and @nobody");
+    }
+
+    #[test]
+    fn test_author_column() {
+        let mut tasks = stub_tasks();
+        let michael = Keys::generate();
+        let michaela = Keys::generate();
+        let anonymous = Keys::generate().public_key();
+        tasks.users.insert(michael.public_key(), Metadata::new().name("Michael"));
+        tasks.users.insert(michaela.public_key(), Metadata::new().name("Michaela"));
+        assert_eq!(tasks.get_author(&michaela.public_key(), None), "Michaela");
+        assert_eq!(tasks.get_author(&michaela.public_key(), Some(20)), "Michaela");
+        assert_eq!(tasks.find_user("michael"), Some(michael.public_key()));
+        assert_eq!(tasks.find_user("michaela"), Some(michaela.public_key()));
+        assert_eq!(tasks.find_user("mich"), None);
+        assert_eq!(tasks.get_author(&anonymous, None), anonymous.to_bech32().unwrap()[..12]);
+
+        let event = EventBuilder::new(TASK_KIND, "task", []).to_event(&michaela).unwrap();
+        let id = event.id;
+        tasks.add(event);
+        let author = |tasks: &TasksRelay| tasks.get_property(tasks.get_by_id(&id).unwrap(), "author");
+        assert_eq!(author(&tasks), "Michaela");
+        tasks.settings.author_width = 5;
+        assert_eq!(author(&tasks), "Mich…");
+        tasks.settings.key_length = 8;
+        assert_eq!(tasks.get_author(&anonymous, None), anonymous.to_bech32().unwrap()[..8]);
+
+        assert_eq!(author_color(&michael.public_key()), author_color(&michael.public_key()));
+    }
+
+    #[test]
+    fn test_unknown_users() {
+        let mut tasks = stub_tasks();
+        let known = Keys::generate().public_key();
+        let unknown = Keys::generate().public_key();
+        tasks.users.insert(known, Metadata::new().name("known"));
+        assert_eq!(tasks.get_author(&known, None), "known");
+        tasks.get_author(&unknown, None);
+        tasks.get_author(&unknown, None);
+        assert_eq!(tasks.take_unknown_users(), vec![unknown]);
+        // Requested only once per session
+        tasks.get_author(&unknown, None);
+        assert_eq!(tasks.take_unknown_users(), vec![]);
+        assert!(tasks.whois(&known).starts_with(&"known".bold().to_string()));
+    }
+
+    #[test]
+    fn test_author_filter() {
+        let mut tasks = stub_tasks();
+        let own = tasks.make_task("own").unwrap();
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        tasks.users.insert(alice.public_key(), Metadata::new().name("alice"));
+        tasks.users.insert(bob.public_key(), Metadata::new().name("bob"));
+        let event = EventBuilder::new(TASK_KIND, "by alice", []).to_event(&alice).unwrap();
+        let by_alice = event.id;
+        tasks.add(event);
+        let event = EventBuilder::new(TASK_KIND, "by bob", []).to_event(&bob).unwrap();
+        let by_bob = event.id;
+        tasks.add(event);
+        assert_tasks!(tasks, [own, by_alice, by_bob]);
+
+        assert_eq!(tasks.find_user("al"), Some(alice.public_key()));
+        assert!(tasks.add_author_filter(alice.public_key()));
+        assert_tasks!(tasks, [by_alice]);
+        assert!(tasks.add_author_filter(bob.public_key()));
+        assert!(!tasks.add_author_filter(alice.public_key()));
+        assert_tasks!(tasks, [by_alice, by_bob]);
+        assert!(tasks.get_prompt_suffix().contains(" @alice,bob"));
+
+        tasks.clear_author_filter();
+        assert_tasks!(tasks, [own, by_alice, by_bob]);
+        tasks.add_author_filter(tasks.sender.pubkey());
+        assert_tasks!(tasks, [own]);
+        tasks.clear_filters();
+        assert_tasks!(tasks, [own, by_alice, by_bob]);
+        assert!(!tasks.get_prompt_suffix().contains('@'));
+    }
+
+    #[test]
+    fn test_follows() {
+        let mut tasks = stub_tasks();
+        let own = tasks.make_task("own").unwrap();
+        let friend = Keys::generate();
+        let stranger = Keys::generate();
+        let friend_task = tasks.make_task("friend").unwrap();
+        tasks.add(EventBuilder::new(TASK_KIND, "by friend", []).to_event(&friend).unwrap());
+        tasks.add(EventBuilder::new(TASK_KIND, "by stranger", []).to_event(&stranger).unwrap());
+        assert_eq!(tasks.visible_tasks().len(), 4);
+
+        let keys = tasks.sender.keys.clone();
+        tasks.add(EventBuilder::new(Kind::ContactList, "", [Tag::public_key(friend.public_key())]).to_event(&keys).unwrap());
+        tasks.add(EventBuilder::new(Kind::ContactList, "", [])
+            .custom_created_at(Timestamp::now() - 100).to_event(&keys).unwrap());
+        assert!(tasks.toggle_follows_only());
+        assert_eq!(tasks.visible_tasks().len(), 3);
+        assert!(tasks.get_prompt_suffix().contains("@+"));
+
+        assert!(tasks.set_following(stranger.public_key(), true));
+        assert!(!tasks.set_following(stranger.public_key(), true));
+        assert_eq!(tasks.visible_tasks().len(), 4);
+        assert!(tasks.set_following(friend.public_key(), false));
+        assert_eq!(tasks.follows, HashSet::from([stranger.public_key()]));
+        assert_eq!(tasks.visible_tasks().len(), 3);
+        assert!(tasks.get_by_id(&own).is_some() && tasks.get_by_id(&friend_task).is_some());
+    }
+
+    #[test]
+    fn test_position_cache() {
+        let mut tasks = stub_tasks();
+        let keys = tasks.sender.keys.clone();
+        let a = tasks.make_task("a").unwrap();
+        let b = tasks.make_task("b").unwrap();
+        let c = tasks.make_task("c").unwrap();
+        let now = Timestamp::now();
+        for (target, stamp) in [(a, now - 100), (c, now + 1000), (b, now - 300), (a, now - 200)] {
+            tasks.add(build_tracking([target]).custom_created_at(stamp).to_event(&keys).unwrap());
+            assert_eq!(tasks.get_position_ref(), tasks.get_position_at(tasks.now()).1);
+        }
+        assert_position!(tasks, a);
+        tasks.add(build_tracking([b]).custom_created_at(now - 50).to_event(&keys).unwrap());
+        assert_position!(tasks, b);
+        assert_eq!(tasks.get_position_at(now - 150).1, Some(&a));
+    }
+
+    #[test]
+    fn test_position_history() {
+        let mut tasks = stub_tasks();
+        let keys = tasks.sender.keys.clone();
+        let a = tasks.make_task("a").unwrap();
+        let b = tasks.make_task("b").unwrap();
+        let now = Timestamp::now();
+        for (target, stamp) in [(Some(a), now - 600), (Some(b), now - 500), (Some(b), now - 400), (None, now - 300), (Some(a), now - 100), (Some(b), now + 1000)] {
+            tasks.add(build_tracking(target).custom_created_at(stamp).to_event(&keys).unwrap());
+        }
+        let history = tasks.position_history();
+        assert_eq!(history.iter().map(|(id, _, _)| *id).collect_vec(), vec![Some(a), None, Some(b), Some(a)]);
+        assert_eq!(history[1], (None, now - 300, 200));
+        assert_eq!(history[2], (Some(b), now - 500, 200));
+        assert_eq!(history[3], (Some(a), now - 600, 100));
+        tasks.move_back_by(2);
+        assert_position!(tasks, b);
+        tasks.move_back_by(2);
+        assert_eq!(tasks.get_position(), None);
+    }
+
+    #[test]
+    fn test_undo_sent() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        tasks.custom_time = Some(now - 30);
+        let task = tasks.make_task("task").unwrap();
+        tasks.custom_time = Some(now - 20);
+        tasks.set_state_for(task, "waiting", State::Pending);
+        tasks.custom_time = Some(now - 10);
+        tasks.set_state_for(task, "", State::Done);
+        tasks.custom_time = None;
+        tasks.force_flush();
+        assert_eq!(tasks.sender.queued(), 0);
+
+        // Queued changes are still dropped without a trace
+        let queued = tasks.make_task("queued").unwrap();
+        tasks.undo();
+        assert!(tasks.get_by_id(&queued).is_none());
+        assert_eq!(tasks.journal.len(), 3);
+
+        // Compensating events follow the time of the change
+        tasks.custom_time = Some(now - 5);
+        tasks.undo();
+        let state = tasks.get_by_id(&task).unwrap().state().unwrap();
+        assert_eq!(state.state, State::Pending);
+        assert_eq!(state.comment(), "waiting");
+        assert_eq!(tasks.sender.queued(), 0);
+        tasks.custom_time = Some(now - 3);
+        tasks.undo();
+        assert_eq!(tasks.get_by_id(&task).unwrap().pure_state(), State::Open);
+        tasks.custom_time = None;
+        tasks.undo();
+        assert!(tasks.get_by_id(&task).is_none());
+        assert!(tasks.journal.is_empty());
+        tasks.undo();
+    }
+
+    #[test]
+    fn test_pending_changes() {
+        let mut tasks = stub_tasks();
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.force_flush();
+        assert!(tasks.pending_changes().is_empty());
+
+        tasks.move_to(Some(parent));
+        let child = tasks.make_task("child").unwrap();
+        tasks.set_state_for(child, "", State::Done);
+        tasks.make_note("remember");
+        let changes = tasks.pending_changes();
+        assert!(changes[0].starts_with("switch tracking to \"parent\" at "));
+        assert_eq!(changes[1..], [
+            "create task \"child\" under \"parent\"",
+            "mark \"child\" Done",
+            "add note \"remember\" to \"parent\"",
+        ]);
+    }
+
+    #[test]
+    fn test_queue_references() {
+        use tokio::sync::mpsc;
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut tasks = TasksRelay::with_sender(EventSender {
+            url: Some(Url::parse("wss://relay.example.com").unwrap()),
+            tx,
+            keys: Keys::generate(),
+            queue: Default::default(),
+            cache: None,
+            undo_delay: Settings::default().undo_delay,
+        });
+        let sent = |rx: &mut mpsc::Receiver<MostrMessage>| match rx.try_recv() {
+            Ok(MostrMessage::AddTasks(_, events)) => events.into_iter().map(|e| e.id).collect_vec(),
+            other => panic!("Expected events to be sent, got {:?}", other.map(|_| ())),
+        };
+
+        let a = tasks.make_task("a").unwrap();
+        let done = tasks.set_state_for(a, "", State::Done).unwrap();
+        let removed = tasks.make_task("removed").unwrap();
+        let deletion = tasks.submit(EventBuilder::delete([removed])).unwrap();
+        tasks.force_flush();
+        assert_eq!(sent(&mut rx), vec![a, done, deletion]);
+
+        let b = tasks.make_task("b").unwrap();
+        assert_eq!(tasks.sender.queued(), 1);
+        tasks.undo();
+        assert!(tasks.get_by_id(&b).is_none());
+        assert_eq!(tasks.sender.queued(), 0);
+
+        let c = tasks.make_task("c").unwrap();
+        drop(tasks);
+        assert_eq!(sent(&mut rx), vec![c]);
+    }
+
+    #[test]
+    fn test_orphans() {
+        let mut tasks = stub_tasks();
+        let keys = tasks.sender.keys.clone();
+        let task = EventBuilder::new(TASK_KIND, "late", []).to_event(&keys).unwrap();
+        tasks.add(build_prop(Kind::GitStatusApplied, "", task.id).to_event(&keys).unwrap());
+        for _ in 0..OVERFLOW_ATTEMPTS {
+            assert!(tasks.orphans().is_empty());
+            tasks.process_overflow();
+        }
+        assert_eq!(tasks.orphans().len(), 1);
+        assert_eq!(tasks.all_events().count(), 1);
+
+        tasks.add(task.clone());
+        assert_eq!(tasks.retry_orphans(), 1);
+        assert!(tasks.orphans().is_empty());
+        assert_eq!(tasks.get_by_id(&task.id).unwrap().pure_state(), State::Done);
+    }
+
+    #[test]
+    fn test_prune_history() {
+        let mut tasks = stub_tasks();
+        let other = Keys::generate();
+        let a = tasks.make_task("a").unwrap();
+        let b = tasks.make_task("b").unwrap();
+        let now = Timestamp::now();
+        // A switch every eight hours for 200 days
+        for i in 0..600u64 {
+            let target = if i % 2 == 0 { a } else { b };
+            tasks.add(build_tracking([target]).custom_created_at(now - (600 - i) * 28_800 + 14_400).to_event(&other).unwrap());
+        }
+        tasks.add(build_tracking([]).custom_created_at(now - 3_600).to_event(&other).unwrap());
+        let (total_a, total_b) = (tasks.total_time_tracked(a, true), tasks.total_time_tracked(b, true));
+        tasks.defaults.retention = Some(30);
+
+        assert_eq!(tasks.prune_history(), 510);
+        assert_eq!(tasks.history[&other.public_key()].len(), 91);
+        assert_eq!(tasks.total_time_tracked(a, true), total_a);
+        assert_eq!(tasks.total_time_tracked(b, true), total_b);
+
+        // Stragglers from before the retention period are dropped
+        tasks.add(build_tracking([a]).custom_created_at(now - 100 * 86_400 + 1).to_event(&other).unwrap());
+        assert_eq!(tasks.history[&other.public_key()].len(), 91);
+
+        tasks.track_at(now - 7_200, Some(a));
+        tasks.track_at(now - 3_600, Some(b));
+        assert_eq!(tasks.prune_own_history(now - 1_800), 1);
+        assert_eq!(tasks.time_tracked(a), 3_600);
+        assert_position!(tasks, b);
+    }
+
+    #[test]
+    fn test_merge_from() {
+        let mut first = stub_tasks();
+        let keys = first.sender.keys.clone();
+        let task = first.make_task("shared").unwrap();
+        let event = first.get_by_id(&task).unwrap().event.clone();
+        let now = Timestamp::now();
+        first.track_at(now - 300, Some(task));
+
+        let mut second = stub_tasks();
+        second.add(event);
+        second.add(build_prop(Kind::GitStatusApplied, "", task).to_event(&keys).unwrap());
+        second.add(build_tracking([]).custom_created_at(now - 100).to_event(&keys).unwrap());
+        second.make_task("other");
+
+        let mut merged = stub_tasks();
+        merged.sender.keys = keys;
+        merged.merge_from(&first);
+        merged.merge_from(&second);
+        merged.merge_from(&first);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged.get_by_id(&task).unwrap().pure_state(), State::Done);
+        assert_eq!(merged.time_tracked(task), 200);
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let mut tasks = stub_tasks();
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.move_to(Some(parent));
+        let now = Timestamp::now();
+        tasks.custom_time = Some(now - 600);
+        let first = tasks.make_task("Invoice").unwrap();
+        tasks.custom_time = None;
+        let second = tasks.make_task("invoice").unwrap();
+        tasks.make_task("other");
+        let sub = tasks.make_task_with("sub", [tasks.make_event_tag_from_id(second, MARKER_PARENT)], false).unwrap();
+        let dependent = tasks.make_task_with("dependent", [tasks.make_event_tag_from_id(second, MARKER_DEPENDS)], false).unwrap();
+        tasks.track_at(now - 5000, Some(first));
+        tasks.track_at(now - 4400, Some(sub));
+        tasks.track_at(now - 799, None);
+
+        assert_eq!(tasks.find_duplicates(Some(&parent)), vec![vec![first, second]]);
+        assert!(tasks.describe_duplicates(&[first, second]).ends_with("2 times - 1 subtasks, 01:10 tracked"));
+
+        assert_eq!(tasks.merge_duplicates(&[first, second]), 1);
+        assert_eq!(tasks.tasks.children_ids_for(Some(&first)).collect_vec(), vec![&sub]);
+        assert_eq!(tasks.get_by_id(&sub).unwrap().parent_id(), Some(&first));
+        assert_eq!(tasks.get_by_id(&dependent).unwrap().get_dependendees(), vec![&first]);
+        assert_eq!(tasks.get_by_id(&second).unwrap().pure_state(), State::Closed);
+        assert_eq!(tasks.total_time_tracked(first, true), 4200);
+        assert!(tasks.find_duplicates(Some(&parent)).is_empty());
+    }
+
+    #[test]
+    fn test_last_activity() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        tasks.custom_time = Some(now - 3000);
+        let old = tasks.make_task("old").unwrap();
+        let noted = tasks.make_task("noted").unwrap();
+        let tracked = tasks.make_task("tracked").unwrap();
+        tasks.custom_time = Some(now - 1000);
+        tasks.set_state_for(noted, "", State::Pending);
+        tasks.custom_time = None;
+        tasks.track_at(now - 2000, Some(tracked));
+        tasks.track_at(now - 1990, None);
+        // Scheduled changes do not count yet
+        tasks.custom_time = Some(now + 3000);
+        tasks.set_state_for(old, "", State::Done);
+        tasks.custom_time = None;
+
+        let activity = |id: EventId| tasks.last_activity(tasks.get_by_id(&id).unwrap());
+        assert_eq!(activity(old), now - 3000);
+        assert_eq!(activity(noted), now - 1000);
+        assert_eq!(activity(tracked), now - 2000);
+
+        tasks.set_sorting(VecDeque::from(["updated".to_string()]));
+        tasks.get_columns().clear();
+        tasks.get_columns().push("name".to_string());
+        let mut output = vec![];
+        tasks.write_tasks(&mut output).unwrap();
+        let lines = String::from_utf8(output).unwrap().lines().skip(1).take(3).map(|l| l.trim().to_string()).collect_vec();
+        assert_eq!(lines, vec!["old", "tracked", "noted"]);
+    }
+
+    #[test]
+    fn test_completed() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.move_to(Some(parent));
+        let done = tasks.make_task("done").unwrap();
+        let closed = tasks.make_task("closed").unwrap();
+        let reopened = tasks.make_task("reopened").unwrap();
+        let old = tasks.make_task("old").unwrap();
+        tasks.custom_time = Some(now - 30 * 86_400);
+        tasks.set_state_for(old, "", State::Done);
+        tasks.custom_time = Some(now - 200);
+        tasks.set_state_for(closed, "", State::Closed);
+        tasks.set_state_for(reopened, "", State::Done);
+        tasks.custom_time = Some(now - 100);
+        tasks.set_state_for(done, "", State::Done);
+        tasks.set_state_for(reopened, "", State::Open);
+        tasks.custom_time = None;
+
+        let completed = |id: EventId| tasks.get_by_id(&id).unwrap().completed_at();
+        assert_eq!(completed(done), Some(now - 100));
+        assert_eq!(completed(closed), Some(now - 200));
+        assert_eq!(completed(reopened), None);
+        assert_eq!(completed(parent), None);
+        assert_eq!(tasks.get_by_id(&done).unwrap().get("completed"), Some(format_timestamp_local(&(now - 100))));
+
+        let week = tasks.completed_since(None, now - 7 * 86_400).into_iter().map(|t| t.event.id).collect_vec();
+        assert_eq!(week, vec![closed, done]);
+        assert_eq!(tasks.completed_since(Some(&done), now - 7 * 86_400).len(), 0);
+    }
+
+    #[test]
+    fn test_timeline() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        tasks.custom_time = Some(now - 5000);
+        let id = tasks.make_task("task").unwrap();
+        tasks.custom_time = Some(now - 4000);
+        tasks.set_state_for(id, "blocked", State::Pending);
+        tasks.custom_time = Some(now - 1000);
+        tasks.set_state_for(id, "", State::Done);
+        tasks.custom_time = Some(now - 400);
+        tasks.set_state_for(id, "reopened", State::Open);
+        tasks.custom_time = None;
+
+        let durations = |until: Timestamp| tasks.get_by_id(&id).unwrap().state_timeline(until)
+            .into_iter().map(|(s, d)| (s.state, d)).collect_vec();
+        assert_eq!(durations(now), vec![
+            (State::Open, 1000),
+            (State::Pending, 3000),
+            (State::Done, 600),
+            (State::Open, 400),
+        ]);
+        // Later states are not part of the timeline yet
+        assert_eq!(durations(now - 2000), vec![(State::Open, 1000), (State::Pending, 2000)]);
+        assert_eq!(durations(now - 5000), vec![(State::Open, 0)]);
+
+        let lines = tasks.describe_timeline(&id);
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].contains("Pending: blocked"));
+        assert!(lines[1].ends_with("- 50m"));
+        assert!(lines[4].starts_with("in "));
+        assert!(lines[4].contains("reopened"));
+
+        // Same-second transitions take no time and a state set at creation replaces the default
+        tasks.custom_time = Some(now - 100);
+        let instant = tasks.make_task("instant").unwrap();
+        tasks.set_state_for(instant, "", State::Pending);
+        tasks.custom_time = Some(now - 50);
+        tasks.set_state_for(instant, "", State::Done);
+        tasks.set_state_for(instant, "", State::Closed);
+        tasks.custom_time = None;
+        let timeline = tasks.get_by_id(&instant).unwrap().state_timeline(now);
+        assert_eq!(timeline[0].0.state, State::Pending);
+        assert_eq!(timeline.iter().map(|(_, d)| *d).collect_vec(), vec![50, 0, 50]);
+    }
+
+    #[test]
+    fn test_move_step() {
+        let mut tasks = stub_tasks();
+        let procedure = tasks.make_task("procedure").unwrap();
+        tasks.set_state_for(procedure, "", State::Procedure);
+        tasks.move_to(Some(procedure));
+        let steps = ["one", "two", "three", "four"].map(|name| tasks.make_task(name).unwrap());
+        assert_eq!(tasks.procedure_steps(&procedure), steps);
+
+        assert_eq!(tasks.move_step(steps[3], 1), Some(1));
+        let order = vec![steps[0], steps[3], steps[1], steps[2]];
+        assert_eq!(tasks.procedure_steps(&procedure), order);
+        let depends = |id: &EventId| tasks.get_by_id(id).unwrap().get_dependendees().into_iter().copied().collect_vec();
+        assert_eq!(depends(&steps[0]), vec![]);
+        assert_eq!(depends(&steps[3]), vec![steps[0]]);
+        assert_eq!(depends(&steps[1]), vec![steps[3]]);
+        assert_eq!(depends(&steps[2]), vec![steps[1]]);
+        for step in &order {
+            assert_eq!(tasks.get_by_id(step).unwrap().parent_id(), Some(&procedure));
+        }
+
+        // New steps are appended to the end of the reordered chain
+        let five = tasks.make_task("five").unwrap();
+        assert_eq!(depends(&five), vec![steps[2]]);
+        assert_eq!(tasks.move_step(five, 0), Some(0));
+        assert_eq!(tasks.procedure_steps(&procedure)[..2], [five, steps[0]]);
+        assert_eq!(tasks.move_step(steps[0], 9), Some(4));
+        assert_eq!(tasks.procedure_steps(&procedure).last(), Some(&steps[0]));
+        assert_eq!(tasks.move_step(procedure, 0), None);
+    }
+
+    #[test]
+    fn test_advance() {
+        let mut tasks = stub_tasks();
+        let procedure = tasks.make_task("procedure").unwrap();
+        tasks.set_state_for(procedure, "", State::Procedure);
+        tasks.move_to(Some(procedure));
+        let steps = ["one", "two", "three"].map(|name| tasks.make_task(name).unwrap());
+        assert_eq!(tasks.next_step(&procedure), Some(steps[0]));
+
+        tasks.move_to(Some(steps[0]));
+        tasks.update_state("", State::Done);
+        tasks.move_on();
+        assert_eq!(tasks.get_position(), Some(procedure));
+
+        tasks.advance = true;
+        tasks.move_to(Some(steps[1]));
+        tasks.update_state("", State::Closed);
+        assert_eq!(tasks.next_step(&procedure), Some(steps[2]));
+        tasks.move_on();
+        assert_eq!(tasks.get_position(), Some(steps[2]));
+        tasks.update_state("", State::Done);
+        tasks.move_on();
+        assert_eq!(tasks.get_position(), Some(procedure));
+        assert_eq!(tasks.next_step(&procedure), None);
+
+        // Tasks outside of procedures still move up
+        let plain = tasks.make_task("plain").unwrap();
+        tasks.move_to(Some(plain));
+        let sub = tasks.make_task("sub").unwrap();
+        tasks.move_to(Some(sub));
+        tasks.move_on();
+        assert_eq!(tasks.get_position(), Some(plain));
+    }
+
+    #[test]
+    fn test_unbalanced_progress() {
+        let mut tasks = stub_tasks();
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.move_to(Some(parent));
+        let huge = tasks.make_task("huge").unwrap();
+        let tiny = tasks.make_task("tiny").unwrap();
+        let closed = tasks.make_task("closed").unwrap();
+        tasks.set_state_for(closed, "", State::Closed);
+        tasks.move_to(Some(huge));
+        for name in ["a", "b", "c"] {
+            tasks.make_task(name);
+        }
+        tasks.set_state_for(tiny, "", State::Done);
+
+        let property = |id: EventId, name: &str| tasks.get_property(tasks.get_by_id(&id).unwrap(), name);
+        assert_eq!(property(parent, "progress"), "50%");
+        assert_eq!(property(parent, "wprogress"), "25%");
+        assert_eq!(property(parent, "subtasks"), "1/2");
+        assert_eq!(property(parent, "rsubtasks"), "1/5");
+        assert_eq!(property(huge, "wprogress"), " 0%");
+        assert_eq!(property(tiny, "wprogress"), "");
+
+        tasks.set_state_for(huge, "", State::Done);
+        assert_eq!(property(parent, "progress"), "100%");
+        assert_eq!(property(parent, "wprogress"), "100%");
+        assert_eq!(property(parent, "rsubtasks"), "2/5");
+    }
+
+    #[test]
+    fn test_collapse_closed() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        let project = tasks.make_task("project").unwrap();
+        tasks.move_to(Some(project));
+        let open = tasks.make_task("open").unwrap();
+        let closed = tasks.make_task("closed").unwrap();
+        tasks.move_to(Some(closed));
+        let hidden = tasks.make_task("hidden").unwrap();
+        tasks.track_at(now - 600, Some(open));
+        tasks.track_at(now - 300, Some(hidden));
+        tasks.track_at(now - 100, None);
+        tasks.set_state_for(closed, "", State::Closed);
+        tasks.submit(build_bookmarks(None, [hidden]));
+        tasks.move_to(Some(project));
+        tasks.set_depth(3);
+
+        assert_tasks!(tasks, [open]);
+        assert_eq!(tasks.get_matching(None, "hidden"), vec![]);
+        let rtime = |tasks: &TasksRelay| tasks.get_property(tasks.get_by_id(&project).unwrap(), "rtime");
+        assert_eq!(rtime(&tasks), "00:05");
+        assert_eq!(tasks.total_time_tracked(project, true) - tasks.total_time_tracked(project, false), 199);
+
+        tasks.toggle_closed();
+        assert_eq!(tasks.get_prompt_suffix(), " ?+closed");
+        assert_tasks!(tasks, [open, hidden]);
+        assert_eq!(tasks.get_matching(None, "hidden"), vec![hidden]);
+        assert_eq!(rtime(&tasks), "00:08");
+
+        tasks.toggle_closed();
+        tasks.set_state_filter(StateFilter::All);
+        assert_eq!(rtime(&tasks), "00:08");
+    }
+
+    #[test]
+    fn test_time_depth() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        let project = tasks.make_task("project").unwrap();
+        tasks.move_to(Some(project));
+        let child = tasks.make_task("child").unwrap();
+        tasks.move_to(Some(child));
+        let grandchild = tasks.make_task("grandchild").unwrap();
+        tasks.move_to(None);
+        tasks.track_at(now - 3600, Some(project));
+        tasks.track_at(now - 3000, Some(child));
+        tasks.track_at(now - 2400, Some(grandchild));
+        tasks.track_at(now - 1170, None);
+        let other = Keys::generate();
+        tasks.add(build_tracking([project]).custom_created_at(now - 900).to_event(&other).unwrap());
+        tasks.add(build_tracking([]).custom_created_at(now - 600).to_event(&other).unwrap());
+
+        let property = |tasks: &TasksRelay, prop: &str| tasks.get_property(tasks.get_by_id(&project).unwrap(), prop);
+        assert_eq!(property(&tasks, "time"), " 10m");
+        assert_eq!(property(&tasks, "dtime"), "00:15");
+        assert_eq!(property(&tasks, "rtime:0"), "00:15");
+        assert_eq!(property(&tasks, "rtime:1"), "00:25");
+        assert_eq!(property(&tasks, "rtime:2"), "00:45");
+        assert_eq!(property(&tasks, "rtime"), "00:45");
+        assert_eq!(tasks.get_raw_property(tasks.get_by_id(&project).unwrap(), "rtime:1"), json!(1500));
+
+        // Subtrees are looked up again after changes
+        tasks.move_to(Some(project));
+        let sibling = tasks.make_task("sibling").unwrap();
+        tasks.move_to(None);
+        tasks.add(build_tracking([sibling]).custom_created_at(now - 500).to_event(&other).unwrap());
+        tasks.add(build_tracking([]).custom_created_at(now - 200).to_event(&other).unwrap());
+        assert_eq!(property(&tasks, "rtime:1"), "00:30");
+        assert_eq!(property(&tasks, "dtime"), "00:15");
+    }
+
+    #[test]
+    fn test_footer() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        let project = tasks.make_task("project").unwrap();
+        tasks.move_to(Some(project));
+        let a = tasks.make_task("a").unwrap();
+        let b = tasks.make_task("b").unwrap();
+        tasks.move_to(Some(b));
+        let b1 = tasks.make_task("b1").unwrap();
+        let b2 = tasks.make_task("b2").unwrap();
+        tasks.set_state_for(b2, "", State::Done);
+        tasks.set_state_for(a, "", State::Done);
+        tasks.track_at(now - 3000, Some(a));
+        tasks.track_at(now - 2400, Some(b1));
+        tasks.track_at(now - 1170, None);
+        tasks.move_to(Some(project));
+        tasks.set_state_filter(StateFilter::All);
+
+        let listed = [tasks.get_by_id(&b).unwrap(), tasks.get_by_id(&b1).unwrap()];
+        assert_eq!(tasks.outermost(&listed).map(|t| t.event.id).collect_vec(), vec![b]);
+        assert_eq!(tasks.aggregate_property(&listed, "rtime"), "00:20");
+        assert_eq!(tasks.aggregate_property(&listed, "name"), "");
+
+        let columns = tasks.get_columns();
+        columns.clear();
+        columns.extend(["name", "subtasks", "rtime", "progress"].map(|s| s.to_string()));
+        let mut output = vec![];
+        tasks.write_tasks(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect_vec();
+        assert_eq!(lines[lines.len() - 2], "---- \t-------- \t----- \t--------");
+        assert_eq!(lines[lines.len() - 1], "2 tasks \t1/2 \t00:30 \t50%");
+
+        // Without anything to sum up only the total tracked time is given
+        let columns = tasks.get_columns();
+        columns.clear();
+        columns.push("name".to_string());
+        let mut output = vec![];
+        tasks.write_tasks(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().last().unwrap(), "2 visible tasks tracked a total of 00h30m");
+    }
+
+    #[test]
+    fn test_tracked_between() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        let a = tasks.make_task("a").unwrap();
+        let b = tasks.make_task("b").unwrap();
+        let c = tasks.make_task("c").unwrap();
+        tasks.track_at(Timestamp::from(500), Some(a));
+        tasks.track_at(Timestamp::from(1400), Some(b));
+        tasks.track_at(Timestamp::from(2500), None);
+        tasks.track_at(now - 120, Some(c));
+        tasks.track_at(now + 600, Some(a));
+
+        // Intervals straddling the edges of the range only count within it
+        let window = (Some(Timestamp::from(1000)), Some(Timestamp::from(2000)));
+        assert_eq!(tasks.tracked_between(window.0, window.1), vec![(b, 600), (a, 400)]);
+        assert_eq!(tasks.tracked_between(Some(Timestamp::from(1500)), Some(Timestamp::from(1600))), vec![(b, 100)]);
+        assert_eq!(tasks.tracked_between(Some(Timestamp::from(3000)), Some(Timestamp::from(4000))), vec![]);
+        // Ongoing tracking counts until now, planned tracking not at all
+        assert!(matches!(tasks.tracked_between(Some(now - 60), None).as_slice(), [(id, 60..=62)] if id == &c));
+        let all = tasks.tracked_between(None, None);
+        assert_eq!(all[..2], [(b, 1099), (a, 900)]);
+        assert_eq!(all[2].0, c);
+
+        let report = tasks.tracked_report(window.0, window.1);
+        assert_eq!(report, vec!["00h10m b", "00h06m a", "00h16m in total"]);
+    }
 
-#[cfg(test)]
-mod tasks_test {
-    use std::collections::HashSet;
+    #[test]
+    fn test_day_summary() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        assert_eq!(tasks.day_summary(now - 3600).len(), 1);
+        assert!(tasks.day_summary(now - 3600)[0].starts_with("Nothing tracked, 0 completed, 0 created since "));
+
+        let work = tasks.make_task("work").unwrap();
+        let report = tasks.make_task_with("report", tasks.position_tags_for(Some(&work)), true).unwrap();
+        let home = tasks.make_task("home").unwrap();
+        tasks.track_at(now - 7200, Some(home));
+        tasks.track_at(now - 1800, Some(report));
+        tasks.track_at(now - 600, Some(home));
+        tasks.set_state_for(report, "", State::Done);
+
+        let summary = tasks.day_summary(now - 3600);
+        // Tracking from before is clipped, ongoing tracking counts until now
+        assert!(summary[0].starts_with("01h00m tracked, 1 completed, 3 created since "), "{}", summary[0]);
+        assert_eq!(summary[1], "  00h40m home");
+        assert_eq!(summary[2], "  00h20m work");
+        assert!(summary[3].ends_with(" work>report"), "{}", summary[3]);
+        assert_eq!(summary[4], "Longest on home for 00h40m");
+        assert_eq!(summary.len(), 5);
+    }
 
-    use super::*;
+    #[test]
+    fn test_sort_keys() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        let long = tasks.make_task("long").unwrap();
+        let short = tasks.make_task("short").unwrap();
+        let idle = tasks.make_task("idle").unwrap();
+        let mid = tasks.make_task("mid").unwrap();
+        tasks.track_at(now - 397_000, Some(long));
+        tasks.track_at(now - 37_000, Some(mid));
+        tasks.track_at(now - 1_000, Some(short));
+        tasks.track_at(now - 460, None);
+        let titles = |tasks: &TasksRelay| tasks.listed_tasks().iter().map(|t| t.get_title()).collect_vec();
+
+        // 09m, 10h and 100h compare as durations rather than text
+        tasks.set_sorting(VecDeque::from(["rtime".to_string()]));
+        assert_eq!(titles(&tasks), vec!["idle", "short", "mid", "long"]);
+
+        tasks.set_state_for(long, "", State::Done);
+        tasks.set_state_for(short, "", State::Pending);
+        tasks.set_state_for(mid, "", State::Done);
+        tasks.set_state_filter(StateFilter::All);
+        tasks.set_sorting(VecDeque::from(["state".to_string()]));
+        let states = tasks.listed_tasks().iter().map(|t| t.pure_state()).collect_vec();
+        assert_eq!(states, vec![State::Open, State::Pending, State::Done, State::Done]);
+        assert_eq!(&titles(&tasks)[..2], ["idle", "short"]);
+        tasks.set_sorting(VecDeque::from(["state".to_string(), "rtime".to_string()]));
+        assert_eq!(titles(&tasks), vec!["idle", "short", "mid", "long"]);
+    }
 
-    fn stub_tasks() -> TasksRelay {
-        use tokio::sync::mpsc;
-        use nostr_sdk::Keys;
+    #[test]
+    fn test_sticky_time() {
+        let mut tasks = stub_tasks();
+        let start = Timestamp::now() - 86_400;
+        tasks.sticky_time = Some(start);
+        let task = tasks.make_task("backfilled").unwrap();
+        tasks.set_state_for(task, "", State::Done);
+        tasks.custom_time = Some(start - 100);
+        let other = tasks.make_task("one-shot").unwrap();
+        tasks.custom_time = None;
+        let last = tasks.make_task("last").unwrap();
+
+        let created = |id: EventId| tasks.get_by_id(&id).unwrap().event.created_at;
+        assert_eq!(created(task), start);
+        assert_eq!(tasks.get_by_id(&task).unwrap().completed_at(), Some(start + 1));
+        assert_eq!(created(other), start - 100);
+        assert_eq!(created(last), start + 2);
+        assert_eq!(tasks.sticky_time, Some(start + 3));
+    }
 
-        let (tx, _rx) = mpsc::channel(16);
-        TasksRelay::with_sender(EventSender {
-            url: None,
-            tx,
-            keys: Keys::generate(),
-            queue: Default::default(),
-        })
+    #[test]
+    fn test_describe_changes() {
+        let mut tasks = stub_tasks();
+        let other = Keys::generate();
+        let task = tasks.make_task("task").unwrap();
+        let before = tasks.snapshot();
+        assert!(tasks.describe_changes(&before).is_empty());
+
+        tasks.add(build_prop(Kind::GitStatusApplied, "", task).to_event(&other).unwrap());
+        tasks.add(EventBuilder::new(TASK_KIND, "new", []).to_event(&other).unwrap());
+        let author = tasks.get_author(&other.public_key(), None);
+        assert_eq!(tasks.describe_changes(&before), vec![
+            format!("\"task\" marked Done by {author}"),
+            format!("New task \"new\" by {author}"),
+        ]);
     }
 
-    macro_rules! assert_position {
-        ($left:expr, $right:expr $(,)?) => {
-            assert_eq!($left.get_position_ref(), Some(&$right))
-        };
+    #[test]
+    fn test_children_index() {
+        let mut tasks = stub_tasks();
+        let keys = tasks.sender.keys.clone();
+        let unknown = EventId::all_zeros();
+        let mut ids = vec![];
+        for i in 0..60 {
+            let parent = match i % 4 {
+                0 => None,
+                3 => Some(unknown),
+                _ => ids.get(i / 2).copied(),
+            };
+            let event = EventBuilder::new(TASK_KIND, format!("task {i}"), parent.map(|id| tasks.make_event_tag_from_id(id, MARKER_PARENT)))
+                .to_event(&keys).unwrap();
+            ids.push(event.id);
+            tasks.add(event);
+        }
+        let deleted = tasks.get_by_id(&ids[1]).unwrap().event.clone();
+        tasks.remove(&deleted);
+
+        let scan = |id: Option<&EventId>| tasks.tasks.values()
+            .filter(|t| t.parent_id() == id)
+            .map(|t| *t.get_id())
+            .collect::<HashSet<_>>();
+        for id in ids.iter().map(Some).chain([None, Some(&unknown)]) {
+            assert_eq!(tasks.tasks.children_for(id).map(|t| *t.get_id()).collect::<HashSet<_>>(), scan(id));
+        }
     }
 
-    macro_rules! assert_tasks {
-        ($left:expr, $right:expr $(,)?) => {
-            assert_eq!($left.visible_tasks().iter().map(|t| t.event.id).collect::<HashSet<EventId>>(), 
-                       HashSet::from($right))
+    #[test]
+    fn test_relay_defaults() {
+        let mut tasks = stub_tasks();
+        let company = tasks.make_task("Company").unwrap();
+        tasks.move_to(Some(company));
+        let projects = tasks.make_task("Projects").unwrap();
+        tasks.move_to(None);
+        assert_eq!(tasks.find_by_path("company/projects"), Some(projects));
+        assert_eq!(tasks.find_by_path("Projects"), None);
+
+        tasks.defaults = RelayDefaults {
+            position: Some("Company/Projects".to_string()),
+            tags: vec!["work".to_string()],
+            sorting: None,
+            columns: Some(vec!["name".to_string()]),
+            retention: None,
         };
+        tasks.add_tag("other".to_string());
+        tasks.set_sorting(VecDeque::from(["rtime".to_string()]));
+        tasks.apply_defaults();
+        assert_position!(tasks, projects);
+        assert_eq!(tasks.get_prompt_suffix(), " #work");
+        assert_eq!(tasks.get_columns(), &vec!["name".to_string()]);
+        assert_eq!(tasks.sorting, VecDeque::from(["rtime".to_string()]));
     }
 
     #[test]
-    fn test_bookmarks() {
+    fn test_switch_keys() {
         let mut tasks = stub_tasks();
-        let zero = EventId::all_zeros();
-        let test = tasks.make_task("test: tag");
-        let parent = tasks.make_task("parent");
-        assert_eq!(tasks.visible_tasks().len(), 2);
-        tasks.move_to(Some(parent));
-        let pin = tasks.make_task("pin");
-
-        assert_eq!(tasks.filtered_tasks(None, true).len(), 2);
-        assert_eq!(tasks.filtered_tasks(None, false).len(), 2);
-        assert_eq!(tasks.filtered_tasks(Some(&zero), false).len(), 0);
-        assert_eq!(tasks.visible_tasks().len(), 1);
-        assert_eq!(tasks.filtered_tasks(Some(&pin), false).len(), 0);
-        assert_eq!(tasks.filtered_tasks(Some(&zero), false).len(), 0);
+        let task = tasks.make_task("task").unwrap();
+        tasks.move_to(Some(task));
+        tasks.toggle_bookmark(task).unwrap();
+        let previous = tasks.sender.keys.clone();
+
+        let keys = Keys::generate();
+        tasks.switch_keys(&keys, None);
+        assert_eq!(tasks.sender.pubkey(), keys.public_key());
+        assert_eq!(tasks.sender.queued(), 0);
+        assert_eq!(tasks.get_position(), None);
+        assert!(tasks.bookmarks.is_empty());
+        tasks.add(build_bookmarks(None, [task]).to_event(&previous).unwrap());
+        assert!(tasks.bookmarks.is_empty());
+        tasks.add(build_bookmarks(None, [task]).to_event(&keys).unwrap());
+        assert_eq!(tasks.bookmarks, vec![task]);
+    }
 
-        tasks.submit(EventBuilder::new(Kind::Bookmarks, "", [Tag::event(pin), Tag::event(zero)]));
-        assert_eq!(tasks.visible_tasks().len(), 1);
-        assert_eq!(tasks.filtered_tasks(Some(&pin), true).len(), 0);
-        assert_eq!(tasks.filtered_tasks(Some(&pin), false).len(), 0);
-        assert_eq!(tasks.filtered_tasks(Some(&zero), true).len(), 0);
-        assert_eq!(tasks.filtered_tasks(Some(&zero), false), vec![tasks.get_by_id(&pin).unwrap()]);
+    #[test]
+    fn test_expiration() {
+        let mut tasks = stub_tasks();
+        let milk = tasks.make_task("milk: expires:1d").unwrap();
+        assert!(tasks.get_by_id(&milk).unwrap().expiration().is_some());
+        assert_tasks!(tasks, [milk]);
+
+        tasks.custom_time = Some(Timestamp::now() - 10);
+        tasks.set_expiration(milk, Some(Timestamp::now() - 5));
+        tasks.custom_time = None;
+        assert_tasks!(tasks, []);
+        assert_eq!(tasks.get_property(tasks.get_by_id(&milk).unwrap(), "state"), State::Closed.colorize("Expired").to_string());
+
+        tasks.set_expiration(milk, None);
+        assert_eq!(tasks.get_by_id(&milk).unwrap().expiration(), None);
+        assert_tasks!(tasks, [milk]);
+    }
 
+    #[test]
+    fn test_watch_only() {
+        let mut tasks = stub_tasks();
+        let task = tasks.make_task("task").unwrap();
+        tasks.move_to(Some(task));
+        let keys = Keys::from_public_key(tasks.sender.pubkey());
+        tasks.switch_keys(&keys, None);
+        assert!(tasks.is_watch_only());
+        // History is still attributed to the key
+        assert_position!(tasks, task);
+
+        assert_eq!(tasks.make_task("other"), None);
         tasks.move_to(None);
-        assert_eq!(tasks.depth, 1);
-        assert_tasks!(tasks, [pin, test, parent]);
-        tasks.set_depth(2);
-        assert_tasks!(tasks, [pin, test]);
-        tasks.add_tag("tag".to_string());
-        assert_tasks!(tasks, [test]);
-        assert_eq!(tasks.filtered_tasks(None, true), vec![tasks.get_by_id(&test).unwrap()]);
-        tasks.submit(EventBuilder::new(Kind::Bookmarks, "", []));
-        tasks.clear_filters();
-        assert_tasks!(tasks, [pin, test]);
-        tasks.set_depth(1);
-        assert_tasks!(tasks, [test, parent]);
+        assert_position!(tasks, task);
+        assert_eq!(tasks.update_state("", State::Done), None);
+        assert!(tasks.toggle_bookmark(task).is_err());
+        assert_eq!(tasks.len(), 1);
+    }
+
+    #[test]
+    fn test_export_import() {
+        let mut tasks = stub_tasks();
+        let parent = tasks.make_task("parent").unwrap();
+        let child = tasks.make_task_with("child", tasks.position_tags_for(Some(&parent)), false).unwrap();
+        tasks.submit(build_prop(Kind::TextNote, "note", parent));
+        let now = Timestamp::now();
+        tasks.track_at(now - 100, Some(child));
+        tasks.track_at(now - 50, None);
+        tasks.toggle_bookmark(child).unwrap();
+
+        let mut buffer = Vec::new();
+        assert_eq!(tasks.export_events(&mut buffer).unwrap(), 6);
+        buffer.extend_from_slice(b"garbage\n");
+
+        let mut imported = stub_tasks();
+        assert_eq!(imported.import_events(&buffer[..], false), (6, 1));
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported.get_task_path(Some(child)), "parent>child");
+        assert_eq!(imported.get_by_id(&parent).unwrap().props.len(), 1);
+        assert_eq!(imported.total_time_tracked(parent, true), 49);
+    }
+
+    #[test]
+    fn test_bookmarks_per_relay() {
+        let mut tasks = stub_tasks();
+        let url = Url::parse("wss://relay.example.com").unwrap();
+        tasks.sender.url = Some(url.clone());
+        let keys = tasks.sender.keys.clone();
+        let task = tasks.make_task("task").unwrap();
+        let foreign = EventId::all_zeros();
+
+        // A list shared by all relays is taken over without the tasks of other relays
+        tasks.add(build_bookmarks(None, [task, foreign]).to_event(&keys).unwrap());
+        assert_eq!(tasks.bookmarks, vec![task, foreign]);
+        tasks.process_overflow();
+        assert_eq!(tasks.bookmarks, vec![task]);
+
+        let other = Url::parse("wss://other.example.com").unwrap();
+        tasks.add(build_bookmarks(Some(&other), [foreign]).to_event(&keys).unwrap());
+        assert_eq!(tasks.bookmarks, vec![task]);
+
+        let second = tasks.make_task("second").unwrap();
+        tasks.toggle_bookmark(second).unwrap();
+        let event = tasks.bookmarks_event.clone().unwrap();
+        assert!(is_relay_bookmarks(&event, Some(&url)));
+        assert_eq!(event.identifier(), Some("mostr:wss://relay.example.com/"));
+        assert_eq!(referenced_events(&event).collect_vec(), vec![&task, &second]);
+
+        // The own list of the relay takes precedence over newer shared lists
+        tasks.add(build_bookmarks(None, [task]).custom_created_at(Timestamp::now() + 100).to_event(&keys).unwrap());
+        assert_eq!(tasks.bookmarks, vec![task, second]);
+    }
+
+    #[test]
+    fn test_bookmarks_ordering() {
+        let mut tasks = stub_tasks();
+        let first = tasks.make_task("first").unwrap();
+        let second = tasks.make_task("second").unwrap();
+        let keys = tasks.sender.keys.clone();
+        let newer = build_bookmarks(None, [second]).to_event(&keys).unwrap();
+        let older = build_bookmarks(None, [first]).custom_created_at(Timestamp::now() - 100).to_event(&keys).unwrap();
+        tasks.add(newer);
+        tasks.add(older);
+        assert_eq!(tasks.bookmarks, vec![second]);
+
+        // Lists of other clients are left alone
+        tasks.add(EventBuilder::new(Kind::Bookmarks, "", [Tag::event(first)]).to_event(&keys).unwrap());
+        assert_eq!(tasks.bookmarks, vec![second]);
+        tasks.add(build_bookmarks(None, [first]).to_event(&Keys::generate()).unwrap());
+        assert_eq!(tasks.bookmarks, vec![second]);
+
+        // Unchanged list is not republished
+        let queued = tasks.sender.queued();
+        tasks.publish_bookmarks().unwrap();
+        assert_eq!(tasks.sender.queued(), queued);
+        tasks.toggle_bookmark(first).unwrap();
+        assert_eq!(tasks.sender.queued(), queued + 1);
+        assert_eq!(tasks.bookmarks, vec![second, first]);
     }
 
     #[test]
@@ -1525,7 +5732,7 @@ mod tasks_test {
         let side = tasks.submit(build_task("side", vec![tasks.make_event_tag(&tasks.get_current_task().unwrap().event, MARKER_DEPENDS)], None));
         assert_eq!(tasks.visible_tasks(),
                    Vec::<&Task>::new());
-        let sub_id = tasks.make_task("sub");
+        let sub_id = tasks.make_task("sub").unwrap();
         assert_eq!(tasks.visible_tasks().iter().map(|t| t.event.id).collect_vec(),
                    Vec::from([sub_id]));
         assert_eq!(tasks.len(), 3);
@@ -1533,26 +5740,43 @@ mod tasks_test {
         assert_eq!(sub.get_dependendees(), Vec::<&EventId>::new());
     }
 
+    #[test]
+    fn test_dependency_columns() {
+        let mut tasks = stub_tasks();
+        tasks.make_task_and_enter("proc", State::Procedure);
+        let side = tasks.submit(build_task("side", vec![tasks.make_event_tag(&tasks.get_current_task().unwrap().event, MARKER_DEPENDS)], None)).unwrap();
+        let sub = tasks.make_task("sub").unwrap();
+
+        let proc = tasks.get_current_task().unwrap();
+        let title = proc.get_title();
+        assert_eq!(tasks.get_property(tasks.get_by_id(&side).unwrap(), "depends"), title.bright_red().to_string());
+        assert_eq!(tasks.get_property(tasks.get_by_id(&sub).unwrap(), "depends"), "");
+        assert_eq!(tasks.get_property(proc, "dependents"), "side".bright_red().to_string());
+
+        tasks.update_state("", State::Done);
+        assert_eq!(tasks.get_property(tasks.get_by_id(&side).unwrap(), "depends"), title.dimmed().to_string());
+    }
+
     #[test]
     fn test_filter_or_create() {
         let mut tasks = stub_tasks();
         let zeros = EventId::all_zeros();
         let zero = Some(&zeros);
 
-        let id1 = tasks.filter_or_create(zero, "newer");
+        let id1 = tasks.filter_or_create(zero, "newer", |_| true);
         assert_eq!(tasks.len(), 1);
         assert_eq!(tasks.visible_tasks().len(), 0);
         assert_eq!(tasks.get_by_id(&id1.unwrap()).unwrap().parent_id(), zero);
 
         tasks.move_to(zero.cloned());
         assert_eq!(tasks.visible_tasks().len(), 1);
-        let sub = tasks.make_task("test");
+        let sub = tasks.make_task("test").unwrap();
         assert_eq!(tasks.len(), 2);
         assert_eq!(tasks.visible_tasks().len(), 2);
         assert_eq!(tasks.get_by_id(&sub).unwrap().parent_id(), zero);
 
         // Do not substring match invisible subtask
-        let id2 = tasks.filter_or_create(None, "#new-is gold wrapped").unwrap();
+        let id2 = tasks.filter_or_create(None, "#new-is gold wrapped", |_| true).unwrap();
         assert_eq!(tasks.len(), 3);
         assert_eq!(tasks.visible_tasks().len(), 2);
         let new2 = tasks.get_by_id(&id2).unwrap();
@@ -1561,18 +5785,89 @@ mod tasks_test {
         tasks.move_up();
         assert_eq!(tasks.get_matching(tasks.get_position_ref(), "wrapped").len(), 1);
         assert_eq!(tasks.get_matching(tasks.get_position_ref(), "new-i").len(), 1);
-        tasks.filter_or_create(None, "is gold");
+        tasks.filter_or_create(None, "is gold", |_| true);
         assert_position!(tasks, id2);
 
         assert_eq!(tasks.get_own_events_history().count(), 3);
         // Global match
-        let idagain = tasks.filter_or_create(None, "newer");
+        let idagain = tasks.filter_or_create(None, "newer", |_| true);
         assert_eq!(idagain, None);
         assert_position!(tasks, id1.unwrap());
         assert_eq!(tasks.get_own_events_history().count(), 4);
         assert_eq!(tasks.len(), 3);
     }
 
+    #[test]
+    fn test_creation_safety() {
+        let mut tasks = stub_tasks();
+        let existing = tasks.make_task("existing").unwrap();
+        let asked = RefCell::new(vec![]);
+        let answer = |yes: bool| {
+            let asked = &asked;
+            move |name: &str| {
+                asked.borrow_mut().push(name.to_string());
+                yes
+            }
+        };
+
+        tasks.settings.creation = Creation::Confirm;
+        assert_eq!(tasks.filter_or_create(None, "exist", answer(false)), None);
+        assert_position!(tasks, existing);
+        tasks.move_up();
+        assert!(!tasks.needs_confirmation(None, "exist"));
+        assert!(!tasks.needs_confirmation(None, "typo!"));
+        assert!(tasks.needs_confirmation(None, "typo"));
+        assert_eq!(tasks.filter_or_create(None, "typo", answer(false)), None);
+        assert_eq!(tasks.len(), 1);
+        assert!(tasks.filter_or_create(None, "wanted", answer(true)).is_some());
+        assert_eq!(asked.borrow().as_slice(), ["typo", "wanted"]);
+        let explicit = tasks.filter_or_create(None, "shout !", answer(false)).unwrap();
+        assert_eq!(tasks.get_by_id(&explicit).unwrap().get_title(), "shout");
+        assert_eq!(asked.borrow().len(), 2);
+
+        tasks.settings.creation = Creation::Explicit;
+        assert_eq!(tasks.filter_or_create(None, "other", answer(true)), None);
+        assert!(tasks.filter_or_create(None, "other!", answer(false)).is_some());
+        assert_eq!(tasks.len(), 4);
+        assert_eq!(asked.borrow().len(), 2);
+
+        tasks.settings.creation = Creation::Direct;
+        let direct = tasks.filter_or_create(None, "loud!", answer(false)).unwrap();
+        assert_eq!(tasks.get_by_id(&direct).unwrap().get_title(), "loud!");
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        tasks.custom_time = Some(now - 200 * 86_400);
+        let project = tasks.make_task("project").unwrap();
+        tasks.move_to(Some(project));
+        let stale = tasks.make_task("stale").unwrap();
+        let tracked = tasks.make_task("tracked").unwrap();
+        let updated = tasks.make_task("updated").unwrap();
+        let done = tasks.make_task("done").unwrap();
+        tasks.set_state_for(done, "", State::Done);
+        tasks.custom_time = Some(now - 10 * 86_400);
+        tasks.set_state_for(updated, "", State::Pending);
+        tasks.track_at(now - 5 * 86_400, Some(tracked));
+        tasks.track_at(now - 5 * 86_400 + 60, None);
+        tasks.custom_time = None;
+        let fresh = tasks.make_task("fresh").unwrap();
+
+        let since = now - 90 * 86_400;
+        let ids = |tasks: &TasksRelay, since| tasks.stale_tasks(Some(&project), since).into_iter().map(|(_, t)| t.event.id).collect_vec();
+        assert_eq!(ids(&tasks, since), vec![stale]);
+        assert_eq!(ids(&tasks, now - 7 * 86_400), vec![stale, updated]);
+        assert_eq!(ids(&tasks, now + 86_400), vec![stale, updated, tracked, fresh]);
+
+        assert_eq!(tasks.sweep(&[stale], State::Closed, "auto-closed after 90d inactivity"), 1);
+        let task = tasks.get_by_id(&stale).unwrap();
+        assert_eq!(task.pure_state(), State::Closed);
+        assert_eq!(task.state().unwrap().get_label(), "auto-closed after 90d inactivity");
+        assert!(ids(&tasks, since).is_empty());
+    }
+
     #[test]
     fn test_tracking() {
         let mut tasks = stub_tasks();
@@ -1595,7 +5890,7 @@ mod tasks_test {
         assert_eq!(tasks.get_own_events_history().count(), 3);
         assert!(tasks.time_tracked(zero) > 999);
 
-        let some = tasks.make_task("some");
+        let some = tasks.make_task("some").unwrap();
         tasks.track_at(Timestamp::from(22 + 1), Some(some));
         assert_eq!(tasks.get_own_events_history().count(), 4);
         assert_eq!(tasks.time_tracked(zero), 12);
@@ -1605,22 +5900,87 @@ mod tasks_test {
     }
 
     #[test]
-    #[ignore]
     fn test_timestamps() {
         let mut tasks = stub_tasks();
-        let zero = EventId::all_zeros();
+        let task = tasks.make_task("task").unwrap();
+        let now = Timestamp::now();
+
+        tasks.track_at(now - 100, Some(task));
+        tasks.track_at(now + 1000, Some(task));
+        let (past, planned) = timestamps(tasks.get_own_events_history(), &[&task], now);
+        assert_eq!(past, vec![(&(now - 100), Some(&task))]);
+        assert_eq!(planned, vec![(&(now + 1000), Some(&task))]);
+        tasks.move_to(Some(task));
+        let (_, times) = tasks.times_tracked();
+        let times = times.collect_vec();
+        assert_eq!(times.len(), 2);
+        assert!(times[0].contains(" started by "), "{}", times[0]);
+        assert!(times[1].contains("will track \"task\" from"), "{}", times[1]);
+        let mut out = Vec::new();
+        tasks.write_tasks(&mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains(
+            &format!("Tracking since {} ", format_timestamp_relative(&(now - 100)))));
+
+        // A planned stop ends the current tracking
+        tasks.track_at(now + 2000, None);
+        let (_, planned) = timestamps(tasks.get_own_events_history(), &[&task], now);
+        assert_eq!(planned.len(), 2);
+        assert_eq!(planned[1].1, None);
+
+        let ids = vec![&task];
+        let durations = |threshold| Durations {
+            events: Box::new(tasks.get_own_events_history()),
+            ids: &ids,
+            threshold: Some(threshold),
+        }.map(|d| d.as_secs()).collect_vec();
+        assert_eq!(durations(now), vec![100]);
+        assert_eq!(durations(now - 50), vec![50]);
+        assert_eq!(durations(now + 1500), vec![1600]);
+        // Stops are moved back by a second
+        assert_eq!(durations(now + 3000), vec![2099]);
+        assert!(durations(now - 200).is_empty());
+    }
+
+
+    #[test]
+    fn test_plan() {
+        let mut tasks = stub_tasks();
+        let first = tasks.make_task("first").unwrap();
+        let second = tasks.make_task("second").unwrap();
+        let now = Timestamp::now();
 
-        tasks.track_at(Timestamp::from(Timestamp::now().as_u64() + 100), Some(zero));
-        assert_eq!(timestamps(tasks.get_own_events_history(), &vec![&zero]).collect_vec().len(), 2)
-        // TODO Does not show both future and current tracking properly, need to split by current time
+        assert!(!tasks.plan(now - 100, now + 100, first));
+        assert!(!tasks.plan(now + 200, now + 100, first));
+        assert!(tasks.plan(now + 3600, now + 7200, first));
+        assert_eq!(tasks.planned_blocks(), vec![(now + 3600, Some(now + 7199), first)]);
+        assert!(!tasks.plan(now + 5000, now + 9000, second));
+        assert!(!tasks.plan(now + 1000, now + 4000, second));
+        assert!(tasks.plan(now + 7200, now + 9000, second));
+        assert_eq!(tasks.planned_blocks().len(), 2);
+        assert_eq!(tasks.get_position(), None);
     }
 
+    #[test]
+    fn test_rapid_moves() {
+        let mut tasks = stub_tasks();
+        let ids = (0..15).map(|i| tasks.make_task(&format!("t{i}")).unwrap()).collect_vec();
+        for id in &ids {
+            tasks.move_to(Some(*id));
+        }
+        assert_position!(tasks, ids[14]);
+        let history = tasks.get_own_history().unwrap();
+        assert!(history.len() <= tasks.settings.max_offset as usize + 1);
+        assert!(history.iter().all(|(stamp, event)| stamp == &event.created_at));
+        let stamps = tasks.get_own_events_history().map(|e| e.created_at).collect_vec();
+        assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(tasks.get_own_events_history().last().and_then(referenced_event), Some(&ids[14]));
+    }
 
     #[test]
     fn test_depth() {
         let mut tasks = stub_tasks();
 
-        let t1 = tasks.make_task("t1");
+        let t1 = tasks.make_task("t1").unwrap();
         let task1 = tasks.get_by_id(&t1).unwrap();
         assert_eq!(tasks.depth, 1);
         assert_eq!(task1.pure_state(), State::Open);
@@ -1633,17 +5993,17 @@ mod tasks_test {
         assert_position!(tasks, t1);
         tasks.depth = 2;
         assert_eq!(tasks.visible_tasks().len(), 0);
-        let t11 = tasks.make_task("t11: tag");
+        let t11 = tasks.make_task("t11: tag").unwrap();
         assert_eq!(tasks.visible_tasks().len(), 1);
         assert_eq!(tasks.get_task_path(Some(t11)), "t1>t11");
         assert_eq!(tasks.relative_path(t11), "t11");
-        let t12 = tasks.make_task("t12");
+        let t12 = tasks.make_task("t12").unwrap();
         assert_eq!(tasks.visible_tasks().len(), 2);
 
         tasks.move_to(Some(t11));
         assert_position!(tasks, t11);
         assert_eq!(tasks.visible_tasks().len(), 0);
-        let t111 = tasks.make_task("t111");
+        let t111 = tasks.make_task("t111").unwrap();
         assert_tasks!(tasks, [t111]);
         assert_eq!(tasks.get_task_path(Some(t111)), "t1>t11>t111");
         assert_eq!(tasks.relative_path(t111), "t111");
@@ -1683,7 +6043,7 @@ mod tasks_test {
     fn test_empty_task_title_fallback_to_id() {
         let mut tasks = stub_tasks();
 
-        let empty = tasks.make_task("");
+        let empty = tasks.make_task("").unwrap();
         let empty_task = tasks.get_by_id(&empty).unwrap();
         let empty_id = empty_task.event.id.to_string();
         assert_eq!(empty_task.get_title(), empty_id);
@@ -1697,7 +6057,7 @@ mod tasks_test {
         let zero = EventId::all_zeros();
         assert_eq!(tasks.get_task_path(Some(zero)), zero.to_string());
         tasks.move_to(Some(zero));
-        let dangling = tasks.make_task("test");
+        let dangling = tasks.make_task("test").unwrap();
         assert_eq!(
             tasks.get_task_path(Some(dangling)),
             "0000000000000000000000000000000000000000000000000000000000000000>test"
@@ -1705,6 +6065,796 @@ mod tasks_test {
         assert_eq!(tasks.relative_path(dangling), "test");
     }
 
+    #[test]
+    fn test_project() {
+        let mut tasks = stub_tasks();
+        let project = |tasks: &TasksRelay, id: EventId| tasks.get_property(tasks.get_by_id(&id).unwrap(), "project");
+
+        let t1 = tasks.make_task("t1").unwrap();
+        tasks.move_to(Some(t1));
+        let t11 = tasks.make_task("t11").unwrap();
+        tasks.make_task("t12");
+        tasks.move_to(Some(t11));
+        let t111 = tasks.make_task("t111").unwrap();
+        assert_eq!(project(&tasks, t111), "t1");
+        assert_eq!(project(&tasks, t1), "t1");
+
+        tasks.move_to(None);
+        let t2 = tasks.make_task("t2").unwrap();
+        tasks.move_to(Some(t2));
+        let t21 = tasks.make_task("t21").unwrap();
+        tasks.move_to(None);
+        assert_eq!(project(&tasks, t21), "t2");
+        tasks.depth = 9;
+        tasks.set_sorting(VecDeque::from(["project".to_string(), "name".to_string()]));
+        assert_eq!(tasks.listed_tasks().iter().map(|t| t.get_title()).collect_vec(), vec!["t111", "t12", "t21"]);
+        // Moving the subtask updates its project
+        tasks.merge_duplicates(&[t1, t2]);
+        assert_eq!(project(&tasks, t21), "t1");
+
+        tasks.move_to(Some(EventId::all_zeros()));
+        let dangling = tasks.make_task("test").unwrap();
+        assert_eq!(project(&tasks, dangling), "00000000");
+    }
+
+    #[test]
+    fn test_clipped_notes() {
+        let mut tasks = stub_tasks();
+        let task = tasks.make_task("task").unwrap();
+        tasks.move_to(Some(task));
+        tasks.make_note(&(1..=20).map(|i| format!("line {i}")).join("\n"));
+        assert!(tasks.format_notes(tasks.get_current_task().unwrap())[0].ends_with("line 19\nline 20"));
+
+        let mut output = vec![];
+        tasks.write_tasks(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("line 10 (… 10 more lines, use , to read)"), "{output}");
+        assert!(!output.contains("line 11"));
+    }
+
+    #[test]
+    fn test_dangling() {
+        let mut tasks = stub_tasks();
+        let zero = EventId::all_zeros();
+        let known = tasks.make_task("known").unwrap();
+        let child = tasks.make_task_with("child", tasks.position_tags_for(Some(&zero)), true).unwrap();
+        let blocked = tasks.make_task_with("blocked", [
+            tasks.make_event_tag_from_id(known, MARKER_PARENT),
+            tasks.make_event_tag_from_id(zero, MARKER_DEPENDS),
+        ], true).unwrap();
+        tasks.make_task_with("fine", [tasks.make_event_tag_from_id(known, MARKER_DEPENDS)], true);
+        assert_eq!(tasks.dangling(), vec![(zero, vec![
+            (MARKER_PARENT.to_string(), child),
+            (MARKER_DEPENDS.to_string(), blocked),
+        ].into_iter().sorted().collect_vec())]);
+        // Local tasks cannot be fetched
+        assert!(!tasks.fetch_dangling());
+
+        assert_eq!(tasks.detach_dangling(Some("1234")), 0);
+        assert_eq!(tasks.detach_dangling(Some("0000")), 2);
+        assert!(tasks.dangling().is_empty());
+        assert_eq!(tasks.get_by_id(&child).unwrap().parent_id(), None);
+        assert_eq!(tasks.get_by_id(&blocked).unwrap().parent_id(), Some(&known));
+        assert!(tasks.get_by_id(&blocked).unwrap().get_dependendees().is_empty());
+    }
+
+    #[test]
+    fn test_import_todotxt() {
+        use chrono::NaiveDate;
+        let mut tasks = stub_tasks();
+        let todo = "(A) Call Mom: birthday +Family @phone due:2024-05-03
+x 2024-05-02 2024-04-30 Buy flowers +Family
+
+Call Mom: birthday +Family
+Water plants
+";
+        assert_eq!(tasks.import_todotxt(todo.as_bytes()), (4, 1));
+        assert_eq!(tasks.import_todotxt(todo.as_bytes()), (0, 4));
+        assert_eq!(tasks.len(), 4);
+
+        let family = tasks.children_for(None).find(|t| t.get_title() == "Family").unwrap().event.id;
+        let call = tasks.children_for(Some(&family)).find(|t| t.get_title() == "Call Mom: birthday").unwrap();
+        assert_eq!(call.priority(), Some('A'));
+        assert_eq!(call.due(), Some(day_start(NaiveDate::from_ymd_opt(2024, 5, 3).unwrap()).to_timestamp()));
+        assert_eq!(call.get("hashtags").as_deref(), Some("phone"));
+        let flowers = tasks.children_for(Some(&family)).find(|t| t.get_title() == "Buy flowers").unwrap();
+        assert_eq!(flowers.pure_state(), State::Done);
+        assert_eq!(flowers.event.created_at, day_start(NaiveDate::from_ymd_opt(2024, 4, 30).unwrap()).to_timestamp());
+        assert_eq!(flowers.completed_at(), Some(day_start(NaiveDate::from_ymd_opt(2024, 5, 2).unwrap()).to_timestamp()));
+        assert_eq!(tasks.custom_time, None);
+    }
+
+    #[test]
+    fn test_import_taskwarrior() {
+        let mut tasks = stub_tasks();
+        let export = r#"[
+{"id":0,"description":"Plant tomatoes","end":"20240502T080000Z","entry":"20240501T120000Z","project":"Home.Garden","status":"completed","uuid":"a1","tags":["outside"],"annotations":[{"entry":"20240501T130000Z","description":"Buy seeds first"}],"recur":"weekly"},
+{"id":1,"description":"Harvest","entry":"20240501T120000Z","project":"Home.Garden","status":"pending","uuid":"b2","depends":["a1","c3"]},
+{"id":2,"description":"Call plumber","entry":"20240501T120000Z","project":"Home","status":"deleted","end":"20240503T100000Z","uuid":"c4"}
+]"#;
+        let (count, unmapped) = tasks.import_taskwarrior(export).unwrap();
+        assert_eq!(count, 5);
+        assert_eq!(unmapped.into_iter().collect_vec(), vec!["recur"]);
+
+        let find = |tasks: &TasksRelay, title: &str| tasks.tasks.values().find(|t| t.get_title() == title).unwrap().event.id;
+        let home = find(&tasks, "Home");
+        let garden = find(&tasks, "Garden");
+        let plant = tasks.get_by_id(&find(&tasks, "Plant tomatoes")).unwrap();
+        assert_eq!(plant.parent_id(), Some(&garden));
+        assert_eq!(plant.event.created_at, Timestamp::from(1714564800));
+        assert_eq!(plant.completed_at(), Some(Timestamp::from(1714636800)));
+        assert_eq!(plant.get("hashtags").as_deref(), Some("outside"));
+        assert_eq!(plant.description_events().map(|e| (e.created_at, e.content.as_str())).collect_vec(),
+                   vec![(Timestamp::from(1714568400), "Buy seeds first")]);
+        let harvest = tasks.get_by_id(&find(&tasks, "Harvest")).unwrap();
+        assert_eq!(harvest.parent_id(), Some(&garden));
+        assert_eq!(harvest.get_dependendees(), vec![&plant.event.id]);
+        let plumber = tasks.get_by_id(&find(&tasks, "Call plumber")).unwrap();
+        assert_eq!(plumber.parent_id(), Some(&home));
+        assert_eq!(plumber.pure_state(), State::Closed);
+        assert_eq!(tasks.custom_time, None);
+        assert!(tasks.import_taskwarrior("[{").is_err());
+    }
+
+    #[test]
+    fn test_task_views() {
+        let mut tasks = stub_tasks();
+        let start = Timestamp::from(1714564800);
+        tasks.custom_time = Some(start);
+        let garden = tasks.make_task("Garden: home").unwrap();
+        let plant = tasks.make_task_with("Plant tomatoes", tasks.position_tags_for(Some(&garden)), false).unwrap();
+        tasks.set_state_for(plant, "", State::Done);
+        tasks.custom_time = None;
+        tasks.track_at(start + 600, Some(plant));
+        tasks.track_at(start + 1801, None);
+        tasks.properties = ["name", "state", "rtime", "created", "progress"].into_iter().map(|s| s.to_string()).collect();
+
+        let json = tasks.to_json(true)
+            .replace(&garden.to_string(), "GARDEN")
+            .replace(&plant.to_string(), "PLANT");
+        let parse = |json: &str| serde_json::from_str::<Value>(json).unwrap();
+        assert_eq!(parse(&json), parse(include_str!("../tests/golden/task_views.json")));
+        // Only the listing without all
+        assert_eq!(tasks.task_views(false).len(), 1);
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let mut tasks = stub_tasks();
+        tasks.custom_time = Some(Timestamp::from(1714564800));
+        let task = tasks.make_task("\\Say \"hi\", then\nleave").unwrap();
+        tasks.make_task("Plain");
+        tasks.custom_time = None;
+        tasks.track_at(Timestamp::from(1714564800 + 60), Some(task));
+        tasks.track_at(Timestamp::from(1714564800 + 181), None);
+        tasks.properties = ["name", "rtime", "created", "state"].into_iter().map(|s| s.to_string()).collect();
+        tasks.sorting = VecDeque::from(["name".to_string()]);
+
+        let mut csv = vec![];
+        assert_eq!(tasks.export_csv(&mut csv).unwrap(), 2);
+        let created = format_timestamp(&Timestamp::from(1714564800), "%Y-%m-%dT%H:%M:%S%:z");
+        assert_eq!(String::from_utf8(csv).unwrap(), format!(
+            "name,rtime,created,state\nPlain,0,{created},Open\n\"Say \"\"hi\"\", then\nleave\",120,{created},Open\n"));
+    }
+
+    #[test]
+    fn test_export_html() {
+        let mut tasks = stub_tasks();
+        let project = tasks.make_task("Release <v2> & \"more\"").unwrap();
+        tasks.move_to(Some(project));
+        let script = tasks.make_task_with("\\<script>alert('x')</script>", tasks.position_tags_for(Some(&project)), false).unwrap();
+        let done = tasks.make_task("Changelog").unwrap();
+        tasks.set_state_for(done, "", State::Done);
+        tasks.move_to(Some(script));
+        tasks.make_note("Uses <b>bold</b>\nand a second line");
+        tasks.move_to(None);
+
+        let mut output = Vec::new();
+        assert_eq!(tasks.export_html(&mut output).unwrap(), 3);
+        let html = String::from_utf8(output).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.trim_end().ends_with("</html>"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<b>"));
+        assert!(!html.contains("<v2>"));
+        assert!(html.contains("Release &lt;v2&gt; &amp; &quot;more&quot;"));
+        assert!(html.contains("&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"));
+        assert!(html.contains("<p class=\"note\">Uses &lt;b&gt;bold&lt;/b&gt;<br>and a second line</p>"));
+        assert!(html.contains("<progress value=\"0.50\" max=\"1\"></progress> 50%"));
+        assert!(html.contains(&format!("{} 2", state_badge(&State::Open, "Open"))));
+        assert!(html.contains(&format!("{} 1", state_badge(&State::Done, "Done"))));
+
+        tasks.move_to(Some(project));
+        let mut output = Vec::new();
+        assert_eq!(tasks.export_html(&mut output).unwrap(), 2);
+        // Only in the title and heading
+        assert_eq!(String::from_utf8(output).unwrap().matches("Release &lt;v2&gt;").count(), 2);
+    }
+
+    #[test]
+    fn test_updated_tasks() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        let other = Keys::generate();
+        let mut task_by_other = |name: &str, created_at: Timestamp| {
+            let event = EventBuilder::new(TASK_KIND, name, []).custom_created_at(created_at).to_event(&other).unwrap();
+            let id = event.id;
+            tasks.add(event);
+            id
+        };
+        task_by_other("old", now - 7200);
+        let noted = task_by_other("noted", now - 7200);
+        let fresh = task_by_other("fresh", now - 60);
+        tasks.add(build_prop(Kind::TextNote, "news", noted).custom_created_at(now - 120).to_event(&other).unwrap());
+        // Own changes are no news
+        tasks.make_task("mine");
+        assert!(tasks.updated_tasks().is_empty());
+
+        tasks.restore_session(&RelaySession { saved: Some(now - 3600), ..Default::default() });
+        assert_eq!(tasks.updated_tasks(), vec![fresh, noted]);
+        assert!(tasks.set_view_updated());
+        assert_eq!(tasks.visible_tasks().len(), 2);
+        // Reviewed
+        assert!(tasks.updated_tasks().is_empty());
+        assert!(!tasks.set_view_updated());
+    }
+
+    #[test]
+    fn test_session() {
+        let mut tasks = stub_tasks();
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.move_to(Some(parent));
+        tasks.add_tag("work".to_string());
+        tasks.remove_tag("private");
+        tasks.set_state_filter(StateFilter::State("Done".to_string()));
+        tasks.set_depth(2);
+        let session = tasks.session();
+        assert_eq!(session.position, Some(parent));
+        assert_eq!(session.tags, vec!["work"]);
+        assert_eq!(session.excluded, vec!["private"]);
+        assert_eq!(session.state.as_deref(), Some("Done"));
+
+        let mut restored = stub_tasks();
+        restored.restore_session(&session);
+        assert_eq!(restored.depth, 2);
+        assert_eq!(restored.session().tags, session.tags);
+        assert_eq!(restored.session().excluded, session.excluded);
+        assert_eq!(restored.session().state, session.state);
+        // Unknown position is skipped
+        assert_eq!(restored.get_position(), None);
+
+        let mut other = stub_tasks();
+        other.add(tasks.get_by_id(&parent).unwrap().event.clone());
+        other.restore_session(&RelaySession { saved: None, ..session.clone() });
+        assert_position!(other, parent);
+    }
+
+    #[test]
+    fn test_review() {
+        let mut tasks = stub_tasks();
+        let me = tasks.sender.pubkey();
+        let other = Keys::generate().public_key();
+        let mine = tasks.make_task("mine").unwrap();
+        let theirs = tasks.make_task("theirs").unwrap();
+        let anyone = tasks.make_task("anyone").unwrap();
+        tasks.custom_time = Some(Timestamp::now() - 10);
+        tasks.request_review(mine, Some(me));
+        tasks.request_review(theirs, Some(other));
+        tasks.request_review(anyone, None);
+        tasks.custom_time = None;
+        let task = tasks.get_by_id(&mine).unwrap();
+        assert_eq!(task.pure_state(), State::Pending);
+        assert!(task.state().unwrap().matches_label("review"));
+
+        tasks.set_state_filter(StateFilter::Review(me));
+        assert_tasks!(tasks, [mine]);
+        tasks.set_state_filter(StateFilter::State("Review".to_string()));
+        assert_tasks!(tasks, [mine, theirs, anyone]);
+
+        tasks.review(mine, true, "");
+        tasks.review(theirs, false, "Needs tests");
+        let state = |tasks: &TasksRelay, id: &EventId| tasks.get_property(tasks.get_by_id(id).unwrap(), "state");
+        let name = tasks.get_author(&me, None);
+        assert_eq!(state(&tasks, &mine), format!("Done ✓ {name}"));
+        assert_eq!(state(&tasks, &theirs), format!("Needs tests ✗ {name}"));
+        assert_eq!(state(&tasks, &anyone), "Review");
+        assert_eq!(tasks.get_by_id(&theirs).unwrap().pure_state(), State::Open);
+        tasks.set_state_filter(StateFilter::Review(me));
+        assert_tasks!(tasks, []);
+    }
+
+    #[test]
+    fn test_state_conflicts() {
+        let keys = Keys::generate();
+        let other = Keys::generate();
+        let now = Timestamp::now();
+        let task = EventBuilder::new(TASK_KIND, "shared", []).custom_created_at(now - 100).to_event(&keys).unwrap();
+        let id = task.id;
+        let prop = |state: State, stamp: Timestamp, keys: &Keys| build_prop(state.into(), "", id).custom_created_at(stamp).to_event(keys).unwrap();
+        let done = prop(State::Done, now - 50, &keys);
+        let reopened = prop(State::Open, now - 40, &other);
+        let tie = [prop(State::Pending, now - 30, &keys), prop(State::Closed, now - 30, &other)];
+        let tie_winner = tie.iter().max_by_key(|e| e.id).unwrap().kind;
+
+        for reverse in [false, true] {
+            let mut tasks = stub_tasks();
+            tasks.sender.keys = keys.clone();
+            tasks.add(task.clone());
+            let mut events = vec![done.clone(), reopened.clone()];
+            if reverse {
+                events.reverse();
+            }
+            events.into_iter().for_each(|e| tasks.add(e));
+            assert_eq!(tasks.get_by_id(&id).unwrap().pure_state(), State::Open);
+
+            let mut tie = tie.to_vec();
+            if reverse {
+                tie.reverse();
+            }
+            tie.into_iter().for_each(|e| tasks.add(e));
+            assert_eq!(Kind::from(tasks.get_by_id(&id).unwrap().pure_state()), tie_winner);
+        }
+
+        for reverse in [false, true] {
+            let mut tasks = stub_tasks();
+            tasks.settings.owner_states = true;
+            tasks.add(task.clone());
+            let mut events = vec![done.clone(), reopened.clone()];
+            if reverse {
+                events.reverse();
+            }
+            events.into_iter().for_each(|e| tasks.add(e));
+            assert_eq!(tasks.get_by_id(&id).unwrap().pure_state(), State::Done);
+            let timeline = tasks.describe_timeline(&id);
+            assert!(timeline.iter().any(|line| line.ends_with("discarded as conflicting with the creator")), "{:?}", timeline);
+        }
+
+        let mut tasks = stub_tasks();
+        tasks.add(task.clone());
+        tasks.add(prop(State::Closed, now + 3600, &other));
+        tasks.add(prop(State::Done, now + 60, &other));
+        let shared = tasks.get_by_id(&id).unwrap();
+        assert_eq!(shared.discarded_states().map(|(state, reason)| (state.state, reason)).collect_vec(), vec![(State::Closed, Discarded::Skewed)]);
+        assert_eq!(shared.pure_state(), State::Open);
+    }
+
+    #[test]
+    fn test_others_tasks() {
+        let mut tasks = stub_tasks();
+        let other = Keys::generate();
+        let theirs = EventBuilder::new(TASK_KIND, "theirs", []).to_event(&other).unwrap();
+        let id = theirs.id;
+        tasks.add(theirs);
+        let own = tasks.make_task("own").unwrap();
+        let state = |tasks: &TasksRelay| tasks.get_by_id(&id).unwrap().pure_state();
+
+        assert_eq!(tasks.settings.others_tasks, Ownership::Warn);
+        assert!(tasks.set_state_for(own, "", State::Done).is_some());
+        assert!(tasks.set_state_for(id, "", State::Done).is_none());
+        assert_eq!(state(&tasks), State::Open);
+        assert!(tasks.set_state_for(id, "", State::Done).is_some());
+        assert_eq!(state(&tasks), State::Done);
+        assert!(tasks.set_expiration(id, None).is_some());
+
+        tasks.settings.others_tasks = Ownership::Forbid;
+        assert!(tasks.set_state_for(id, "", State::Open).is_none());
+        assert!(tasks.set_state_for(id, "", State::Open).is_none());
+        assert_eq!(state(&tasks), State::Done);
+
+        tasks.settings.others_tasks = Ownership::Allow;
+        let mut other_tasks = stub_tasks();
+        other_tasks.sender.keys = other;
+        other_tasks.settings.others_tasks = Ownership::Forbid;
+        let mine = tasks.get_by_id(&own).unwrap().event.clone();
+        other_tasks.add(mine);
+        assert!(other_tasks.set_state_for(own, "", State::Open).is_none());
+        assert!(tasks.set_state_for(id, "", State::Pending).is_some());
+    }
+
+    #[test]
+    fn test_state_changer() {
+        let mut tasks = stub_tasks();
+        let now = Timestamp::now();
+        tasks.custom_time = Some(now - 100);
+        let id = tasks.make_task("shared").unwrap();
+        tasks.custom_time = None;
+        let property = |tasks: &TasksRelay, name: &str| tasks.get_property(tasks.get_by_id(&id).unwrap(), name);
+        assert_eq!(property(&tasks, "state"), "Open");
+        assert_eq!(property(&tasks, "statechanger"), "");
+
+        let other = Keys::generate();
+        tasks.add(build_prop(State::Done.into(), "", id).custom_created_at(now - 50).to_event(&other).unwrap());
+        let me = tasks.get_author(&tasks.sender.pubkey(), None);
+        let them = tasks.get_author(&other.public_key(), None);
+        assert_eq!(tasks.get_by_id(&id).unwrap().state().unwrap().author, other.public_key());
+        assert_eq!(property(&tasks, "state"), format!("Done {them}"));
+        assert_eq!(property(&tasks, "statechanger"), them);
+
+        tasks.set_state_for(id, "", State::Open);
+        assert_eq!(property(&tasks, "state"), "Open");
+        assert_eq!(property(&tasks, "statechanger"), me);
+        assert_eq!(tasks.state_changer(tasks.get_by_id(&id).unwrap()), None);
+
+        let lines = tasks.describe_timeline(&id);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains(&format!("Open {me} - ")));
+        assert!(lines[1].contains(&format!("Done {them} - ")));
+        assert!(lines[2].contains(&format!("Open {me} - ")));
+    }
+
+    #[test]
+    fn test_note_thread() {
+        let mut tasks = stub_tasks();
+        let task = tasks.make_task("discussion").unwrap();
+        tasks.move_to(Some(task));
+        tasks.custom_time = Some(Timestamp::from(100));
+        tasks.make_note("first note");
+        tasks.custom_time = Some(Timestamp::from(200));
+        tasks.reply_to_note(1, "reply to first").unwrap();
+        tasks.custom_time = Some(Timestamp::from(300));
+        tasks.reply_to_note(2, "reply to reply").unwrap();
+        tasks.custom_time = Some(Timestamp::from(400));
+        tasks.make_note("second note");
+        tasks.custom_time = Some(Timestamp::from(500));
+        tasks.reply_to_note(1, "another reply to first").unwrap();
+        assert!(tasks.reply_to_note(9, "nowhere").is_none());
+        assert!(tasks.reply_to_note(0, "nowhere").is_none());
+
+        let current = tasks.get_current_task().unwrap();
+        assert_eq!(current.description_thread().into_iter().map(|(level, note)| (level, note.content.as_str())).collect_vec(), vec![
+            (0, "first note"),
+            (1, "reply to first"),
+            (2, "reply to reply"),
+            (1, "another reply to first"),
+            (0, "second note"),
+        ]);
+        let notes = tasks.format_notes(current);
+        assert!(notes[2].starts_with("    3 "));
+        assert!(notes[2].ends_with(": reply to reply"));
+        assert!(notes[3].starts_with("  5 "));
+        assert!(notes[4].starts_with("4 "));
+        // Replies are still plain notes on the task
+        assert_eq!(current.descriptions().count(), 5);
+    }
+
+    #[test]
+    fn test_edit_notes() {
+        let mut tasks = stub_tasks();
+        let task = tasks.make_task("notes").unwrap();
+        tasks.move_to(Some(task));
+        tasks.custom_time = Some(Timestamp::from(100));
+        tasks.make_note("first draft");
+        tasks.custom_time = Some(Timestamp::from(200));
+        tasks.make_note("second note");
+        tasks.custom_time = Some(Timestamp::from(300));
+        tasks.edit_note(1, "first note").unwrap();
+        let contents = |tasks: &TasksRelay| tasks.get_current_task().unwrap().description_events()
+            .map(|note| note.content.clone()).collect_vec();
+        assert_eq!(contents(&tasks), vec!["first note", "second note"]);
+        let notes = tasks.format_notes(tasks.get_current_task().unwrap());
+        assert!(notes[0].starts_with("1 ") && notes[0].contains("(edited)"));
+        assert!(!notes[1].contains("(edited)"));
+
+        // Replies follow the note to its new version
+        tasks.custom_time = Some(Timestamp::from(400));
+        tasks.reply_to_note(1, "a reply").unwrap();
+        tasks.custom_time = Some(Timestamp::from(500));
+        tasks.edit_note(1, "first note, again").unwrap();
+        assert_eq!(tasks.get_current_task().unwrap().description_thread().into_iter()
+                       .map(|(level, note)| (level, note.content.as_str())).collect_vec(), vec![
+            (0, "first note, again"),
+            (1, "a reply"),
+            (0, "second note"),
+        ]);
+
+        tasks.remove_note(2).unwrap();
+        assert_eq!(contents(&tasks), vec!["first note, again", "a reply"]);
+        assert!(tasks.remove_note(3).is_none());
+
+        // Notes of others can be replied to, but not changed
+        let other = Keys::generate();
+        tasks.add(build_prop(Kind::TextNote, "foreign", task).custom_created_at(Timestamp::from(600)).to_event(&other).unwrap());
+        assert!(tasks.edit_note(3, "mine now").is_none());
+        assert!(tasks.remove_note(3).is_none());
+        assert_eq!(contents(&tasks).len(), 3);
+
+        // The replacement takes effect even if it arrives before the original
+        let keys = tasks.sender.keys.clone();
+        let original = build_prop(Kind::TextNote, "typo", task).custom_created_at(Timestamp::from(700)).to_event(&keys).unwrap();
+        let replacement = build_prop(Kind::TextNote, "fixed", task)
+            .add_tags([tasks.make_event_tag(&original, MARKER_SUPERSEDES), Tag::from(TagStandard::PublishedAt(original.created_at))])
+            .custom_created_at(Timestamp::from(800)).to_event(&keys).unwrap();
+        tasks.add(replacement);
+        tasks.add(original);
+        assert_eq!(contents(&tasks).last().unwrap(), "fixed");
+        assert_eq!(contents(&tasks).len(), 4);
+    }
+
+    #[test]
+    fn test_attachments() {
+        let mut tasks = stub_tasks();
+        assert!(tasks.attach("https://example.com/spec").is_none());
+        let task = tasks.make_task("reference").unwrap();
+        tasks.move_to(Some(task));
+        tasks.custom_time = Some(Timestamp::from(100));
+        tasks.attach("https://example.com/spec").unwrap();
+        tasks.custom_time = Some(Timestamp::from(200));
+        tasks.attach("docs/notes.md").unwrap();
+        tasks.custom_time = Some(Timestamp::from(300));
+        tasks.attach("C:\\work\\plan.txt").unwrap();
+        let current = tasks.get_current_task().unwrap();
+        let tags = current.attachments().map(|e| e.tags[1].as_vec()[0].clone()).collect_vec();
+        assert_eq!(tags, vec!["r", PATH_TAG, PATH_TAG]);
+        assert_eq!(current.get("attachments"), Some("3".to_string()));
+        assert_eq!(tasks.get_raw_property(current, "attachments"), json!(3));
+        assert_eq!(tasks.attachment(2), Some("docs/notes.md".to_string()));
+        assert_eq!(tasks.attachment(4), None);
+        assert_eq!(tasks.attachment(0), None);
+
+        tasks.detach(2).unwrap();
+        assert_eq!(tasks.attachment(2), Some("C:\\work\\plan.txt".to_string()));
+        let other = Keys::generate();
+        tasks.add(build_attachment(task, "https://example.org").custom_created_at(Timestamp::from(400)).to_event(&other).unwrap());
+        assert!(tasks.detach(3).is_none());
+        tasks.detach(1).unwrap();
+        tasks.detach(1).unwrap();
+        let current = tasks.get_current_task().unwrap();
+        assert_eq!(current.get("attachments"), Some("1".to_string()));
+        assert_eq!(tasks.attachment(1), Some("https://example.org".to_string()));
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut tasks = stub_tasks();
+        let hook = |tasks: &TasksRelay, event: &Event| tasks.hook_for(event)
+            .map(|(name, env)| (name, env.into_iter().collect::<HashMap<_, _>>()));
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.move_to(Some(parent));
+        let child = tasks.make_task("child").unwrap();
+        let (name, env) = hook(&tasks, &tasks.get_by_id(&child).unwrap().event).unwrap();
+        assert_eq!(name, "task_created");
+        assert_eq!(env["MOSTR_TASK_ID"], child.to_string());
+        assert_eq!(env["MOSTR_TASK_TITLE"], "child");
+        assert_eq!(env["MOSTR_TASK_PATH"], tasks.get_task_path(Some(child)));
+        assert_eq!(env["MOSTR_STATE"], "Open");
+        assert_eq!(env["MOSTR_RELAY"], "");
+        assert_eq!(env["MOSTR_DESCRIPTION"], "create task \"child\" under \"parent\"");
+
+        let done = tasks.set_state_for(child, "", State::Done).unwrap();
+        let event = tasks.get_by_id(&child).unwrap().props.iter().find(|e| e.id == done).unwrap().clone();
+        let (name, env) = hook(&tasks, &event).unwrap();
+        assert_eq!(name, "state_changed");
+        assert_eq!(env["MOSTR_STATE"], "Done");
+
+        tasks.move_to(Some(child));
+        tasks.make_note("a note on the child");
+        let note = tasks.get_by_id(&child).unwrap().props.iter().find(|e| e.kind == Kind::TextNote).unwrap().clone();
+        assert_eq!(hook(&tasks, &note).unwrap().0, "note_added");
+
+        tasks.track_at(Timestamp::from(100), Some(parent));
+        tasks.track_at(Timestamp::from(200), None);
+        let history = tasks.get_own_history().unwrap();
+        let (name, env) = hook(&tasks, &history[&Timestamp::from(100)]).unwrap();
+        assert_eq!((name, env["MOSTR_TASK_ID"].clone()), ("tracking_started", parent.to_string()));
+        let (name, env) = hook(&tasks, &history[&Timestamp::from(199)]).unwrap();
+        assert_eq!((name, env["MOSTR_TASK_ID"].clone()), ("tracking_stopped", parent.to_string()));
+
+        let metadata = EventBuilder::metadata(&Metadata::new()).to_event(&Keys::generate()).unwrap();
+        assert!(hook(&tasks, &metadata).is_none());
+    }
+
+    #[test]
+    fn test_desktop_notifications() {
+        let mut tasks = stub_tasks();
+        tasks.desktop = DesktopNotifications { assigned: true, mentioned: true, finished: true, due: true };
+        let own = tasks.sender.pubkey();
+        let other = Keys::generate();
+        let task = tasks.make_task("task").unwrap();
+
+        let assignment = build_assignment(task, Some(own)).to_event(&other).unwrap();
+        tasks.add(assignment.clone());
+        let (summary, body) = tasks.desktop_notification(&assignment).unwrap();
+        assert!(summary.ends_with(" assigned you"));
+        assert_eq!(body, tasks.get_task_path(Some(task)));
+
+        let note = EventBuilder::new(Kind::TextNote, " hi there ", [Tag::event(task), Tag::public_key(own)]).to_event(&other).unwrap();
+        tasks.add(note.clone());
+        assert_eq!(tasks.desktop_notification(&note).map(|(_, body)| body), Some("hi there".to_string()));
+        let unmentioned = EventBuilder::new(Kind::TextNote, "hi", [Tag::event(task)]).to_event(&other).unwrap();
+        assert!(tasks.desktop_notification(&unmentioned).is_none());
+
+        let done = build_prop(State::Done.into(), "", task).to_event(&other).unwrap();
+        tasks.add(done.clone());
+        assert_eq!(tasks.desktop_notification(&done).unwrap().0,
+                   format!("{} marked \"task\" Done", tasks.get_author(&other.public_key(), None)));
+        assert!(tasks.desktop_notification(&build_prop(State::Open.into(), "", task).to_event(&other).unwrap()).is_none());
+        assert!(tasks.desktop_notification(&build_prop(State::Done.into(), "", task).to_event(&tasks.sender.keys).unwrap()).is_none());
+        tasks.desktop.finished = false;
+        assert!(tasks.desktop_notification(&done).is_none());
+
+        let now = Timestamp::now();
+        tasks.submit(build_task("due", vec![build_due_tag(now + 60)], None));
+        tasks.add(build_task("foreign", vec![build_due_tag(now + 60)], None).to_event(&other).unwrap());
+        let deferred = tasks.make_task("deferred").unwrap();
+        tasks.set_state_for(deferred, "", State::Pending);
+        tasks.custom_time = Some(now + 120);
+        tasks.set_state_for(deferred, "", State::Open);
+        tasks.custom_time = None;
+        let summaries = |since: Timestamp, until: Timestamp| tasks.due_between(since, until).into_iter().map(|(summary, _)| summary).collect_vec();
+        assert!(summaries(now - 10, now).is_empty());
+        assert_eq!(summaries(now, now + 90), vec!["Due: \"due\""]);
+        assert_eq!(summaries(now + 90, now + 150), vec!["Resumed \"deferred\""]);
+    }
+
+    #[test]
+    fn test_export_ical() {
+        let mut tasks = stub_tasks();
+        let due = Timestamp::from(1714564800);
+        let trip = tasks.submit(build_task("Plan trip, then book", vec![build_due_tag(due), Tag::hashtag("travel")], None)).unwrap();
+        tasks.make_task("undated");
+        let done = tasks.submit(build_task("done", vec![build_due_tag(due)], None)).unwrap();
+        tasks.set_state_for(done, "", State::Done);
+        tasks.move_to(Some(trip));
+        tasks.make_note("Check visa");
+        tasks.move_to(None);
+        let start = Timestamp::now() + 3600;
+        assert!(tasks.plan(start, start + 1800, trip));
+
+        let export = |tasks: &TasksRelay| {
+            let mut output = vec![];
+            let count = tasks.export_ical(&mut output).unwrap();
+            (count, String::from_utf8(output).unwrap())
+        };
+        let (count, output) = export(&tasks);
+        assert_eq!(count, 2);
+        assert!(output.starts_with("BEGIN:VCALENDAR\r\n"));
+        for line in [
+            format!("UID:{trip}@mostr"),
+            "SUMMARY:Plan trip\\, then book".to_string(),
+            "DESCRIPTION:Check visa".to_string(),
+            "CATEGORIES:travel".to_string(),
+            "DUE:20240501T120000Z".to_string(),
+            "STATUS:NEEDS-ACTION".to_string(),
+            format!("UID:{trip}-{}@mostr", start.as_u64()),
+            format!("DTSTART:{}", crate::ical::format_time(&start)),
+            format!("DTEND:{}", crate::ical::format_time(&(start + 1800))),
+        ] {
+            assert!(output.contains(&format!("{line}\r\n")), "Missing {line} in {output}");
+        }
+        assert!(!output.contains("undated"));
+        assert!(!output.contains("SUMMARY:done"));
+
+        let uids = |output: &str| output.lines().filter(|line| line.starts_with("UID:")).map(|line| line.to_string()).collect_vec();
+        assert_eq!(uids(&export(&tasks).1), uids(&output));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let mut tasks = stub_tasks();
+        tasks.settings.max_content = 20;
+        tasks.settings.max_tags = 5;
+        let other = Keys::generate();
+        let huge = EventBuilder::new(Kind::GitIssue, "x".repeat(2_000_000), (0..5000).map(|i| Tag::hashtag(format!("tag{i}"))))
+            .to_event(&other).unwrap();
+        let id = huge.id;
+        tasks.add(huge);
+        let task = tasks.get_by_id(&id).unwrap();
+        assert!(task.is_shortened());
+        assert_eq!(task.get_title(), format!("{}…", "x".repeat(20)));
+        assert_eq!(task.get_filter_title().len(), 20 + '…'.len_utf8());
+        assert_eq!(task.hashtags().count(), 5);
+        assert_eq!(task.event.content.len(), 2_000_000);
+        assert_eq!(tasks.get_matching(None, "xxx"), vec![id]);
+
+        let small = EventBuilder::new(Kind::GitIssue, "ümlauts", []).to_event(&other).unwrap();
+        tasks.add(small.clone());
+        assert!(!tasks.get_by_id(&small.id).unwrap().is_shortened());
+
+        let future = EventBuilder::new(Kind::GitIssue, "future", [])
+            .custom_created_at(Timestamp::now() + 2 * tasks.settings.max_future)
+            .to_event(&other).unwrap();
+        tasks.add(future.clone());
+        assert!(tasks.get_by_id(&future.id).is_none());
+        let planned = tasks.submit(build_task("planned", vec![], None).custom_created_at(Timestamp::now() + 2 * tasks.settings.max_future));
+        assert!(planned.is_some_and(|id| tasks.get_by_id(&id).is_some()));
+        assert!(tasks.status().contains(" orphans, 1 shortened, 1 with tags capped, 1 rejected as dated ahead, "), "{}", tasks.status());
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut tasks = stub_tasks();
+        let id = tasks.make_task("task").unwrap();
+        let event = tasks.get_by_id(&id).unwrap().event.clone();
+        assert_eq!(tasks.take_duplicates(), 0);
+        for _ in 0..3 {
+            tasks.add(event.clone());
+        }
+        assert_eq!(tasks.take_duplicates(), 3);
+        assert_eq!(tasks.take_duplicates(), 0);
+
+        let url = Url::parse("wss://relay.example.com").unwrap();
+        let sibling = tasks.sibling(url.clone());
+        assert_eq!(sibling.url(), Some(&url));
+        assert_eq!(sibling.sender.pubkey(), tasks.sender.pubkey());
+        assert_eq!(sibling.len(), 0);
+        assert!(sibling.adopts_relays());
+    }
+
+    #[test]
+    fn test_repository() {
+        use nostr_sdk::TagKind;
+        let mut tasks = stub_tasks();
+        let maintainer = Keys::generate();
+        let repo = Coordinate::new(Kind::GitRepoAnnouncement, maintainer.public_key()).identifier("mostr");
+        let parent = tasks.make_task("parent").unwrap();
+        tasks.set_repository(parent, Some(repo.clone()));
+        let child = tasks.make_task_with("child", tasks.position_tags_for(Some(&parent)), false).unwrap();
+        let other = tasks.make_task("other").unwrap();
+        assert_eq!(tasks.get_by_id(&child).unwrap().get("repo").as_deref(), Some("mostr"));
+        assert_eq!(tasks.find_repository("mostr"), Some(repo.clone()));
+        assert_eq!(tasks.find_repository(&repo.to_string()), Some(repo.clone()));
+        assert_eq!(tasks.find_repository("unknown"), None);
+
+        // Issue as published by other NIP-34 clients
+        let issue = EventBuilder::new(TASK_KIND, "Steps to reproduce", [
+            Tag::coordinate(repo.clone()),
+            Tag::custom(TagKind::Custom("subject".into()), ["Crash on start"]),
+        ]).to_event(&maintainer).unwrap();
+        let issue_id = issue.id;
+        tasks.add(issue);
+        tasks.add(build_prop(Kind::GitStatusApplied, "", issue_id).to_event(&maintainer).unwrap());
+        let issue = tasks.get_by_id(&issue_id).unwrap();
+        assert_eq!(issue.get_title(), "Crash on start");
+        assert_eq!(issue.pure_state(), State::Done);
+
+        tasks.set_repo_filter(Some(repo.clone()));
+        assert_tasks!(tasks, [parent]);
+        assert!(tasks.get_prompt_suffix().contains("repo:mostr"));
+        let new = tasks.make_task("new").unwrap();
+        assert_eq!(tasks.get_by_id(&new).unwrap().repository(), Some(repo));
+        tasks.set_repo_filter(None);
+        assert_tasks!(tasks, [parent, other, new]);
+
+        tasks.set_repository(parent, None);
+        assert_eq!(tasks.get_by_id(&parent).unwrap().repository(), None);
+        assert!(tasks.get_by_id(&child).unwrap().repository().is_some());
+    }
+
+    #[test]
+    fn test_export_subtree() {
+        let mut tasks = stub_tasks();
+        let todo = "(A) Call Mom: birthday +Family @phone due:2024-05-03
+x 2024-05-02 2024-04-30 Buy flowers +Family
+Water plants
+";
+        tasks.import_todotxt(todo.as_bytes());
+        let water = tasks.children_for(None).find(|t| t.get_title() == "Water plants").unwrap().event.id;
+        tasks.set_state_for(water, "", State::Closed);
+
+        let mut markdown = vec![];
+        assert_eq!(tasks.export_markdown(&mut markdown).unwrap(), 4);
+        let markdown = String::from_utf8(markdown).unwrap();
+        assert!(markdown.contains("- [ ] Family\n  - [x] Buy flowers\n  - [ ] Call Mom: birthday #phone\n"));
+        assert!(markdown.contains("- [x] ~~Water plants~~\n"));
+
+        let export = |tasks: &TasksRelay| {
+            let mut lines = vec![];
+            tasks.export_todotxt(&mut lines).unwrap();
+            String::from_utf8(lines).unwrap().lines().map(|l| l.to_string()).sorted().collect_vec()
+        };
+        let lines = export(&tasks);
+        assert_eq!(lines.len(), 4);
+        assert!(lines.contains(&"x 2024-05-02 2024-04-30 Buy flowers +Family".to_string()));
+        assert!(lines.iter().any(|l| l.starts_with("(A) ") && l.ends_with(" Call Mom: birthday +Family @phone due:2024-05-03")));
+
+        // Importing the export recreates the same tasks
+        let mut copy = stub_tasks();
+        assert_eq!(copy.import_todotxt(lines.join("\n").as_bytes()), (4, 1));
+        assert_eq!(export(&copy), lines);
+        tasks.move_to(tasks.children_for(None).find(|t| t.get_title() == "Family").map(|t| t.event.id));
+        assert_eq!(export(&tasks).len(), 2);
+    }
+
     #[allow(dead_code)] // #[test]
     fn test_itertools() {
         use itertools::Itertools;
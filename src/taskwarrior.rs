@@ -0,0 +1,138 @@
+use chrono::NaiveDateTime;
+use nostr_sdk::serde_json;
+use nostr_sdk::serde_json::{Map, Value};
+use nostr_sdk::Timestamp;
+
+use crate::helpers::ToTimestamp;
+use crate::task::State;
+
+/// Fields of Taskwarrior tasks which are taken over
+const MAPPED_FIELDS: [&str; 11] = ["uuid", "description", "project", "tags", "status", "entry", "end", "due", "priority", "depends", "annotations"];
+/// Fields which Taskwarrior derives from others
+const DERIVED_FIELDS: [&str; 3] = ["id", "urgency", "modified"];
+
+/// A task from a Taskwarrior export, see https://taskwarrior.org/docs/design/task
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct WarriorTask {
+    pub(crate) uuid: String,
+    pub(crate) description: String,
+    /// Project hierarchy, split on dots
+    pub(crate) project: Vec<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) status: String,
+    pub(crate) entry: Option<Timestamp>,
+    pub(crate) end: Option<Timestamp>,
+    pub(crate) due: Option<Timestamp>,
+    /// Priority as a letter, mapping H, M and L to A, B and C
+    pub(crate) priority: Option<char>,
+    /// UUIDs of the tasks this one depends on
+    pub(crate) depends: Vec<String>,
+    /// Annotations with the time they were added
+    pub(crate) annotations: Vec<(Option<Timestamp>, String)>,
+    /// Names of the fields without equivalent
+    pub(crate) unmapped: Vec<String>,
+}
+
+impl WarriorTask {
+    fn from(object: &Map<String, Value>) -> Self {
+        let str = |key: &str| object.get(key).and_then(Value::as_str);
+        let time = |key: &str| str(key).and_then(parse_time);
+        WarriorTask {
+            uuid: str("uuid").unwrap_or_default().to_string(),
+            description: str("description").unwrap_or_default().trim().to_string(),
+            project: str("project").map_or(vec![], |p| p.split('.').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()),
+            tags: strings(object.get("tags")),
+            status: str("status").unwrap_or("pending").to_string(),
+            entry: time("entry"),
+            end: time("end"),
+            due: time("due"),
+            priority: str("priority").and_then(|p| match p {
+                "H" => Some('A'),
+                "M" => Some('B'),
+                "L" => Some('C'),
+                _ => None,
+            }),
+            depends: strings(object.get("depends")),
+            annotations: object.get("annotations").and_then(Value::as_array).into_iter().flatten()
+                .filter_map(|annotation| Some((
+                    annotation.get("entry").and_then(Value::as_str).and_then(parse_time),
+                    annotation.get("description")?.as_str()?.to_string(),
+                )))
+                .collect(),
+            unmapped: object.keys()
+                .filter(|key| !MAPPED_FIELDS.contains(&key.as_str()) && !DERIVED_FIELDS.contains(&key.as_str()))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// State to set after creation, none for pending tasks
+    pub(crate) fn state(&self) -> Option<State> {
+        match self.status.as_str() {
+            "completed" => Some(State::Done),
+            "deleted" => Some(State::Closed),
+            "waiting" => Some(State::Pending),
+            _ => None,
+        }
+    }
+}
+
+/// Parse the output of `task export`,
+/// either a JSON array or one JSON object per line as in older versions.
+pub(crate) fn parse_export(json: &str) -> serde_json::Result<Vec<WarriorTask>> {
+    let values = if json.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<Value>>(json)?
+    } else {
+        json.lines()
+            .map(|line| line.trim().trim_end_matches(','))
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_str::<Value>)
+            .collect::<serde_json::Result<_>>()?
+    };
+    Ok(values.iter()
+        .filter_map(Value::as_object)
+        .map(WarriorTask::from)
+        .filter(|task| !task.description.is_empty())
+        .collect())
+}
+
+/// Strings from an array or a comma-separated string
+fn strings(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).map(|s| s.to_string()).collect(),
+        Some(Value::String(str)) => str.split(',').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect(),
+        _ => vec![],
+    }
+}
+
+/// Parse a time in the compact ISO 8601 format of Taskwarrior, like `20240501T123000Z`
+fn parse_time(str: &str) -> Option<Timestamp> {
+    NaiveDateTime::parse_from_str(str, "%Y%m%dT%H%M%SZ").ok()
+        .map(|time| time.and_utc().to_timestamp())
+}
+
+#[test]
+fn test_parse_export() {
+    let tasks = parse_export(r#"[
+{"id":0,"description":"Plant tomatoes","end":"20240502T080000Z","entry":"20240501T120000Z","modified":"20240502T080000Z","project":"Home.Garden","status":"completed","uuid":"a1","tags":["outside","spring"],"priority":"H","annotations":[{"entry":"20240501T130000Z","description":"Buy seeds first"}],"recur":"weekly"},
+{"id":1,"description":"Harvest","entry":"20240501T120000Z","status":"pending","uuid":"b2","depends":"a1,c3","urgency":1.5}
+]"#).unwrap();
+    assert_eq!(tasks.len(), 2);
+    let plant = &tasks[0];
+    assert_eq!(plant.project, vec!["Home", "Garden"]);
+    assert_eq!(plant.tags, vec!["outside", "spring"]);
+    assert_eq!(plant.state(), Some(State::Done));
+    assert_eq!(plant.entry, Some(Timestamp::from(1714564800)));
+    assert_eq!(plant.end, Some(Timestamp::from(1714636800)));
+    assert_eq!(plant.priority, Some('A'));
+    assert_eq!(plant.annotations, vec![(Some(Timestamp::from(1714568400)), "Buy seeds first".to_string())]);
+    assert_eq!(plant.unmapped, vec!["recur"]);
+    assert_eq!(tasks[1].depends, vec!["a1", "c3"]);
+    assert_eq!(tasks[1].state(), None);
+    assert!(tasks[1].unmapped.is_empty());
+
+    let lines = parse_export("{\"description\":\"One\",\"uuid\":\"1\",\"depends\":[\"2\"]},\n{\"description\":\"Two\",\"uuid\":\"2\"}\n").unwrap();
+    assert_eq!(lines.iter().map(|t| t.description.as_str()).collect::<Vec<_>>(), vec!["One", "Two"]);
+    assert_eq!(lines[0].depends, vec!["2"]);
+    assert!(parse_export("[{").is_err());
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use chrono::NaiveDate;
+use itertools::Itertools;
+
+/// Prefix of the due date in a todo.txt line
+const DUE_PREFIX: &str = "due:";
+/// Prefix of the priority kept on completed items in a todo.txt line
+const PRIORITY_PREFIX: &str = "pri:";
+
+/// A single line of a todo.txt file, see https://github.com/todotxt/todo.txt
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TodoItem {
+    pub(crate) done: bool,
+    /// Priority as an uppercase letter, `A` being the highest
+    pub(crate) priority: Option<char>,
+    pub(crate) completed: Option<NaiveDate>,
+    pub(crate) created: Option<NaiveDate>,
+    /// Words marked with `+`, without the marker
+    pub(crate) projects: Vec<String>,
+    /// Words marked with `@`, without the marker
+    pub(crate) contexts: Vec<String>,
+    pub(crate) due: Option<NaiveDate>,
+    /// Remaining text of the line
+    pub(crate) title: String,
+}
+
+impl TodoItem {
+    /// Parse a line in todo.txt format, returning None for blank lines.
+    /// Key-value pairs other than the due date are kept in the title.
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let mut item = TodoItem::default();
+        let mut words = line.split_whitespace().peekable();
+        if words.next_if_eq(&"x").is_some() {
+            item.done = true;
+        }
+        item.priority = words.next_if(|word| parse_priority(word).is_some()).and_then(parse_priority);
+        if item.done {
+            item.completed = words.next_if(|word| parse_day(word).is_some()).and_then(parse_day);
+        }
+        item.created = words.next_if(|word| parse_day(word).is_some()).and_then(parse_day);
+        let mut title = vec![];
+        for word in words {
+            if let Some(project) = word.strip_prefix('+').filter(|p| !p.is_empty()) {
+                item.projects.push(project.to_string());
+            } else if let Some(context) = word.strip_prefix('@').filter(|c| !c.is_empty()) {
+                item.contexts.push(context.to_string());
+            } else if let Some(due) = word.strip_prefix(DUE_PREFIX).and_then(parse_day) {
+                item.due = Some(due);
+            } else if let Some(priority) = word.strip_prefix(PRIORITY_PREFIX)
+                .filter(|_| item.priority.is_none())
+                .and_then(|p| p.chars().exactly_one().ok())
+                .filter(char::is_ascii_uppercase) {
+                item.priority = Some(priority);
+            } else {
+                title.push(word);
+            }
+        }
+        item.title = title.join(" ");
+        Some(item).filter(|item| !item.title.is_empty() || !item.projects.is_empty())
+    }
+}
+
+impl Display for TodoItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let day = |date: &NaiveDate| date.format("%Y-%m-%d").to_string();
+        let mut words = vec![];
+        if self.done {
+            words.push("x".to_string());
+            // A creation date alone would be taken as completion date
+            if let Some(completed) = &self.completed {
+                words.push(day(completed));
+                words.extend(self.created.as_ref().map(day));
+            }
+        } else {
+            words.extend(self.priority.map(|p| format!("({p})")));
+            words.extend(self.created.as_ref().map(day));
+        }
+        words.push(self.title.clone());
+        words.extend(self.projects.iter().map(|p| format!("+{p}")));
+        words.extend(self.contexts.iter().map(|c| format!("@{c}")));
+        words.extend(self.due.as_ref().map(|due| format!("{DUE_PREFIX}{}", day(due))));
+        if self.done {
+            words.extend(self.priority.map(|p| format!("{PRIORITY_PREFIX}{p}")));
+        }
+        write!(f, "{}", words.into_iter().filter(|w| !w.is_empty()).join(" "))
+    }
+}
+
+/// Priority from a word like `(A)`
+fn parse_priority(word: &str) -> Option<char> {
+    word.strip_prefix('(')?
+        .strip_suffix(')')?
+        .chars()
+        .exactly_one().ok()
+        .filter(char::is_ascii_uppercase)
+}
+
+fn parse_day(word: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()
+}
+
+#[test]
+fn test_parse_todo_item() {
+    let day = |str: &str| parse_day(str).unwrap();
+    assert_eq!(TodoItem::parse("   "), None);
+    assert_eq!(TodoItem::parse("(A) Call Mom +Family @phone due:2024-05-03"), Some(TodoItem {
+        priority: Some('A'),
+        projects: vec!["Family".to_string()],
+        contexts: vec!["phone".to_string()],
+        due: Some(day("2024-05-03")),
+        title: "Call Mom".to_string(),
+        ..Default::default()
+    }));
+    assert_eq!(TodoItem::parse("x 2024-05-02 2024-04-30 Review pull request +mostr pri:B"), Some(TodoItem {
+        done: true,
+        priority: Some('B'),
+        completed: Some(day("2024-05-02")),
+        created: Some(day("2024-04-30")),
+        projects: vec!["mostr".to_string()],
+        title: "Review pull request".to_string(),
+        ..Default::default()
+    }));
+    let item = TodoItem::parse("2024-04-30 (B) x marks the spot key:value due:soon").unwrap();
+    assert_eq!(item.created, Some(day("2024-04-30")));
+    assert_eq!(item.priority, None);
+    assert!(!item.done);
+    assert_eq!(item.title, "(B) x marks the spot key:value due:soon");
+    assert_eq!(TodoItem::parse("xylophone lessons").unwrap().title, "xylophone lessons");
+}
+
+#[test]
+fn test_todo_item_round_trip() {
+    for line in [
+        "(A) 2024-04-30 Call Mom +Family @phone due:2024-05-03",
+        "x 2024-05-02 2024-04-30 Review pull request +mostr pri:B",
+        "x 2024-05-02 Buy flowers",
+        "Water plants",
+    ] {
+        let item = TodoItem::parse(line).unwrap();
+        assert_eq!(item.to_string(), line);
+        assert_eq!(TodoItem::parse(&item.to_string()), Some(item));
+    }
+}